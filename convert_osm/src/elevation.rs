@@ -0,0 +1,272 @@
+//! Read a digital elevation model (DEM), either as an SRTM `.hgt` tile or a GeoTIFF. See
+//! <https://dwtkns.com/srtm30m/> or <https://www.usgs.gov/core-science-systems/ngp/3dep> for
+//! sources. GeoTIFFs must use longitude and latitude coordinates (EPSG:4326); reproject other
+//! files first with something like `gdalwarp -t_srs EPSG:4326 in.tif out.tif`. Signed integer
+//! samples aren't supported; convert them with `gdal_translate -ot Float32 in.tif out.tif`.
+
+use std::fs::File;
+use std::io::BufReader;
+
+use byteorder::{BigEndian, ReadBytesExt};
+use tiff::decoder::{Decoder, DecodingResult};
+use tiff::tags::Tag;
+use tiff::ColorType;
+
+use geom::{Distance, LonLat};
+
+/// A grid of elevation samples in meters. Rows go from north to south, and columns from west to
+/// east.
+pub struct Elevation {
+    /// The position of the center of the first sample, in the northwest corner
+    lon0: f64,
+    lat0: f64,
+    /// Degrees between samples
+    lon_step: f64,
+    lat_step: f64,
+    width: usize,
+    height: usize,
+    data: Vec<f32>,
+    no_data: Option<f32>,
+}
+
+impl Elevation {
+    pub fn load(path: &str) -> Result<Elevation, String> {
+        info!("Reading elevation data from {}", path);
+        let lower = path.to_lowercase();
+        if lower.ends_with(".hgt") {
+            load_hgt(path)
+        } else if lower.ends_with(".tif") || lower.ends_with(".tiff") {
+            load_geotiff(path)
+        } else {
+            Err(format!("{} isn't a .hgt or .tif file", path))
+        }
+    }
+
+    /// Interpolates between the 4 closest samples. None if the point is outside the grid or
+    /// there's no data nearby.
+    pub fn get(&self, pt: LonLat) -> Option<Distance> {
+        let x = (pt.x() - self.lon0) / self.lon_step;
+        let y = (self.lat0 - pt.y()) / self.lat_step;
+        // Points up to half a sample outside the grid are still covered by the edge samples
+        let max_x = (self.width - 1) as f64;
+        let max_y = (self.height - 1) as f64;
+        if x < -0.5 || y < -0.5 || x > max_x + 0.5 || y > max_y + 0.5 {
+            return None;
+        }
+        let x = x.max(0.0).min(max_x);
+        let y = y.max(0.0).min(max_y);
+
+        let x1 = x.floor() as usize;
+        let y1 = y.floor() as usize;
+        let x2 = (x1 + 1).min(self.width - 1);
+        let y2 = (y1 + 1).min(self.height - 1);
+        let fx = x - x1 as f64;
+        let fy = y - y1 as f64;
+
+        let mut total = 0.0;
+        let mut total_weight = 0.0;
+        for (col, row, weight) in &[
+            (x1, y1, (1.0 - fx) * (1.0 - fy)),
+            (x2, y1, fx * (1.0 - fy)),
+            (x1, y2, (1.0 - fx) * fy),
+            (x2, y2, fx * fy),
+        ] {
+            let weight = *weight;
+            let value = self.data[row * self.width + col];
+            if weight == 0.0 || !value.is_finite() || Some(value) == self.no_data {
+                continue;
+            }
+            total += weight * f64::from(value);
+            total_weight += weight;
+        }
+        if total_weight == 0.0 {
+            return None;
+        }
+        Some(Distance::meters(total / total_weight))
+    }
+}
+
+// SRTM tiles are named after their southwest corner, like N47W122.hgt. They're square grids of
+// big-endian 16-bit samples, with the edges overlapping the neighboring tiles.
+fn load_hgt(path: &str) -> Result<Elevation, String> {
+    let name = abstutil::basename(path).to_uppercase();
+    let (lat, lon) =
+        parse_hgt_name(&name).ok_or_else(|| format!("{} isn't named like N47W122.hgt", path))?;
+
+    let f = File::open(path).map_err(|err| err.to_string())?;
+    let num_samples = f.metadata().map_err(|err| err.to_string())?.len() as usize / 2;
+    let dim = (num_samples as f64).sqrt().round() as usize;
+    if dim < 2 || dim * dim != num_samples {
+        return Err(format!("{} isn't a square grid of samples", path));
+    }
+
+    let mut reader = BufReader::new(f);
+    let mut data = Vec::with_capacity(num_samples);
+    for _ in 0..num_samples {
+        let value = reader
+            .read_i16::<BigEndian>()
+            .map_err(|err| err.to_string())?;
+        data.push(f32::from(value));
+    }
+
+    let step = 1.0 / ((dim - 1) as f64);
+    Ok(Elevation {
+        lon0: lon,
+        lat0: lat + 1.0,
+        lon_step: step,
+        lat_step: step,
+        width: dim,
+        height: dim,
+        data,
+        // Voids
+        no_data: Some(-32768.0),
+    })
+}
+
+fn parse_hgt_name(name: &str) -> Option<(f64, f64)> {
+    let lon_idx = name.find(&['E', 'W'][..])?;
+    let (lat, lon) = name.split_at(lon_idx);
+    let parse = |x: &str, negative: char| -> Option<f64> {
+        let value = x[1..].parse::<f64>().ok()?;
+        if x.starts_with(negative) {
+            Some(-value)
+        } else {
+            Some(value)
+        }
+    };
+    if !lat.starts_with('N') && !lat.starts_with('S') {
+        return None;
+    }
+    Some((parse(lat, 'S')?, parse(lon, 'W')?))
+}
+
+// GeoTIFF keys, from http://docs.opengeospatial.org/is/19-008r4/19-008r4.html
+const GT_MODEL_TYPE: u16 = 1024;
+const GT_RASTER_TYPE: u16 = 1025;
+const MODEL_TYPE_GEOGRAPHIC: u16 = 2;
+const RASTER_PIXEL_IS_POINT: u16 = 2;
+
+fn load_geotiff(path: &str) -> Result<Elevation, String> {
+    let f = File::open(path).map_err(|err| err.to_string())?;
+    let mut decoder = Decoder::new(BufReader::new(f)).map_err(|err| err.to_string())?;
+    match decoder.colortype().map_err(|err| err.to_string())? {
+        ColorType::Gray(_) => {}
+        x => {
+            return Err(format!(
+                "{} has {:?} pixels; elevation needs one sample per pixel",
+                path, x
+            ));
+        }
+    }
+    let (width, height) = decoder.dimensions().map_err(|err| err.to_string())?;
+
+    // Each key is 4 numbers (the ID, where the value's stored, the count, and the value), after a
+    // 4 number header
+    let mut model_type = None;
+    let mut pixel_is_point = false;
+    if let Ok(keys) = decoder.get_tag_u16_vec(Tag::GeoKeyDirectoryTag) {
+        for key in keys.chunks(4).skip(1) {
+            if key.len() == 4 && key[1] == 0 {
+                if key[0] == GT_MODEL_TYPE {
+                    model_type = Some(key[3]);
+                } else if key[0] == GT_RASTER_TYPE {
+                    pixel_is_point = key[3] == RASTER_PIXEL_IS_POINT;
+                }
+            }
+        }
+    }
+    if model_type != Some(MODEL_TYPE_GEOGRAPHIC) {
+        return Err(format!(
+            "{} doesn't use longitude and latitude coordinates; reproject it to EPSG:4326",
+            path
+        ));
+    }
+
+    let scale = decoder
+        .get_tag_f64_vec(Tag::ModelPixelScaleTag)
+        .map_err(|err| format!("{} has no pixel scale: {}", path, err))?;
+    let tiepoint = decoder
+        .get_tag_f64_vec(Tag::ModelTiepointTag)
+        .map_err(|err| format!("{} has no tiepoint: {}", path, err))?;
+    if scale.len() < 2 || tiepoint.len() < 6 {
+        return Err(format!("{} has a malformed pixel scale or tiepoint", path));
+    }
+    let no_data = decoder
+        .get_tag_ascii_string(Tag::GdalNodata)
+        .ok()
+        .and_then(|x| x.trim_matches(char::from(0)).trim().parse::<f32>().ok());
+
+    let data: Vec<f32> = match decoder
+        .read_image()
+        .map_err(|err| format!("{}: {}", path, err))?
+    {
+        DecodingResult::U8(x) => x.into_iter().map(f32::from).collect(),
+        DecodingResult::U16(x) => x.into_iter().map(f32::from).collect(),
+        DecodingResult::U32(x) => x.into_iter().map(|v| v as f32).collect(),
+        DecodingResult::U64(x) => x.into_iter().map(|v| v as f32).collect(),
+        DecodingResult::F32(x) => x,
+        DecodingResult::F64(x) => x.into_iter().map(|v| v as f32).collect(),
+    };
+    let (width, height) = (width as usize, height as usize);
+    if width < 2 || height < 2 || data.len() != width * height {
+        return Err(format!("{} has an unexpected number of samples", path));
+    }
+
+    // The tiepoint maps raster position (i, j) to (lon, lat). By default, that raster position
+    // refers to the corner of a pixel, not its center.
+    let (i, j, lon, lat) = (tiepoint[0], tiepoint[1], tiepoint[3], tiepoint[4]);
+    let offset = if pixel_is_point { 0.0 } else { 0.5 };
+    Ok(Elevation {
+        lon0: lon + (offset - i) * scale[0],
+        lat0: lat - (offset - j) * scale[1],
+        lon_step: scale[0],
+        lat_step: scale[1],
+        width,
+        height,
+        data,
+        no_data,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hgt_origin() {
+        assert_eq!(parse_hgt_name("N47W122"), Some((47.0, -122.0)));
+        assert_eq!(parse_hgt_name("S34E151"), Some((-34.0, 151.0)));
+        assert_eq!(parse_hgt_name("seattle"), None);
+    }
+
+    #[test]
+    fn test_interpolation() {
+        // 3x3 samples, 0.5 degrees apart, covering 47-48N and 122-121W
+        let e = Elevation {
+            lon0: -122.0,
+            lat0: 48.0,
+            lon_step: 0.5,
+            lat_step: 0.5,
+            width: 3,
+            height: 3,
+            data: vec![0.0, 10.0, 20.0, 30.0, 40.0, 50.0, 60.0, -32768.0, 80.0],
+            no_data: Some(-32768.0),
+        };
+        // Exactly on a sample
+        assert_eq!(
+            e.get(LonLat::new(-121.5, 47.5)),
+            Some(Distance::meters(40.0))
+        );
+        // Halfway between the northwest samples
+        assert_eq!(
+            e.get(LonLat::new(-121.75, 47.75)),
+            Some(Distance::meters(20.0))
+        );
+        // Voids are skipped
+        assert_eq!(
+            e.get(LonLat::new(-121.25, 47.0)),
+            Some(Distance::meters(80.0))
+        );
+        assert_eq!(e.get(LonLat::new(-123.0, 47.5)), None);
+    }
+}
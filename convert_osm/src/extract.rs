@@ -5,7 +5,10 @@ use osm::{NodeID, OsmID, RelationID, WayID};
 use abstutil::{retain_btreemap, Tags, Timer};
 use geom::{HashablePt2D, Polygon, Pt2D, Ring};
 use kml::{ExtraShape, ExtraShapes};
-use map_model::raw::{RawArea, RawBuilding, RawMap, RawParkingLot, RawRoad, RestrictionType};
+use map_model::raw::{
+    RawArea, RawBikeShareStation, RawBuilding, RawMap, RawParkingLot, RawRoad, RestrictionScope,
+    RestrictionType,
+};
 use map_model::{osm, Amenity, AreaType, NamePerLanguage};
 
 use crate::osm_geom::{get_multipolygon_members, glue_multipolygon, multipoly_geometry};
@@ -17,10 +20,17 @@ pub struct OsmExtract {
     /// Traffic signals to the direction they apply (or just true if unspecified)
     pub traffic_signals: HashMap<HashablePt2D, bool>,
     pub osm_node_ids: HashMap<HashablePt2D, NodeID>,
-    /// (ID, restriction type, from way ID, via node ID, to way ID)
-    pub simple_turn_restrictions: Vec<(RestrictionType, WayID, NodeID, WayID)>,
-    /// (relation ID, from way ID, via way ID, to way ID)
-    pub complicated_turn_restrictions: Vec<(RelationID, WayID, WayID, WayID)>,
+    /// (restriction type, from way ID, via node ID, to way ID, scope)
+    pub simple_turn_restrictions: Vec<(RestrictionType, WayID, NodeID, WayID, RestrictionScope)>,
+    /// (relation ID, restriction type, from way ID, via way IDs in order, to way ID, scope)
+    pub complicated_turn_restrictions: Vec<(
+        RelationID,
+        RestrictionType,
+        WayID,
+        Vec<WayID>,
+        WayID,
+        RestrictionScope,
+    )>,
     /// (location, amenity)
     pub amenities: Vec<(Pt2D, Amenity)>,
 }
@@ -55,6 +65,25 @@ pub fn extract_osm(map: &mut RawMap, opts: &Options, timer: &mut Timer) -> OsmEx
         for amenity in get_bldg_amenities(&node.tags) {
             out.amenities.push((node.pt, amenity));
         }
+        // Shops renting bikes don't have docks
+        if node.tags.is("amenity", "bicycle_rental") && !node.tags.is("bicycle_rental", "shop") {
+            map.bike_share_stations.push(RawBikeShareStation {
+                external_id: id.0.to_string(),
+                name: node
+                    .tags
+                    .get("name")
+                    .cloned()
+                    .unwrap_or_else(|| format!("unnamed station {}", id.0)),
+                point: node.pt,
+                // If the capacity isn't tagged, guess something typical
+                capacity: node
+                    .tags
+                    .get("capacity")
+                    .and_then(|x| x.parse::<usize>().ok())
+                    .unwrap_or(10),
+                initial_bikes: None,
+            });
+        }
     }
 
     // and cycleways
@@ -196,8 +225,9 @@ pub fn extract_osm(map: &mut RawMap, opts: &Options, timer: &mut Timer) -> OsmEx
             }
         } else if rel.tags.is("type", "restriction") {
             let mut from_way_id: Option<WayID> = None;
-            let mut via_node_id: Option<NodeID> = None;
-            let mut via_way_id: Option<WayID> = None;
+            let mut via_node_ids: Vec<NodeID> = Vec::new();
+            // In order, if the restriction passes through several ways
+            let mut via_way_ids: Vec<WayID> = Vec::new();
             let mut to_way_id: Option<WayID> = None;
             for (role, member) in &rel.members {
                 match member {
@@ -207,12 +237,12 @@ pub fn extract_osm(map: &mut RawMap, opts: &Options, timer: &mut Timer) -> OsmEx
                         } else if role == "to" {
                             to_way_id = Some(*w);
                         } else if role == "via" {
-                            via_way_id = Some(*w);
+                            via_way_ids.push(*w);
                         }
                     }
                     OsmID::Node(n) => {
                         if role == "via" {
-                            via_node_id = Some(*n);
+                            via_node_ids.push(*n);
                         }
                     }
                     OsmID::Relation(r) => {
@@ -220,23 +250,30 @@ pub fn extract_osm(map: &mut RawMap, opts: &Options, timer: &mut Timer) -> OsmEx
                     }
                 }
             }
-            if let Some(restriction) = rel.tags.get("restriction") {
-                if let Some(rt) = RestrictionType::new(restriction) {
-                    if let (Some(from), Some(via), Some(to)) = (from_way_id, via_node_id, to_way_id)
-                    {
-                        out.simple_turn_restrictions.push((rt, from, via, to));
-                    } else if let (Some(from), Some(via), Some(to)) =
-                        (from_way_id, via_way_id, to_way_id)
+            for (rt, scope) in RestrictionType::all_from_osm(&rel.tags) {
+                match (from_way_id, to_way_id) {
+                    (Some(from), Some(to)) if via_node_ids.len() == 1 && via_way_ids.is_empty() => {
+                        out.simple_turn_restrictions
+                            .push((rt, from, via_node_ids[0], to, scope));
+                    }
+                    (Some(from), Some(to))
+                        if via_node_ids.is_empty() && !via_way_ids.is_empty() =>
                     {
-                        if rt == RestrictionType::BanTurns {
-                            out.complicated_turn_restrictions.push((id, from, via, to));
-                        } else {
-                            timer.warn(format!(
-                                "Weird complicated turn restriction \"{}\" from {} to {} via {}: \
-                                 {}",
-                                restriction, from, to, via, id
-                            ));
-                        }
+                        out.complicated_turn_restrictions.push((
+                            id,
+                            rt,
+                            from,
+                            via_way_ids.clone(),
+                            to,
+                            scope,
+                        ));
+                    }
+                    _ => {
+                        timer.warn(format!(
+                            "Weird turn restriction {}: from {:?} to {:?} via nodes {:?} and \
+                             ways {:?}",
+                            id, from_way_id, to_way_id, via_node_ids, via_way_ids
+                        ));
                     }
                 }
             }
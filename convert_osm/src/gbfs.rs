@@ -0,0 +1,77 @@
+//! Import bike share stations from a snapshot of a GBFS feed
+//! (<https://github.com/NABSA/gbfs/blob/master/gbfs.md>). The directory should contain
+//! `station_information.json` and optionally `station_status.json`, saved from the feed at the
+//! time of day the simulation should start from.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+
+use abstutil::Timer;
+use geom::LonLat;
+use map_model::raw::{RawBikeShareStation, RawMap};
+
+#[derive(Deserialize)]
+struct Feed<T> {
+    data: Stations<T>,
+}
+
+#[derive(Deserialize)]
+struct Stations<T> {
+    stations: Vec<T>,
+}
+
+#[derive(Deserialize)]
+struct StationInformation {
+    station_id: String,
+    name: String,
+    lat: f64,
+    lon: f64,
+    capacity: Option<usize>,
+}
+
+#[derive(Deserialize)]
+struct StationStatus {
+    station_id: String,
+    num_bikes_available: usize,
+    num_docks_available: Option<usize>,
+}
+
+/// Replaces any stations found in OSM.
+pub fn import(map: &mut RawMap, dir: &str, timer: &mut Timer) -> Result<(), String> {
+    let info: Feed<StationInformation> =
+        abstutil::maybe_read_json(format!("{}/station_information.json", dir), timer)
+            .map_err(|err| format!("couldn't read station_information.json: {}", err))?;
+    let mut status: HashMap<String, StationStatus> = HashMap::new();
+    let status_path = format!("{}/station_status.json", dir);
+    if abstutil::file_exists(&status_path) {
+        let feed: Feed<StationStatus> = abstutil::maybe_read_json(status_path, timer)
+            .map_err(|err| format!("couldn't read station_status.json: {}", err))?;
+        for s in feed.data.stations {
+            status.insert(s.station_id.clone(), s);
+        }
+    }
+
+    map.bike_share_stations.clear();
+    for station in info.data.stations {
+        let gps = LonLat::new(station.lon, station.lat);
+        if !map.gps_bounds.contains(gps) {
+            continue;
+        }
+        let status = status.get(&station.station_id);
+        let capacity = station
+            .capacity
+            .or_else(|| {
+                status.and_then(|s| s.num_docks_available.map(|d| d + s.num_bikes_available))
+            })
+            .unwrap_or(10);
+        map.bike_share_stations.push(RawBikeShareStation {
+            external_id: station.station_id,
+            name: station.name,
+            point: gps.to_pt(&map.gps_bounds),
+            capacity,
+            initial_bikes: status.map(|s| s.num_bikes_available),
+        });
+    }
+    Ok(())
+}
@@ -8,13 +8,14 @@ use map_model::{osm, Amenity, MapConfig};
 use serde::{Deserialize, Serialize};
 
 mod clip;
+mod elevation;
 mod extract;
+mod gbfs;
 pub mod osm_geom;
 mod parking;
 pub mod reader;
 mod snappy;
 mod split_ways;
-mod srtm;
 mod transit;
 
 pub struct Options {
@@ -28,11 +29,13 @@ pub struct Options {
     pub onstreet_parking: OnstreetParking,
     pub public_offstreet_parking: PublicOffstreetParking,
     pub private_offstreet_parking: PrivateOffstreetParking,
-    /// If provided, pull elevation data from this SRTM file. The SRTM parser is incorrect, so the
-    /// results will be nonsense.
+    /// If provided, pull elevation data from this SRTM `.hgt` tile or GeoTIFF.
     pub elevation: Option<String>,
     /// OSM railway=rail will be included as light rail if so. Cosmetic only.
     pub include_railroads: bool,
+    /// If provided, read bike share stations from a directory with a GBFS snapshot, instead of
+    /// using stations from OSM.
+    pub bike_share_gbfs: Option<String>,
 }
 
 /// What roads will have on-street parking lanes? Data from
@@ -106,8 +109,16 @@ pub fn convert(opts: Options, timer: &mut abstutil::Timer) -> RawMap {
 
     parking::apply_parking(&mut map, &opts, timer);
 
+    if let Some(ref dir) = opts.bike_share_gbfs {
+        if let Err(err) = gbfs::import(&mut map, dir, timer) {
+            timer.error(format!("Keeping bike share stations from OSM: {}", err));
+        }
+    }
+
     if let Some(ref path) = opts.elevation {
-        use_elevation(&mut map, path, timer);
+        if let Err(err) = use_elevation(&mut map, path, timer) {
+            timer.error(format!("Not using elevation data: {}", err));
+        }
     }
 
     snappy::snap_cycleways(&mut map, timer);
@@ -134,16 +145,28 @@ fn use_amenities(map: &mut RawMap, amenities: Vec<(Pt2D, Amenity)>, timer: &mut
     }
 }
 
-fn use_elevation(map: &mut RawMap, path: &str, timer: &mut Timer) {
+fn use_elevation(map: &mut RawMap, path: &str, timer: &mut Timer) -> Result<(), String> {
+    let elevation = elevation::Elevation::load(path)?;
     timer.start("apply elevation data to intersections");
-    let elevation = srtm::Elevation::load(path).unwrap();
+    let mut missing = 0;
     for i in map.intersections.values_mut() {
         // TODO Not sure why, but I've seen nodes from South Carolina wind up in the updated
         // Seattle extract. And I think there's a bug with clipping, because they survive to this
         // point. O_O
         if map.boundary_polygon.contains_pt(i.point) {
-            i.elevation = elevation.get(i.point.to_gps(&map.gps_bounds));
+            if let Some(height) = elevation.get(i.point.to_gps(&map.gps_bounds)) {
+                i.elevation = height;
+            } else {
+                missing += 1;
+            }
         }
     }
+    if missing > 0 {
+        timer.warn(format!(
+            "{} intersections aren't covered by {}; leaving them at sea level",
+            missing, path
+        ));
+    }
     timer.stop("apply elevation data to intersections");
+    Ok(())
 }
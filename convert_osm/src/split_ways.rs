@@ -98,7 +98,7 @@ pub fn split_up_roads(
 
     // Resolve simple turn restrictions (via a node)
     let mut restrictions = Vec::new();
-    for (restriction, from_osm, via_osm, to_osm) in input.simple_turn_restrictions {
+    for (restriction, from_osm, via_osm, to_osm, scope) in input.simple_turn_restrictions {
         let roads = map.roads_per_intersection(via_osm);
         // If some of the roads are missing, they were likely filtered out -- usually service
         // roads.
@@ -106,65 +106,38 @@ pub fn split_up_roads(
             roads.iter().find(|r| r.osm_way_id == from_osm),
             roads.iter().find(|r| r.osm_way_id == to_osm),
         ) {
-            restrictions.push((*from, restriction, *to));
+            restrictions.push((*from, restriction, *to, scope));
         }
     }
-    for (from, rt, to) in restrictions {
+    for (from, rt, to, scope) in restrictions {
         map.roads
             .get_mut(&from)
             .unwrap()
             .turn_restrictions
-            .push((rt, to));
+            .push((rt, to, scope));
     }
 
-    // Resolve complicated turn restrictions (via a way). TODO Only handle via ways immediately
-    // connected to both roads, for now
+    // Resolve complicated turn restrictions (via one or more ways)
     let mut complicated_restrictions = Vec::new();
-    for (rel_osm, from_osm, via_osm, to_osm) in input.complicated_turn_restrictions {
-        let via_candidates: Vec<OriginalRoad> = map
-            .roads
-            .keys()
-            .filter(|r| r.osm_way_id == via_osm)
-            .cloned()
-            .collect();
-        if via_candidates.len() != 1 {
-            timer.warn(format!(
-                "Couldn't resolve turn restriction from way {} to way {} via way {}. Candidate \
-                 roads for via: {:?}. See {}",
-                from_osm, to_osm, via_osm, via_candidates, rel_osm
-            ));
-            continue;
-        }
-        let via = via_candidates[0];
-
-        let maybe_from = map
-            .roads_per_intersection(via.i1)
-            .into_iter()
-            .chain(map.roads_per_intersection(via.i2).into_iter())
-            .find(|r| r.osm_way_id == from_osm);
-        let maybe_to = map
-            .roads_per_intersection(via.i1)
-            .into_iter()
-            .chain(map.roads_per_intersection(via.i2).into_iter())
-            .find(|r| r.osm_way_id == to_osm);
-        match (maybe_from, maybe_to) {
-            (Some(from), Some(to)) => {
-                complicated_restrictions.push((from, via, to));
+    for (rel_osm, rt, from_osm, via_osm, to_osm, scope) in input.complicated_turn_restrictions {
+        match resolve_via_ways(map, from_osm, &via_osm, to_osm) {
+            Some((from, via, to)) => {
+                complicated_restrictions.push((from, rt, via, to, scope));
             }
-            _ => {
+            None => {
                 timer.warn(format!(
-                    "Couldn't resolve turn restriction from {} to {} via {:?}",
-                    from_osm, to_osm, via
+                    "Couldn't resolve turn restriction from way {} to way {} via ways {:?}. See {}",
+                    from_osm, to_osm, via_osm, rel_osm
                 ));
             }
         }
     }
-    for (from, via, to) in complicated_restrictions {
+    for (from, rt, via, to, scope) in complicated_restrictions {
         map.roads
             .get_mut(&from)
             .unwrap()
             .complicated_turn_restrictions
-            .push((via, to));
+            .push((rt, via, to, scope));
     }
 
     timer.start("match traffic signals to intersections");
@@ -194,6 +167,70 @@ pub fn split_up_roads(
     (input.amenities, pt_to_road)
 }
 
+/// Find the chain of roads that a turn restriction passes through. Each via way may have been split
+/// into several roads, and so might the from and to ways, so search outwards from every piece of
+/// the from way.
+fn resolve_via_ways(
+    map: &RawMap,
+    from_osm: osm::WayID,
+    via_osm: &Vec<osm::WayID>,
+    to_osm: osm::WayID,
+) -> Option<(OriginalRoad, Vec<OriginalRoad>, OriginalRoad)> {
+    let via_roads: Vec<OriginalRoad> = map
+        .roads
+        .keys()
+        .filter(|r| via_osm.contains(&r.osm_way_id))
+        .cloned()
+        .collect();
+    for from in map.roads.keys().filter(|r| r.osm_way_id == from_osm) {
+        for i in [from.i1, from.i2].iter().cloned() {
+            if let Some((via, to)) =
+                follow_via_roads(map, i, Vec::new(), &via_roads, via_osm, to_osm, *from)
+            {
+                return Some((*from, via, to));
+            }
+        }
+    }
+    None
+}
+
+// Depth-first search along the via roads, starting from an intersection. Only finishes once every
+// via way has been used.
+fn follow_via_roads(
+    map: &RawMap,
+    i: osm::NodeID,
+    path: Vec<OriginalRoad>,
+    via_roads: &Vec<OriginalRoad>,
+    via_osm: &Vec<osm::WayID>,
+    to_osm: osm::WayID,
+    from: OriginalRoad,
+) -> Option<(Vec<OriginalRoad>, OriginalRoad)> {
+    if via_osm
+        .iter()
+        .all(|w| path.iter().any(|r| r.osm_way_id == *w))
+    {
+        if let Some(to) = map
+            .roads_per_intersection(i)
+            .into_iter()
+            .find(|r| r.osm_way_id == to_osm && *r != from && !path.contains(r))
+        {
+            return Some((path, to));
+        }
+    }
+    for r in via_roads {
+        if path.contains(r) || (r.i1 != i && r.i2 != i) {
+            continue;
+        }
+        let next = if r.i1 == i { r.i2 } else { r.i1 };
+        let mut path = path.clone();
+        path.push(*r);
+        if let Some(result) = follow_via_roads(map, next, path, via_roads, via_osm, to_osm, from) {
+            return Some(result);
+        }
+    }
+    None
+}
+
 // TODO Consider doing this in PolyLine::new always. extend() there does this too.
 fn dedupe_angles(pts: Vec<Pt2D>) -> Vec<Pt2D> {
     let mut result = Vec::new();
@@ -142,7 +142,10 @@ impl State<App> for DebugMode {
                             match prev_state.clone().and_then(|path| {
                                 Sim::load_savestate(path, &app.primary.map, &mut timer).ok()
                             }) {
-                                Some(new_sim) => {
+                                Some(mut new_sim) => {
+                                    new_sim.restore_unsaved_options(
+                                        &app.primary.current_flags.sim_flags.opts,
+                                    );
                                     app.primary.sim = new_sim;
                                     app.recalculate_current_selection(ctx);
                                     None
@@ -168,7 +171,10 @@ impl State<App> for DebugMode {
                         match next_state.clone().and_then(|path| {
                             Sim::load_savestate(path, &app.primary.map, &mut timer).ok()
                         }) {
-                            Some(new_sim) => {
+                            Some(mut new_sim) => {
+                                new_sim.restore_unsaved_options(
+                                    &app.primary.current_flags.sim_flags.opts,
+                                );
                                 app.primary.sim = new_sim;
                                 app.recalculate_current_selection(ctx);
                                 None
@@ -196,6 +202,9 @@ impl State<App> for DebugMode {
                                 app.primary.sim =
                                     Sim::load_savestate(ss_path, &app.primary.map, &mut timer)
                                         .expect("Can't load savestate");
+                                app.primary.sim.restore_unsaved_options(
+                                    &app.primary.current_flags.sim_flags.opts,
+                                );
                                 app.recalculate_current_selection(ctx);
                             });
                             Transition::Pop
@@ -1,7 +1,9 @@
-use map_model::{EditCmd, LaneID, LaneType, Map};
+use std::collections::BTreeSet;
+
+use map_model::{EditCmd, LaneID, LaneType, Map, TurnType};
 use widgetry::{
-    Btn, Choice, Color, EventCtx, GfxCtx, HorizontalAlignment, Key, Line, Outcome, Panel, State,
-    Text, TextExt, VerticalAlignment, Widget,
+    Btn, Checkbox, Choice, Color, EventCtx, GfxCtx, HorizontalAlignment, Key, Line, Outcome, Panel,
+    State, Text, TextExt, VerticalAlignment, Widget,
 };
 
 use crate::app::App;
@@ -10,7 +12,7 @@ use crate::edit::zones::ZoneEditor;
 use crate::edit::{
     apply_map_edits, can_edit_lane, maybe_edit_intersection, speed_limit_choices, try_change_lt,
 };
-use crate::game::Transition;
+use crate::game::{PopupMsg, Transition};
 use crate::helpers::ID;
 use crate::render::Renderable;
 use crate::sandbox::GameplayMode;
@@ -73,7 +75,7 @@ impl LaneEditor {
         }
 
         let parent = app.primary.map.get_parent(l);
-        let col = vec![
+        let mut col = vec![
             Widget::row(vec![
                 Line(format!("Editing {}", l)).small_heading().draw(ctx),
                 Btn::plaintext_custom(
@@ -85,6 +87,23 @@ impl LaneEditor {
             "Type of lane".draw_text(ctx),
             Widget::custom_row(row).centered(),
             Btn::text_fg("reverse direction").build_def(ctx, Key::F),
+        ];
+        if lt == LaneType::Driving || lt == LaneType::Bus {
+            let allowed = app.primary.map.get_l(l).get_turn_restrictions();
+            let mut row = vec!["Turns from this lane:".draw_text(ctx).centered_vert()];
+            for (label, turn_type) in TURN_ARROWS {
+                row.push(Checkbox::checkbox(
+                    ctx,
+                    *label,
+                    None,
+                    allowed
+                        .map(|types| types.contains(turn_type))
+                        .unwrap_or(true),
+                ));
+            }
+            col.push(Widget::row(row));
+        }
+        col.extend(vec![
             {
                 let mut choices = speed_limit_choices(app);
                 if !choices.iter().any(|c| c.data == parent.speed_limit) {
@@ -100,7 +119,7 @@ impl LaneEditor {
             },
             Btn::text_fg("Change access restrictions").build_def(ctx, Key::A),
             Btn::text_bg2("Finish").build_def(ctx, Key::Escape),
-        ];
+        ]);
 
         let panel = Panel::new(Widget::col(col))
             .aligned(HorizontalAlignment::Center, VerticalAlignment::Top)
@@ -201,13 +220,46 @@ impl State<App> for LaneEditor {
                 }
             },
             Outcome::Changed => {
-                let mut edits = app.primary.map.get_edits().clone();
-                edits.commands.push(app.primary.map.edit_road_cmd(
-                    app.primary.map.get_l(self.l).parent,
-                    |new| {
-                        new.speed_limit = self.panel.dropdown_value("speed limit");
-                    },
-                ));
+                let map = &app.primary.map;
+                let r = map.get_parent(self.l);
+                let idx = r.offset(self.l);
+                let mut allowed_turns = None;
+                if self.panel.has_widget(TURN_ARROWS[0].0) {
+                    let types: BTreeSet<TurnType> = TURN_ARROWS
+                        .iter()
+                        .filter(|(label, _)| self.panel.is_checked(label))
+                        .map(|(_, turn_type)| *turn_type)
+                        .collect();
+                    if types.is_empty() {
+                        // Reset the checkboxes to the current turns
+                        return Transition::Multi(vec![
+                            Transition::Replace(LaneEditor::new(
+                                ctx,
+                                app,
+                                self.l,
+                                self.mode.clone(),
+                            )),
+                            Transition::Push(PopupMsg::new(
+                                ctx,
+                                "Error",
+                                vec!["Vehicles must be able to go somewhere from this lane"],
+                            )),
+                        ]);
+                    }
+                    allowed_turns = Some(types);
+                }
+
+                let mut edits = map.get_edits().clone();
+                edits.commands.push(map.edit_road_cmd(r.id, |new| {
+                    new.speed_limit = self.panel.dropdown_value("speed limit");
+                    if let Some(ref types) = allowed_turns {
+                        if types.len() == TURN_ARROWS.len() {
+                            new.allowed_turns.remove(&idx);
+                        } else {
+                            new.allowed_turns.insert(idx, types.clone());
+                        }
+                    }
+                }));
                 apply_map_edits(ctx, app, edits);
                 return Transition::Replace(LaneEditor::new(ctx, app, self.l, self.mode.clone()));
             }
@@ -230,6 +282,12 @@ impl State<App> for LaneEditor {
     }
 }
 
+const TURN_ARROWS: &[(&str, TurnType)] = &[
+    ("left", TurnType::Left),
+    ("straight", TurnType::Straight),
+    ("right", TurnType::Right),
+];
+
 // Allow doing this anywhere. Players can create really wacky roads with many direction changes,
 // but it's not really useful to limit creativity. ;)
 fn reverse_lane(map: &Map, l: LaneID) -> EditCmd {
@@ -237,5 +295,7 @@ fn reverse_lane(map: &Map, l: LaneID) -> EditCmd {
     let idx = r.offset(l);
     map.edit_road_cmd(r.id, |new| {
         new.lanes_ltr[idx].1 = new.lanes_ltr[idx].1.opposite();
+        // The lane now leads to different turns
+        new.allowed_turns.remove(&idx);
     })
 }
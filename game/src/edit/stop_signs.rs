@@ -5,7 +5,7 @@ use maplit::btreeset;
 use abstutil::Timer;
 use geom::Polygon;
 use map_model::{
-    ControlStopSign, ControlTrafficSignal, EditCmd, EditIntersection, IntersectionID, RoadID,
+    ControlStopSign, ControlTrafficSignal, EditCmd, EditIntersection, IntersectionID, Map, RoadID,
 };
 use widgetry::{
     Btn, EventCtx, GeomBatch, GfxCtx, HorizontalAlignment, Key, Line, Outcome, Panel, State, Text,
@@ -21,6 +21,7 @@ use crate::sandbox::GameplayMode;
 
 // TODO For now, individual turns can't be manipulated. Banning turns could be useful, but I'm not
 // sure what to do about the player orphaning a section of the map.
+/// Also edits roundabouts, which use yield signs instead.
 pub struct StopSignEditor {
     panel: Panel,
     id: IntersectionID,
@@ -38,6 +39,7 @@ impl StopSignEditor {
         mode: GameplayMode,
     ) -> Box<dyn State<App>> {
         app.primary.current_selection = None;
+        let roundabout = app.primary.map.get_i(id).is_roundabout();
         let geom = app
             .primary
             .map
@@ -45,23 +47,35 @@ impl StopSignEditor {
             .roads
             .iter()
             .map(|(r, ss)| {
-                let (octagon, pole) =
-                    DrawIntersection::stop_sign_geom(ss, &app.primary.map).unwrap();
-                (*r, (octagon, pole))
+                let (sign, pole) = if roundabout {
+                    DrawIntersection::yield_sign_geom(ss, &app.primary.map).unwrap()
+                } else {
+                    DrawIntersection::stop_sign_geom(ss, &app.primary.map).unwrap()
+                };
+                (*r, (sign, pole))
             })
             .collect();
 
         let panel = Panel::new(Widget::col(vec![
-            Line("Stop sign editor").small_heading().draw(ctx),
-            if ControlStopSign::new(&app.primary.map, id)
-                != app.primary.map.get_stop_sign(id).clone()
-            {
+            Line(if roundabout {
+                "Roundabout editor"
+            } else {
+                "Stop sign editor"
+            })
+            .small_heading()
+            .draw(ctx),
+            if default_signs(&app.primary.map, id) != app.primary.map.get_stop_sign(id).clone() {
                 Btn::text_fg("reset to default").build_def(ctx, Key::R)
             } else {
                 Btn::text_fg("reset to default").inactive(ctx)
             },
             Btn::text_fg("close intersection for construction").build_def(ctx, Key::C),
             Btn::text_fg("convert to traffic signal").build_def(ctx, None),
+            if roundabout {
+                Btn::text_fg("convert to stop signs").build_def(ctx, None)
+            } else {
+                Btn::text_fg("convert to roundabout").build_def(ctx, None)
+            },
             Btn::text_fg("Finish").build_def(ctx, Key::Escape),
         ]))
         .aligned(HorizontalAlignment::Center, VerticalAlignment::Top)
@@ -95,10 +109,12 @@ impl State<App> for StopSignEditor {
 
         if let Some(r) = self.selected_sign {
             let mut sign = app.primary.map.get_stop_sign(self.id).clone();
-            let label = if sign.roads[&r].must_stop {
-                "remove stop sign"
-            } else {
-                "add stop sign"
+            let roundabout = app.primary.map.get_i(self.id).is_roundabout();
+            let label = match (roundabout, sign.roads[&r].must_stop) {
+                (false, true) => "remove stop sign",
+                (false, false) => "add stop sign",
+                (true, true) => "remove yield sign",
+                (true, false) => "add yield sign",
             };
             if app.per_obj.left_click(ctx, label) {
                 sign.flip_sign(r);
@@ -107,7 +123,11 @@ impl State<App> for StopSignEditor {
                 edits.commands.push(EditCmd::ChangeIntersection {
                     i: self.id,
                     old: app.primary.map.get_i_edit(self.id),
-                    new: EditIntersection::StopSign(sign),
+                    new: if roundabout {
+                        EditIntersection::Roundabout(sign)
+                    } else {
+                        EditIntersection::StopSign(sign)
+                    },
                 });
                 apply_map_edits(ctx, app, edits);
                 return Transition::Replace(StopSignEditor::new(
@@ -126,13 +146,15 @@ impl State<App> for StopSignEditor {
                 }
                 "reset to default" => {
                     let mut edits = app.primary.map.get_edits().clone();
+                    let sign = default_signs(&app.primary.map, self.id);
                     edits.commands.push(EditCmd::ChangeIntersection {
                         i: self.id,
                         old: app.primary.map.get_i_edit(self.id),
-                        new: EditIntersection::StopSign(ControlStopSign::new(
-                            &app.primary.map,
-                            self.id,
-                        )),
+                        new: if app.primary.map.get_i(self.id).is_roundabout() {
+                            EditIntersection::Roundabout(sign)
+                        } else {
+                            EditIntersection::StopSign(sign)
+                        },
                     });
                     apply_map_edits(ctx, app, edits);
                     return Transition::Replace(StopSignEditor::new(
@@ -158,6 +180,31 @@ impl State<App> for StopSignEditor {
                         return Transition::Pop;
                     }
                 }
+                "convert to roundabout" | "convert to stop signs" => {
+                    let mut edits = app.primary.map.get_edits().clone();
+                    edits.commands.push(EditCmd::ChangeIntersection {
+                        i: self.id,
+                        old: app.primary.map.get_i_edit(self.id),
+                        new: if x == "convert to roundabout" {
+                            EditIntersection::Roundabout(ControlStopSign::roundabout(
+                                &app.primary.map,
+                                self.id,
+                            ))
+                        } else {
+                            EditIntersection::StopSign(ControlStopSign::new(
+                                &app.primary.map,
+                                self.id,
+                            ))
+                        },
+                    });
+                    apply_map_edits(ctx, app, edits);
+                    return Transition::Replace(StopSignEditor::new(
+                        ctx,
+                        app,
+                        self.id,
+                        self.mode.clone(),
+                    ));
+                }
                 "convert to traffic signal" => {
                     let mut edits = app.primary.map.get_edits().clone();
                     edits.commands.push(EditCmd::ChangeIntersection {
@@ -215,7 +262,11 @@ impl State<App> for StopSignEditor {
         if let Some(r) = self.selected_sign {
             let mut osd = Text::new();
             osd.add_appended(vec![
-                Line("Stop sign for "),
+                Line(if map.get_i(self.id).is_roundabout() {
+                    "Yield sign for "
+                } else {
+                    "Stop sign for "
+                }),
                 Line(
                     app.primary
                         .map
@@ -230,3 +281,12 @@ impl State<App> for StopSignEditor {
         }
     }
 }
+
+/// The signs this intersection would have without any edits to them
+fn default_signs(map: &Map, id: IntersectionID) -> ControlStopSign {
+    if map.get_i(id).is_roundabout() {
+        ControlStopSign::roundabout(map, id)
+    } else {
+        ControlStopSign::new(map, id)
+    }
+}
@@ -1,7 +1,10 @@
+use std::collections::BTreeSet;
+
 use abstutil::Timer;
-use geom::Duration;
+use geom::{Duration, Speed};
 use map_model::{
-    ControlStopSign, ControlTrafficSignal, EditCmd, EditIntersection, IntersectionID, PhaseType,
+    ActuatedTiming, BusRouteID, ControlStopSign, ControlTrafficSignal, EditCmd, EditIntersection,
+    IntersectionID, Map, PathStep, PedestrianTiming, PhaseType, TransitPriority,
 };
 use widgetry::{
     Btn, Checkbox, Choice, DrawBaselayer, EventCtx, GfxCtx, Key, Line, Outcome, Panel, Spinner,
@@ -25,6 +28,11 @@ impl ChangeDuration {
         signal: &ControlTrafficSignal,
         idx: usize,
     ) -> Box<dyn State<App>> {
+        let phase_type = &signal.stages[idx].phase_type;
+        let actuated = match phase_type {
+            PhaseType::Actuated(timing) => *timing,
+            _ => ActuatedTiming::new(phase_type.simple_duration()),
+        };
         Box::new(ChangeDuration {
             panel: Panel::new(Widget::col(vec![
                 Widget::row(vec![
@@ -50,21 +58,41 @@ impl ChangeDuration {
                 ]),
                 Widget::row(vec![
                     "Type:".draw_text(ctx),
-                    Checkbox::toggle(
+                    Widget::dropdown(
                         ctx,
                         "phase type",
-                        "fixed",
-                        "adaptive",
-                        None,
-                        match signal.stages[idx].phase_type {
-                            PhaseType::Fixed(_) => true,
-                            PhaseType::Adaptive(_) => false,
-                        },
+                        match phase_type {
+                            PhaseType::Fixed(_) => "fixed",
+                            PhaseType::Adaptive(_) => "adaptive",
+                            PhaseType::Actuated(_) => "actuated",
+                        }
+                        .to_string(),
+                        Choice::strings(vec!["fixed", "adaptive", "actuated"]),
                     ),
                 ]),
                 Line("Minimum time is set by the time required for crosswalk")
                     .secondary()
                     .draw(ctx),
+                Line("For actuated stages, the duration above is the minimum green time")
+                    .secondary()
+                    .draw(ctx),
+                Widget::row(vec![
+                    "Maximum green seconds:".draw_text(ctx),
+                    Spinner::new(
+                        ctx,
+                        (
+                            signal.get_min_crossing_time(idx).inner_seconds() as isize,
+                            300,
+                        ),
+                        actuated.max_green.inner_seconds() as isize,
+                    )
+                    .named("max green"),
+                ]),
+                Widget::row(vec![
+                    "Extension seconds:".draw_text(ctx),
+                    Spinner::new(ctx, (1, 30), actuated.extension.inner_seconds() as isize)
+                        .named("extension"),
+                ]),
                 Btn::text_bg2("Apply").build_def(ctx, Key::Enter),
             ]))
             .build(ctx),
@@ -80,10 +108,20 @@ impl State<App> for ChangeDuration {
                 "close" => Transition::Pop,
                 "Apply" => {
                     let dt = Duration::seconds(self.panel.spinner("duration") as f64);
-                    let new_type = if self.panel.is_checked("phase type") {
-                        PhaseType::Fixed(dt)
-                    } else {
-                        PhaseType::Adaptive(dt)
+                    let new_type = match self
+                        .panel
+                        .dropdown_value::<String, _>("phase type")
+                        .as_str()
+                    {
+                        "fixed" => PhaseType::Fixed(dt),
+                        "adaptive" => PhaseType::Adaptive(dt),
+                        "actuated" => PhaseType::Actuated(ActuatedTiming {
+                            min_green: dt,
+                            max_green: Duration::seconds(self.panel.spinner("max green") as f64)
+                                .max(dt),
+                            extension: Duration::seconds(self.panel.spinner("extension") as f64),
+                        }),
+                        _ => unreachable!(),
                     };
                     let idx = self.idx;
                     return Transition::Multi(vec![
@@ -116,6 +154,273 @@ impl State<App> for ChangeDuration {
     }
 }
 
+pub struct ChangeTransitPriority {
+    panel: Panel,
+    routes: Vec<BusRouteID>,
+    idx: usize,
+}
+
+impl ChangeTransitPriority {
+    pub fn new(
+        ctx: &mut EventCtx,
+        app: &App,
+        signal: &ControlTrafficSignal,
+        idx: usize,
+    ) -> Box<dyn State<App>> {
+        let map = &app.primary.map;
+        let tsp = signal
+            .transit_priority
+            .clone()
+            .unwrap_or_else(TransitPriority::defaults);
+        let routes = routes_through(map, signal.id);
+
+        let mut col = vec![
+            Widget::row(vec![
+                Line("Transit signal priority").small_heading().draw(ctx),
+                Btn::close(ctx),
+            ]),
+            Checkbox::switch(
+                ctx,
+                "enable transit signal priority",
+                None,
+                signal.transit_priority.is_some(),
+            ),
+            Line("Hold the green for an approaching bus or train...")
+                .secondary()
+                .draw(ctx),
+            Widget::row(vec![
+                "Maximum extension seconds:".draw_text(ctx),
+                Spinner::new(ctx, (1, 60), tsp.max_extension.inner_seconds() as isize)
+                    .named("max extension"),
+            ]),
+            Line("...or end the current stage early when one is waiting at a red light")
+                .secondary()
+                .draw(ctx),
+            Widget::row(vec![
+                "Maximum early green seconds:".draw_text(ctx),
+                Spinner::new(ctx, (0, 60), tsp.max_early_green.inner_seconds() as isize)
+                    .named("max early green"),
+            ]),
+        ];
+        if routes.is_empty() {
+            col.push(
+                Line("No transit routes cross this intersection")
+                    .secondary()
+                    .draw(ctx),
+            );
+        } else {
+            col.push(
+                Line("Which routes get priority? Leave all unchecked for every route.")
+                    .secondary()
+                    .draw(ctx),
+            );
+            for r in &routes {
+                col.push(Checkbox::checkbox(
+                    ctx,
+                    &map.get_br(*r).full_name,
+                    None,
+                    tsp.routes.contains(r),
+                ));
+            }
+        }
+        col.push(Btn::text_bg2("Apply").build_def(ctx, Key::Enter));
+
+        Box::new(ChangeTransitPriority {
+            panel: Panel::new(Widget::col(col)).build(ctx),
+            routes,
+            idx,
+        })
+    }
+}
+
+impl State<App> for ChangeTransitPriority {
+    fn event(&mut self, ctx: &mut EventCtx, app: &mut App) -> Transition {
+        match self.panel.event(ctx) {
+            Outcome::Clicked(x) => match x.as_ref() {
+                "close" => Transition::Pop,
+                "Apply" => {
+                    let tsp = if self.panel.is_checked("enable transit signal priority") {
+                        let map = &app.primary.map;
+                        Some(TransitPriority {
+                            routes: self
+                                .routes
+                                .iter()
+                                .filter(|r| self.panel.is_checked(&map.get_br(**r).full_name))
+                                .cloned()
+                                .collect(),
+                            max_extension: Duration::seconds(
+                                self.panel.spinner("max extension") as f64
+                            ),
+                            max_early_green: Duration::seconds(
+                                self.panel.spinner("max early green") as f64,
+                            ),
+                        })
+                    } else {
+                        None
+                    };
+                    let idx = self.idx;
+                    Transition::Multi(vec![
+                        Transition::Pop,
+                        Transition::ModifyState(Box::new(move |state, ctx, app| {
+                            let editor = state.downcast_mut::<TrafficSignalEditor>().unwrap();
+                            editor.add_new_edit(ctx, app, idx, |ts| {
+                                ts.transit_priority = tsp.clone();
+                            });
+                        })),
+                    ])
+                }
+                _ => unreachable!(),
+            },
+            _ => {
+                if ctx.normal_left_click() && ctx.canvas.get_cursor_in_screen_space().is_none() {
+                    return Transition::Pop;
+                }
+                Transition::Keep
+            }
+        }
+    }
+
+    fn draw(&self, g: &mut GfxCtx, _: &App) {
+        self.panel.draw(g);
+    }
+
+    fn draw_baselayer(&self) -> DrawBaselayer {
+        DrawBaselayer::PreviousState
+    }
+}
+
+pub struct ChangePedestrianTiming {
+    panel: Panel,
+}
+
+impl ChangePedestrianTiming {
+    pub fn new(ctx: &mut EventCtx, signal: &ControlTrafficSignal) -> Box<dyn State<App>> {
+        let timing = signal
+            .pedestrian_timing
+            .clone()
+            .unwrap_or_else(PedestrianTiming::defaults);
+        Box::new(ChangePedestrianTiming {
+            panel: Panel::new(Widget::col(vec![
+                Widget::row(vec![
+                    Line("Pedestrian timing").small_heading().draw(ctx),
+                    Btn::close(ctx),
+                ]),
+                Checkbox::switch(
+                    ctx,
+                    "explicit WALK and DON'T WALK intervals",
+                    None,
+                    signal.pedestrian_timing.is_some(),
+                ),
+                Line("Pedestrians can only start crossing during WALK.")
+                    .secondary()
+                    .draw(ctx),
+                Line("DON'T WALK flashes for as long as it takes to cross at the design speed.")
+                    .secondary()
+                    .draw(ctx),
+                Widget::row(vec![
+                    "Minimum WALK seconds:".draw_text(ctx),
+                    Spinner::new(ctx, (1, 60), timing.min_walk.inner_seconds() as isize)
+                        .named("min walk"),
+                ]),
+                Widget::row(vec![
+                    "Design walking speed (cm/s):".draw_text(ctx),
+                    Spinner::new(
+                        ctx,
+                        (50, 200),
+                        (timing.clearance_speed.inner_meters_per_second() * 100.0).round() as isize,
+                    )
+                    .named("clearance speed"),
+                ]),
+                Widget::row(vec![
+                    "Leading pedestrian interval seconds:".draw_text(ctx),
+                    Spinner::new(
+                        ctx,
+                        (0, 15),
+                        timing.leading_interval.inner_seconds() as isize,
+                    )
+                    .named("leading interval"),
+                ]),
+                Line("Stages too short for these intervals will be lengthened.")
+                    .secondary()
+                    .draw(ctx),
+                Btn::text_bg2("Apply").build_def(ctx, Key::Enter),
+            ]))
+            .build(ctx),
+        })
+    }
+}
+
+impl State<App> for ChangePedestrianTiming {
+    fn event(&mut self, ctx: &mut EventCtx, _: &mut App) -> Transition {
+        match self.panel.event(ctx) {
+            Outcome::Clicked(x) => match x.as_ref() {
+                "close" => Transition::Pop,
+                "Apply" => {
+                    let timing = if self
+                        .panel
+                        .is_checked("explicit WALK and DON'T WALK intervals")
+                    {
+                        Some(PedestrianTiming {
+                            min_walk: Duration::seconds(self.panel.spinner("min walk") as f64),
+                            clearance_speed: Speed::meters_per_second(
+                                (self.panel.spinner("clearance speed") as f64) / 100.0,
+                            ),
+                            leading_interval: Duration::seconds(
+                                self.panel.spinner("leading interval") as f64,
+                            ),
+                        })
+                    } else {
+                        None
+                    };
+                    Transition::Multi(vec![
+                        Transition::Pop,
+                        Transition::ModifyState(Box::new(move |state, ctx, app| {
+                            let editor = state.downcast_mut::<TrafficSignalEditor>().unwrap();
+                            editor.add_new_edit(ctx, app, 0, |ts| {
+                                ts.set_pedestrian_timing(timing.clone());
+                            });
+                        })),
+                    ])
+                }
+                _ => unreachable!(),
+            },
+            _ => {
+                if ctx.normal_left_click() && ctx.canvas.get_cursor_in_screen_space().is_none() {
+                    return Transition::Pop;
+                }
+                Transition::Keep
+            }
+        }
+    }
+
+    fn draw(&self, g: &mut GfxCtx, _: &App) {
+        self.panel.draw(g);
+    }
+
+    fn draw_baselayer(&self) -> DrawBaselayer {
+        DrawBaselayer::PreviousState
+    }
+}
+
+/// Every transit route that makes a turn through this intersection
+fn routes_through(map: &Map, i: IntersectionID) -> Vec<BusRouteID> {
+    let mut routes = BTreeSet::new();
+    for br in map.all_bus_routes() {
+        for req in br.all_steps(map) {
+            if let Some(path) = map.pathfind(req) {
+                if path.get_steps().iter().any(|step| match step {
+                    PathStep::Turn(t) => t.parent == i,
+                    _ => false,
+                }) {
+                    routes.insert(br.id);
+                    break;
+                }
+            }
+        }
+    }
+    routes.into_iter().collect()
+}
+
 pub fn edit_entire_signal(
     ctx: &mut EventCtx,
     app: &App,
@@ -132,17 +437,21 @@ pub fn edit_entire_signal(
 
     let use_template = "use template";
     let all_walk = "add an all-walk stage at the end";
+    let ped_timing = "change pedestrian WALK timing";
     let stop_sign = "convert to stop signs";
+    let roundabout = "convert to roundabout";
     let close = "close intersection for construction";
     let reset = "reset to default";
 
     let mut choices = vec![use_template];
     if has_sidewalks {
         choices.push(all_walk);
+        choices.push(ped_timing);
     }
     // TODO Conflating stop signs and construction here
     if mode.can_edit_stop_signs() {
         choices.push(stop_sign);
+        choices.push(roundabout);
         choices.push(close);
     }
     choices.push(reset);
@@ -184,14 +493,25 @@ pub fn edit_entire_signal(
                     }
                 })),
             ]),
-            x if x == stop_sign => {
+            x if x == ped_timing => Transition::Replace(ChangePedestrianTiming::new(
+                ctx,
+                app.primary.map.get_traffic_signal(i),
+            )),
+            x if x == stop_sign || x == roundabout => {
                 original.apply(app);
 
                 let mut edits = app.primary.map.get_edits().clone();
                 edits.commands.push(EditCmd::ChangeIntersection {
                     i,
                     old: app.primary.map.get_i_edit(i),
-                    new: EditIntersection::StopSign(ControlStopSign::new(&app.primary.map, i)),
+                    new: if x == roundabout {
+                        EditIntersection::Roundabout(ControlStopSign::roundabout(
+                            &app.primary.map,
+                            i,
+                        ))
+                    } else {
+                        EditIntersection::StopSign(ControlStopSign::new(&app.primary.map, i))
+                    },
                 });
                 apply_map_edits(ctx, app, edits);
                 Transition::Multi(vec![
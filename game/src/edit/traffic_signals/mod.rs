@@ -207,6 +207,14 @@ impl State<App> for TrafficSignalEditor {
                         self.original.clone(),
                     ));
                 }
+                if x == "Transit signal priority" {
+                    return Transition::Push(edits::ChangeTransitPriority::new(
+                        ctx,
+                        app,
+                        &canonical_signal,
+                        self.current_stage,
+                    ));
+                }
                 if x == "Tune offsets between signals" {
                     return Transition::Push(offsets::ShowAbsolute::new(
                         ctx,
@@ -214,6 +222,13 @@ impl State<App> for TrafficSignalEditor {
                         self.members.clone(),
                     ));
                 }
+                if x == "Coordinate as a green wave" {
+                    return Transition::Push(offsets::GreenWaveCorridor::new(
+                        ctx,
+                        app,
+                        self.members.clone(),
+                    ));
+                }
                 if x == "Add a new stage" {
                     self.add_new_edit(ctx, app, num_stages, |ts| {
                         ts.stages.push(Stage::new());
@@ -330,7 +345,7 @@ impl State<App> for TrafficSignalEditor {
                 }
                 "Export" => {
                     for signal in BundleEdits::get_current(app, &self.members).signals {
-                        let ts = signal.export(&app.primary.map);
+                        let ts = signal.export(&app.primary.map).raw;
                         abstutil::write_json(
                             format!("traffic_signal_data/{}.json", ts.intersection_osm_node_id),
                             &ts,
@@ -611,8 +626,25 @@ fn make_side_panel(
 
     if members.len() == 1 {
         col.push(Btn::text_bg2("Edit entire signal").build_def(ctx, Key::E));
+        col.push(Btn::text_bg2("Transit signal priority").build_def(ctx, Key::T));
+        if let Some(ref tsp) = canonical_signal.transit_priority {
+            col.push(
+                format!(
+                    "Transit priority for {}: up to {} extension, {} early green",
+                    match tsp.routes.len() {
+                        0 => "every route".to_string(),
+                        1 => "1 route".to_string(),
+                        n => format!("{} routes", n),
+                    },
+                    tsp.max_extension,
+                    tsp.max_early_green
+                )
+                .draw_text(ctx),
+            );
+        }
     } else {
         col.push(Btn::text_bg2("Tune offsets between signals").build_def(ctx, Key::O));
+        col.push(Btn::text_bg2("Coordinate as a green wave").build_def(ctx, Key::G));
     }
 
     let translations = squish_polygons_together(
@@ -644,6 +676,12 @@ fn make_side_panel(
                     match canonical_stage.phase_type {
                         PhaseType::Fixed(d) => format!("Stage {}: {}", idx + 1, d),
                         PhaseType::Adaptive(d) => format!("Stage {}: {} (adaptive)", idx + 1, d),
+                        PhaseType::Actuated(timing) => format!(
+                            "Stage {}: {} to {} (actuated)",
+                            idx + 1,
+                            timing.min_green,
+                            timing.max_green
+                        ),
                     }
                     .draw_text(ctx),
                     Btn::svg_def("system/assets/tools/edit.svg").build(
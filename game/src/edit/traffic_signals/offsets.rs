@@ -2,18 +2,19 @@ use std::collections::BTreeSet;
 
 use maplit::btreeset;
 
-use geom::{Distance, Duration};
-use map_model::IntersectionID;
+use geom::{Distance, Duration, Speed};
+use map_model::{IntersectionID, SignalCorridor};
 use sim::Scenario;
 use widgetry::{
-    Btn, Color, Drawable, EventCtx, GfxCtx, HorizontalAlignment, Key, Line, Outcome, Panel,
-    RewriteColor, Spinner, State, Text, TextExt, VerticalAlignment, Widget,
+    Btn, Checkbox, Color, DrawBaselayer, Drawable, EventCtx, GfxCtx, HorizontalAlignment, Key,
+    Line, Outcome, Panel, RewriteColor, Spinner, State, Text, TextExt, VerticalAlignment, Widget,
 };
 
 use crate::app::App;
 use crate::common::CommonState;
-use crate::edit::traffic_signals::fade_irrelevant;
-use crate::game::Transition;
+use crate::edit::speed_limit_choices;
+use crate::edit::traffic_signals::{fade_irrelevant, TrafficSignalEditor};
+use crate::game::{PopupMsg, Transition};
 use crate::helpers::ID;
 
 pub struct ShowAbsolute {
@@ -320,3 +321,118 @@ impl State<App> for TuneRelative {
         g.redraw(&self.labels);
     }
 }
+
+pub struct GreenWaveCorridor {
+    members: BTreeSet<IntersectionID>,
+    panel: Panel,
+}
+
+impl GreenWaveCorridor {
+    pub fn new(
+        ctx: &mut EventCtx,
+        app: &App,
+        members: BTreeSet<IntersectionID>,
+    ) -> Box<dyn State<App>> {
+        Box::new(GreenWaveCorridor {
+            panel: Panel::new(Widget::col(vec![
+                Widget::row(vec![
+                    Line(format!(
+                        "Coordinate {} signals as a green wave",
+                        members.len()
+                    ))
+                    .small_heading()
+                    .draw(ctx),
+                    Btn::close(ctx),
+                ]),
+                "The signals must all lie along one route.".draw_text(ctx),
+                Widget::row(vec![
+                    "Vehicles travelling at".draw_text(ctx),
+                    Widget::dropdown(
+                        ctx,
+                        "speed",
+                        Speed::miles_per_hour(25.0),
+                        speed_limit_choices(app),
+                    ),
+                    "shouldn't have to stop".draw_text(ctx),
+                ]),
+                Checkbox::checkbox(ctx, "change the cycle length too", None, false),
+                Btn::text_bg2("Optimize offsets").build_def(ctx, Key::Enter),
+            ]))
+            .build(ctx),
+            members,
+        })
+    }
+}
+
+impl State<App> for GreenWaveCorridor {
+    fn event(&mut self, ctx: &mut EventCtx, app: &mut App) -> Transition {
+        match self.panel.event(ctx) {
+            Outcome::Clicked(x) => match x.as_ref() {
+                "close" => Transition::Pop,
+                "Optimize offsets" => {
+                    let map = &app.primary.map;
+                    let result = SignalCorridor::order_members(map, &self.members).and_then(
+                        |intersections| {
+                            SignalCorridor {
+                                intersections,
+                                progression_speed: self.panel.dropdown_value("speed"),
+                                optimize_cycle_length: self
+                                    .panel
+                                    .is_checked("change the cycle length too"),
+                            }
+                            .optimize(map)
+                        },
+                    );
+                    match result {
+                        Ok(wave) => {
+                            let msg = vec![
+                                format!(
+                                    "One full cycle lasts {}",
+                                    wave.cycle_length.to_string(&app.opts.units)
+                                ),
+                                format!(
+                                    "Window to pass through every signal in one direction: {}",
+                                    wave.outbound_bandwidth.to_string(&app.opts.units)
+                                ),
+                                format!(
+                                    "Window in the other direction: {}",
+                                    wave.inbound_bandwidth.to_string(&app.opts.units)
+                                ),
+                            ];
+                            let signals = wave.signals;
+                            Transition::Multi(vec![
+                                Transition::Pop,
+                                Transition::ModifyState(Box::new(move |state, ctx, app| {
+                                    let editor =
+                                        state.downcast_mut::<TrafficSignalEditor>().unwrap();
+                                    editor.add_new_edit(ctx, app, 0, |ts| {
+                                        if let Some(new) = signals.iter().find(|s| s.id == ts.id) {
+                                            *ts = new.clone();
+                                        }
+                                    });
+                                })),
+                                Transition::Push(PopupMsg::new(ctx, "Green wave", msg)),
+                            ])
+                        }
+                        Err(err) => Transition::Push(PopupMsg::new(ctx, "Error", vec![err])),
+                    }
+                }
+                _ => unreachable!(),
+            },
+            _ => {
+                if ctx.normal_left_click() && ctx.canvas.get_cursor_in_screen_space().is_none() {
+                    return Transition::Pop;
+                }
+                Transition::Keep
+            }
+        }
+    }
+
+    fn draw(&self, g: &mut GfxCtx, _: &App) {
+        self.panel.draw(g);
+    }
+
+    fn draw_baselayer(&self) -> DrawBaselayer {
+        DrawBaselayer::PreviousState
+    }
+}
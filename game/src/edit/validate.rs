@@ -130,8 +130,10 @@ pub fn try_change_lt(
     let mut edits = orig_edits.clone();
     let cmd = {
         let r = map.get_l(l).parent;
+        let idx = map.get_r(r).offset(l);
         map.edit_road_cmd(r, |new| {
-            new.lanes_ltr[map.get_r(r).offset(l)].0 = new_lt;
+            new.lanes_ltr[idx].0 = new_lt;
+            new.allowed_turns.remove(&idx);
         })
     };
     edits.commands.push(cmd.clone());
@@ -3,7 +3,8 @@ use std::collections::BTreeSet;
 use enumset::EnumSet;
 use maplit::btreeset;
 
-use map_model::{AccessRestrictions, PathConstraints, RoadID};
+use geom::{Duration, Time};
+use map_model::{AccessRestrictions, ConditionalAccess, PathConstraints, RoadID, TimeWindow};
 use sim::TripMode;
 use widgetry::{
     Btn, Color, Drawable, EventCtx, GfxCtx, HorizontalAlignment, Key, Line, Outcome, Panel,
@@ -37,13 +38,30 @@ impl ZoneEditor {
             // Starting a new zone
             btreeset! { start.id }
         };
-        let allow_through_traffic = start
-            .access_restrictions
-            .allow_through_traffic
+        // A zone might only be restricted during part of the day, like a school street
+        let ar = &start.access_restrictions;
+        let (allow, cap_vehicles_per_hour, window) = match ar.conditional.get(0) {
+            Some(c) => (
+                c.allow_through_traffic,
+                c.cap_vehicles_per_hour,
+                Some(c.window),
+            ),
+            None => (ar.allow_through_traffic, ar.cap_vehicles_per_hour, None),
+        };
+        let mut allow_through_traffic: BTreeSet<TripMode> = allow
             .into_iter()
             .map(|c| TripMode::from_constraints(c))
             .collect();
-        let cap_vehicles_per_hour = start.access_restrictions.cap_vehicles_per_hour;
+        // Carpools and ride-hailing vehicles are just cars
+        if allow_through_traffic.contains(&TripMode::Drive) {
+            allow_through_traffic.insert(TripMode::Carpool);
+            allow_through_traffic.insert(TripMode::RideHail);
+            allow_through_traffic.insert(TripMode::Delivery);
+            allow_through_traffic.insert(TripMode::ParkAndRide);
+        }
+        if allow_through_traffic.contains(&TripMode::Bike) {
+            allow_through_traffic.insert(TripMode::BikeShare);
+        }
 
         let (unzoomed, zoomed, legend) = draw_zone(ctx, app, &members);
         let orig_members = members.clone();
@@ -64,6 +82,23 @@ impl ZoneEditor {
                     Spinner::new(ctx, (0, 1000), cap_vehicles_per_hour.unwrap_or(0) as isize)
                         .named("cap_vehicles"),
                 ]),
+                Widget::row(vec![
+                    "Only restrict from hour".draw_text(ctx),
+                    Spinner::new(
+                        ctx,
+                        (0, 24),
+                        window.map(|w| w.start.get_parts().0).unwrap_or(0) as isize,
+                    )
+                    .named("start_hour"),
+                    "until".draw_text(ctx),
+                    Spinner::new(
+                        ctx,
+                        (0, 24),
+                        window.map(|w| w.end.get_parts().0).unwrap_or(0) as isize,
+                    )
+                    .named("end_hour"),
+                    "(the same hour = all day)".draw_text(ctx),
+                ]),
                 Widget::custom_row(vec![
                     Btn::text_fg("Apply").build_def(ctx, Key::Enter),
                     Btn::text_fg("Cancel").build_def(ctx, Key::Escape),
@@ -106,16 +141,33 @@ impl State<App> for ZoneEditor {
                     // The original allow_through_traffic always includes this, and there's no way
                     // to exclude it, so stay consistent.
                     allow_through_traffic.insert(PathConstraints::Train);
-                    let new_access_restrictions = AccessRestrictions {
-                        allow_through_traffic,
-                        cap_vehicles_per_hour: {
-                            let n = self.panel.spinner("cap_vehicles") as usize;
-                            if n == 0 {
-                                None
-                            } else {
-                                Some(n)
-                            }
-                        },
+                    let cap_vehicles_per_hour = {
+                        let n = self.panel.spinner("cap_vehicles") as usize;
+                        if n == 0 {
+                            None
+                        } else {
+                            Some(n)
+                        }
+                    };
+                    let start_hour = self.panel.spinner("start_hour") as usize;
+                    let end_hour = self.panel.spinner("end_hour") as usize;
+                    let new_access_restrictions = if start_hour == end_hour {
+                        AccessRestrictions {
+                            allow_through_traffic,
+                            cap_vehicles_per_hour,
+                            conditional: Vec::new(),
+                        }
+                    } else {
+                        let mut ar = AccessRestrictions::new();
+                        ar.conditional.push(ConditionalAccess {
+                            window: TimeWindow {
+                                start: Time::START_OF_DAY + Duration::hours(start_hour),
+                                end: Time::START_OF_DAY + Duration::hours(end_hour),
+                            },
+                            allow_through_traffic,
+                            cap_vehicles_per_hour,
+                        });
+                        ar
                     };
                     for r in &self.selector.roads {
                         let old_access_restrictions =
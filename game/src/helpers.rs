@@ -29,6 +29,7 @@ impl ID {
             AgentID::Car(id) => ID::Car(id),
             AgentID::Pedestrian(id) => ID::Pedestrian(id),
             AgentID::BusPassenger(_, bus) => ID::Car(bus),
+            AgentID::CarPassenger(_, car) => ID::Car(car),
         }
     }
 
@@ -132,9 +133,11 @@ pub fn cmp_duration_shorter(app: &App, after: Duration, before: Duration) -> Vec
 pub fn color_for_mode(app: &App, m: TripMode) -> Color {
     match m {
         TripMode::Walk => app.cs.unzoomed_pedestrian,
-        TripMode::Bike => app.cs.unzoomed_bike,
-        TripMode::Transit => app.cs.unzoomed_bus,
-        TripMode::Drive => app.cs.unzoomed_car,
+        TripMode::Bike | TripMode::BikeShare => app.cs.unzoomed_bike,
+        TripMode::Transit | TripMode::ParkAndRide => app.cs.unzoomed_bus,
+        TripMode::Drive | TripMode::Carpool | TripMode::RideHail | TripMode::Delivery => {
+            app.cs.unzoomed_car
+        }
     }
 }
 
@@ -144,7 +147,7 @@ pub fn color_for_agent_type(app: &App, a: AgentType) -> Color {
         AgentType::Bike => app.cs.unzoomed_bike,
         AgentType::Bus | AgentType::Train => app.cs.unzoomed_bus,
         AgentType::TransitRider => app.cs.bus_trip,
-        AgentType::Car => app.cs.unzoomed_car,
+        AgentType::Car | AgentType::CarPassenger => app.cs.unzoomed_car,
     }
 }
 
@@ -156,6 +159,9 @@ pub fn color_for_trip_phase(app: &App, tpt: TripPhaseType) -> Color {
         TripPhaseType::Parking => app.cs.parking_trip,
         TripPhaseType::WaitingForBus(_, _) => app.cs.bus_layer,
         TripPhaseType::RidingBus(_, _, _) => app.cs.bus_trip,
+        TripPhaseType::WaitingForRide(_) => app.cs.parking_trip,
+        TripPhaseType::RidingInCar(_) => app.cs.unzoomed_car,
+        TripPhaseType::Delivering(_) => app.cs.parking_trip,
         TripPhaseType::Cancelled | TripPhaseType::Finished => unreachable!(),
         TripPhaseType::DelayedStart => Color::YELLOW,
     }
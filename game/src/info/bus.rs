@@ -107,11 +107,12 @@ pub fn bus_status(ctx: &mut EventCtx, app: &App, details: &mut Details, id: CarI
         Tab::BusRoute(route.id),
     );
 
+    let passengers = app.primary.sim.num_transit_passengers(id);
     rows.push(
-        Line(format!(
-            "Currently has {} passengers",
-            app.primary.sim.num_transit_passengers(id),
-        ))
+        Line(match app.primary.sim.transit_capacity(id) {
+            Some(cap) => format!("Currently has {} / {} passengers", passengers, cap),
+            None => format!("Currently has {} passengers", passengers),
+        })
         .draw(ctx),
     );
 
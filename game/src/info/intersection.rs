@@ -124,6 +124,15 @@ pub fn delay(
 
     rows.push(delay_plot(ctx, app, id, opts, fan_chart));
 
+    // Compare actuated stages against fixed timing
+    let analytics = app.primary.sim.get_analytics();
+    if let Some(dt) = analytics.avg_signal_delay(id, true) {
+        rows.push(Line(format!("Average delay during actuated stages: {}", dt)).draw(ctx));
+        if let Some(dt) = analytics.avg_signal_delay(id, false) {
+            rows.push(Line(format!("Average delay during fixed stages: {}", dt)).draw(ctx));
+        }
+    }
+
     rows
 }
 
@@ -276,17 +285,52 @@ pub fn traffic_signal(
             // TODO Say "normally" or something?
             txt.add(Line(format!("One cycle lasts {}", total)));
         }
+        if let Some(ref timing) = signal.pedestrian_timing {
+            txt.add(Line(format!(
+                "Pedestrians get at least {} of WALK",
+                timing.min_walk
+            )));
+            if timing.leading_interval > Duration::ZERO {
+                txt.add(Line(format!(
+                    "Leading pedestrian interval: {}",
+                    timing.leading_interval
+                )));
+            }
+        }
         rows.push(txt.draw(ctx));
     }
 
+    if signal.transit_priority.is_some() {
+        rows.push(transit_priority_report(ctx, app, id));
+    }
+
     for (idx, stage) in signal.stages.iter().enumerate() {
         rows.push(
             match stage.phase_type {
                 PhaseType::Fixed(d) => Line(format!("Stage {}: {}", idx + 1, d)),
                 PhaseType::Adaptive(d) => Line(format!("Stage {}: {} (adaptive)", idx + 1, d)),
+                PhaseType::Actuated(timing) => Line(format!(
+                    "Stage {}: {} to {} (actuated)",
+                    idx + 1,
+                    timing.min_green,
+                    timing.max_green
+                )),
             }
             .draw(ctx),
         );
+        if let Some(clearance) = stage
+            .protected_movements
+            .iter()
+            .filter(|m| m.crosswalk)
+            .filter_map(|m| signal.clearance_interval(m))
+            .max()
+        {
+            rows.push(
+                Line(format!("DON'T WALK flashes for the last {}", clearance))
+                    .secondary()
+                    .draw(ctx),
+            );
+        }
 
         {
             let mut orig_batch = GeomBatch::new();
@@ -316,6 +360,58 @@ pub fn traffic_signal(
     rows
 }
 
+fn transit_priority_report(ctx: &EventCtx, app: &App, id: IntersectionID) -> Widget {
+    let stats = app.primary.sim.get_analytics().transit_priority_stats(id);
+    let mut txt = Text::new();
+    txt.add(Line("Transit signal priority").small_heading());
+    txt.add(Line(format!(
+        "Held the green {} times ({} total)",
+        prettyprint_usize(stats.green_extensions),
+        stats.time_extended
+    )));
+    txt.add(Line(format!(
+        "Ended a stage early {} times ({} total)",
+        prettyprint_usize(stats.early_greens),
+        stats.time_cut_short
+    )));
+    txt.add(Line(format!(
+        "Saved transit roughly {} of delay",
+        stats.transit_delay_saved
+    )));
+
+    let baseline = if app.has_prebaked().is_some() {
+        Some(app.prebaked().transit_priority_stats(id))
+    } else {
+        None
+    };
+    let mut compare =
+        |label: &str, now: Option<Duration>, before: Option<Duration>| match (now, before) {
+            (Some(now), Some(before)) => {
+                txt.add(Line(format!(
+                    "Average {} delay: {} ({} before)",
+                    label, now, before
+                )));
+            }
+            (Some(now), None) => {
+                txt.add(Line(format!("Average {} delay: {}", label, now)));
+            }
+            (None, _) => {
+                txt.add(Line(format!("No {} crossings yet", label)).secondary());
+            }
+        };
+    compare(
+        "transit",
+        stats.avg_transit_delay(),
+        baseline.as_ref().and_then(|b| b.avg_transit_delay()),
+    );
+    compare(
+        "cross street",
+        stats.avg_cross_street_delay(),
+        baseline.as_ref().and_then(|b| b.avg_cross_street_delay()),
+    );
+    txt.draw(ctx)
+}
+
 fn delay_plot(
     ctx: &EventCtx,
     app: &App,
@@ -338,7 +434,7 @@ fn delay_plot(
         app.primary.sim.time()
     };
     if let Some(list) = data.intersection_delays.get(&i) {
-        for (_, t, dt, agent_type) in list {
+        for (_, t, dt, agent_type, _) in list {
             if *t > limit {
                 break;
             }
@@ -388,6 +484,7 @@ fn header(
         IntersectionType::TrafficSignal => format!("{} (Traffic signals)", id),
         IntersectionType::Border => format!("Border #{}", id.0),
         IntersectionType::Construction => format!("{} (under construction)", id),
+        IntersectionType::Roundabout => format!("{} (Roundabout)", id),
     };
     rows.push(Widget::row(vec![
         Line(label).small_heading().draw(ctx),
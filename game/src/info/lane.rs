@@ -18,17 +18,28 @@ pub fn info(ctx: &EventCtx, app: &App, details: &mut Details, id: LaneID) -> Vec
     if !l.is_walkable() {
         kv.push(("Type", l.lane_type.describe().to_string()));
     }
+    for (window, lt) in &l.conditional_types {
+        kv.push(("Time of day", format!("{}: {}", window, lt.describe())));
+    }
     if r.is_private() {
+        let now = app.primary.sim.time();
+        for c in &r.access_restrictions.conditional {
+            kv.push(("Restricted", c.window.to_string()));
+        }
         let mut ban = Vec::new();
         for p in PathConstraints::all() {
-            if !r.access_restrictions.allow_through_traffic.contains(p) {
+            if !r
+                .access_restrictions
+                .allow_through_traffic_at(now)
+                .contains(p)
+            {
                 ban.push(format!("{:?}", p).to_ascii_lowercase());
             }
         }
         if !ban.is_empty() {
             kv.push(("No through-traffic for", ban.join(", ")));
         }
-        if let Some(cap) = r.access_restrictions.cap_vehicles_per_hour {
+        if let Some(cap) = r.access_restrictions.cap_vehicles_per_hour_at(now) {
             kv.push((
                 "Cap for vehicles this hour",
                 format!(
@@ -116,16 +127,22 @@ pub fn debug(ctx: &EventCtx, app: &App, details: &mut Details, id: LaneID) -> Ve
         ));
     }
 
-    if let Some(types) = l.get_turn_restrictions(r) {
+    if let Some(types) = l.get_turn_restrictions() {
         kv.push((
             "Turn restrictions".to_string(),
-            format!("{:?}", types.into_iter().collect::<Vec<_>>()),
+            format!("{:?}", types.iter().collect::<Vec<_>>()),
         ));
     }
-    for (restriction, to) in &r.turn_restrictions {
+    for (restriction, to, scope) in &r.turn_restrictions {
         kv.push((
             format!("Restriction from this road to {}", to),
-            format!("{:?}", restriction),
+            format!("{:?}{}", restriction, scope),
+        ));
+    }
+    for (restriction, via, to, scope) in &r.complicated_turn_restrictions {
+        kv.push((
+            format!("Restriction from this road via {:?} to {}", via, to),
+            format!("{:?}{}", restriction, scope),
         ));
     }
 
@@ -140,7 +157,7 @@ pub fn debug(ctx: &EventCtx, app: &App, details: &mut Details, id: LaneID) -> Ve
     ));
     kv.push((
         "Incline / grade".to_string(),
-        format!("{:.1}%", r.percent_grade(map) * 100.0),
+        format!("{:.1}%", l.percent_grade * 100.0),
     ));
     kv.push((
         "Elevation details".to_string(),
@@ -133,9 +133,12 @@ pub fn trips(
                     ctx.prerender,
                     match trip.mode {
                         TripMode::Walk => "system/assets/meters/pedestrian.svg",
-                        TripMode::Bike => "system/assets/meters/bike.svg",
-                        TripMode::Drive => "system/assets/meters/car.svg",
-                        TripMode::Transit => "system/assets/meters/bus.svg",
+                        TripMode::Bike | TripMode::BikeShare => "system/assets/meters/bike.svg",
+                        TripMode::Drive
+                        | TripMode::Carpool
+                        | TripMode::RideHail
+                        | TripMode::Delivery => "system/assets/meters/car.svg",
+                        TripMode::Transit | TripMode::ParkAndRide => "system/assets/meters/bus.svg",
                     },
                 )
                 // we want the icon to be about the same height as the text
@@ -504,12 +507,16 @@ pub fn parked_car(
 
     // TODO prev trips, next trips, etc
 
-    let p = app.primary.sim.get_owner_of_car(id).unwrap();
-    rows.push(Btn::text_bg2(format!("Owned by {}", p)).build_def(ctx, None));
-    details.hyperlinks.insert(
-        format!("Owned by {}", p),
-        Tab::PersonTrips(p, BTreeMap::new()),
-    );
+    if let Some(p) = app.primary.sim.get_owner_of_car(id) {
+        rows.push(Btn::text_bg2(format!("Owned by {}", p)).build_def(ctx, None));
+        details.hyperlinks.insert(
+            format!("Owned by {}", p),
+            Tab::PersonTrips(p, BTreeMap::new()),
+        );
+    } else {
+        // Ride-hailing vehicles don't belong to anybody
+        rows.push("Part of the ride-hailing fleet".draw_text(ctx));
+    }
 
     if let Some(p) = app.primary.sim.lookup_parked_car(id) {
         match p.spot {
@@ -572,13 +579,18 @@ fn header(
                         ("walking", Some("system/assets/meters/pedestrian.svg"))
                     }
                     AgentID::Car(c) => match c.1 {
-                        VehicleType::Car => ("driving", Some("system/assets/meters/car.svg")),
+                        VehicleType::Car | VehicleType::Truck => {
+                            ("driving", Some("system/assets/meters/car.svg"))
+                        }
                         VehicleType::Bike => ("biking", Some("system/assets/meters/bike.svg")),
                         VehicleType::Bus | VehicleType::Train => unreachable!(),
                     },
                     AgentID::BusPassenger(_, _) => {
                         ("riding a bus", Some("system/assets/meters/bus.svg"))
                     }
+                    AgentID::CarPassenger(_, _) => {
+                        ("riding in a car", Some("system/assets/meters/car.svg"))
+                    }
                 }
             } else {
                 // TODO Really should clean up the TripModeChange issue
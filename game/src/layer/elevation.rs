@@ -1,7 +1,7 @@
 use geom::{ArrowCap, Distance, PolyLine};
 use widgetry::{
-    Btn, Color, Drawable, EventCtx, GeomBatch, GfxCtx, HorizontalAlignment, Line, Panel, Text,
-    TextExt, VerticalAlignment, Widget,
+    Btn, Color, Drawable, EventCtx, GeomBatch, GfxCtx, HorizontalAlignment, Line, Panel, TextExt,
+    VerticalAlignment, Widget,
 };
 
 use crate::app::App;
@@ -43,10 +43,8 @@ impl Elevation {
             let pct = r.percent_grade(&app.primary.map).abs();
             max = max.max(pct);
 
-            let color = app.cs.good_to_bad_red.eval(
-                // TODO Rescale based on a reasonable steepest grade, once the data doesn't suck
-                pct.max(0.0).min(1.0),
-            );
+            // Anything steeper than this is hard to bike up
+            let color = app.cs.good_to_bad_red.eval((pct / 0.15).min(1.0));
             colorer.add_r(r.id, color);
         }
 
@@ -93,12 +91,8 @@ impl Elevation {
                 "Elevation change".draw_text(ctx),
                 Btn::close(ctx),
             ]),
-            Text::from_multiline(vec![
-                Line(format!("Steepest road: {:.0}% grade", max * 100.0)),
-                Line("Note: elevation data is currently wrong!").secondary(),
-            ])
-            .draw(ctx),
-            ColorLegend::gradient(ctx, &app.cs.good_to_bad_red, vec!["flat", "steep"]),
+            Line(format!("Steepest road: {:.0}% grade", max * 100.0)).draw(ctx),
+            ColorLegend::gradient(ctx, &app.cs.good_to_bad_red, vec!["flat", "15%+"]),
         ]))
         .aligned(HorizontalAlignment::Right, VerticalAlignment::Center)
         .build(ctx);
@@ -92,6 +92,16 @@ impl DrawIntersection {
                     }
                 }
             }
+            IntersectionType::Roundabout => {
+                for ss in map.get_stop_sign(i.id).roads.values() {
+                    if ss.must_stop {
+                        if let Some((triangle, pole)) = DrawIntersection::yield_sign_geom(ss, map) {
+                            default_geom.push(app.cs.stop_sign, triangle);
+                            default_geom.push(app.cs.stop_sign_pole, pole);
+                        }
+                    }
+                }
+            }
             IntersectionType::Construction => {
                 // TODO Centering seems weird
                 default_geom.append(
@@ -113,6 +123,18 @@ impl DrawIntersection {
 
     // Returns the (octagon, pole) if there's room to draw it.
     pub fn stop_sign_geom(ss: &RoadWithStopSign, map: &Map) -> Option<(Polygon, Polygon)> {
+        DrawIntersection::sign_geom(ss, map, make_octagon)
+    }
+
+    pub fn yield_sign_geom(ss: &RoadWithStopSign, map: &Map) -> Option<(Polygon, Polygon)> {
+        DrawIntersection::sign_geom(ss, map, make_yield_triangle)
+    }
+
+    fn sign_geom(
+        ss: &RoadWithStopSign,
+        map: &Map,
+        make_sign: fn(Pt2D, Distance, Angle) -> Polygon,
+    ) -> Option<(Polygon, Polygon)> {
         let trim_back = Distance::meters(0.1);
         let edge_lane = map.get_l(ss.lane_closest_to_edge);
         // TODO The dream of trimming f64's was to isolate epsilon checks like this...
@@ -130,7 +152,7 @@ impl DrawIntersection {
             last_line.shift_left(edge_lane.width)
         };
 
-        let octagon = make_octagon(last_line.pt2(), Distance::meters(1.0), last_line.angle());
+        let sign = make_sign(last_line.pt2(), Distance::meters(1.0), last_line.angle());
         let pole = Line::must_new(
             last_line
                 .pt2()
@@ -141,7 +163,7 @@ impl DrawIntersection {
                 .project_away(Distance::meters(0.9), last_line.angle().opposite()),
         )
         .make_polygons(Distance::meters(0.3));
-        Some((octagon, pole))
+        Some((sign, pole))
     }
 }
 
@@ -359,6 +381,16 @@ fn make_octagon(center: Pt2D, radius: Distance, facing: Angle) -> Polygon {
     .to_polygon()
 }
 
+// Points back towards the traffic that has to yield
+fn make_yield_triangle(center: Pt2D, radius: Distance, facing: Angle) -> Polygon {
+    Ring::must_new(
+        (0..=3)
+            .map(|i| center.project_away(radius, facing.rotate_degs(f64::from(i * 360 / 3))))
+            .collect(),
+    )
+    .to_polygon()
+}
+
 pub fn make_crosswalk(batch: &mut GeomBatch, turn: &Turn, map: &Map, cs: &ColorScheme) {
     if make_rainbow_crosswalk(batch, turn, map) {
         return;
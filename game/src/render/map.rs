@@ -232,7 +232,7 @@ impl DrawMap {
             unzoomed_pieces.push((
                 i.get_zorder(map),
                 i.polygon.clone(),
-                if i.is_stop_sign() {
+                if i.is_stop_sign() || i.is_roundabout() {
                     if i.is_light_rail(map) {
                         cs.light_rail_track
                     } else if i.is_private(map) {
@@ -506,7 +506,7 @@ impl UnzoomedAgents {
 
     pub fn color(&self, agent: &UnzoomedAgent) -> Option<Color> {
         match agent.id.to_vehicle_type() {
-            Some(VehicleType::Car) => {
+            Some(VehicleType::Car) | Some(VehicleType::Truck) => {
                 if self.cars {
                     Some(self.car_color)
                 } else {
@@ -14,6 +14,7 @@ mod parking_overhead;
 mod summaries;
 mod table;
 mod traffic_signals;
+mod transit_performance;
 mod trip_table;
 
 // Oh the dashboards melted, but we still had the radio
@@ -26,6 +27,7 @@ pub enum DashTab {
     ParkingOverhead,
     ActiveTraffic,
     TransitRoutes,
+    TransitPerformance,
     CommuterPatterns,
     TrafficSignals,
 }
@@ -38,6 +40,7 @@ impl DashTab {
             Choice::new("Parking Overhead", DashTab::ParkingOverhead),
             Choice::new("Active Traffic", DashTab::ActiveTraffic),
             Choice::new("Transit Routes", DashTab::TransitRoutes),
+            Choice::new("Transit Schedules", DashTab::TransitPerformance),
             Choice::new("Commuter Patterns", DashTab::CommuterPatterns),
             Choice::new("Traffic Signal Demand", DashTab::TrafficSignals),
         ];
@@ -80,6 +83,7 @@ impl DashTab {
             DashTab::ParkingOverhead => parking_overhead::ParkingOverhead::new(ctx, app),
             DashTab::ActiveTraffic => misc::ActiveTraffic::new(ctx, app),
             DashTab::TransitRoutes => misc::TransitRoutes::new(ctx, app),
+            DashTab::TransitPerformance => transit_performance::TransitPerformance::new(ctx, app),
             DashTab::CommuterPatterns => CommuterPatterns::new(ctx, app),
             DashTab::TrafficSignals => TrafficSignalDemand::new(ctx, app),
             DashTab::CancelledTripTable | DashTab::UnfinishedTripTable => unreachable!(),
@@ -0,0 +1,244 @@
+use abstutil::prettyprint_usize;
+use geom::Duration;
+use map_model::BusRouteID;
+use widgetry::{
+    Checkbox, DrawBaselayer, EventCtx, GfxCtx, Line, Outcome, Panel, State, Text, Widget,
+};
+
+use crate::app::App;
+use crate::common::Tab;
+use crate::game::Transition;
+use crate::sandbox::dashboards::table::{Col, Filter, Table};
+use crate::sandbox::dashboards::DashTab;
+use crate::sandbox::SandboxMode;
+
+pub struct TransitPerformance {
+    table: Table<Entry, Filters>,
+    panel: Panel,
+}
+
+struct Entry {
+    route: BusRouteID,
+    name: String,
+    arrivals: usize,
+    /// From 0 to 100
+    on_time_pct: Option<usize>,
+    avg_delay: Option<Duration>,
+    max_delay: Duration,
+    bunched: usize,
+    /// The worst headway variation of any stop along the route, times 100
+    headway_variation: Option<usize>,
+}
+
+struct Filters {
+    unserved_routes: bool,
+}
+
+impl TransitPerformance {
+    pub fn new(ctx: &mut EventCtx, app: &App) -> Box<dyn State<App>> {
+        let table = make_table(app);
+        let panel = make_panel(ctx, app, &table);
+        Box::new(TransitPerformance { table, panel })
+    }
+
+    fn recalc(&mut self, ctx: &mut EventCtx, app: &App) {
+        let mut new = make_panel(ctx, app, &self.table);
+        new.restore(ctx, &self.panel);
+        self.panel = new;
+    }
+}
+
+impl State<App> for TransitPerformance {
+    fn event(&mut self, ctx: &mut EventCtx, app: &mut App) -> Transition {
+        match self.panel.event(ctx) {
+            Outcome::Clicked(x) => {
+                if self.table.clicked(&x) {
+                    self.recalc(ctx, app);
+                } else if let Ok(idx) = x.parse::<usize>() {
+                    let route = BusRouteID(idx);
+                    return Transition::Multi(vec![
+                        Transition::Pop,
+                        Transition::ModifyState(Box::new(move |state, ctx, app| {
+                            let sandbox = state.downcast_mut::<SandboxMode>().unwrap();
+                            let mut actions = sandbox.contextual_actions();
+                            sandbox.controls.common.as_mut().unwrap().launch_info_panel(
+                                ctx,
+                                app,
+                                Tab::BusRoute(route),
+                                &mut actions,
+                            );
+                        })),
+                    ]);
+                } else if x == "close" {
+                    return Transition::Pop;
+                } else {
+                    unreachable!()
+                }
+            }
+            Outcome::Changed => {
+                if let Some(t) = DashTab::TransitPerformance.transition(ctx, app, &self.panel) {
+                    return t;
+                }
+
+                self.table.panel_changed(&self.panel);
+                self.recalc(ctx, app);
+            }
+            _ => {}
+        }
+
+        Transition::Keep
+    }
+
+    fn draw_baselayer(&self) -> DrawBaselayer {
+        DrawBaselayer::Custom
+    }
+
+    fn draw(&self, g: &mut GfxCtx, app: &App) {
+        g.clear(app.cs.dialog_bg);
+        self.panel.draw(g);
+    }
+}
+
+fn produce_raw_data(app: &App) -> Vec<Entry> {
+    let map = &app.primary.map;
+    let analytics = app.primary.sim.get_analytics();
+    let mut data = Vec::new();
+    for route in map.all_bus_routes() {
+        let perf = analytics.transit_route_performance(route.id, map);
+        let arrivals = perf.arrivals.len();
+        let total_delay = perf
+            .arrivals
+            .iter()
+            .fold(Duration::ZERO, |sum, a| sum + a.delay());
+        data.push(Entry {
+            route: route.id,
+            name: route.full_name.clone(),
+            arrivals,
+            on_time_pct: perf.on_time_pct().map(|pct| (pct * 100.0).round() as usize),
+            avg_delay: if arrivals == 0 {
+                None
+            } else {
+                Some(total_delay / (arrivals as f64))
+            },
+            max_delay: perf
+                .stops
+                .iter()
+                .map(|s| s.max_delay)
+                .max()
+                .unwrap_or(Duration::ZERO),
+            bunched: perf.bunched(),
+            headway_variation: perf
+                .stops
+                .iter()
+                .filter_map(|s| s.headway_variation())
+                .map(|cv| (cv * 100.0).round() as usize)
+                .max(),
+        });
+    }
+    data
+}
+
+fn make_table(app: &App) -> Table<Entry, Filters> {
+    let filter: Filter<Entry, Filters> = Filter {
+        state: Filters {
+            unserved_routes: false,
+        },
+        to_controls: Box::new(move |ctx, _, state| {
+            Checkbox::switch(
+                ctx,
+                "routes without arrivals yet",
+                None,
+                state.unserved_routes,
+            )
+        }),
+        from_controls: Box::new(|panel| Filters {
+            unserved_routes: panel.is_checked("routes without arrivals yet"),
+        }),
+        apply: Box::new(|state, x| state.unserved_routes || x.arrivals > 0),
+    };
+
+    let mut table = Table::new(
+        produce_raw_data(app),
+        Box::new(|x| x.route.0.to_string()),
+        "Arrivals",
+        filter,
+    );
+    table.static_col("Route", Box::new(|x| x.name.clone()));
+    table.column(
+        "Arrivals",
+        Box::new(|ctx, _, x| Text::from(Line(prettyprint_usize(x.arrivals))).render(ctx)),
+        Col::Sortable(Box::new(|rows| rows.sort_by_key(|x| x.arrivals))),
+    );
+    table.column(
+        "On-time",
+        Box::new(|ctx, _, x| {
+            Text::from(Line(match x.on_time_pct {
+                Some(pct) => format!("{}%", pct),
+                None => "---".to_string(),
+            }))
+            .render(ctx)
+        }),
+        Col::Sortable(Box::new(|rows| rows.sort_by_key(|x| x.on_time_pct))),
+    );
+    table.column(
+        "Average delay",
+        Box::new(|ctx, app, x| {
+            Text::from(Line(match x.avg_delay {
+                Some(dt) => dt.to_string(&app.opts.units),
+                None => "---".to_string(),
+            }))
+            .render(ctx)
+        }),
+        Col::Sortable(Box::new(|rows| rows.sort_by_key(|x| x.avg_delay))),
+    );
+    table.column(
+        "Worst delay",
+        Box::new(|ctx, app, x| {
+            Text::from(Line(x.max_delay.to_string(&app.opts.units))).render(ctx)
+        }),
+        Col::Sortable(Box::new(|rows| rows.sort_by_key(|x| x.max_delay))),
+    );
+    table.column(
+        "Bunched arrivals",
+        Box::new(|ctx, _, x| Text::from(Line(prettyprint_usize(x.bunched))).render(ctx)),
+        Col::Sortable(Box::new(|rows| rows.sort_by_key(|x| x.bunched))),
+    );
+    table.column(
+        "Headway variation",
+        Box::new(|ctx, _, x| {
+            Text::from(Line(match x.headway_variation {
+                Some(cv) => format!("{}%", cv),
+                None => "---".to_string(),
+            }))
+            .render(ctx)
+        }),
+        Col::Sortable(Box::new(|rows| rows.sort_by_key(|x| x.headway_variation))),
+    );
+
+    table
+}
+
+fn make_panel(ctx: &mut EventCtx, app: &App, table: &Table<Entry, Filters>) -> Panel {
+    Panel::new(Widget::col(vec![
+        DashTab::TransitPerformance.picker(ctx, app),
+        Text::from_multiline(vec![
+            Line(
+                "The schedule assumes vehicles leave at their route's start times and travel at \
+                 the speed limit.",
+            ),
+            Line("Arrivals up to 1 minute early or 5 minutes late are on-time."),
+            Line(
+                "A vehicle is bunched if it arrives at a stop less than a quarter of the \
+                 scheduled gap after the previous one.",
+            ),
+            Line(
+                "Headway variation is how unevenly spaced arrivals were at the worst stop along \
+                 the route. 0% means perfectly regular.",
+            ),
+        ])
+        .draw(ctx),
+        table.render(ctx, app),
+    ]))
+    .exact_size_percent(90, 90)
+    .build(ctx)
+}
@@ -517,7 +517,8 @@ impl ContextualActions for Actions {
                     if app.primary.map.get_i(i).is_traffic_signal() {
                         actions.push((Key::E, "edit traffic signal".to_string()));
                     }
-                    if app.primary.map.get_i(i).is_stop_sign()
+                    if (app.primary.map.get_i(i).is_stop_sign()
+                        || app.primary.map.get_i(i).is_roundabout())
                         && self.gameplay.can_edit_stop_signs()
                     {
                         actions.push((Key::E, "edit stop sign".to_string()));
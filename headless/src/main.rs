@@ -15,7 +15,7 @@ extern crate log;
 use std::collections::{BTreeMap, BTreeSet, HashMap};
 use std::convert::TryFrom;
 use std::error::Error;
-use std::sync::RwLock;
+use std::sync::{Arc, Mutex, RwLock, Weak};
 
 use hyper::{Body, Request, Response, Server, StatusCode};
 use rand::SeedableRng;
@@ -25,26 +25,31 @@ use serde::{Deserialize, Serialize};
 use abstutil::{serialize_btreemap, CmdArgs, MapName, Timer};
 use geom::{Distance, Duration, LonLat, Time};
 use map_model::{
-    CompressedMovementID, ControlTrafficSignal, EditCmd, EditIntersection, IntersectionID, Map,
-    MovementID, PermanentMapEdits, RoadID, TurnID,
+    BusRouteID, CompressedMovementID, ControlTrafficSignal, EditCmd, EditIntersection,
+    IntersectionID, Map, MovementID, PermanentMapEdits, RoadID, SignalCorridor, TurnID,
 };
 use sim::{
-    AgentID, AgentType, ExternalPerson, PersonID, Scenario, ScenarioModifier, Sim, SimFlags,
-    SimOptions, TripID, TripMode, VehicleType,
+    AgentID, AgentType, EventLogOptions, ExternalPerson, PersonID, Scenario, ScenarioModifier, Sim,
+    SimFlags, SimOptions, TripID, TripMode, VehicleType,
 };
 
+mod stream;
+
 lazy_static::lazy_static! {
-    static ref MAP: RwLock<Map> = RwLock::new(Map::blank());
-    static ref SIM: RwLock<Sim> = RwLock::new(Sim::new(&Map::blank(), SimOptions::new("tmp"), &mut Timer::throwaway()));
-    static ref LOAD: RwLock<LoadSim> = RwLock::new({
-        LoadSim {
+    static ref SESSIONS: RwLock<Sessions> = RwLock::new(Sessions {
+        sessions: BTreeMap::new(),
+        next_id: 0,
+        default_load: LoadSim {
             scenario: abstutil::path_scenario(&MapName::seattle("montlake"), "weekday"),
             modifiers: Vec::new(),
             edits: None,
             rng_seed: SimFlags::RNG_SEED,
             opts: SimOptions::default(),
-        }
+        },
     });
+    // Sessions with the same map and edits share one copy of the map, until one of them modifies
+    // it.
+    static ref MAPS: Mutex<Vec<(MapKey, Weak<Map>)>> = Mutex::new(Vec::new());
 }
 
 #[tokio::main]
@@ -56,16 +61,23 @@ async fn main() {
         .unwrap_or(SimFlags::RNG_SEED);
     let opts = SimOptions::from_args(&mut args, rng_seed);
     let port = args.required("--port").parse::<u16>().unwrap();
+    let stream_port = args.optional_parse("--stream_port", |s| s.parse::<u16>());
     args.done();
 
-    {
-        let mut load = LOAD.write().unwrap();
-        load.rng_seed = rng_seed;
-        load.opts = opts;
-
-        let (map, sim) = load.setup(&mut timer);
-        *MAP.write().unwrap() = map;
-        *SIM.write().unwrap() = sim;
+    let load = {
+        let mut sessions = SESSIONS.write().unwrap();
+        sessions.default_load.rng_seed = rng_seed;
+        sessions.default_load.opts = opts;
+        sessions.default_load.clone()
+    };
+    // Clients that don't specify a session use this one.
+    create_session(load, &mut timer);
+
+    if let Some(stream_port) = stream_port {
+        tokio::spawn(stream::serve(std::net::SocketAddr::from((
+            [127, 0, 0, 1],
+            stream_port,
+        ))));
     }
 
     let addr = std::net::SocketAddr::from(([127, 0, 0, 1], port));
@@ -89,41 +101,131 @@ async fn serve_req(req: Request<Body>) -> Result<Response<Body>, hyper::Error> {
             .collect();
     let body = hyper::body::to_bytes(req).await?.to_vec();
     info!("Handling {}", path);
-    Ok(
-        match handle_command(
-            &path,
-            &params,
-            &body,
-            &mut SIM.write().unwrap(),
-            &mut MAP.write().unwrap(),
-            &mut LOAD.write().unwrap(),
-        ) {
-            Ok(resp) => Response::new(Body::from(resp)),
-            Err(err) => {
-                error!("{}: {}", path, err);
-                Response::builder()
-                    .status(StatusCode::BAD_REQUEST)
-                    .body(Body::from(format!("Bad command {}: {}", path, err)))
-                    .unwrap()
+    Ok(match handle_request(&path, &params, &body) {
+        Ok(resp) => Response::new(Body::from(resp)),
+        Err(err) => {
+            error!("{}: {}", path, err);
+            Response::builder()
+                .status(StatusCode::BAD_REQUEST)
+                .body(Body::from(format!("Bad command {}: {}", path, err)))
+                .unwrap()
+        }
+    })
+}
+
+fn handle_request(
+    path: &str,
+    params: &HashMap<String, String>,
+    body: &Vec<u8>,
+) -> Result<String, Box<dyn Error>> {
+    match path {
+        "/sim/create" => {
+            let load = if body.is_empty() {
+                SESSIONS.read().unwrap().default_load.clone()
+            } else {
+                let args: LoadSim = abstutil::from_json(body)?;
+                let sessions = SESSIONS.read().unwrap();
+                LoadSim {
+                    rng_seed: sessions.default_load.rng_seed,
+                    opts: sessions.default_load.opts.clone(),
+                    ..args
+                }
+            };
+            Ok(create_session(load, &mut Timer::new("create session")).to_string())
+        }
+        "/sim/fork" => {
+            let (mut load, map, mut sim) = {
+                let parent = get_session(session_id(params)?)?;
+                let parent = parent.lock().unwrap();
+                let sim = if params.contains_key("path") || params.contains_key("t") {
+                    let path = savestate_path(&parent.sim, params)?;
+                    restore_savestate(path, &parent.map, &parent.load)?
+                } else {
+                    parent.sim.clone()
+                };
+                (parent.load.clone(), parent.map.clone(), sim)
+            };
+            let mut sessions = SESSIONS.write().unwrap();
+            let id = sessions.reserve_id();
+            // Don't append to the parent's event log
+            load.opts.event_log = session_event_log(&sessions.default_load, id);
+            sim.set_event_log(load.opts.event_log.clone());
+            sessions
+                .sessions
+                .insert(id, Arc::new(Mutex::new(Session { load, map, sim })));
+            Ok(id.to_string())
+        }
+        "/sim/delete" => {
+            let id = session_id(params)?;
+            if id == 0 {
+                return Err("the default session can't be deleted".into());
             }
-        },
-    )
+            if SESSIONS.write().unwrap().sessions.remove(&id).is_some() {
+                Ok(format!("session {} deleted", id))
+            } else {
+                Err(format!("no session {}", id).into())
+            }
+        }
+        _ => {
+            let session = get_session(session_id(params)?)?;
+            let mut session = session.lock().unwrap();
+            handle_command(path, params, body, &mut session)
+        }
+    }
+}
+
+fn get_session(id: usize) -> Result<Arc<Mutex<Session>>, Box<dyn Error>> {
+    SESSIONS
+        .read()
+        .unwrap()
+        .sessions
+        .get(&id)
+        .cloned()
+        .ok_or_else(|| format!("no session {}", id).into())
+}
+
+/// Find a savestate by its path, or the latest one at or before some time.
+fn savestate_path(sim: &Sim, params: &HashMap<String, String>) -> Result<String, Box<dyn Error>> {
+    if let Some(path) = params.get("path") {
+        return Ok(path.clone());
+    }
+    let t = Time::parse(params.get("t").ok_or("specify a savestate by path or t")?)?;
+    sim.find_savestate_at_or_before(t)
+        .ok_or_else(|| format!("no savestate at or before {}", t).into())
+}
+
+fn restore_savestate(path: String, map: &Map, load: &LoadSim) -> Result<Sim, Box<dyn Error>> {
+    let mut sim = Sim::load_savestate(path, map, &mut Timer::new("restore savestate"))?;
+    sim.restore_unsaved_options(&load.opts);
+    if !load.opts.skip_analytics {
+        sim.reset_analytics(map);
+    }
+    Ok(sim)
+}
+
+/// Requests that don't specify a session use the one created on startup.
+fn session_id(params: &HashMap<String, String>) -> Result<usize, Box<dyn Error>> {
+    match params.get("session") {
+        Some(id) => Ok(id.parse::<usize>()?),
+        None => Ok(0),
+    }
 }
 
 fn handle_command(
     path: &str,
     params: &HashMap<String, String>,
     body: &Vec<u8>,
-    sim: &mut Sim,
-    map: &mut Map,
-    load: &mut LoadSim,
+    session: &mut Session,
 ) -> Result<String, Box<dyn Error>> {
+    let sim = &mut session.sim;
+    let map: &Map = &session.map;
+    let load = &mut session.load;
     match path {
         // Controlling the simulation
         "/sim/reset" => {
             let (new_map, new_sim) = load.setup(&mut Timer::new("reset sim"));
-            *map = new_map;
-            *sim = new_sim;
+            session.map = new_map;
+            session.sim = new_sim;
             Ok(format!("sim reloaded"))
         }
         "/sim/load" => {
@@ -135,11 +237,20 @@ fn handle_command(
 
             // Also reset
             let (new_map, new_sim) = load.setup(&mut Timer::new("reset sim"));
-            *map = new_map;
-            *sim = new_sim;
+            session.map = new_map;
+            session.sim = new_sim;
 
             Ok(format!("flags changed and sim reloaded"))
         }
+        "/sim/save" => Ok(sim.save()),
+        "/sim/restore" => {
+            let path = savestate_path(sim, params)?;
+            let mut restored = restore_savestate(path.clone(), map, load)?;
+            // Keep appending to the same event log
+            restored.take_event_log(sim);
+            *sim = restored;
+            Ok(format!("restored {}, it's now {}", path, sim.time()))
+        }
         "/sim/get-time" => Ok(sim.time().to_string()),
         "/sim/goto-time" => {
             let t = Time::parse(&params["t"])?;
@@ -194,11 +305,21 @@ fn handle_command(
                 old: map.get_i_edit(id),
                 new: EditIntersection::TrafficSignal(ts.export(map)),
             });
+            let map = session.map_mut();
             map.must_apply_edits(edits, &mut Timer::throwaway());
             map.recalculate_pathfinding_after_edits(&mut Timer::throwaway());
 
             Ok(format!("{} has been updated", id))
         }
+        "/traffic-signals/optimize-corridor" => {
+            let corridor: SignalCorridor = abstutil::from_json(body)?;
+            let wave = corridor.optimize(map)?;
+            let edits = wave.make_edits(map);
+            let map = session.map_mut();
+            map.must_apply_edits(edits, &mut Timer::throwaway());
+            map.recalculate_pathfinding_after_edits(&mut Timer::throwaway());
+            Ok(abstutil::to_json(&wave))
+        }
         "/traffic-signals/get-delays" => {
             let i = IntersectionID(params["id"].parse::<usize>()?);
             let t1 = Time::parse(&params["t1"])?;
@@ -212,18 +333,25 @@ fn handle_command(
 
             let mut delays = Delays {
                 per_direction: BTreeMap::new(),
+                actuated: Vec::new(),
+                fixed: Vec::new(),
             };
             for m in ts.movements.keys() {
                 delays.per_direction.insert(m.clone(), Vec::new());
             }
             if let Some(list) = sim.get_analytics().intersection_delays.get(&i) {
-                for (idx, t, dt, _) in list {
+                for (idx, t, dt, _, actuated) in list {
                     if *t >= t1 && *t <= t2 {
                         delays
                             .per_direction
                             .get_mut(movements[*idx as usize])
                             .unwrap()
                             .push(*dt);
+                        if *actuated {
+                            delays.actuated.push(*dt);
+                        } else {
+                            delays.fixed.push(*dt);
+                        }
                     }
                 }
             }
@@ -253,6 +381,15 @@ fn handle_command(
             }
             Ok(abstutil::to_json(&thruput))
         }
+        "/traffic-signals/get-transit-priority" => {
+            let i = IntersectionID(params["id"].parse::<usize>()?);
+            if map.maybe_get_traffic_signal(i).is_none() {
+                return Err(format!("{} isn't a traffic signal", i).into());
+            }
+            Ok(abstutil::to_json(
+                &sim.get_analytics().transit_priority_stats(i),
+            ))
+        }
         "/traffic-signals/get-all-current-state" => {
             let mut all_state = BTreeMap::new();
             for i in map.all_intersections() {
@@ -318,6 +455,15 @@ fn handle_command(
                 .map(|((r, a, hr), cnt)| (*r, *a, *hr, *cnt))
                 .collect(),
         })),
+        "/data/get-transit-performance" => {
+            let r = BusRouteID(params["id"].parse::<usize>()?);
+            if r.0 >= map.all_bus_routes().len() {
+                return Err(format!("{} doesn't exist", r).into());
+            }
+            Ok(abstutil::to_json(
+                &sim.get_analytics().transit_route_performance(r, map),
+            ))
+        }
         // Controlling the map
         "/map/get-edits" => {
             let mut edits = map.get_edits().clone();
@@ -355,6 +501,9 @@ struct FinishedTrip {
 struct Delays {
     #[serde(serialize_with = "serialize_btreemap")]
     per_direction: BTreeMap<MovementID, Vec<Duration>>,
+    /// The same delays, split by whether an actuated or fixed stage let the agent through
+    actuated: Vec<Duration>,
+    fixed: Vec<Duration>,
 }
 
 #[derive(Serialize)]
@@ -402,7 +551,7 @@ struct TrafficSignalState {
     waiting: Vec<(AgentID, TurnID, Time)>,
 }
 
-#[derive(Deserialize)]
+#[derive(Clone, Deserialize)]
 struct LoadSim {
     scenario: String,
     modifiers: Vec<ScenarioModifier>,
@@ -415,15 +564,10 @@ struct LoadSim {
 }
 
 impl LoadSim {
-    fn setup(&self, timer: &mut Timer) -> (Map, Sim) {
+    fn setup(&self, timer: &mut Timer) -> (Arc<Map>, Sim) {
         let mut scenario: Scenario = abstutil::read_binary(self.scenario.clone(), timer);
 
-        let mut map = Map::new(scenario.map_name.path(), timer);
-        if let Some(perma) = self.edits.clone() {
-            let edits = perma.to_edits(&map).unwrap();
-            map.must_apply_edits(edits, timer);
-            map.recalculate_pathfinding_after_edits(timer);
-        }
+        let map = shared_map(&scenario.map_name, &self.edits, timer);
 
         for m in &self.modifiers {
             scenario = m.apply(&map, scenario);
@@ -437,6 +581,106 @@ impl LoadSim {
     }
 }
 
+struct Sessions {
+    sessions: BTreeMap<usize, Arc<Mutex<Session>>>,
+    next_id: usize,
+    /// New sessions use the scenario and flags from startup, unless they specify otherwise.
+    default_load: LoadSim,
+}
+
+impl Sessions {
+    fn reserve_id(&mut self) -> usize {
+        self.next_id += 1;
+        self.next_id - 1
+    }
+}
+
+fn create_session(mut load: LoadSim, timer: &mut Timer) -> usize {
+    let id = {
+        let mut sessions = SESSIONS.write().unwrap();
+        let id = sessions.reserve_id();
+        load.opts.event_log = session_event_log(&sessions.default_load, id);
+        id
+    };
+    // Don't hold the lock while loading the map and scenario
+    let (map, sim) = load.setup(timer);
+    SESSIONS
+        .write()
+        .unwrap()
+        .sessions
+        .insert(id, Arc::new(Mutex::new(Session { load, map, sim })));
+    id
+}
+
+/// Each session writes its own event log, if the server was started with one.
+fn session_event_log(default_load: &LoadSim, id: usize) -> Option<EventLogOptions> {
+    let mut opts = default_load.opts.event_log.clone()?;
+    if id > 0 {
+        opts.path = format!("{}.session{}", opts.path, id);
+    }
+    Some(opts)
+}
+
+struct Session {
+    load: LoadSim,
+    map: Arc<Map>,
+    sim: Sim,
+}
+
+impl Session {
+    /// Before modifying the map, make sure no other session is sharing it.
+    fn map_mut(&mut self) -> &mut Map {
+        let this = Arc::downgrade(&self.map);
+        MAPS.lock()
+            .unwrap()
+            .retain(|(_, weak)| !weak.ptr_eq(&this) && weak.upgrade().is_some());
+        drop(this);
+
+        // If another session (like a fork) shares the map, copy it, keeping any live edits
+        if Arc::get_mut(&mut self.map).is_none() {
+            self.map = Arc::new(self.map.deep_clone());
+        }
+        Arc::get_mut(&mut self.map).unwrap()
+    }
+}
+
+/// The map's name and the edits applied to it, serialized for comparison.
+type MapKey = (MapName, Option<String>);
+
+fn shared_map(name: &MapName, edits: &Option<PermanentMapEdits>, timer: &mut Timer) -> Arc<Map> {
+    let key = (name.clone(), edits.as_ref().map(abstutil::to_json_terse));
+    if let Some(map) = find_shared_map(&mut MAPS.lock().unwrap(), &key) {
+        return map;
+    }
+    // Don't make every other session wait while this map loads
+    let map = Arc::new(load_map(name, edits, timer));
+
+    // Another session might've loaded the same map in the meantime
+    let mut maps = MAPS.lock().unwrap();
+    if let Some(existing) = find_shared_map(&mut maps, &key) {
+        return existing;
+    }
+    maps.push((key, Arc::downgrade(&map)));
+    map
+}
+
+fn find_shared_map(maps: &mut Vec<(MapKey, Weak<Map>)>, key: &MapKey) -> Option<Arc<Map>> {
+    maps.retain(|(_, weak)| weak.upgrade().is_some());
+    maps.iter()
+        .find(|(k, _)| k == key)
+        .and_then(|(_, weak)| weak.upgrade())
+}
+
+fn load_map(name: &MapName, edits: &Option<PermanentMapEdits>, timer: &mut Timer) -> Map {
+    let mut map = Map::new(name.path(), timer);
+    if let Some(perma) = edits.clone() {
+        let edits = perma.to_edits(&map).unwrap();
+        map.must_apply_edits(edits, timer);
+        map.recalculate_pathfinding_after_edits(timer);
+    }
+    map
+}
+
 fn export_geometry(map: &Map, i: IntersectionID) -> geojson::GeoJson {
     use geojson::{Feature, FeatureCollection, GeoJson};
 
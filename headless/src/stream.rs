@@ -0,0 +1,203 @@
+//! Streams a running simulation to websocket clients. After connecting, the client sends one JSON
+//! message describing what to stream, like `{"session": 0, "step": 5.0}`. The server then
+//! repeatedly advances that session's simulation by `step` seconds and sends a JSON
+//! `StreamUpdate` describing what changed.
+
+use std::collections::BTreeMap;
+use std::error::Error;
+
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio::net::{TcpListener, TcpStream};
+use tokio_tungstenite::tungstenite::Message;
+
+use abstutil::Timer;
+use geom::{Duration, LonLat, Time};
+use map_model::{IntersectionID, Map};
+use sim::{AgentID, Sim, TripID, TripMode};
+
+#[derive(Deserialize)]
+struct StreamConfig {
+    #[serde(default)]
+    session: usize,
+    /// How far to advance the simulation between updates, in seconds
+    step: Duration,
+    /// Stop streaming at this time, in seconds after midnight. By default, stream until the end
+    /// of the day.
+    #[serde(default)]
+    until: Option<Time>,
+    /// Wait this many real seconds between updates, so clients can watch a run in near-real
+    /// time. By default, updates are sent as fast as the simulation runs.
+    #[serde(default)]
+    delay: Option<Duration>,
+}
+
+/// Everything that changed since the previous update.
+#[derive(Serialize)]
+struct StreamUpdate {
+    time: Time,
+    /// Agents that appeared or moved
+    moved: Vec<(AgentID, LonLat)>,
+    /// Agents that disappeared
+    removed: Vec<AgentID>,
+    trips_started: Vec<TripID>,
+    /// The duration is null if the trip was cancelled
+    trips_finished: Vec<(TripID, TripMode, Option<Duration>)>,
+    /// Traffic signals that changed to a new stage, and the index of that stage
+    stage_changes: Vec<(IntersectionID, usize)>,
+}
+
+/// Remembers what was last sent to one client.
+struct StreamState {
+    positions: BTreeMap<AgentID, LonLat>,
+    num_finished_trips: usize,
+    /// When the last update happened
+    time: Time,
+    stages: BTreeMap<IntersectionID, usize>,
+}
+
+pub async fn serve(addr: std::net::SocketAddr) {
+    let mut listener = TcpListener::bind(&addr).await.unwrap();
+    info!("Streaming on ws://{}", addr);
+    loop {
+        match listener.accept().await {
+            Ok((stream, _)) => {
+                tokio::spawn(async move {
+                    if let Err(err) = stream_to_client(stream).await {
+                        error!("Stopped streaming: {}", err);
+                    }
+                });
+            }
+            Err(err) => {
+                error!("Couldn't accept websocket connection: {}", err);
+            }
+        }
+    }
+}
+
+async fn stream_to_client(stream: TcpStream) -> Result<(), Box<dyn Error + Send + Sync>> {
+    let mut ws = tokio_tungstenite::accept_async(stream).await?;
+    let config: StreamConfig = match ws.next().await {
+        Some(Ok(Message::Text(raw))) => serde_json::from_str(&raw)?,
+        _ => {
+            return Err("the first message must describe what to stream".into());
+        }
+    };
+    if config.step <= Duration::ZERO {
+        return Err("step must be positive".into());
+    }
+    let until = config
+        .until
+        .unwrap_or(Time::START_OF_DAY + Duration::hours(24));
+
+    let mut state = StreamState {
+        positions: BTreeMap::new(),
+        num_finished_trips: 0,
+        time: Time::START_OF_DAY,
+        stages: BTreeMap::new(),
+    };
+    loop {
+        // Running the simulation blocks, so keep it off the async worker threads
+        let (id, step) = (config.session, config.step);
+        let (update, new_state) =
+            tokio::task::spawn_blocking(move || step_session(id, step, until, state)).await??;
+        state = new_state;
+        let update = match update {
+            Some(update) => update,
+            None => {
+                break;
+            }
+        };
+        ws.send(Message::Text(abstutil::to_json_terse(&update)))
+            .await?;
+        if let Some(delay) = config.delay {
+            tokio::time::delay_for(std::time::Duration::from_secs_f64(delay.inner_seconds())).await;
+        }
+    }
+    ws.send(Message::Close(None)).await?;
+    Ok(())
+}
+
+/// Advance the session's simulation by one step and describe what changed. None if the session
+/// is already done.
+fn step_session(
+    id: usize,
+    step: Duration,
+    until: Time,
+    mut state: StreamState,
+) -> Result<(Option<StreamUpdate>, StreamState), String> {
+    let session = crate::get_session(id).map_err(|err| err.to_string())?;
+    let mut guard = session.lock().unwrap();
+    let session = &mut *guard;
+    let now = session.sim.time();
+    if now >= until {
+        return Ok((None, state));
+    }
+    let dt = step.min(until - now);
+    session
+        .sim
+        .timed_step(&session.map, dt, &mut None, &mut Timer::throwaway());
+    let update = state.update(&session.sim, &session.map, now);
+    Ok((Some(update), state))
+}
+
+impl StreamState {
+    /// Describe what changed since the last update, which happened at `prev_time`.
+    fn update(&mut self, sim: &Sim, map: &Map, prev_time: Time) -> StreamUpdate {
+        let mut moved = Vec::new();
+        let mut positions = BTreeMap::new();
+        for a in sim.get_unzoomed_agents(map) {
+            let pos = a.pos.to_gps(map.get_gps_bounds());
+            if self.positions.get(&a.id) != Some(&pos) {
+                moved.push((a.id, pos));
+            }
+            positions.insert(a.id, pos);
+        }
+        let removed = self
+            .positions
+            .keys()
+            .filter(|a| !positions.contains_key(a))
+            .cloned()
+            .collect();
+        self.positions = positions;
+
+        let analytics = sim.get_analytics();
+        // If the session was reset or restored from a savestate since the last update, the
+        // finished trips were recorded from scratch.
+        if prev_time < self.time || analytics.finished_trips.len() < self.num_finished_trips {
+            self.num_finished_trips = 0;
+        }
+        self.time = sim.time();
+        let trips_started = analytics
+            .started_trips
+            .iter()
+            .filter(|(_, t)| **t > prev_time)
+            .map(|(trip, _)| *trip)
+            .collect();
+        let trips_finished = analytics.finished_trips[self.num_finished_trips..]
+            .iter()
+            .map(|(_, trip, mode, dt)| (*trip, *mode, *dt))
+            .collect();
+        self.num_finished_trips = analytics.finished_trips.len();
+
+        let mut stage_changes = Vec::new();
+        for i in map.all_intersections() {
+            if !i.is_traffic_signal() {
+                continue;
+            }
+            let (stage, _) = sim.current_stage_and_remaining_time(i.id);
+            if self.stages.insert(i.id, stage) != Some(stage) {
+                stage_changes.push((i.id, stage));
+            }
+        }
+
+        StreamUpdate {
+            time: sim.time(),
+            moved,
+            removed,
+            trips_started,
+            trips_finished,
+            stage_changes,
+        }
+    }
+}
@@ -25,6 +25,9 @@ pub struct GenericCityImporter {
     pub elevation: Option<String>,
     /// OSM railway=rail will be included as light rail if so. Cosmetic only.
     pub include_railroads: bool,
+    /// If provided, read bike share stations from a directory with a GBFS snapshot, instead of
+    /// using stations from OSM.
+    pub bike_share_gbfs: Option<String>,
 }
 
 impl GenericCityImporter {
@@ -65,6 +68,7 @@ impl GenericCityImporter {
                 private_offstreet_parking: self.private_offstreet_parking.clone(),
                 elevation: self.elevation.clone(),
                 include_railroads: self.include_railroads,
+                bike_share_gbfs: self.bike_share_gbfs.clone(),
             },
             timer,
         );
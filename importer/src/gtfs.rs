@@ -0,0 +1,387 @@
+//! Import public transit schedules from a static GTFS feed. See
+//! https://developers.google.com/transit/gtfs/reference.
+//!
+//! Bus and train routes are still built from OSM relations. This matches GTFS stops to the
+//! existing `BusStop`s, figures out which GTFS trips run along each `BusRoute`, and then overrides
+//! the route's spawn times with the real departures.
+
+use std::collections::{BTreeMap, BTreeSet};
+use std::error::Error;
+use std::fs::File;
+
+use serde::de::DeserializeOwned;
+use serde::Deserialize;
+
+use abstutil::{Counter, Timer};
+use geom::{Distance, Duration, Line, LonLat, Pt2D, Time};
+use map_model::{BusRoute, BusStopID, Map, PathConstraints};
+
+/// GTFS stops farther than this from a bus stop don't match it.
+const STOP_MATCH_THRESHOLD: Distance = Distance::const_meters(30.0);
+/// A trip following some shape is only used for a route if every stop along the route is at least
+/// this close to the shape.
+const SHAPE_MATCH_THRESHOLD: Distance = Distance::const_meters(50.0);
+
+/// Reads a GTFS feed from a local .zip file, then overrides the spawn times and GTFS trip marker
+/// of every route in the map that matches some trips in the feed. Saves the map afterwards.
+pub fn import_schedules(
+    map: &mut Map,
+    path: &str,
+    timer: &mut Timer,
+) -> Result<(), Box<dyn Error>> {
+    timer.start(format!("read GTFS feed from {}", path));
+    let feed = Feed::load(path)?;
+    timer.stop(format!("read GTFS feed from {}", path));
+
+    let stop_candidates = match_stops(&feed, map);
+    timer.note(format!(
+        "{} bus stops matched to at least one GTFS stop",
+        stop_candidates.len()
+    ));
+
+    let mut overrides = Vec::new();
+    timer.start_iter("match GTFS trips to routes", map.all_bus_routes().len());
+    for br in map.all_bus_routes() {
+        timer.next();
+        match match_route(br, &feed, &stop_candidates, map) {
+            Some((marker, times)) => {
+                overrides.push((br.id, marker, times));
+            }
+            None => {
+                timer.warn(format!(
+                    "No GTFS trips serve {} ({}), keeping the default schedule",
+                    br.full_name, br.osm_rel_id
+                ));
+            }
+        }
+    }
+
+    timer.note(format!(
+        "Overriding schedules for {} / {} routes from GTFS",
+        overrides.len(),
+        map.all_bus_routes().len()
+    ));
+    for (br, marker, times) in overrides {
+        map.hack_override_gtfs_trip_marker(br, marker);
+        map.hack_override_orig_spawn_times(br, times);
+    }
+    map.save();
+    Ok(())
+}
+
+/// The subset of a GTFS feed needed to produce schedules, limited to a single service.
+struct Feed {
+    stops: BTreeMap<String, LonLat>,
+    /// route_id to route_type
+    routes: BTreeMap<String, usize>,
+    /// trip_id to (route_id, shape_id)
+    trips: BTreeMap<String, (String, Option<String>)>,
+    /// Per trip_id, the stops visited in order, with the departure time from each
+    stop_times: BTreeMap<String, Vec<(String, Time)>>,
+    /// Per shape_id, the points in order
+    shapes: BTreeMap<String, Vec<LonLat>>,
+}
+
+impl Feed {
+    fn load(path: &str) -> Result<Feed, Box<dyn Error>> {
+        let mut archive = zip::ZipArchive::new(File::open(path)?)?;
+
+        let mut stops = BTreeMap::new();
+        for rec in read_csv::<StopRecord>(&mut archive, "stops.txt", true)? {
+            // Some kinds of stops, like entrances to a station, might not have a position
+            if let (Some(lon), Some(lat)) = (rec.stop_lon, rec.stop_lat) {
+                stops.insert(rec.stop_id, LonLat::new(lon, lat));
+            }
+        }
+
+        let mut routes = BTreeMap::new();
+        for rec in read_csv::<RouteRecord>(&mut archive, "routes.txt", true)? {
+            routes.insert(rec.route_id, rec.route_type);
+        }
+
+        // A feed usually describes many different days of service. Just use the service with
+        // the most trips, which is usually a typical weekday.
+        let all_trips = read_csv::<TripRecord>(&mut archive, "trips.txt", true)?;
+        let mut services = Counter::new();
+        for rec in &all_trips {
+            services.inc(rec.service_id.clone());
+        }
+        let service = services
+            .borrow()
+            .iter()
+            .max_by_key(|(_, cnt)| **cnt)
+            .map(|(id, _)| id.clone())
+            .ok_or("trips.txt is empty")?;
+        let mut trips = BTreeMap::new();
+        for rec in all_trips {
+            if rec.service_id == service {
+                trips.insert(rec.trip_id, (rec.route_id, rec.shape_id));
+            }
+        }
+
+        let mut stop_times_per_trip: BTreeMap<String, Vec<(usize, String, Time)>> = BTreeMap::new();
+        for rec in read_csv::<StopTimeRecord>(&mut archive, "stop_times.txt", true)? {
+            if !trips.contains_key(&rec.trip_id) {
+                continue;
+            }
+            // Stops that aren't timepoints may omit times entirely.
+            let time = match rec.departure_time.or(rec.arrival_time) {
+                Some(t) => parse_time(&t)?,
+                None => continue,
+            };
+            stop_times_per_trip
+                .entry(rec.trip_id)
+                .or_insert_with(Vec::new)
+                .push((rec.stop_sequence, rec.stop_id, time));
+        }
+        let mut stop_times = BTreeMap::new();
+        for (trip, mut list) in stop_times_per_trip {
+            list.sort_by_key(|(seq, _, _)| *seq);
+            stop_times.insert(
+                trip,
+                list.into_iter()
+                    .map(|(_, stop, time)| (stop, time))
+                    .collect(),
+            );
+        }
+
+        // shapes.txt is optional
+        let mut shapes_per_id: BTreeMap<String, Vec<(usize, LonLat)>> = BTreeMap::new();
+        for rec in read_csv::<ShapeRecord>(&mut archive, "shapes.txt", false)? {
+            shapes_per_id
+                .entry(rec.shape_id)
+                .or_insert_with(Vec::new)
+                .push((
+                    rec.shape_pt_sequence,
+                    LonLat::new(rec.shape_pt_lon, rec.shape_pt_lat),
+                ));
+        }
+        let mut shapes = BTreeMap::new();
+        for (id, mut pts) in shapes_per_id {
+            pts.sort_by_key(|(seq, _)| *seq);
+            shapes.insert(id, pts.into_iter().map(|(_, pt)| pt).collect());
+        }
+
+        Ok(Feed {
+            stops,
+            routes,
+            trips,
+            stop_times,
+            shapes,
+        })
+    }
+}
+
+/// For every bus stop, find all of the nearby GTFS stops. A GTFS stop may match multiple bus stops,
+/// like when stops for both directions are across the street from each other. The order of stops
+/// along a trip disambiguates later.
+fn match_stops(feed: &Feed, map: &Map) -> BTreeMap<BusStopID, BTreeSet<String>> {
+    let gps_bounds = map.get_gps_bounds();
+    let gtfs_pts: Vec<(&String, Pt2D)> = feed
+        .stops
+        .iter()
+        .filter(|(_, ll)| gps_bounds.contains(**ll))
+        .map(|(id, ll)| (id, ll.to_pt(gps_bounds)))
+        .collect();
+
+    let mut candidates = BTreeMap::new();
+    for bs in map.all_bus_stops().values() {
+        let driving_pt = bs.driving_pos.pt(map);
+        let sidewalk_pt = bs.sidewalk_pos.pt(map);
+        let nearby: BTreeSet<String> = gtfs_pts
+            .iter()
+            .filter(|(_, pt)| {
+                pt.dist_to(driving_pt).min(pt.dist_to(sidewalk_pt)) <= STOP_MATCH_THRESHOLD
+            })
+            .map(|(id, _)| (*id).clone())
+            .collect();
+        if !nearby.is_empty() {
+            candidates.insert(bs.id, nearby);
+        }
+    }
+    candidates
+}
+
+/// Finds all trips that visit every stop of a route in order, returning the most common GTFS shape
+/// (or route ID, if the feed has no shapes) used by them and the departure times from the first
+/// stop.
+fn match_route(
+    br: &BusRoute,
+    feed: &Feed,
+    stop_candidates: &BTreeMap<BusStopID, BTreeSet<String>>,
+    map: &Map,
+) -> Option<(String, Vec<Time>)> {
+    let mut route_stops = Vec::new();
+    for bs in &br.stops {
+        route_stops.push(stop_candidates.get(bs)?);
+    }
+    let stop_pts: Vec<Pt2D> = br
+        .stops
+        .iter()
+        .map(|bs| map.get_bs(*bs).driving_pos.pt(map))
+        .collect();
+
+    let mut shape_matches: BTreeMap<&String, bool> = BTreeMap::new();
+    let mut markers = Counter::new();
+    let mut times = Vec::new();
+    for (trip_id, (route_id, shape_id)) in &feed.trips {
+        if feed
+            .routes
+            .get(route_id)
+            .and_then(|rt| route_type_to_constraints(*rt))
+            != Some(br.route_type)
+        {
+            continue;
+        }
+        let departure = match feed
+            .stop_times
+            .get(trip_id)
+            .and_then(|stop_times| first_departure(stop_times, &route_stops))
+        {
+            Some(t) => t,
+            None => continue,
+        };
+        if let Some(shape_id) = shape_id {
+            let follows_route = *shape_matches.entry(shape_id).or_insert_with(|| {
+                feed.shapes
+                    .get(shape_id)
+                    .map(|shape| shape_covers_stops(shape, &stop_pts, map))
+                    .unwrap_or(true)
+            });
+            if !follows_route {
+                continue;
+            }
+        }
+        markers.inc(shape_id.clone().unwrap_or_else(|| route_id.clone()));
+        times.push(departure);
+    }
+    if times.is_empty() {
+        return None;
+    }
+    times.sort();
+    times.dedup();
+
+    let marker = markers
+        .borrow()
+        .iter()
+        .max_by_key(|(_, cnt)| **cnt)
+        .map(|(id, _)| id.clone())
+        .unwrap();
+    Some((marker, times))
+}
+
+/// If the trip visits a matching GTFS stop for every stop of the route, in order, returns the
+/// departure time from the first stop.
+fn first_departure(
+    stop_times: &Vec<(String, Time)>,
+    route_stops: &Vec<&BTreeSet<String>>,
+) -> Option<Time> {
+    let mut departure = None;
+    let mut idx = 0;
+    for candidates in route_stops {
+        idx += stop_times[idx..]
+            .iter()
+            .position(|(stop, _)| candidates.contains(stop))?;
+        if departure.is_none() {
+            departure = Some(stop_times[idx].1);
+        }
+        idx += 1;
+    }
+    departure
+}
+
+fn shape_covers_stops(shape: &Vec<LonLat>, stop_pts: &Vec<Pt2D>, map: &Map) -> bool {
+    let lines: Vec<Line> = shape
+        .windows(2)
+        .filter_map(|pair| {
+            Line::new(
+                pair[0].to_pt(map.get_gps_bounds()),
+                pair[1].to_pt(map.get_gps_bounds()),
+            )
+        })
+        .collect();
+    stop_pts.iter().all(|pt| {
+        lines
+            .iter()
+            .any(|line| line.project_pt(*pt).dist_to(*pt) <= SHAPE_MATCH_THRESHOLD)
+    })
+}
+
+/// See https://developers.google.com/transit/gtfs/reference#routestxt and the extended route
+/// types at https://developers.google.com/transit/gtfs/reference/extended-route-types.
+fn route_type_to_constraints(route_type: usize) -> Option<PathConstraints> {
+    match route_type {
+        3 | 700..=799 => Some(PathConstraints::Bus),
+        0 | 1 | 2 | 100..=199 | 400..=499 | 900..=999 => Some(PathConstraints::Train),
+        _ => None,
+    }
+}
+
+/// GTFS times may exceed 24 hours for trips continuing past midnight. Wrap those around to the
+/// beginning of the day.
+fn parse_time(raw: &str) -> Result<Time, Box<dyn Error>> {
+    let mut time = Time::parse(raw.trim())?;
+    while time >= Time::START_OF_DAY + Duration::hours(24) {
+        time = time - Duration::hours(24);
+    }
+    Ok(time)
+}
+
+fn read_csv<T: DeserializeOwned>(
+    archive: &mut zip::ZipArchive<File>,
+    name: &str,
+    required: bool,
+) -> Result<Vec<T>, Box<dyn Error>> {
+    let file = match archive.by_name(name) {
+        Ok(file) => file,
+        Err(zip::result::ZipError::FileNotFound) if !required => {
+            return Ok(Vec::new());
+        }
+        Err(err) => {
+            return Err(format!("can't read {}: {}", name, err).into());
+        }
+    };
+    let mut results = Vec::new();
+    for rec in csv::Reader::from_reader(file).deserialize() {
+        results.push(rec?);
+    }
+    Ok(results)
+}
+
+#[derive(Deserialize)]
+struct StopRecord {
+    stop_id: String,
+    stop_lat: Option<f64>,
+    stop_lon: Option<f64>,
+}
+
+#[derive(Deserialize)]
+struct RouteRecord {
+    route_id: String,
+    route_type: usize,
+}
+
+#[derive(Deserialize)]
+struct TripRecord {
+    route_id: String,
+    service_id: String,
+    trip_id: String,
+    shape_id: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct StopTimeRecord {
+    trip_id: String,
+    arrival_time: Option<String>,
+    departure_time: Option<String>,
+    stop_id: String,
+    stop_sequence: usize,
+}
+
+#[derive(Deserialize)]
+struct ShapeRecord {
+    shape_id: String,
+    shape_pt_lat: f64,
+    shape_pt_lon: f64,
+    shape_pt_sequence: usize,
+}
@@ -10,6 +10,7 @@ mod berlin;
 mod configuration;
 mod dependencies;
 mod generic;
+mod gtfs;
 mod leeds;
 mod london;
 mod seattle;
@@ -28,6 +29,7 @@ struct Job {
 
     skip_ch: bool,
     keep_bldg_tags: bool,
+    gtfs: Option<String>,
 
     only_map: Option<String>,
 
@@ -54,6 +56,9 @@ fn main() {
         skip_ch: args.enabled("--skip_ch"),
         // Preserve OSM tags for buildings, increasing the file size.
         keep_bldg_tags: args.enabled("--keep_bldg_tags"),
+        // When producing the final Map, override bus and train schedules using a GTFS feed. Pass
+        // the path to a local .zip file.
+        gtfs: args.optional("--gtfs"),
 
         // Only process one map. If not specified, process all maps defined by clipping polygons in
         // importer/config/$city/.
@@ -179,6 +184,14 @@ fn main() {
                 timer.stop(format!("add GTFS schedules for {}", name.describe()));
             }
 
+            if let Some(ref path) = job.gtfs {
+                timer.start(format!("import GTFS schedules for {}", name.describe()));
+                if let Err(err) = gtfs::import_schedules(&mut map, path, &mut timer) {
+                    panic!("Couldn't import GTFS from {}: {}", path, err);
+                }
+                timer.stop(format!("import GTFS schedules for {}", name.describe()));
+            }
+
             Some(map)
         } else if job.scenario {
             Some(map_model::Map::new(name.path(), &mut timer))
@@ -249,6 +262,7 @@ fn oneshot(
             private_offstreet_parking: convert_osm::PrivateOffstreetParking::FixedPerBldg(1),
             elevation: None,
             include_railroads: true,
+            bike_share_gbfs: None,
         },
         &mut timer,
     );
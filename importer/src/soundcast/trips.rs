@@ -3,7 +3,7 @@ use std::collections::HashMap;
 use abstutil::{prettyprint_usize, MultiMap, Parallelism, Timer};
 use geom::LonLat;
 use map_model::{osm, BuildingID, IntersectionID, Map, PathConstraints, PathRequest, PathStep};
-use sim::{IndividTrip, OrigPersonID, PersonSpec, Scenario, TripEndpoint, TripMode};
+use sim::{IndividTrip, OrigPersonID, PersonSpec, Scenario, TripEndpoint, TripMode, TripPurpose};
 
 use crate::soundcast::popdat::{Endpoint, OrigTrip, PopDat};
 
@@ -205,15 +205,27 @@ fn clip_trips(map: &Map, popdat: &PopDat, huge_map: &Map, timer: &mut Timer) ->
                 map,
                 &osm_id_to_bldg,
                 match orig.mode {
-                    TripMode::Walk | TripMode::Transit => {
+                    TripMode::Walk | TripMode::Transit | TripMode::BikeShare => {
                         (&incoming_borders_walking, &outgoing_borders_walking)
                     }
-                    TripMode::Drive => (&incoming_borders_driving, &outgoing_borders_driving),
+                    TripMode::Drive
+                    | TripMode::Carpool
+                    | TripMode::RideHail
+                    | TripMode::Delivery
+                    | TripMode::ParkAndRide => {
+                        (&incoming_borders_driving, &outgoing_borders_driving)
+                    }
                     TripMode::Bike => (&incoming_borders_biking, &outgoing_borders_biking),
                 },
                 match orig.mode {
-                    TripMode::Walk | TripMode::Transit => PathConstraints::Pedestrian,
-                    TripMode::Drive => PathConstraints::Car,
+                    TripMode::Walk | TripMode::Transit | TripMode::BikeShare => {
+                        PathConstraints::Pedestrian
+                    }
+                    TripMode::Drive
+                    | TripMode::Carpool
+                    | TripMode::RideHail
+                    | TripMode::Delivery
+                    | TripMode::ParkAndRide => PathConstraints::Car,
                     TripMode::Bike => PathConstraints::Bike,
                 },
                 maybe_huge_map.as_ref(),
@@ -236,6 +248,33 @@ fn clip_trips(map: &Map, popdat: &PopDat, huge_map: &Map, timer: &mut Timer) ->
     trips
 }
 
+/// Soundcast describes driving to a park-and-ride lot as its own trip, with the purpose of
+/// transferring, followed by a transit trip. Join each of these into one park-and-ride trip.
+fn merge_park_and_ride(
+    pairs: Vec<(TripEndpoint, IndividTrip)>,
+) -> Vec<(TripEndpoint, IndividTrip)> {
+    let mut result: Vec<(TripEndpoint, IndividTrip)> = Vec::new();
+    for (from, trip) in pairs {
+        if let Some((prev_from, prev)) = result.last_mut() {
+            if prev.mode == TripMode::Drive
+                && matches!(prev.purpose, TripPurpose::ParkAndRideTransfer)
+                && trip.mode == TripMode::Transit
+                && prev.destination == from
+                // Both ends of park-and-ride trips must be buildings
+                && matches!(prev_from, TripEndpoint::Bldg(_))
+                && matches!(trip.destination, TripEndpoint::Bldg(_))
+            {
+                prev.mode = TripMode::ParkAndRide;
+                prev.purpose = trip.purpose;
+                prev.destination = trip.destination;
+                continue;
+            }
+        }
+        result.push((from, trip));
+    }
+    result
+}
+
 pub fn make_weekday_scenario(
     map: &Map,
     popdat: &PopDat,
@@ -275,6 +314,7 @@ pub fn make_weekday_scenario(
         // Actually, the sequence in the Soundcast dataset crosses midnight. Don't do that; sort by
         // departure time starting with midnight.
         pairs.sort_by_key(|(_, t)| t.depart);
+        let pairs = merge_park_and_ride(pairs);
         // Sanity check that endpoints match up
         for pair in pairs.windows(2) {
             let destination = &pair[0].1.destination;
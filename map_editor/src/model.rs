@@ -197,6 +197,7 @@ impl Model {
             IntersectionType::StopSign => Color::RED,
             IntersectionType::Border => Color::BLUE,
             IntersectionType::Construction => Color::ORANGE,
+            IntersectionType::Roundabout => Color::PURPLE,
         };
 
         let poly = if self.intersection_geom && !self.map.roads_per_intersection(id).is_empty() {
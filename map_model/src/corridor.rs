@@ -0,0 +1,433 @@
+//! Coordinates the offsets of a sequence of traffic signals, so that vehicles travelling between
+//! them at some progression speed hit a "green wave" in both directions.
+//!
+//! Time is discretized to whole seconds, since that's the resolution signal timing is exported at.
+//! The bandwidth in one direction is the longest window of time where a vehicle could pass the
+//! first signal and reach every other signal while it's green for the corridor. Offsets are found
+//! by coordinate descent over the total bandwidth in both directions.
+
+use std::collections::BTreeSet;
+
+use serde::{Deserialize, Serialize};
+
+use geom::{Distance, Duration, Speed};
+
+use crate::{
+    ActuatedTiming, ControlTrafficSignal, EditCmd, EditIntersection, IntersectionID, Map, MapEdits,
+    PhaseType, RoadID,
+};
+
+const MIN_CYCLE_LENGTH: usize = 40;
+const MAX_CYCLE_LENGTH: usize = 180;
+const CYCLE_LENGTH_STEP: usize = 5;
+const MAX_PASSES: usize = 10;
+
+/// An ordered sequence of traffic signals along a route.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct SignalCorridor {
+    pub intersections: Vec<IntersectionID>,
+    /// The speed vehicles are expected to travel between the signals
+    pub progression_speed: Speed,
+    /// Also search for a common cycle length, rescaling the stages of every signal to fit it.
+    /// Otherwise, all of the signals must already have the same cycle length.
+    pub optimize_cycle_length: bool,
+}
+
+/// The result of optimizing a SignalCorridor.
+#[derive(Clone, Debug, Serialize)]
+pub struct GreenWave {
+    pub cycle_length: Duration,
+    /// In the same order as the corridor's intersections
+    pub offsets: Vec<Duration>,
+    /// The window of time for vehicles to pass every signal without stopping, travelling in the
+    /// order of the corridor's intersections
+    pub outbound_bandwidth: Duration,
+    /// The same, travelling in the opposite direction
+    pub inbound_bandwidth: Duration,
+    /// The new signals, in the same order as the corridor's intersections
+    #[serde(skip_serializing)]
+    pub signals: Vec<ControlTrafficSignal>,
+}
+
+impl SignalCorridor {
+    pub fn optimize(&self, map: &Map) -> Result<GreenWave, String> {
+        if self.intersections.len() < 2 {
+            return Err("A corridor needs at least two traffic signals".to_string());
+        }
+        if self.intersections.iter().collect::<BTreeSet<_>>().len() != self.intersections.len() {
+            return Err("A corridor can't visit the same intersection twice".to_string());
+        }
+        if self.progression_speed <= Speed::ZERO {
+            return Err(format!(
+                "Progression speed must be positive, not {} m/s",
+                self.progression_speed.inner_meters_per_second()
+            ));
+        }
+        let mut signals = Vec::new();
+        for i in &self.intersections {
+            signals.push(
+                map.maybe_get_traffic_signal(*i)
+                    .ok_or(format!("{} isn't a traffic signal", i))?
+                    .clone(),
+            );
+        }
+
+        // The roads between each consecutive pair of signals
+        let mut links: Vec<Vec<RoadID>> = Vec::new();
+        // When a vehicle moving outbound reaches each signal, relative to passing the first
+        let mut arrivals = vec![0];
+        let mut total_time = Duration::ZERO;
+        for pair in self.intersections.windows(2) {
+            let path = map
+                .simple_path_btwn(pair[0], pair[1])
+                .ok_or(format!("No path between {} and {}", pair[0], pair[1]))?;
+            let dist: Distance = path.iter().map(|r| map.get_r(*r).center_pts.length()).sum();
+            total_time += dist / self.progression_speed;
+            arrivals.push(total_time.inner_seconds().round() as usize);
+            links.push(path);
+        }
+
+        let cycle_lengths: Vec<usize> = signals.iter().map(cycle_length).collect();
+        if !self.optimize_cycle_length {
+            if let Some(idx) = cycle_lengths.iter().position(|c| *c != cycle_lengths[0]) {
+                return Err(format!(
+                    "{} has a cycle length of {}s, but {} has {}s. Try optimizing the cycle \
+                     length too.",
+                    self.intersections[0],
+                    cycle_lengths[0],
+                    self.intersections[idx],
+                    cycle_lengths[idx]
+                ));
+            }
+        }
+        let candidates: Vec<usize> = if self.optimize_cycle_length {
+            let mut list: BTreeSet<usize> = (MIN_CYCLE_LENGTH..=MAX_CYCLE_LENGTH)
+                .step_by(CYCLE_LENGTH_STEP)
+                .collect();
+            list.extend(cycle_lengths.iter().cloned());
+            list.into_iter().collect()
+        } else {
+            vec![cycle_lengths[0]]
+        };
+
+        let mut best: Option<(f64, GreenWave)> = None;
+        for cycle in candidates {
+            let mut rescaled = Vec::new();
+            for ts in &signals {
+                if let Some(ts) = with_cycle_length(ts, cycle) {
+                    rescaled.push(ts);
+                } else {
+                    break;
+                }
+            }
+            // Some signal can't fit its crosswalks into this cycle
+            if rescaled.len() != signals.len() {
+                continue;
+            }
+            let wave = self.optimize_offsets(map, rescaled, &links, &arrivals, cycle)?;
+            // Longer cycles have more absolute bandwidth, so compare the fraction of the cycle
+            let efficiency = (wave.outbound_bandwidth + wave.inbound_bandwidth).inner_seconds()
+                / (2.0 * cycle as f64);
+            if best
+                .as_ref()
+                .map(|(score, _)| efficiency > *score)
+                .unwrap_or(true)
+            {
+                best = Some((efficiency, wave));
+            }
+        }
+        best.map(|(_, wave)| wave).ok_or_else(|| {
+            "No cycle length leaves enough time for every crosswalk along the corridor".to_string()
+        })
+    }
+
+    fn optimize_offsets(
+        &self,
+        map: &Map,
+        mut signals: Vec<ControlTrafficSignal>,
+        links: &[Vec<RoadID>],
+        arrivals: &[usize],
+        cycle: usize,
+    ) -> Result<GreenWave, String> {
+        let n = signals.len();
+        let total = *arrivals.last().unwrap();
+        let mut outbound = Vec::new();
+        let mut inbound = Vec::new();
+        for (idx, ts) in signals.iter().enumerate() {
+            let before = if idx == 0 {
+                None
+            } else {
+                links[idx - 1].last()
+            };
+            let after = links.get(idx).and_then(|path| path.first());
+            outbound.push(green_mask(ts, before, after, cycle, map)?);
+            let before = links.get(idx).and_then(|path| path.first());
+            let after = if idx == 0 {
+                None
+            } else {
+                links[idx - 1].last()
+            };
+            inbound.push(green_mask(ts, before, after, cycle, map)?);
+        }
+        let inbound_arrivals: Vec<usize> = arrivals.iter().map(|t| total - t).collect();
+
+        let score = |offsets: &[usize], upto: usize| {
+            bandwidth(
+                &outbound[..upto],
+                &arrivals[..upto],
+                &offsets[..upto],
+                cycle,
+            ) + bandwidth(
+                &inbound[..upto],
+                &inbound_arrivals[..upto],
+                &offsets[..upto],
+                cycle,
+            )
+        };
+
+        // Anchor the first signal where it is. Greedily place the others one at a time, then keep
+        // adjusting each one until nothing improves.
+        let mut offsets = vec![0; n];
+        offsets[0] = (signals[0].offset.inner_seconds().round() as usize) % cycle;
+        for idx in 1..n {
+            offsets[idx] = best_offset(&mut offsets, idx, cycle, |o| score(o, idx + 1));
+        }
+        let mut current = score(&offsets, n);
+        for _ in 0..MAX_PASSES {
+            let mut improved = false;
+            for idx in 1..n {
+                let orig = offsets[idx];
+                offsets[idx] = best_offset(&mut offsets, idx, cycle, |o| score(o, n));
+                let new = score(&offsets, n);
+                if new > current {
+                    current = new;
+                    improved = true;
+                } else {
+                    offsets[idx] = orig;
+                }
+            }
+            if !improved {
+                break;
+            }
+        }
+
+        for (ts, offset) in signals.iter_mut().zip(offsets.iter()) {
+            ts.offset = Duration::seconds(*offset as f64);
+        }
+        Ok(GreenWave {
+            cycle_length: Duration::seconds(cycle as f64),
+            offsets: offsets
+                .iter()
+                .map(|o| Duration::seconds(*o as f64))
+                .collect(),
+            outbound_bandwidth: Duration::seconds(
+                bandwidth(&outbound, arrivals, &offsets, cycle) as f64
+            ),
+            inbound_bandwidth: Duration::seconds(bandwidth(
+                &inbound,
+                &inbound_arrivals,
+                &offsets,
+                cycle,
+            ) as f64),
+            signals,
+        })
+    }
+
+    /// Orders a set of traffic signals along the longest simple path between any two of them.
+    /// Fails if some of the signals aren't on that path.
+    pub fn order_members(
+        map: &Map,
+        members: &BTreeSet<IntersectionID>,
+    ) -> Result<Vec<IntersectionID>, String> {
+        let mut longest: Option<(Distance, IntersectionID, Vec<RoadID>)> = None;
+        for i1 in members {
+            for i2 in members {
+                if i1 >= i2 {
+                    continue;
+                }
+                if let Some(path) = map.simple_path_btwn(*i1, *i2) {
+                    let dist: Distance =
+                        path.iter().map(|r| map.get_r(*r).center_pts.length()).sum();
+                    if longest.as_ref().map(|(d, _, _)| dist > *d).unwrap_or(true) {
+                        longest = Some((dist, *i1, path));
+                    }
+                }
+            }
+        }
+        let (_, start, path) =
+            longest.ok_or("The signals aren't connected to each other".to_string())?;
+
+        let mut ordered = vec![start];
+        let mut current = start;
+        for r in path {
+            let r = map.get_r(r);
+            current = if r.src_i == current { r.dst_i } else { r.src_i };
+            if members.contains(&current) {
+                ordered.push(current);
+            }
+        }
+        if ordered.len() != members.len() {
+            return Err("The signals don't all lie along one route".to_string());
+        }
+        Ok(ordered)
+    }
+}
+
+impl GreenWave {
+    /// Applies the new signals on top of the map's current edits.
+    pub fn make_edits(&self, map: &Map) -> MapEdits {
+        let mut edits = map.get_edits().clone();
+        for ts in &self.signals {
+            edits.commands.push(EditCmd::ChangeIntersection {
+                i: ts.id,
+                old: map.get_i_edit(ts.id),
+                new: EditIntersection::TrafficSignal(ts.export(map)),
+            });
+        }
+        edits
+    }
+}
+
+// For actuated stages, this uses the minimum green time.
+fn stage_seconds(phase_type: &PhaseType) -> usize {
+    (phase_type.simple_duration().inner_seconds().round() as usize).max(1)
+}
+
+fn cycle_length(ts: &ControlTrafficSignal) -> usize {
+    ts.stages.iter().map(|s| stage_seconds(&s.phase_type)).sum()
+}
+
+/// Proportionally stretches or shrinks every stage to fit the cycle length. Returns None if a
+/// stage would become too short for its crosswalks.
+fn with_cycle_length(ts: &ControlTrafficSignal, cycle: usize) -> Option<ControlTrafficSignal> {
+    let orig = cycle_length(ts);
+    if orig == cycle {
+        return Some(ts.clone());
+    }
+    let factor = (cycle as f64) / (orig as f64);
+    let mut new = ts.clone();
+    let mut remaining = cycle as isize;
+    for idx in 0..new.stages.len() {
+        let secs = if idx == new.stages.len() - 1 {
+            remaining
+        } else {
+            ((stage_seconds(&ts.stages[idx].phase_type) as f64) * factor).round() as isize
+        };
+        remaining -= secs;
+        if secs < 1 || Duration::seconds(secs as f64) < ts.get_min_crossing_time(idx) {
+            return None;
+        }
+        let dt = Duration::seconds(secs as f64);
+        new.stages[idx].phase_type = match ts.stages[idx].phase_type {
+            PhaseType::Fixed(_) => PhaseType::Fixed(dt),
+            PhaseType::Adaptive(_) => PhaseType::Adaptive(dt),
+            PhaseType::Actuated(timing) => PhaseType::Actuated(ActuatedTiming {
+                min_green: dt,
+                max_green: Duration::seconds((timing.max_green * factor).inner_seconds().round())
+                    .max(dt),
+                extension: timing.extension,
+            }),
+        };
+    }
+    Some(new)
+}
+
+/// For every second of the cycle (starting from the first stage), is there a protected movement
+/// along the corridor? Vehicles arrive from the `before` road and leave by the `after` road; at
+/// the ends of the corridor, one of these is missing.
+fn green_mask(
+    ts: &ControlTrafficSignal,
+    before: Option<&RoadID>,
+    after: Option<&RoadID>,
+    cycle: usize,
+    map: &Map,
+) -> Result<Vec<bool>, String> {
+    let mut mask = Vec::new();
+    for stage in &ts.stages {
+        let green = stage.protected_movements.iter().any(|m| {
+            !m.crosswalk
+                && before.map(|r| m.from.id == *r).unwrap_or(true)
+                && after.map(|r| m.to.id == *r).unwrap_or(true)
+        });
+        for _ in 0..stage_seconds(&stage.phase_type) {
+            mask.push(green);
+        }
+    }
+    assert_eq!(mask.len(), cycle);
+    if !mask.contains(&true) {
+        return Err(format!(
+            "No stage at {} ({}) protects the movement along the corridor",
+            ts.id,
+            map.get_i(ts.id).name(None, map)
+        ));
+    }
+    Ok(mask)
+}
+
+/// How many consecutive seconds could a vehicle pass the start of the corridor and make it
+/// through every signal while it's green?
+fn bandwidth(masks: &[Vec<bool>], arrivals: &[usize], offsets: &[usize], cycle: usize) -> usize {
+    // A signal is in its first stage when the time plus its offset is a multiple of the cycle.
+    let through: Vec<bool> = (0..cycle)
+        .map(|t| {
+            masks
+                .iter()
+                .zip(arrivals.iter().zip(offsets.iter()))
+                .all(|(mask, (arrival, offset))| mask[(t + arrival + offset) % cycle])
+        })
+        .collect();
+    if through.iter().all(|x| *x) {
+        return cycle;
+    }
+    // Find the longest run, wrapping around the end of the cycle
+    let mut best = 0;
+    let mut run = 0;
+    for x in through.iter().chain(through.iter()) {
+        if *x {
+            run += 1;
+            best = best.max(run);
+        } else {
+            run = 0;
+        }
+    }
+    best
+}
+
+/// Tries every offset for one signal, returning the best. Ties keep the earliest offset.
+fn best_offset<F: Fn(&[usize]) -> usize>(
+    offsets: &mut [usize],
+    idx: usize,
+    cycle: usize,
+    score: F,
+) -> usize {
+    let mut best = (0, 0);
+    for o in 0..cycle {
+        offsets[idx] = o;
+        let s = score(offsets);
+        if o == 0 || s > best.1 {
+            best = (o, s);
+        }
+    }
+    best.0
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bandwidth_wraps_around() {
+        // Green for the first half of a 10s cycle
+        let mask: Vec<bool> = (0..10).map(|t| t < 5).collect();
+        let masks = vec![mask.clone(), mask];
+
+        // Perfectly in sync
+        assert_eq!(5, bandwidth(&masks, &[0, 3], &[0, 7], 10));
+        // The second signal turns green 2s too late
+        assert_eq!(3, bandwidth(&masks, &[0, 3], &[0, 5], 10));
+        // The window straddles the end of the cycle
+        assert_eq!(5, bandwidth(&masks, &[0, 0], &[7, 7], 10));
+        // Never both green
+        assert_eq!(0, bandwidth(&masks, &[0, 5], &[0, 0], 10));
+    }
+}
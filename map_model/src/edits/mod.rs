@@ -13,8 +13,8 @@ use geom::{Speed, Time};
 use crate::make::initial::lane_specs::get_lane_specs_ltr;
 use crate::{
     connectivity, AccessRestrictions, BusRouteID, ControlStopSign, ControlTrafficSignal, Direction,
-    IntersectionID, IntersectionType, LaneID, LaneType, Map, MapConfig, PathConstraints,
-    Pathfinder, Road, RoadID, TurnID, Zone,
+    ExportedTrafficSignal, IntersectionID, IntersectionType, LaneID, LaneType, Map, MapConfig,
+    PathConstraints, Pathfinder, Road, RoadID, TimeWindow, TurnID, TurnType, Zone,
 };
 
 mod compat;
@@ -47,9 +47,10 @@ pub struct MapEdits {
 #[derive(Debug, Clone, PartialEq)]
 pub enum EditIntersection {
     StopSign(ControlStopSign),
+    Roundabout(ControlStopSign),
     // Don't keep ControlTrafficSignal here, because it contains movements that should be
     // generated after all lane edits are applied.
-    TrafficSignal(seattle_traffic_signals::TrafficSignal),
+    TrafficSignal(ExportedTrafficSignal),
     Closed,
 }
 
@@ -58,15 +59,30 @@ pub struct EditRoad {
     pub lanes_ltr: Vec<(LaneType, Direction)>,
     pub speed_limit: Speed,
     pub access_restrictions: AccessRestrictions,
+    /// Keyed by the index into lanes_ltr. Lanes without any time windows are omitted.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub conditional_types: BTreeMap<usize, Vec<(TimeWindow, LaneType)>>,
+    /// Keyed by the index into lanes_ltr. Lanes allowing any turn are omitted.
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub allowed_turns: BTreeMap<usize, BTreeSet<TurnType>>,
 }
 
 impl EditRoad {
     pub fn get_orig_from_osm(r: &Road, cfg: &MapConfig) -> EditRoad {
+        let specs = get_lane_specs_ltr(&r.osm_tags, cfg);
         EditRoad {
-            lanes_ltr: get_lane_specs_ltr(&r.osm_tags, cfg)
-                .into_iter()
-                .map(|spec| (spec.lt, spec.dir))
+            conditional_types: specs
+                .iter()
+                .enumerate()
+                .filter(|(_, spec)| !spec.conditional_types.is_empty())
+                .map(|(idx, spec)| (idx, spec.conditional_types.clone()))
+                .collect(),
+            allowed_turns: specs
+                .iter()
+                .enumerate()
+                .filter_map(|(idx, spec)| spec.turns.clone().map(|turns| (idx, turns)))
                 .collect(),
+            lanes_ltr: specs.into_iter().map(|spec| (spec.lt, spec.dir)).collect(),
             speed_limit: r.speed_limit_from_osm(),
             access_restrictions: r.access_restrictions_from_osm(),
         }
@@ -101,6 +117,12 @@ impl EditRoad {
         if self.access_restrictions != other.access_restrictions {
             changes.push(format!("access restrictions"));
         }
+        if self.conditional_types != other.conditional_types {
+            changes.push("time-of-day lane types".to_string());
+        }
+        if self.allowed_turns != other.allowed_turns {
+            changes.push("turn lanes".to_string());
+        }
         changes
     }
 }
@@ -246,7 +268,11 @@ impl MapEdits {
             } else {
                 let lanes_ltr = r.lanes_ltr();
                 for (idx, (lt, dir)) in orig.lanes_ltr.into_iter().enumerate() {
-                    if lanes_ltr[idx].1 != dir || lanes_ltr[idx].2 != lt {
+                    if lanes_ltr[idx].1 != dir
+                        || lanes_ltr[idx].2 != lt
+                        || map.get_l(lanes_ltr[idx].0).allowed_turns.as_ref()
+                            != orig.allowed_turns.get(&idx)
+                    {
                         lanes.insert(lanes_ltr[idx].0);
                     }
                 }
@@ -285,6 +311,7 @@ impl EditCmd {
             // TODO Describe changes
             EditCmd::ChangeIntersection { i, new, .. } => match new {
                 EditIntersection::StopSign(_) => format!("stop sign #{}", i.0),
+                EditIntersection::Roundabout(_) => format!("roundabout #{}", i.0),
                 EditIntersection::TrafficSignal(_) => format!("traffic signal #{}", i.0),
                 EditIntersection::Closed => format!("close {}", i),
             },
@@ -311,12 +338,19 @@ impl EditCmd {
                     let lane = &mut map.lanes[(road.lanes_ltr[idx].0).0];
                     road.lanes_ltr[idx].2 = lt;
                     lane.lane_type = lt;
+                    lane.conditional_types = new
+                        .conditional_types
+                        .get(&idx)
+                        .cloned()
+                        .unwrap_or_else(Vec::new);
+                    lane.allowed_turns = new.allowed_turns.get(&idx).cloned();
 
                     // Direction change?
                     if road.lanes_ltr[idx].1 != dir {
                         road.lanes_ltr[idx].1 = dir;
                         std::mem::swap(&mut lane.src_i, &mut lane.dst_i);
                         lane.lane_center_pts = lane.lane_center_pts.reversed();
+                        lane.percent_grade = -lane.percent_grade;
                     }
                 }
 
@@ -357,6 +391,10 @@ impl EditCmd {
                         map.intersections[i.0].intersection_type = IntersectionType::StopSign;
                         map.stop_signs.insert(*i, ss.clone());
                     }
+                    EditIntersection::Roundabout(ref ss) => {
+                        map.intersections[i.0].intersection_type = IntersectionType::Roundabout;
+                        map.stop_signs.insert(*i, ss.clone());
+                    }
                     EditIntersection::TrafficSignal(ref raw_ts) => {
                         map.intersections[i.0].intersection_type = IntersectionType::TrafficSignal;
                         if old == &EditIntersection::Closed {
@@ -449,6 +487,10 @@ fn recalculate_turns(
             // of lane changes, we can do the same here.
             map.stop_signs.insert(id, ControlStopSign::new(map, id));
         }
+        IntersectionType::Roundabout => {
+            map.stop_signs
+                .insert(id, ControlStopSign::roundabout(map, id));
+        }
         IntersectionType::TrafficSignal => {
             map.traffic_signals
                 .insert(id, ControlTrafficSignal::new(map, id, timer));
@@ -491,6 +533,30 @@ impl Map {
                 .collect(),
             speed_limit: r.speed_limit,
             access_restrictions: r.access_restrictions.clone(),
+            conditional_types: r
+                .lanes_ltr()
+                .into_iter()
+                .enumerate()
+                .filter_map(|(idx, (l, _, _))| {
+                    let lane = self.get_l(l);
+                    if lane.conditional_types.is_empty() {
+                        None
+                    } else {
+                        Some((idx, lane.conditional_types.clone()))
+                    }
+                })
+                .collect(),
+            allowed_turns: r
+                .lanes_ltr()
+                .into_iter()
+                .enumerate()
+                .filter_map(|(idx, (l, _, _))| {
+                    self.get_l(l)
+                        .allowed_turns
+                        .clone()
+                        .map(|turns| (idx, turns))
+                })
+                .collect(),
         }
     }
 
@@ -505,6 +571,9 @@ impl Map {
     pub fn get_i_edit(&self, i: IntersectionID) -> EditIntersection {
         match self.get_i(i).intersection_type {
             IntersectionType::StopSign => EditIntersection::StopSign(self.get_stop_sign(i).clone()),
+            IntersectionType::Roundabout => {
+                EditIntersection::Roundabout(self.get_stop_sign(i).clone())
+            }
             IntersectionType::TrafficSignal => {
                 EditIntersection::TrafficSignal(self.get_traffic_signal(i).export(self))
             }
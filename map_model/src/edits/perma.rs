@@ -7,7 +7,7 @@ use geom::Time;
 
 use crate::edits::{EditCmd, EditIntersection, EditRoad, MapEdits};
 use crate::raw::OriginalRoad;
-use crate::{osm, ControlStopSign, IntersectionID, Map};
+use crate::{osm, ControlStopSign, ExportedTrafficSignal, IntersectionID, Map};
 
 /// MapEdits are converted to this before serializing. Referencing things like LaneID in a Map won't
 /// work if the basemap is rebuilt from new OSM data, so instead we use stabler OSM IDs that're less
@@ -38,7 +38,14 @@ pub enum PermanentEditIntersection {
         )]
         must_stop: BTreeMap<OriginalRoad, bool>,
     },
-    TrafficSignal(seattle_traffic_signals::TrafficSignal),
+    Roundabout {
+        #[serde(
+            serialize_with = "serialize_btreemap",
+            deserialize_with = "deserialize_btreemap"
+        )]
+        must_yield: BTreeMap<OriginalRoad, bool>,
+    },
+    TrafficSignal(ExportedTrafficSignal),
     Closed,
 }
 
@@ -201,6 +208,13 @@ impl EditIntersection {
                     .map(|(r, val)| (map.get_r(*r).orig_id, val.must_stop))
                     .collect(),
             },
+            EditIntersection::Roundabout(ref ss) => PermanentEditIntersection::Roundabout {
+                must_yield: ss
+                    .roads
+                    .iter()
+                    .map(|(r, val)| (map.get_r(*r).orig_id, val.must_stop))
+                    .collect(),
+            },
             EditIntersection::TrafficSignal(ref raw_ts) => {
                 PermanentEditIntersection::TrafficSignal(raw_ts.clone())
             }
@@ -212,33 +226,43 @@ impl EditIntersection {
 impl PermanentEditIntersection {
     fn from_permanent(self, i: IntersectionID, map: &Map) -> Result<EditIntersection, String> {
         match self {
-            PermanentEditIntersection::StopSign { must_stop } => {
-                let mut translated_must_stop = BTreeMap::new();
-                for (r, stop) in must_stop {
-                    translated_must_stop.insert(map.find_r_by_osm_id(r)?, stop);
-                }
-
-                // Make sure the roads exactly match up
-                let mut ss = ControlStopSign::new(map, i);
-                if translated_must_stop.len() != ss.roads.len() {
-                    return Err(format!(
-                        "Stop sign has {} roads now, but {} from edits",
-                        ss.roads.len(),
-                        translated_must_stop.len()
-                    ));
-                }
-                for (r, stop) in translated_must_stop {
-                    if let Some(road) = ss.roads.get_mut(&r) {
-                        road.must_stop = stop;
-                    } else {
-                        return Err(format!("{} doesn't connect to {}", i, r));
-                    }
-                }
-
-                Ok(EditIntersection::StopSign(ss))
-            }
+            PermanentEditIntersection::StopSign { must_stop } => Ok(EditIntersection::StopSign(
+                translate_stop_sign(must_stop, i, map)?,
+            )),
+            PermanentEditIntersection::Roundabout { must_yield } => Ok(
+                EditIntersection::Roundabout(translate_stop_sign(must_yield, i, map)?),
+            ),
             PermanentEditIntersection::TrafficSignal(ts) => Ok(EditIntersection::TrafficSignal(ts)),
             PermanentEditIntersection::Closed => Ok(EditIntersection::Closed),
         }
     }
 }
+
+fn translate_stop_sign(
+    must_stop: BTreeMap<OriginalRoad, bool>,
+    i: IntersectionID,
+    map: &Map,
+) -> Result<ControlStopSign, String> {
+    let mut translated_must_stop = BTreeMap::new();
+    for (r, stop) in must_stop {
+        translated_must_stop.insert(map.find_r_by_osm_id(r)?, stop);
+    }
+
+    // Make sure the roads exactly match up
+    let mut ss = ControlStopSign::new(map, i);
+    if translated_must_stop.len() != ss.roads.len() {
+        return Err(format!(
+            "Stop sign has {} roads now, but {} from edits",
+            ss.roads.len(),
+            translated_must_stop.len()
+        ));
+    }
+    for (r, stop) in translated_must_stop {
+        if let Some(road) = ss.roads.get_mut(&r) {
+            road.must_stop = stop;
+        } else {
+            return Err(format!("{} doesn't connect to {}", i, r));
+        }
+    }
+    Ok(ss)
+}
@@ -32,11 +32,13 @@ use abstutil::{deserialize_btreemap, serialize_btreemap, MapName};
 use geom::{Bounds, Distance, GPSBounds, Polygon};
 
 pub use crate::city::City;
+pub use crate::corridor::{GreenWave, SignalCorridor};
 pub use crate::edits::{
     EditCmd, EditEffects, EditIntersection, EditRoad, MapEdits, PermanentMapEdits,
 };
 pub use crate::map::{DrivingSide, MapConfig};
 pub use crate::objects::area::{Area, AreaID, AreaType};
+pub use crate::objects::bike_share::{BikeShareStation, BikeShareStationID};
 pub use crate::objects::building::{
     Amenity, Building, BuildingID, BuildingType, NamePerLanguage, OffstreetParking,
 };
@@ -48,18 +50,23 @@ pub use crate::objects::lane::{
 pub use crate::objects::parking_lot::{ParkingLot, ParkingLotID};
 pub use crate::objects::road::{DirectedRoadID, Direction, Road, RoadID};
 pub use crate::objects::stop_signs::{ControlStopSign, RoadWithStopSign};
-pub use crate::objects::traffic_signals::{ControlTrafficSignal, PhaseType, Stage};
+pub use crate::objects::time_window::TimeWindow;
+pub use crate::objects::traffic_signals::{
+    ActuatedTiming, ControlTrafficSignal, ExportedTrafficSignal, ExportedTransitPriority,
+    PedestrianTiming, PhaseType, Stage, TransitPriority,
+};
 pub use crate::objects::turn::{
     CompressedMovementID, Movement, MovementID, Turn, TurnID, TurnPriority, TurnType,
 };
-pub use crate::objects::zone::{AccessRestrictions, Zone};
+pub use crate::objects::zone::{AccessRestrictions, ConditionalAccess, Zone};
 pub use crate::pathfind::uber_turns::{IntersectionCluster, UberTurn, UberTurnGroup};
 use crate::pathfind::Pathfinder;
-pub use crate::pathfind::{Path, PathConstraints, PathRequest, PathStep};
+pub use crate::pathfind::{Path, PathConstraints, PathRequest, PathStep, TravelTimes};
 pub use crate::traversable::{Position, Traversable};
 
 mod city;
 pub mod connectivity;
+mod corridor;
 mod edits;
 mod make;
 mod map;
@@ -96,6 +103,7 @@ pub struct Map {
     bus_routes: Vec<BusRoute>,
     areas: Vec<Area>,
     parking_lots: Vec<ParkingLot>,
+    bike_share_stations: Vec<BikeShareStation>,
     boundary_polygon: Polygon,
 
     // Note that border nodes belong in neither!
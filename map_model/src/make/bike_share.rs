@@ -0,0 +1,77 @@
+use std::collections::HashSet;
+
+use abstutil::Timer;
+use geom::{Distance, HashablePt2D};
+
+use crate::make::match_points_to_lanes;
+use crate::objects::building::sidewalk_to_bike;
+use crate::raw::RawBikeShareStation;
+use crate::{BikeShareStation, BikeShareStationID, Map};
+
+/// Match bike share stations to the nearest sidewalk, and find where bikes enter and leave the
+/// road network next to it.
+pub fn make_stations(
+    input: &Vec<RawBikeShareStation>,
+    map: &Map,
+    timer: &mut Timer,
+) -> Vec<BikeShareStation> {
+    if input.is_empty() {
+        return Vec::new();
+    }
+
+    let mut query: HashSet<HashablePt2D> = HashSet::new();
+    for station in input {
+        query.insert(station.point.to_hashable());
+    }
+    let sidewalk_pts = match_points_to_lanes(
+        map.get_bounds(),
+        query,
+        map.all_lanes(),
+        |l| l.is_walkable(),
+        // Like buildings, don't put stations too close to intersections
+        Distance::meters(7.5),
+        // Stations are mapped as points right next to the sidewalk; anything further is probably
+        // outside the map
+        Distance::meters(100.0),
+        timer,
+    );
+
+    let mut results = Vec::new();
+    for station in input {
+        let sidewalk_pos = match sidewalk_pts.get(&station.point.to_hashable()) {
+            Some(pos) => *pos,
+            None => {
+                timer.warn(format!(
+                    "Skipping bike share station {} ({}), because it's not near a sidewalk",
+                    station.name, station.external_id
+                ));
+                continue;
+            }
+        };
+        let biking_pos = match sidewalk_to_bike(sidewalk_pos, map) {
+            Some((pos, _)) => pos,
+            None => {
+                timer.warn(format!(
+                    "Skipping bike share station {} ({}), because it's not next to a road bikes \
+                     can use",
+                    station.name, station.external_id
+                ));
+                continue;
+            }
+        };
+        results.push(BikeShareStation {
+            id: BikeShareStationID(results.len()),
+            name: station.name.clone(),
+            external_id: station.external_id.clone(),
+            point: station.point,
+            capacity: station.capacity,
+            initial_bikes: station
+                .initial_bikes
+                .unwrap_or(station.capacity / 2)
+                .min(station.capacity),
+            sidewalk_pos,
+            biking_pos,
+        });
+    }
+    results
+}
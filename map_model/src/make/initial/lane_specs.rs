@@ -1,11 +1,12 @@
 /// Purely from OSM tags, determine the lanes that a road segment has.
+use std::collections::BTreeSet;
 use std::iter;
 
 use abstutil::Tags;
 use geom::Distance;
 
 use crate::{
-    osm, Direction, DrivingSide, LaneType, MapConfig, NORMAL_LANE_THICKNESS,
+    osm, Direction, DrivingSide, LaneType, MapConfig, TimeWindow, TurnType, NORMAL_LANE_THICKNESS,
     SERVICE_ROAD_LANE_THICKNESS, SHOULDER_THICKNESS, SIDEWALK_THICKNESS,
 };
 
@@ -14,6 +15,10 @@ pub struct LaneSpec {
     pub lt: LaneType,
     pub dir: Direction,
     pub width: Distance,
+    /// From `*:conditional` tags
+    pub conditional_types: Vec<(TimeWindow, LaneType)>,
+    /// From `turn:lanes`. None means any turn is allowed.
+    pub turns: Option<BTreeSet<TurnType>>,
 }
 
 fn fwd(lt: LaneType) -> LaneSpec {
@@ -25,6 +30,8 @@ fn fwd(lt: LaneType) -> LaneSpec {
             LaneType::Shoulder => SHOULDER_THICKNESS,
             _ => NORMAL_LANE_THICKNESS,
         },
+        conditional_types: Vec::new(),
+        turns: None,
     }
 }
 
@@ -37,6 +44,8 @@ fn back(lt: LaneType) -> LaneSpec {
             LaneType::Shoulder => SHOULDER_THICKNESS,
             _ => NORMAL_LANE_THICKNESS,
         },
+        conditional_types: Vec::new(),
+        turns: None,
     }
 }
 
@@ -177,6 +186,33 @@ pub fn get_lane_specs_ltr(tags: &Tags, cfg: &MapConfig) -> Vec<LaneSpec> {
         }
     }
 
+    // Peak-hour bus lanes
+    let fwd_bus_conditional = tags
+        .get("bus:lanes:forward:conditional")
+        .or_else(|| tags.get("psv:lanes:forward:conditional"))
+        .or_else(|| {
+            if oneway {
+                tags.get("bus:lanes:conditional")
+                    .or_else(|| tags.get("psv:lanes:conditional"))
+            } else {
+                None
+            }
+        });
+    if let Some(value) = fwd_bus_conditional {
+        let offset = if fwd_side[0].lt == LaneType::SharedLeftTurn {
+            1
+        } else {
+            0
+        };
+        conditional_bus_lanes(value, &mut fwd_side[offset..]);
+    }
+    if let Some(value) = tags
+        .get("bus:lanes:backward:conditional")
+        .or_else(|| tags.get("psv:lanes:backward:conditional"))
+    {
+        conditional_bus_lanes(value, &mut back_side);
+    }
+
     if tags.is_any("cycleway", vec!["lane", "track"]) {
         fwd_side.push(fwd(LaneType::Biking));
         if !back_side.is_empty() {
@@ -214,10 +250,16 @@ pub fn get_lane_specs_ltr(tags: &Tags, cfg: &MapConfig) -> Vec<LaneSpec> {
         let parking_lane_back = tags.is_any(osm::PARKING_LEFT, has_parking.clone())
             || tags.is_any(osm::PARKING_BOTH, has_parking);
         if parking_lane_fwd {
-            fwd_side.push(fwd(LaneType::Parking));
+            let mut spec = fwd(LaneType::Parking);
+            spec.conditional_types =
+                conditional_parking(tags, vec![osm::PARKING_RIGHT, osm::PARKING_BOTH]);
+            fwd_side.push(spec);
         }
         if parking_lane_back {
-            back_side.push(back(LaneType::Parking));
+            let mut spec = back(LaneType::Parking);
+            spec.conditional_types =
+                conditional_parking(tags, vec![osm::PARKING_LEFT, osm::PARKING_BOTH]);
+            back_side.push(spec);
         }
     }
 
@@ -252,6 +294,26 @@ pub fn get_lane_specs_ltr(tags: &Tags, cfg: &MapConfig) -> Vec<LaneSpec> {
         }
     }
 
+    // Turn lanes only apply where the original OSM way ends; it may have been split into several
+    // roads.
+    let fwd_turns = if tags.contains_key(osm::ENDPT_FWD) {
+        tags.get("turn:lanes:forward")
+            .or_else(|| tags.get("turn:lanes"))
+    } else {
+        None
+    };
+    let back_turns = if tags.contains_key(osm::ENDPT_BACK) {
+        tags.get("turn:lanes:backward")
+    } else {
+        None
+    };
+    let fwd_widths = tags
+        .get("width:lanes:forward")
+        .or_else(|| tags.get("width:lanes"));
+    let back_widths = tags.get("width:lanes:backward");
+    per_lane_tags(fwd_turns, fwd_widths, &mut fwd_side, cfg.driving_side);
+    per_lane_tags(back_turns, back_widths, &mut back_side, cfg.driving_side);
+
     let mut need_fwd_shoulder = fwd_side
         .last()
         .map(|spec| spec.lt != LaneType::Sidewalk)
@@ -287,6 +349,141 @@ pub fn get_lane_specs_ltr(tags: &Tags, cfg: &MapConfig) -> Vec<LaneSpec> {
     assemble_ltr(fwd_side, back_side, cfg.driving_side)
 }
 
+// Something like "|designated @ (Mo-Fr 07:00-09:00)". The driving lanes must already be filled
+// out, in the same order as the lanes in the tag.
+fn conditional_bus_lanes(value: &str, lanes: &mut [LaneSpec]) {
+    for (spec, windows) in TimeWindow::parse_osm_conditional(value) {
+        let parts: Vec<&str> = spec.split("|").collect();
+        if parts.len() != lanes.len() {
+            continue;
+        }
+        for (idx, part) in parts.into_iter().enumerate() {
+            if part == "designated" && lanes[idx].lt == LaneType::Driving {
+                for window in &windows {
+                    lanes[idx].conditional_types.push((*window, LaneType::Bus));
+                }
+            }
+        }
+    }
+}
+
+// Values like "left|through;right" for turn:lanes or "3.5|3" for width:lanes. Like `lanes`, these
+// only count the lanes for motor vehicles, from left to right in the direction of travel. If the
+// number of parts doesn't match, the tag is ignored.
+fn per_lane_tags(
+    turns: Option<&String>,
+    widths: Option<&String>,
+    side: &mut [LaneSpec],
+    driving_side: DrivingSide,
+) {
+    // The sides are ordered from the road center going outwards
+    let mut lanes: Vec<&mut LaneSpec> = side
+        .iter_mut()
+        .filter(|spec| spec.lt == LaneType::Driving || spec.lt == LaneType::Bus)
+        .collect();
+    if driving_side == DrivingSide::Left {
+        lanes.reverse();
+    }
+
+    if let Some(value) = turns {
+        let parts: Vec<&str> = value.split('|').collect();
+        if parts.len() == lanes.len() {
+            for (spec, part) in lanes.iter_mut().zip(parts) {
+                spec.turns = parse_turn_lane(part);
+            }
+        }
+    }
+    if let Some(value) = widths {
+        let parts: Vec<&str> = value.split('|').collect();
+        if parts.len() == lanes.len() {
+            for (spec, part) in lanes.iter_mut().zip(parts) {
+                if let Some(width) = parse_width(part) {
+                    spec.width = width;
+                }
+            }
+        }
+    }
+}
+
+fn parse_turn_lane(part: &str) -> Option<BTreeSet<TurnType>> {
+    // TODO Probably the target lane should get marked as LaneType::Bus
+    if part == "no" || part == "none" || part == "yes" || part == "psv" || part == "bus" {
+        return None;
+    }
+    // Empty means no restrictions
+    if part.is_empty() {
+        return None;
+    }
+    let mut types = BTreeSet::new();
+    for s in part.split(';') {
+        match s {
+            "left" | "left\\left" => {
+                types.insert(TurnType::Left);
+            }
+            "right" => {
+                types.insert(TurnType::Right);
+            }
+            // TODO What is blank supposed to mean? From few observed cases, same as through
+            "through" | "" => {
+                types.insert(TurnType::Straight);
+            }
+            // TODO Check this more carefully
+            "slight_right" | "slight right" | "merge_to_right" | "sharp_right" => {
+                types.insert(TurnType::Straight);
+                types.insert(TurnType::Right);
+            }
+            "slight_left" | "slight left" | "merge_to_left" | "sharp_left" => {
+                types.insert(TurnType::Straight);
+                types.insert(TurnType::Left);
+            }
+            // TODO We need TurnType::UTurn. Until then, u-turns usually show up as left turns.
+            "reverse" => {
+                types.insert(TurnType::Left);
+            }
+            s => {
+                warn!("Unknown turn restriction {}", s);
+            }
+        }
+    }
+    if types.is_empty() {
+        None
+    } else {
+        Some(types)
+    }
+}
+
+// In meters, like "3.5" or "3.5 m". Anything implausible for one lane is skipped.
+fn parse_width(part: &str) -> Option<Distance> {
+    let meters = part
+        .trim()
+        .trim_end_matches('m')
+        .trim()
+        .parse::<f64>()
+        .ok()?;
+    if (1.0..=10.0).contains(&meters) {
+        Some(Distance::meters(meters))
+    } else {
+        None
+    }
+}
+
+// A parking lane where stopping is banned at rush hour acts as another travel lane then.
+fn conditional_parking(tags: &Tags, keys: Vec<&str>) -> Vec<(TimeWindow, LaneType)> {
+    let mut results = Vec::new();
+    for key in keys {
+        if let Some(value) = tags.get(&format!("{}:conditional", key)) {
+            for (restriction, windows) in TimeWindow::parse_osm_conditional(value) {
+                if restriction == "no_stopping" || restriction == "no_parking" {
+                    for window in windows {
+                        results.push((window, LaneType::Driving));
+                    }
+                }
+            }
+        }
+    }
+    results
+}
+
 fn assemble_ltr(
     mut fwd_side: Vec<LaneSpec>,
     mut back_side: Vec<LaneSpec>,
@@ -466,4 +663,107 @@ mod tests {
         }
         assert!(ok);
     }
+
+    #[test]
+    fn test_conditional_lanes() {
+        let cfg = MapConfig {
+            driving_side: DrivingSide::Right,
+            bikes_can_use_bus_lanes: true,
+            inferred_sidewalks: true,
+        };
+        let actual = get_lane_specs_ltr(
+            &tags(vec![
+                "lanes=2",
+                "oneway=yes",
+                "sidewalk=both",
+                "parking:lane:right=parallel",
+                "parking:lane:right:conditional=no_stopping @ (Mo-Fr 16:00-18:00)",
+                "bus:lanes:conditional=|designated @ (Mo-Fr 07:00-09:00)",
+            ]),
+            &cfg,
+        );
+        let summary = actual
+            .iter()
+            .map(|s| {
+                let mut x = lt_to_char(s.lt).to_string();
+                for (window, lt) in &s.conditional_types {
+                    x.push_str(&format!(" {}={}", window, lt_to_char(*lt)));
+                }
+                x
+            })
+            .collect::<Vec<_>>();
+        assert_eq!(
+            summary,
+            vec!["s", "d", "d 07:00-09:00=B", "p 16:00-18:00=d", "s"]
+        );
+    }
+
+    #[test]
+    fn test_turn_and_width_lanes() {
+        let summarize = |input: Vec<&str>, driving_side| {
+            let cfg = MapConfig {
+                driving_side,
+                bikes_can_use_bus_lanes: true,
+                inferred_sidewalks: false,
+            };
+            get_lane_specs_ltr(&tags(input), &cfg)
+                .into_iter()
+                .map(|s| {
+                    let turns = match s.turns {
+                        Some(types) => format!("{:?}", types.into_iter().collect::<Vec<_>>()),
+                        None => "any".to_string(),
+                    };
+                    format!("{} {} {}", lt_to_char(s.lt), s.width.inner_meters(), turns)
+                })
+                .collect::<Vec<_>>()
+        };
+
+        assert_eq!(
+            summarize(
+                vec![
+                    "lanes=3",
+                    "oneway=yes",
+                    "abst:endpt_fwd=true",
+                    "turn:lanes=left|through|through;right",
+                    "width:lanes=3|3.5 m|4",
+                ],
+                DrivingSide::Right
+            ),
+            vec!["d 3 [Left]", "d 3.5 [Straight]", "d 4 [Straight, Right]"]
+        );
+        // Left-handed driving lists lanes in the same order, but the road center is on the right
+        assert_eq!(
+            summarize(
+                vec![
+                    "lanes=2",
+                    "oneway=yes",
+                    "abst:endpt_fwd=true",
+                    "turn:lanes=through|right",
+                    "width:lanes=3|bogus",
+                ],
+                DrivingSide::Left
+            ),
+            vec!["d 3 [Straight]", "d 2.5 [Right]"]
+        );
+        // Turn lanes only apply where the original way ends, and must match the number of lanes
+        assert_eq!(
+            summarize(
+                vec!["lanes=2", "oneway=yes", "turn:lanes=left|through"],
+                DrivingSide::Right
+            ),
+            vec!["d 2.5 any", "d 2.5 any"]
+        );
+        assert_eq!(
+            summarize(
+                vec![
+                    "lanes=2",
+                    "oneway=yes",
+                    "abst:endpt_fwd=true",
+                    "turn:lanes=left"
+                ],
+                DrivingSide::Right
+            ),
+            vec!["d 2.5 any", "d 2.5 any"]
+        );
+    }
 }
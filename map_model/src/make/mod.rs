@@ -14,6 +14,7 @@ use crate::{
     Map, MapEdits, Movement, PathConstraints, Position, Road, RoadID, Zone,
 };
 
+mod bike_share;
 mod bridges;
 mod buildings;
 pub mod initial;
@@ -51,6 +52,7 @@ impl Map {
             bus_routes: Vec::new(),
             areas: Vec::new(),
             parking_lots: Vec::new(),
+            bike_share_stations: Vec::new(),
             zones: Vec::new(),
             boundary_polygon: raw.boundary_polygon.clone(),
             stop_signs: BTreeMap::new(),
@@ -103,22 +105,25 @@ impl Map {
                 turn_restrictions: raw.roads[&r.id]
                     .turn_restrictions
                     .iter()
-                    .filter_map(|(rt, to)| {
+                    .filter_map(|(rt, to, scope)| {
                         // Missing roads are filtered (like service roads) or clipped out
-                        road_id_mapping.get(to).map(|to| (*rt, *to))
+                        road_id_mapping.get(to).map(|to| (*rt, *to, scope.clone()))
                     })
                     .collect(),
                 complicated_turn_restrictions: raw.roads[&r.id]
                     .complicated_turn_restrictions
                     .iter()
-                    .filter_map(|(via, to)| {
-                        if let (Some(via), Some(to)) =
-                            (road_id_mapping.get(via), road_id_mapping.get(to))
-                        {
-                            Some((*via, *to))
+                    .filter_map(|(rt, via, to, scope)| {
+                        let via_ids: Option<Vec<RoadID>> = via
+                            .iter()
+                            .map(|r| road_id_mapping.get(r).cloned())
+                            .collect();
+                        if let (Some(via_ids), Some(to)) = (via_ids, road_id_mapping.get(to)) {
+                            Some((*rt, via_ids, *to, scope.clone()))
                         } else {
                             timer.warn(format!(
-                                "Complicated turn restriction from {} has invalid via {} or dst {}",
+                                "Complicated turn restriction from {} has invalid via {:?} or dst \
+                                 {}",
                                 r.id, via, to
                             ));
                             None
@@ -161,6 +166,7 @@ impl Map {
                 .shift_left(r.half_width)
                 .unwrap_or_else(|_| road.center_pts.clone());
 
+            let road_grade = road.percent_grade(&map);
             let mut width_so_far = Distance::ZERO;
             for lane in &r.lane_specs_ltr {
                 let id = LaneID(map.lanes.len());
@@ -193,9 +199,16 @@ impl Map {
                     id,
                     lane_center_pts,
                     width: lane.width,
+                    percent_grade: if lane.dir == Direction::Fwd {
+                        road_grade
+                    } else {
+                        -road_grade
+                    },
                     src_i,
                     dst_i,
                     lane_type: lane.lt,
+                    conditional_types: lane.conditional_types.clone(),
+                    allowed_turns: lane.turns.clone(),
                     parent: road_id,
                     bus_stops: BTreeSet::new(),
                     driving_blackhole: false,
@@ -230,6 +243,14 @@ impl Map {
                     i.intersection_type = IntersectionType::StopSign;
                 }
             }
+            if i.intersection_type == IntersectionType::StopSign {
+                for r in &i.roads {
+                    if map.roads[r.0].is_roundabout() {
+                        i.intersection_type = IntersectionType::Roundabout;
+                        break;
+                    }
+                }
+            }
         }
 
         let mut all_turns = Vec::new();
@@ -271,6 +292,8 @@ impl Map {
             timer,
         );
 
+        map.bike_share_stations = bike_share::make_stations(&raw.bike_share_stations, &map, timer);
+
         map.zones = Zone::make_all(&map);
 
         // Create medians first, so they wind up rendering underneath areas from OSM. Sometimes
@@ -303,6 +326,9 @@ impl Map {
                 IntersectionType::StopSign => {
                     stop_signs.insert(i.id, ControlStopSign::new(&map, i.id));
                 }
+                IntersectionType::Roundabout => {
+                    stop_signs.insert(i.id, ControlStopSign::roundabout(&map, i.id));
+                }
                 IntersectionType::TrafficSignal => match Movement::for_i(i.id, &map) {
                     Ok(_) => {
                         traffic_signals.insert(i.id, ControlTrafficSignal::new(&map, i.id, timer));
@@ -323,7 +349,9 @@ impl Map {
         map.traffic_signals = traffic_signals;
         // Fix up the type for any problematic traffic signals
         for i in map.stop_signs.keys() {
-            map.intersections[i.0].intersection_type = IntersectionType::StopSign;
+            if map.intersections[i.0].intersection_type == IntersectionType::TrafficSignal {
+                map.intersections[i.0].intersection_type = IntersectionType::StopSign;
+            }
         }
 
         traffic_signals::synchronize(&mut map);
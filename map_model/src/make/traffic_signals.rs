@@ -1,11 +1,11 @@
-use std::collections::HashSet;
+use std::collections::{BTreeMap, HashSet};
 
 use abstutil::Timer;
 use geom::Duration;
 
 use crate::{
-    ControlTrafficSignal, IntersectionCluster, IntersectionID, Map, Movement, MovementID,
-    PhaseType, RoadID, Stage, TurnPriority, TurnType,
+    ControlTrafficSignal, ExportedTrafficSignal, IntersectionCluster, IntersectionID, Map,
+    Movement, MovementID, PhaseType, RoadID, Stage, TurnPriority, TurnType,
 };
 
 /// Applies a bunch of heuristics to a single intersection, returning the valid results in
@@ -24,7 +24,13 @@ pub fn get_possible_policies(
         .unwrap()
         .remove(&map.get_i(id).orig_id.0)
     {
-        match ControlTrafficSignal::import(raw, id, map) {
+        let exported = ExportedTrafficSignal {
+            raw,
+            actuated: BTreeMap::new(),
+            transit_priority: None,
+            pedestrian_timing: None,
+        };
+        match ControlTrafficSignal::import(exported, id, map) {
             Ok(ts) => {
                 results.push(("hand-mapped current real settings".to_string(), ts));
             }
@@ -95,6 +101,8 @@ fn new(id: IntersectionID, map: &Map) -> ControlTrafficSignal {
         stages: Vec::new(),
         offset: Duration::ZERO,
         movements: Movement::for_i(id, map).unwrap(),
+        transit_priority: None,
+        pedestrian_timing: None,
     }
 }
 
@@ -5,7 +5,6 @@ use nbez::{Bez3o, BezCurve, Point2d};
 use abstutil::Timer;
 use geom::{Distance, PolyLine, Pt2D};
 
-use crate::raw::RestrictionType;
 use crate::{Intersection, Lane, LaneID, Map, RoadID, Turn, TurnID, TurnType};
 
 /// Generate all driving and walking turns at an intersection, accounting for OSM turn restrictions.
@@ -131,10 +130,7 @@ fn ensure_unique(turns: Vec<Turn>) -> Vec<Turn> {
 }
 
 fn is_turn_allowed(turn: &Turn, map: &Map) -> bool {
-    if let Some(types) = map
-        .get_l(turn.id.src)
-        .get_turn_restrictions(map.get_parent(turn.id.src))
-    {
+    if let Some(types) = map.get_l(turn.id.src).get_turn_restrictions() {
         types.contains(&turn.turn_type)
     } else {
         true
@@ -146,44 +142,29 @@ fn does_turn_pass_restrictions(turn: &Turn, i: &Intersection, map: &Map) -> bool
         return true;
     }
 
+    // Restrictions that only apply to some vehicles or during part of the day keep the turn; the
+    // pathfinders handle those.
     let src = map.get_parent(turn.id.src);
     let dst = map.get_l(turn.id.dst).parent;
-
-    for (restriction, to) in &src.turn_restrictions {
-        // The restriction only applies to one direction of the road.
-        if !i.roads.contains(to) {
-            continue;
-        }
-        match restriction {
-            RestrictionType::BanTurns => {
-                if dst == *to {
-                    return false;
-                }
-            }
-            RestrictionType::OnlyAllowTurns => {
-                if dst != *to {
-                    return false;
-                }
-            }
-        }
-    }
-
-    true
+    !src.broken_turn_restrictions(i, dst)
+        .into_iter()
+        .any(|scope| scope.is_unconditional())
 }
 
 fn make_vehicle_turns(i: &Intersection, map: &Map, timer: &mut Timer) -> Vec<Turn> {
     let mut turns = Vec::new();
 
     // Just generate every possible combination of turns between incoming and outgoing lanes.
+    // Lanes only used by vehicles during part of the day still need turns.
     let is_deadend = i.roads.len() == 1;
     for src in &i.incoming_lanes {
         let src = map.get_l(*src);
-        if !src.lane_type.is_for_moving_vehicles() {
+        if !src.ever_for_moving_vehicles() {
             continue;
         }
         for dst in &i.outgoing_lanes {
             let dst = map.get_l(*dst);
-            if !dst.lane_type.is_for_moving_vehicles() {
+            if !dst.ever_for_moving_vehicles() {
                 continue;
             }
             // Only allow U-turns at deadends
@@ -333,10 +314,25 @@ fn remove_merging_turns(map: &Map, input: Vec<Turn>, turn_type: TurnType) -> Vec
             continue;
         }
 
+        // If turn lanes explicitly have something like "left|left|", then there are multiple
+        // source lanes.
+        let marked: HashSet<LaneID> = group
+            .iter()
+            .map(|t| t.id.src)
+            .filter(|l| {
+                map.get_l(*l)
+                    .get_turn_restrictions()
+                    .map(|types| types.contains(&turn_type))
+                    .unwrap_or(false)
+            })
+            .collect();
+        if marked.len() > 1 {
+            turns.extend(group.into_iter().filter(|t| marked.contains(&t.id.src)));
+            continue;
+        }
+
         // We have multiple lanes all with a turn to the same destination road. Most likely, only
         // the rightmost or leftmost can actually make the turn.
-        // TODO If OSM turn restrictions explicitly have something like "left|left|", then there
-        // are multiple source lanes!
         let road = map.get_parent(group[0].id.src);
         let src = if turn_type == TurnType::Right {
             group
@@ -6,14 +6,15 @@ use petgraph::graphmap::UnGraphMap;
 use serde::{Deserialize, Serialize};
 
 use abstutil::{MapName, Timer};
-use geom::{Bounds, Distance, GPSBounds, Polygon, Pt2D, Ring, Time};
+use geom::{Bounds, Distance, Duration, GPSBounds, Polygon, Pt2D, Ring, Time};
 
 use crate::raw::{OriginalRoad, RawMap};
 use crate::{
-    osm, Area, AreaID, Building, BuildingID, BuildingType, BusRoute, BusRouteID, BusStop,
-    BusStopID, ControlStopSign, ControlTrafficSignal, Intersection, IntersectionID, Lane, LaneID,
-    LaneType, Map, MapEdits, MovementID, OffstreetParking, ParkingLot, ParkingLotID, Path,
-    PathConstraints, PathRequest, Pathfinder, Position, Road, RoadID, Turn, TurnID, TurnType, Zone,
+    osm, Area, AreaID, BikeShareStation, BikeShareStationID, Building, BuildingID, BuildingType,
+    BusRoute, BusRouteID, BusStop, BusStopID, ControlStopSign, ControlTrafficSignal, Intersection,
+    IntersectionID, Lane, LaneID, LaneType, Map, MapEdits, MovementID, OffstreetParking,
+    ParkingLot, ParkingLotID, Path, PathConstraints, PathRequest, Pathfinder, Position, Road,
+    RoadID, Turn, TurnID, TurnType, Zone,
 };
 
 #[derive(Clone, Debug, Serialize, Deserialize)]
@@ -83,6 +84,11 @@ impl Map {
                             prettyprint_usize(map.parking_lots.len()),
                             prettyprint_usize(serialized_size_bytes(&map.parking_lots))
                         );
+                        info!(
+                            "- {} bike share stations: {} bytes",
+                            prettyprint_usize(map.bike_share_stations.len()),
+                            prettyprint_usize(serialized_size_bytes(&map.bike_share_stations))
+                        );
                         info!(
                             "- {} zones: {} bytes",
                             prettyprint_usize(map.zones.len()),
@@ -147,6 +153,7 @@ impl Map {
             bus_routes: Vec::new(),
             areas: Vec::new(),
             parking_lots: Vec::new(),
+            bike_share_stations: Vec::new(),
             zones: Vec::new(),
             boundary_polygon: Ring::must_new(vec![
                 Pt2D::new(0.0, 0.0),
@@ -174,6 +181,14 @@ impl Map {
         }
     }
 
+    /// A full copy of the map, including its edits. The pathfinder isn't cheap to clone, so this
+    /// goes through the same format used to save maps.
+    pub fn deep_clone(&self) -> Map {
+        let mut map: Map = abstutil::from_binary(&abstutil::to_binary(self)).unwrap();
+        map.edits = self.edits.clone();
+        map
+    }
+
     pub fn all_roads(&self) -> &Vec<Road> {
         &self.roads
     }
@@ -202,10 +217,32 @@ impl Map {
         &self.parking_lots
     }
 
+    pub fn all_bike_share_stations(&self) -> &Vec<BikeShareStation> {
+        &self.bike_share_stations
+    }
+
     pub fn all_zones(&self) -> &Vec<Zone> {
         &self.zones
     }
 
+    /// Every time of day when some lane changes type or some road's access restrictions change.
+    pub fn time_restriction_changes(&self) -> BTreeSet<Time> {
+        let mut times = BTreeSet::new();
+        for l in &self.lanes {
+            for (window, _) in &l.conditional_types {
+                times.insert(window.start);
+                times.insert(window.end);
+            }
+        }
+        for r in &self.roads {
+            for c in &r.access_restrictions.conditional {
+                times.insert(c.window.start);
+                times.insert(c.window.end);
+            }
+        }
+        times
+    }
+
     pub fn maybe_get_r(&self, id: RoadID) -> Option<&Road> {
         self.roads.get(id.0)
     }
@@ -283,6 +320,10 @@ impl Map {
         &self.parking_lots[id.0]
     }
 
+    pub fn get_bss(&self, id: BikeShareStationID) -> &BikeShareStation {
+        &self.bike_share_stations[id.0]
+    }
+
     pub fn get_stop_sign(&self, id: IntersectionID) -> &ControlStopSign {
         &self.stop_signs[&id]
     }
@@ -372,7 +413,9 @@ impl Map {
         let mut turns: Vec<&Turn> = self
             .get_next_turns_and_lanes(from, self.get_l(from).dst_i)
             .into_iter()
-            .filter(|(_, l)| constraints.can_use(l, self))
+            .filter(|(t, l)| {
+                constraints.can_use(l, self) && !self.is_turn_restricted(t, constraints, None)
+            })
             .map(|(t, _)| t)
             .collect();
         // Sidewalks are bidirectional
@@ -387,6 +430,24 @@ impl Map {
         turns
     }
 
+    /// Does a turn restriction that only applies to some vehicles or during part of the day ban
+    /// this turn? With no time, only restrictions that apply all day count. Restrictions applying
+    /// to everybody all the time don't have a turn generated in the first place.
+    pub fn is_turn_restricted(
+        &self,
+        t: &Turn,
+        constraints: PathConstraints,
+        time: Option<Time>,
+    ) -> bool {
+        if t.between_sidewalks() {
+            return false;
+        }
+        self.get_parent(t.id.src)
+            .broken_turn_restrictions(self.get_i(t.id.parent), self.get_l(t.id.dst).parent)
+            .into_iter()
+            .any(|scope| scope.applies(constraints, time))
+    }
+
     pub fn get_next_roads(&self, from: RoadID) -> BTreeSet<RoadID> {
         let mut roads: BTreeSet<RoadID> = BTreeSet::new();
         let r = self.get_r(from);
@@ -544,13 +605,25 @@ impl Map {
         assert!(!self.pathfinder_dirty);
         self.pathfinder.pathfind(req, self)
     }
+    /// Like `pathfind_at`, but never uses the banned lanes, and entering some lanes costs extra.
+    /// This builds a new graph every time, so it's much slower. Only for cars.
     pub fn pathfind_avoiding_lanes(
         &self,
         req: PathRequest,
-        avoid: BTreeSet<LaneID>,
+        departure: Time,
+        banned: BTreeSet<LaneID>,
+        extra_costs: BTreeMap<LaneID, Duration>,
     ) -> Option<Path> {
         assert!(!self.pathfinder_dirty);
-        self.pathfinder.pathfind_avoiding_lanes(req, avoid, self)
+        self.pathfinder
+            .pathfind_avoiding_lanes(req, departure, banned, extra_costs, self)
+    }
+    /// Like `pathfind`, but respects lane types and access restrictions that only apply during
+    /// part of the day. They're checked at the departure time, so a vehicle may keep using a lane
+    /// that closes after it sets off.
+    pub fn pathfind_at(&self, req: PathRequest, departure: Time) -> Option<Path> {
+        assert!(!self.pathfinder_dirty);
+        self.pathfinder.pathfind_at(req, departure, self)
     }
 
     pub fn should_use_transit(
@@ -561,6 +634,14 @@ impl Map {
         self.pathfinder.should_use_transit(self, start, end)
     }
 
+    pub fn should_use_park_and_ride(
+        &self,
+        start: Position,
+        end: Position,
+    ) -> Option<(ParkingLotID, BusStopID, Option<BusStopID>, BusRouteID)> {
+        self.pathfinder.should_use_park_and_ride(self, start, end)
+    }
+
     // None for SharedSidewalkCorners
     pub fn get_movement(&self, t: TurnID) -> Option<MovementID> {
         if let Some(ref ts) = self.maybe_get_traffic_signal(t.parent) {
@@ -637,6 +718,10 @@ impl Map {
         self.bus_routes[br.0].spawn_times = times;
     }
 
+    pub fn hack_override_gtfs_trip_marker(&mut self, br: BusRouteID, marker: String) {
+        self.bus_routes[br.0].gtfs_trip_marker = Some(marker);
+    }
+
     pub fn get_languages(&self) -> BTreeSet<&str> {
         let mut languages = BTreeSet::new();
         for r in self.all_roads() {
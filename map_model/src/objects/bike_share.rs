@@ -0,0 +1,43 @@
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use abstutil::{deserialize_usize, serialize_usize};
+use geom::Pt2D;
+
+use crate::Position;
+
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct BikeShareStationID(
+    #[serde(
+        serialize_with = "serialize_usize",
+        deserialize_with = "deserialize_usize"
+    )]
+    pub usize,
+);
+
+impl fmt::Display for BikeShareStationID {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "Bike share station #{}", self.0)
+    }
+}
+
+/// A dock where people can pick up and return bikes they don't own. Stations hold a fixed number
+/// of bikes; once every dock is full, nobody else can return a bike there.
+#[derive(Serialize, Deserialize, Clone, Debug)]
+pub struct BikeShareStation {
+    pub id: BikeShareStationID,
+    pub name: String,
+    /// From OSM or the bike share system's own feed, to match stations up with outside data
+    pub external_id: String,
+    pub point: Pt2D,
+    /// The total number of docks
+    pub capacity: usize,
+    /// How many bikes are docked at the start of the day
+    pub initial_bikes: usize,
+
+    /// Where people walk to and from the station
+    pub sidewalk_pos: Position,
+    /// Where bikes leave and arrive at the station
+    pub biking_pos: Position,
+}
@@ -29,6 +29,9 @@ pub enum IntersectionType {
     TrafficSignal,
     Border,
     Construction,
+    /// Like a stop sign, but roads that don't have priority only yield, instead of stopping
+    /// first. Where roads enter a roundabout, the roundabout itself has priority.
+    Roundabout,
 }
 
 /// An intersection connects roads. Most have >2 roads and are controlled by stop signs or traffic
@@ -79,6 +82,10 @@ impl Intersection {
         self.intersection_type == IntersectionType::TrafficSignal
     }
 
+    pub fn is_roundabout(&self) -> bool {
+        self.intersection_type == IntersectionType::Roundabout
+    }
+
     pub fn is_light_rail(&self, map: &Map) -> bool {
         self.roads.iter().all(|r| map.get_r(*r).is_light_rail())
     }
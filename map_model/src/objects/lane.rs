@@ -4,10 +4,10 @@ use std::fmt;
 use serde::{Deserialize, Serialize};
 
 use abstutil::{deserialize_usize, serialize_usize, wraparound_get};
-use geom::{Distance, Line, PolyLine, Polygon, Pt2D, Ring};
+use geom::{Distance, Line, PolyLine, Polygon, Pt2D, Ring, Time};
 
 use crate::{
-    osm, BusStopID, DirectedRoadID, Direction, IntersectionID, Map, Road, RoadID, TurnType,
+    BusStopID, DirectedRoadID, IntersectionID, Map, PathConstraints, RoadID, TimeWindow, TurnType,
 };
 
 /// Bit longer than the longest car.
@@ -110,8 +110,15 @@ pub struct Lane {
     pub id: LaneID,
     pub parent: RoadID,
     pub lane_type: LaneType,
+    /// During these parts of the day, the lane acts like a different type, like a parking lane
+    /// that becomes a travel lane at rush hour. The first matching window wins.
+    pub conditional_types: Vec<(TimeWindow, LaneType)>,
     pub lane_center_pts: PolyLine,
     pub width: Distance,
+    /// [-1.0, 1.0], going from src_i to dst_i. 0 is flat, positive is uphill.
+    pub percent_grade: f64,
+    /// From `turn:lanes` in OSM, or edited. Only used for driving and bus lanes.
+    pub allowed_turns: Option<BTreeSet<TurnType>>,
 
     pub src_i: IntersectionID,
     pub dst_i: IntersectionID,
@@ -221,6 +228,52 @@ impl Lane {
         self.lane_type == LaneType::LightRail
     }
 
+    /// How many times faster than on flat ground somebody moves along this lane, because of hills.
+    /// Pedestrians may walk against the lane's direction, which reverses the grade.
+    pub fn speed_factor(&self, constraints: PathConstraints, contraflow: bool) -> f64 {
+        let grade = if contraflow {
+            -self.percent_grade
+        } else {
+            self.percent_grade
+        };
+        match constraints {
+            // Tobler's hiking function, relative to flat ground. Walking is fastest going slightly
+            // downhill. Don't trust extreme grades too much.
+            PathConstraints::Pedestrian => (-3.5 * ((grade + 0.05).abs() - 0.05)).exp().max(0.1),
+            // A 5% climb takes a typical cyclist down to about 2/3 of their usual speed. Going
+            // downhill is faster, but people brake eventually.
+            PathConstraints::Bike => (1.0 - 6.5 * grade).max(0.25).min(1.5),
+            _ => 1.0,
+        }
+    }
+
+    /// The type of this lane at some time of day
+    pub fn lane_type_at(&self, time: Time) -> LaneType {
+        self.conditional_types
+            .iter()
+            .find(|(window, _)| window.contains(time))
+            .map(|(_, lt)| *lt)
+            .unwrap_or(self.lane_type)
+    }
+
+    /// Do vehicles ever move along this lane, during any part of the day?
+    pub fn ever_for_moving_vehicles(&self) -> bool {
+        self.all_lane_types()
+            .into_iter()
+            .any(|lt| lt.is_for_moving_vehicles())
+    }
+
+    /// Every type this lane has over the course of the day
+    pub fn all_lane_types(&self) -> Vec<LaneType> {
+        let mut types = vec![self.lane_type];
+        for (_, lt) in &self.conditional_types {
+            if !types.contains(lt) {
+                types.push(*lt);
+            }
+        }
+        types
+    }
+
     // TODO Store this natively if this winds up being useful.
     pub fn get_directed_parent(&self, map: &Map) -> DirectedRoadID {
         let r = map.get_r(self.parent);
@@ -230,69 +283,13 @@ impl Lane {
         }
     }
 
-    pub fn get_turn_restrictions(&self, road: &Road) -> Option<BTreeSet<TurnType>> {
-        if !self.is_driving() {
-            return None;
-        }
-
-        let dir = road.dir(self.id);
-        let all = if dir == Direction::Fwd && road.osm_tags.contains_key(osm::ENDPT_FWD) {
-            road.osm_tags
-                .get("turn:lanes:forward")
-                .or_else(|| road.osm_tags.get("turn:lanes"))?
-        } else if dir == Direction::Back && road.osm_tags.contains_key(osm::ENDPT_BACK) {
-            road.osm_tags.get("turn:lanes:backward")?
+    /// The turns vehicles starting from this lane may make. None means any turn.
+    pub fn get_turn_restrictions(&self) -> Option<&BTreeSet<TurnType>> {
+        if self.lane_type == LaneType::Driving || self.lane_type == LaneType::Bus {
+            self.allowed_turns.as_ref()
         } else {
-            return None;
-        };
-        let parts: Vec<&str> = all.split('|').collect();
-        // Verify the number of parts matches the road's lanes
-        let lanes: Vec<LaneID> = road
-            .children(dir)
-            .into_iter()
-            .filter(|(_, lt)| *lt == LaneType::Driving || *lt == LaneType::Bus)
-            .map(|(id, _)| id)
-            .collect();
-        if parts.len() != lanes.len() {
-            warn!("{}'s turn restrictions don't match the lanes", road.orig_id);
-            return None;
+            None
         }
-        // TODO More warnings if this fails
-        let part = parts[lanes.iter().position(|l| *l == self.id)?];
-        // TODO Probably the target lane should get marked as LaneType::Bus
-        if part == "no" || part == "none" || part == "yes" || part == "psv" || part == "bus" {
-            return None;
-        }
-        // Empty means no restrictions
-        if part == "" {
-            return None;
-        }
-        Some(
-            part.split(';')
-                .flat_map(|s| match s {
-                    "left" | "left\\left" => vec![TurnType::Left],
-                    "right" => vec![TurnType::Right],
-                    // TODO What is blank supposed to mean? From few observed cases, same as through
-                    "through" | "" => vec![TurnType::Straight],
-                    // TODO Check this more carefully
-                    "slight_right" | "slight right" | "merge_to_right" | "sharp_right" => {
-                        vec![TurnType::Straight, TurnType::Right]
-                    }
-                    "slight_left" | "slight left" | "merge_to_left" | "sharp_left" => {
-                        vec![TurnType::Straight, TurnType::Left]
-                    }
-                    "reverse" => {
-                        // TODO We need TurnType::UTurn. Until then, u-turns usually show up as
-                        // left turns.
-                        vec![TurnType::Left]
-                    }
-                    s => {
-                        warn!("Unknown turn restriction {}", s);
-                        vec![]
-                    }
-                })
-                .collect(),
-        )
     }
 
     /// Starting from this lane, follow the lane's left edge to the intersection, continuing to
@@ -1,4 +1,5 @@
 pub mod area;
+pub mod bike_share;
 pub mod building;
 pub mod bus_stop;
 pub mod intersection;
@@ -6,6 +7,7 @@ pub mod lane;
 pub mod parking_lot;
 pub mod road;
 pub mod stop_signs;
+pub mod time_window;
 pub mod traffic_signals;
 pub mod turn;
 pub mod zone;
@@ -6,10 +6,10 @@ use serde::{Deserialize, Serialize};
 use abstutil::{deserialize_usize, serialize_usize, Tags};
 use geom::{Distance, PolyLine, Polygon, Speed};
 
-use crate::raw::{OriginalRoad, RestrictionType};
+use crate::raw::{OriginalRoad, RestrictionScope, RestrictionType};
 use crate::{
-    osm, AccessRestrictions, BusStopID, IntersectionID, Lane, LaneID, LaneType, Map,
-    PathConstraints, Zone,
+    osm, AccessRestrictions, BusStopID, ConditionalAccess, Intersection, IntersectionID, Lane,
+    LaneID, LaneType, Map, PathConstraints, TimeWindow, Zone,
 };
 
 #[derive(Clone, Copy, Debug, Eq, Hash, PartialEq, PartialOrd, Ord, Serialize, Deserialize)]
@@ -94,10 +94,11 @@ impl DirectedRoadID {
 pub struct Road {
     pub id: RoadID,
     pub osm_tags: Tags,
-    /// self is 'from'
-    pub turn_restrictions: Vec<(RestrictionType, RoadID)>,
-    /// self is 'from'. (via, to). Only BanTurns.
-    pub complicated_turn_restrictions: Vec<(RoadID, RoadID)>,
+    /// self is 'from'. (restriction, to, scope)
+    pub turn_restrictions: Vec<(RestrictionType, RoadID, RestrictionScope)>,
+    /// self is 'from'. (restriction, via roads in order, to, scope)
+    pub complicated_turn_restrictions:
+        Vec<(RestrictionType, Vec<RoadID>, RoadID, RestrictionScope)>,
     pub orig_id: OriginalRoad,
     pub speed_limit: Speed,
     pub access_restrictions: AccessRestrictions,
@@ -324,6 +325,11 @@ impl Road {
         grade
     }
 
+    /// Part of the ring of a roundabout
+    pub fn is_roundabout(&self) -> bool {
+        self.osm_tags.is("junction", "roundabout")
+    }
+
     pub fn is_light_rail(&self) -> bool {
         self.lanes_ltr().len() == 1 && self.lanes_ltr()[0].2 == LaneType::LightRail
     }
@@ -350,6 +356,44 @@ impl Road {
         self.access_restrictions != AccessRestrictions::new()
     }
 
+    /// Which turn restrictions does going from this road to another at some intersection break?
+    /// The caller decides if each one applies to some vehicle at some time.
+    pub fn broken_turn_restrictions(&self, i: &Intersection, to: RoadID) -> Vec<&RestrictionScope> {
+        let mut broken = Vec::new();
+        for (rt, restricted_to, scope) in &self.turn_restrictions {
+            // The restriction only applies to one direction of the road.
+            if !i.roads.contains(restricted_to) {
+                continue;
+            }
+            let ok = match rt {
+                RestrictionType::BanTurns => to != *restricted_to,
+                RestrictionType::OnlyAllowTurns => to == *restricted_to,
+            };
+            if !ok {
+                broken.push(scope);
+            }
+        }
+        broken
+    }
+
+    /// Which turn restrictions via entire roads does following these roads after this one break?
+    pub fn broken_complicated_restrictions(&self, next: &[RoadID]) -> Vec<&RestrictionScope> {
+        let mut broken = Vec::new();
+        for (rt, via, to, scope) in &self.complicated_turn_restrictions {
+            if next.len() <= via.len() || next[..via.len()] != via[..] {
+                continue;
+            }
+            let ok = match rt {
+                RestrictionType::BanTurns => next[via.len()] != *to,
+                RestrictionType::OnlyAllowTurns => next[via.len()] == *to,
+            };
+            if !ok {
+                broken.push(scope);
+            }
+        }
+        broken
+    }
+
     pub(crate) fn access_restrictions_from_osm(&self) -> AccessRestrictions {
         let allow_through_traffic = if self.osm_tags.is("access", "private") {
             EnumSet::new()
@@ -362,9 +406,47 @@ impl Road {
         } else {
             EnumSet::all()
         };
+
+        // Some roads are closed to through traffic only part of the day, like school streets
+        let mut conditional = Vec::new();
+        let exempt_buses = self.osm_tags.is("psv", "yes") || self.osm_tags.is("bus", "yes");
+        for (key, allow) in &[
+            ("access:conditional", EnumSet::new()),
+            (
+                "vehicle:conditional",
+                EnumSet::only(PathConstraints::Pedestrian),
+            ),
+            (
+                "motor_vehicle:conditional",
+                PathConstraints::Pedestrian | PathConstraints::Bike,
+            ),
+        ] {
+            let mut allow = *allow;
+            if *key != "access:conditional" && exempt_buses {
+                allow |= PathConstraints::Bus;
+            }
+            if let Some(value) = self.osm_tags.get(key) {
+                for (restriction, windows) in TimeWindow::parse_osm_conditional(value) {
+                    if restriction == "no"
+                        || restriction == "private"
+                        || restriction == "destination"
+                    {
+                        for window in windows {
+                            conditional.push(ConditionalAccess {
+                                window,
+                                allow_through_traffic: allow & allow_through_traffic,
+                                cap_vehicles_per_hour: None,
+                            });
+                        }
+                    }
+                }
+            }
+        }
+
         AccessRestrictions {
             allow_through_traffic,
             cap_vehicles_per_hour: None,
+            conditional,
         }
     }
 
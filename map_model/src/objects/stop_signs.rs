@@ -33,6 +33,8 @@ use crate::{
 // 6) Additionally, individual turns can be banned completely.
 //    - Even though letting players manipulate this could make parts of the map unreachable?
 
+/// Stop signs and yield-controlled intersections, like roundabouts. For roundabouts, `must_stop`
+/// means the road only has to yield.
 #[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
 pub struct ControlStopSign {
     pub id: IntersectionID,
@@ -117,6 +119,26 @@ impl ControlStopSign {
         ss
     }
 
+    /// Traffic on a roundabout has priority over everyone entering it. If the intersection isn't
+    /// on a roundabout, the highest-ranked roads have priority, unless all roads have the same
+    /// rank, in which case everybody yields.
+    pub fn roundabout(map: &Map, id: IntersectionID) -> ControlStopSign {
+        let mut ss = ControlStopSign::new(map, id);
+        if ss.roads.len() <= 2 {
+            return ss;
+        }
+        if ss.roads.keys().any(|r| map.get_r(*r).is_roundabout()) {
+            for (r, cfg) in ss.roads.iter_mut() {
+                cfg.must_stop = !map.get_r(*r).is_roundabout();
+            }
+        } else if ss.roads.values().all(|cfg| !cfg.must_stop) {
+            for cfg in ss.roads.values_mut() {
+                cfg.must_stop = true;
+            }
+        }
+        ss
+    }
+
     /// Get the priority of a turn according to the stop sign -- either protected or yield, never
     /// banned.
     // TODO Or cache
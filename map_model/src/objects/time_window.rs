@@ -0,0 +1,199 @@
+//! Some lane types and access restrictions only apply during part of the day, like peak-hour bus
+//! lanes or school streets. The simulation just models one typical weekday, so restrictions for
+//! particular days of the week are only kept if they apply on weekdays.
+
+use std::fmt;
+
+use serde::{Deserialize, Serialize};
+
+use geom::{Duration, Time};
+
+const WEEKDAYS: [&str; 7] = ["Mo", "Tu", "We", "Th", "Fr", "Sa", "Su"];
+
+/// A recurring period of every day. If `end` is before `start`, the window wraps around midnight.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct TimeWindow {
+    pub start: Time,
+    pub end: Time,
+}
+
+impl TimeWindow {
+    /// Is the time of day (ignoring which day of the simulation) in this window?
+    pub fn contains(self, t: Time) -> bool {
+        let time_of_day = time_of_day(t);
+        let start = time_of_day_or_midnight(self.start);
+        let end = time_of_day_or_midnight(self.end);
+        if start <= end {
+            start <= time_of_day && time_of_day < end
+        } else {
+            time_of_day >= start || time_of_day < end
+        }
+    }
+
+    /// Parses the value of an OSM `*:conditional` tag, like `no @ (Mo-Fr 07:00-09:00,16:00-18:00)`,
+    /// into each value and the times of day it applies. Conditions that aren't about the time of
+    /// day, or that never apply on weekdays, are skipped.
+    pub fn parse_osm_conditional(value: &str) -> Vec<(String, Vec<TimeWindow>)> {
+        let mut results = Vec::new();
+        for rule in split_rules(value) {
+            let mut parts = rule.splitn(2, '@');
+            let value = parts.next().unwrap().trim();
+            let condition = match parts.next() {
+                Some(c) => c.trim().trim_start_matches('(').trim_end_matches(')'),
+                None => continue,
+            };
+            let windows = parse_condition(condition);
+            if !value.is_empty() && !windows.is_empty() {
+                results.push((value.to_string(), windows));
+            }
+        }
+        results
+    }
+}
+
+impl fmt::Display for TimeWindow {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (h1, m1, _, _) = self.start.get_parts();
+        let (h2, m2, _, _) = self.end.get_parts();
+        write!(f, "{:02}:{:02}-{:02}:{:02}", h1, m1, h2, m2)
+    }
+}
+
+fn time_of_day(t: Time) -> Duration {
+    (t - Time::START_OF_DAY) % Duration::hours(24)
+}
+
+// A window ending at 24:00 really ends at midnight.
+fn time_of_day_or_midnight(t: Time) -> Duration {
+    if t - Time::START_OF_DAY == Duration::hours(24) {
+        Duration::hours(24)
+    } else {
+        time_of_day(t)
+    }
+}
+
+// Rules are separated by semicolons, but those can also appear inside a parenthesized condition.
+fn split_rules(value: &str) -> Vec<&str> {
+    let mut rules = Vec::new();
+    let mut depth = 0;
+    let mut start = 0;
+    for (idx, c) in value.char_indices() {
+        match c {
+            '(' => depth += 1,
+            ')' => depth -= 1,
+            ';' if depth == 0 => {
+                rules.push(&value[start..idx]);
+                start = idx + 1;
+            }
+            _ => {}
+        }
+    }
+    rules.push(&value[start..]);
+    rules
+}
+
+// Like "Mo-Fr 07:00-09:00,16:00-18:00; Sa 10:00-12:00"
+fn parse_condition(condition: &str) -> Vec<TimeWindow> {
+    let mut windows = Vec::new();
+    for part in condition.split(';') {
+        let mut hours = Vec::new();
+        let mut days = Vec::new();
+        for token in part.split(|c: char| c.is_whitespace() || c == ',') {
+            if token.contains(':') {
+                hours.extend(parse_hours(token));
+            } else if WEEKDAYS.iter().any(|d| token.starts_with(d)) {
+                days.push(token);
+            }
+        }
+        if days.is_empty() || days.into_iter().any(covers_weekday) {
+            windows.extend(hours);
+        }
+    }
+    windows
+}
+
+fn covers_weekday(days: &str) -> bool {
+    let idx = |d: &str| WEEKDAYS.iter().position(|x| *x == d);
+    let mut parts = days.splitn(2, '-');
+    let first = parts.next().and_then(idx);
+    let last = parts.next().and_then(idx).or(first);
+    match (first, last) {
+        (Some(a), Some(b)) if a <= b => a <= 4,
+        // Wraps around the weekend, like Sa-Mo
+        (Some(_), Some(_)) => true,
+        _ => false,
+    }
+}
+
+/// Parses "07:00-09:30"
+fn parse_hours(token: &str) -> Option<TimeWindow> {
+    let mut parts = token.splitn(2, '-');
+    let start = parse_hhmm(parts.next()?)?;
+    let end = parse_hhmm(parts.next()?)?;
+    Some(TimeWindow {
+        start: Time::START_OF_DAY + start,
+        end: Time::START_OF_DAY + end,
+    })
+}
+
+fn parse_hhmm(x: &str) -> Option<Duration> {
+    let mut parts = x.splitn(2, ':');
+    let hours = parts.next()?.trim().parse::<usize>().ok()?;
+    let minutes = parts.next()?.trim().parse::<usize>().ok()?;
+    if hours > 24 || minutes >= 60 {
+        return None;
+    }
+    Some(Duration::hours(hours) + Duration::minutes(minutes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn window(h1: usize, h2: usize) -> TimeWindow {
+        TimeWindow {
+            start: Time::START_OF_DAY + Duration::hours(h1),
+            end: Time::START_OF_DAY + Duration::hours(h2),
+        }
+    }
+
+    #[test]
+    fn test_parse_osm_conditional() {
+        assert_eq!(
+            TimeWindow::parse_osm_conditional("no @ (Mo-Fr 07:00-09:00,16:00-18:00)"),
+            vec![("no".to_string(), vec![window(7, 9), window(16, 18)])]
+        );
+        assert_eq!(
+            TimeWindow::parse_osm_conditional(
+                "no_stopping @ (08:00-09:00); delivery @ (Mo-Fr 10:00-12:00; Sa 10:00-11:00)"
+            ),
+            vec![
+                ("no_stopping".to_string(), vec![window(8, 9)]),
+                ("delivery".to_string(), vec![window(10, 12)])
+            ]
+        );
+        // Only on weekends, or not about the time of day
+        assert!(TimeWindow::parse_osm_conditional("no @ (Sa-Su 10:00-18:00)").is_empty());
+        assert!(TimeWindow::parse_osm_conditional("no @ wet").is_empty());
+        assert_eq!(
+            TimeWindow::parse_osm_conditional("|designated @ (07:00-09:00)")[0].0,
+            "|designated"
+        );
+    }
+
+    #[test]
+    fn test_contains() {
+        let w = window(7, 9);
+        assert!(w.contains(Time::START_OF_DAY + Duration::hours(8)));
+        assert!(!w.contains(Time::START_OF_DAY + Duration::hours(9)));
+        // The next day
+        assert!(w.contains(Time::START_OF_DAY + Duration::hours(31)));
+
+        let overnight = window(22, 6);
+        assert!(overnight.contains(Time::START_OF_DAY + Duration::hours(23)));
+        assert!(overnight.contains(Time::START_OF_DAY + Duration::hours(2)));
+        assert!(!overnight.contains(Time::START_OF_DAY + Duration::hours(12)));
+
+        assert!(window(18, 24).contains(Time::START_OF_DAY + Duration::hours(23)));
+    }
+}
@@ -7,11 +7,11 @@ use abstutil::{deserialize_btreemap, retain_btreeset, serialize_btreemap, Timer}
 use geom::{Distance, Duration, Speed};
 
 use crate::make::traffic_signals::{brute_force, get_possible_policies};
-use crate::objects::traffic_signals::PhaseType::{Adaptive, Fixed};
+use crate::objects::traffic_signals::PhaseType::{Actuated, Adaptive, Fixed};
 use crate::raw::OriginalRoad;
 use crate::{
-    osm, CompressedMovementID, DirectedRoadID, Direction, IntersectionID, Map, Movement,
-    MovementID, TurnID, TurnPriority, TurnType,
+    osm, BusRouteID, CompressedMovementID, DirectedRoadID, Direction, IntersectionID, Map,
+    Movement, MovementID, TurnID, TurnPriority, TurnType,
 };
 
 // The pace to use for crosswalk pace in m/s
@@ -32,6 +32,11 @@ pub struct ControlTrafficSignal {
         deserialize_with = "deserialize_btreemap"
     )]
     pub movements: BTreeMap<MovementID, Movement>,
+    /// If present, approaching buses and trains get priority.
+    pub transit_priority: Option<TransitPriority>,
+    /// If present, pedestrians follow explicit WALK and DON'T WALK intervals, instead of starting
+    /// to cross any time their crosswalk is green.
+    pub pedestrian_timing: Option<PedestrianTiming>,
 }
 
 #[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
@@ -50,17 +55,122 @@ pub enum PhaseType {
     /// repeat the stage entirely.
     // TODO This is a silly policy, but a start towards variable timers.
     Adaptive(Duration),
+    /// Driven by loop detectors on the approach lanes. The stage always lasts for the minimum
+    /// green time, then keeps getting extended while vehicles are detected for its protected
+    /// movements, up to the maximum green time. If nobody is waiting for any of the stage's
+    /// movements, the stage is skipped entirely.
+    Actuated(ActuatedTiming),
+}
+
+#[derive(Clone, Copy, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ActuatedTiming {
+    pub min_green: Duration,
+    pub max_green: Duration,
+    /// How much more time to give the stage each time demand is detected after the minimum green
+    /// time. If no vehicle shows up in this gap, the stage ends ("gaps out").
+    pub extension: Duration,
+}
+
+/// Transit signal priority helps buses and trains through a signal. When a priority vehicle is
+/// approaching on a movement that's currently allowed and can get through within `max_extension`
+/// past when the stage would normally end, the stage is held for it. When a priority vehicle is
+/// waiting at a red light and the next stage would let it go, the current stage ends early, cut
+/// short by up to `max_early_green`, but never before pedestrians can finish crossing. Each stage
+/// is held or cut short at most once.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct TransitPriority {
+    /// Only vehicles serving these routes get priority. If empty, every route does.
+    pub routes: BTreeSet<BusRouteID>,
+    pub max_extension: Duration,
+    pub max_early_green: Duration,
+}
+
+impl TransitPriority {
+    /// Give every route priority, with up to 10 seconds of extension or early green.
+    pub fn defaults() -> TransitPriority {
+        TransitPriority {
+            routes: BTreeSet::new(),
+            max_extension: Duration::seconds(10.0),
+            max_early_green: Duration::seconds(10.0),
+        }
+    }
+
+    pub fn applies_to(&self, route: BusRouteID) -> bool {
+        self.routes.is_empty() || self.routes.contains(&route)
+    }
+}
+
+/// Explicit pedestrian signal timing. Every stage with a protected crosswalk begins with a WALK
+/// interval and ends with a flashing DON'T WALK clearance interval, just long enough to finish
+/// crossing at the design walking speed. Pedestrians can only start crossing during WALK; anybody
+/// arriving later waits for the next cycle. Optionally, a leading pedestrian interval holds back
+/// vehicles crossing the crosswalk at the start of the stage, so pedestrians get a head start.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct PedestrianTiming {
+    /// Stages are lengthened to fit at least this much WALK time.
+    pub min_walk: Duration,
+    /// Clearance intervals are how long it takes to cross at this speed.
+    pub clearance_speed: Speed,
+    /// Zero means no leading pedestrian interval.
+    pub leading_interval: Duration,
+}
+
+impl PedestrianTiming {
+    /// A 7 second minimum WALK, clearance based on 3.5 feet per second, and no leading interval.
+    pub fn defaults() -> PedestrianTiming {
+        PedestrianTiming {
+            min_walk: Duration::seconds(7.0),
+            clearance_speed: Speed::meters_per_second(1.07),
+            leading_interval: Duration::ZERO,
+        }
+    }
 }
 
 impl PhaseType {
     // TODO Maybe don't have this; force callers to acknowledge different policies
+    /// For actuated stages, this is the minimum green time.
     pub fn simple_duration(&self) -> Duration {
         match self {
             PhaseType::Fixed(d) | PhaseType::Adaptive(d) => *d,
+            PhaseType::Actuated(timing) => timing.min_green,
+        }
+    }
+}
+
+impl ActuatedTiming {
+    pub fn new(min_green: Duration) -> ActuatedTiming {
+        ActuatedTiming {
+            min_green,
+            max_green: min_green * 2.0,
+            extension: Duration::seconds(3.0),
         }
     }
 }
 
+/// The seattle_traffic_signals format used to store signal edits can't express actuated timing,
+/// so that's kept on the side, keyed by stage index. In the raw format, actuated stages appear
+/// as adaptive stages lasting for the minimum green time. Transit signal priority and pedestrian
+/// timing are also kept on the side.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ExportedTrafficSignal {
+    #[serde(flatten)]
+    pub raw: seattle_traffic_signals::TrafficSignal,
+    #[serde(default, skip_serializing_if = "BTreeMap::is_empty")]
+    pub actuated: BTreeMap<usize, ActuatedTiming>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub transit_priority: Option<ExportedTransitPriority>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pedestrian_timing: Option<PedestrianTiming>,
+}
+
+/// Like TransitPriority, but referring to routes by their OSM relation.
+#[derive(Clone, Debug, Serialize, Deserialize, PartialEq)]
+pub struct ExportedTransitPriority {
+    pub routes: Vec<osm::RelationID>,
+    pub max_extension: Duration,
+    pub max_early_green: Duration,
+}
+
 impl ControlTrafficSignal {
     pub fn new(map: &Map, id: IntersectionID, timer: &mut Timer) -> ControlTrafficSignal {
         let mut policies = ControlTrafficSignal::get_possible_policies(map, id, timer);
@@ -96,6 +206,38 @@ impl ControlTrafficSignal {
         Duration::seconds(time.inner_seconds().ceil())
     }
 
+    /// How long the flashing DON'T WALK interval lasts for a crosswalk, if this signal has
+    /// explicit pedestrian timing.
+    pub fn clearance_interval(&self, crosswalk: &MovementID) -> Option<Duration> {
+        let timing = self.pedestrian_timing.as_ref()?;
+        let time = self.movements[crosswalk].geom.length() / timing.clearance_speed;
+        Some(Duration::seconds(time.inner_seconds().ceil()))
+    }
+
+    /// Turns explicit pedestrian timing on or off. Any stage too short to fit the leading
+    /// interval, the minimum WALK, and clearance for its crosswalks is lengthened.
+    pub fn set_pedestrian_timing(&mut self, timing: Option<PedestrianTiming>) {
+        self.pedestrian_timing = timing;
+        let timing = if let Some(ref timing) = self.pedestrian_timing {
+            timing.clone()
+        } else {
+            return;
+        };
+        for idx in 0..self.stages.len() {
+            let clearance = self.stages[idx]
+                .protected_movements
+                .iter()
+                .filter(|m| m.crosswalk)
+                .filter_map(|m| self.clearance_interval(m))
+                .max();
+            if let Some(clearance) = clearance {
+                self.stages[idx].enforce_minimum_duration(
+                    timing.leading_interval + timing.min_walk + clearance,
+                );
+            }
+        }
+    }
+
     pub(crate) fn validate(&self) -> Result<(), String> {
         // Does the assignment cover the correct set of movements?
         let expected_movements: BTreeSet<MovementID> = self.movements.keys().cloned().collect();
@@ -149,6 +291,21 @@ impl ControlTrafficSignal {
                     stage.phase_type.simple_duration()
                 ));
             }
+            if let PhaseType::Actuated(timing) = stage.phase_type {
+                if timing.max_green < timing.min_green {
+                    return Err(format!(
+                        "Actuated stage {} has a max green time of {}, below its min green time \
+                         of {}",
+                        stage_index, timing.max_green, timing.min_green
+                    ));
+                }
+                if timing.extension <= Duration::ZERO {
+                    return Err(format!(
+                        "Actuated stage {} needs a positive extension time, not {}",
+                        stage_index, timing.extension
+                    ));
+                }
+            }
             stage_index += 1;
         }
         Ok(())
@@ -302,18 +459,34 @@ impl Stage {
                 .inner_seconds()
                 .ceil(),
         );
+        self.enforce_minimum_duration(time);
+    }
+
+    fn enforce_minimum_duration(&mut self, time: Duration) {
         if time > self.phase_type.simple_duration() {
             self.phase_type = match self.phase_type {
                 PhaseType::Adaptive(_) => Adaptive(time),
                 PhaseType::Fixed(_) => Fixed(time),
+                PhaseType::Actuated(timing) => Actuated(ActuatedTiming {
+                    min_green: time,
+                    max_green: timing.max_green.max(time),
+                    extension: timing.extension,
+                }),
             };
         }
     }
 }
 
 impl ControlTrafficSignal {
-    pub fn export(&self, map: &Map) -> seattle_traffic_signals::TrafficSignal {
-        seattle_traffic_signals::TrafficSignal {
+    pub fn export(&self, map: &Map) -> ExportedTrafficSignal {
+        let mut actuated = BTreeMap::new();
+        for (idx, s) in self.stages.iter().enumerate() {
+            if let PhaseType::Actuated(timing) = s.phase_type {
+                actuated.insert(idx, timing);
+            }
+        }
+
+        let raw = seattle_traffic_signals::TrafficSignal {
             intersection_osm_node_id: map.get_i(self.id).orig_id.0,
             phases: self
                 .stages
@@ -336,20 +509,49 @@ impl ControlTrafficSignal {
                         PhaseType::Adaptive(d) => {
                             seattle_traffic_signals::PhaseType::Adaptive(d.inner_seconds() as usize)
                         }
+                        PhaseType::Actuated(timing) => {
+                            seattle_traffic_signals::PhaseType::Adaptive(
+                                timing.min_green.inner_seconds() as usize,
+                            )
+                        }
                     },
                 })
                 .collect(),
             offset_seconds: self.offset.inner_seconds() as usize,
+        };
+        let transit_priority = self
+            .transit_priority
+            .as_ref()
+            .map(|tsp| ExportedTransitPriority {
+                routes: tsp
+                    .routes
+                    .iter()
+                    .map(|r| map.get_br(*r).osm_rel_id)
+                    .collect(),
+                max_extension: tsp.max_extension,
+                max_early_green: tsp.max_early_green,
+            });
+        ExportedTrafficSignal {
+            raw,
+            actuated,
+            transit_priority,
+            pedestrian_timing: self.pedestrian_timing.clone(),
         }
     }
 
     pub(crate) fn import(
-        raw: seattle_traffic_signals::TrafficSignal,
+        exported: ExportedTrafficSignal,
         id: IntersectionID,
         map: &Map,
     ) -> Result<ControlTrafficSignal, String> {
+        let ExportedTrafficSignal {
+            raw,
+            mut actuated,
+            transit_priority,
+            pedestrian_timing,
+        } = exported;
         let mut stages = Vec::new();
-        for s in raw.phases {
+        for (idx, s) in raw.phases.into_iter().enumerate() {
             let mut errors = Vec::new();
             let mut protected_movements = BTreeSet::new();
             for t in s.protected_turns {
@@ -374,27 +576,58 @@ impl ControlTrafficSignal {
                 }
             }
             if errors.is_empty() {
-                stages.push(Stage {
-                    protected_movements,
-                    yield_movements: permitted_movements,
-                    phase_type: match s.phase_type {
+                let phase_type = if let Some(timing) = actuated.remove(&idx) {
+                    PhaseType::Actuated(timing)
+                } else {
+                    match s.phase_type {
                         seattle_traffic_signals::PhaseType::Fixed(d) => {
                             PhaseType::Fixed(Duration::seconds(d as f64))
                         }
                         seattle_traffic_signals::PhaseType::Adaptive(d) => {
                             PhaseType::Adaptive(Duration::seconds(d as f64))
                         }
-                    },
+                    }
+                };
+                stages.push(Stage {
+                    protected_movements,
+                    yield_movements: permitted_movements,
+                    phase_type,
                 });
             } else {
                 return Err(errors.join("; "));
             }
         }
+        if let Some(idx) = actuated.keys().next() {
+            return Err(format!(
+                "Actuated timing for stage {}, but there are only {} stages",
+                idx,
+                stages.len()
+            ));
+        }
+        let transit_priority = match transit_priority {
+            Some(tsp) => {
+                let mut routes = BTreeSet::new();
+                for osm_rel_id in tsp.routes {
+                    routes.insert(
+                        map.find_br(osm_rel_id)
+                            .ok_or(format!("can't find {}", osm_rel_id))?,
+                    );
+                }
+                Some(TransitPriority {
+                    routes,
+                    max_extension: tsp.max_extension,
+                    max_early_green: tsp.max_early_green,
+                })
+            }
+            None => None,
+        };
         let ts = ControlTrafficSignal {
             id,
             stages,
             offset: Duration::seconds(raw.offset_seconds as f64),
             movements: Movement::for_i(id, map).unwrap(),
+            transit_priority,
+            pedestrian_timing,
         };
         ts.validate()?;
         Ok(ts)
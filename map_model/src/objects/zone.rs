@@ -4,6 +4,7 @@
 //! 2) Stay Healthy Streets, where most car traffic is banned, except for trips beginning/ending in
 //!    the zone
 //! 3) Congestion capping, where only so many cars per hour can enter the zone
+//! 4) School streets, closed to through traffic only around the start of the school day
 
 use std::collections::BTreeSet;
 
@@ -11,15 +12,30 @@ use enumset::EnumSet;
 use petgraph::graphmap::DiGraphMap;
 use serde::{Deserialize, Serialize};
 
-use crate::pathfind::{driving_cost, walking_cost, WalkingNode};
+use geom::Time;
+
+use crate::pathfind::{driving_cost, sidewalk_cost, walking_cost, WalkingNode};
 use crate::{
-    IntersectionID, LaneID, Map, Path, PathConstraints, PathRequest, PathStep, RoadID, TurnID,
+    IntersectionID, LaneID, Map, Path, PathConstraints, PathRequest, PathStep, RoadID, TimeWindow,
+    TurnID,
 };
 
 #[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
 pub struct AccessRestrictions {
     pub allow_through_traffic: EnumSet<PathConstraints>,
     pub cap_vehicles_per_hour: Option<usize>,
+    /// Different restrictions that apply during part of the day. If several windows overlap, the
+    /// first one wins.
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub conditional: Vec<ConditionalAccess>,
+}
+
+/// Replaces the usual AccessRestrictions of a road during some time window.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+pub struct ConditionalAccess {
+    pub window: TimeWindow,
+    pub allow_through_traffic: EnumSet<PathConstraints>,
+    pub cap_vehicles_per_hour: Option<usize>,
 }
 
 impl AccessRestrictions {
@@ -27,8 +43,34 @@ impl AccessRestrictions {
         AccessRestrictions {
             allow_through_traffic: EnumSet::all(),
             cap_vehicles_per_hour: None,
+            conditional: Vec::new(),
+        }
+    }
+
+    /// Who can pass through at some time of day
+    pub fn allow_through_traffic_at(&self, time: Time) -> EnumSet<PathConstraints> {
+        match self.conditional.iter().find(|c| c.window.contains(time)) {
+            Some(c) => c.allow_through_traffic,
+            None => self.allow_through_traffic,
         }
     }
+
+    /// The cap in effect at some time of day
+    pub fn cap_vehicles_per_hour_at(&self, time: Time) -> Option<usize> {
+        match self.conditional.iter().find(|c| c.window.contains(time)) {
+            Some(c) => c.cap_vehicles_per_hour,
+            None => self.cap_vehicles_per_hour,
+        }
+    }
+
+    /// Is there a cap at any time of day?
+    pub fn ever_capped(&self) -> bool {
+        self.cap_vehicles_per_hour.is_some()
+            || self
+                .conditional
+                .iter()
+                .any(|c| c.cap_vehicles_per_hour.is_some())
+    }
 }
 
 /// A contiguous set of roads with access restrictions. This is derived from all the map's roads and
@@ -112,11 +154,10 @@ impl Zone {
             for l in map.get_r(*r).all_lanes() {
                 let l = map.get_l(l);
                 if l.is_walkable() {
-                    let cost = walking_cost(l.length());
                     let n1 = WalkingNode::SidewalkEndpoint(l.id, true);
                     let n2 = WalkingNode::SidewalkEndpoint(l.id, false);
-                    graph.add_edge(n1, n2, cost);
-                    graph.add_edge(n2, n1, cost);
+                    graph.add_edge(n1, n2, sidewalk_cost(l, true));
+                    graph.add_edge(n2, n1, sidewalk_cost(l, false));
 
                     for turn in map.get_turns_for(l.id, PathConstraints::Pedestrian) {
                         if self.members.contains(&map.get_l(turn.id.dst).parent) {
@@ -1,17 +1,23 @@
 //! Uses https://github.com/easbar/fast_paths. Slower creation during map importing, but very fast
 //! queries.
 
+use std::collections::{BTreeMap, BTreeSet};
+use std::sync::{Arc, Mutex};
+
+use petgraph::graphmap::DiGraphMap;
 use serde::{Deserialize, Serialize};
 
 use abstutil::Timer;
+use geom::{Duration, Time};
 
 use crate::pathfind::driving::VehiclePathfinder;
 use crate::pathfind::walking::{
     one_step_walking_path, walking_path_to_steps, SidewalkPathfinder, WalkingNode,
 };
+use crate::pathfind::{dijkstra, find_park_and_ride_lots};
 use crate::{
-    BusRouteID, BusStopID, Intersection, Map, Path, PathConstraints, PathRequest, Position, TurnID,
-    Zone,
+    BusRouteID, BusStopID, Intersection, LaneID, Map, ParkingLotID, Path, PathConstraints,
+    PathRequest, Position, TurnID, Zone,
 };
 
 #[derive(Serialize, Deserialize)]
@@ -22,6 +28,23 @@ pub struct ContractionHierarchyPathfinder {
     train_graph: VehiclePathfinder,
     walking_graph: SidewalkPathfinder,
     walking_with_transit_graph: SidewalkPathfinder,
+
+    /// Most trips don't need these, so they're only prepared when something asks for them.
+    #[serde(skip_serializing, skip_deserializing)]
+    lazy: Mutex<LazyGraphs>,
+}
+
+#[derive(Default)]
+struct LazyGraphs {
+    /// Sorted times of day when some restriction starts or stops. Restrictions stay the same
+    /// from one of these until the next.
+    changes: Option<Vec<Time>>,
+    /// For vehicles that only use lanes and turns open during part of the day
+    graphs: BTreeMap<(PathConstraints, Time), Arc<VehiclePathfinder>>,
+    /// For cars avoiding some lanes
+    all_vehicles: Option<Arc<DiGraphMap<LaneID, TurnID>>>,
+    /// Parking lots near bus stops, and those stops
+    park_and_ride_lots: Option<Arc<BTreeMap<ParkingLotID, Vec<BusStopID>>>>,
 }
 
 impl ContractionHierarchyPathfinder {
@@ -60,9 +83,92 @@ impl ContractionHierarchyPathfinder {
             train_graph,
             walking_graph,
             walking_with_transit_graph,
+            lazy: Mutex::new(LazyGraphs::default()),
         }
     }
 
+    /// Only uses lanes and turns open to vehicles at this time of day, besides where the trip
+    /// starts and ends.
+    pub fn pathfind_at(&self, req: PathRequest, time: Time, map: &Map) -> Option<Path> {
+        let graph = self.graph_at(req.constraints, time, map);
+        graph.pathfind_at(&req, time, map)
+    }
+
+    /// See `dijkstra::pathfind_avoiding_lanes`. Unlike with the contraction hierarchies, banned
+    /// lanes and extra costs can change every time, so this searches a plain graph.
+    pub fn pathfind_avoiding_lanes(
+        &self,
+        req: PathRequest,
+        departure: Time,
+        banned: BTreeSet<LaneID>,
+        extra_costs: BTreeMap<LaneID, Duration>,
+        map: &Map,
+    ) -> Option<Path> {
+        let graph = self
+            .lazy
+            .lock()
+            .unwrap()
+            .all_vehicles
+            .get_or_insert_with(|| Arc::new(dijkstra::build_graph_for_all_vehicles(map)))
+            .clone();
+        dijkstra::pathfind_avoiding_lanes(req, departure, banned, extra_costs, &graph, map)
+    }
+
+    /// Parking lots worth considering for park-and-ride, found once and reused by every trip.
+    pub fn park_and_ride_lots(&self, map: &Map) -> Arc<BTreeMap<ParkingLotID, Vec<BusStopID>>> {
+        self.lazy
+            .lock()
+            .unwrap()
+            .park_and_ride_lots
+            .get_or_insert_with(|| Arc::new(find_park_and_ride_lots(map)))
+            .clone()
+    }
+
+    fn graph_at(
+        &self,
+        constraints: PathConstraints,
+        time: Time,
+        map: &Map,
+    ) -> Arc<VehiclePathfinder> {
+        let slice = {
+            let mut cache = self.lazy.lock().unwrap();
+            let changes = cache
+                .changes
+                .get_or_insert_with(|| restriction_changes(map));
+            // Before the first change of the day, the last one from the day before still holds
+            let time_of_day =
+                Time::START_OF_DAY + (time - Time::START_OF_DAY) % Duration::hours(24);
+            let slice = changes
+                .iter()
+                .rev()
+                .find(|t| **t <= time_of_day)
+                .or_else(|| changes.last())
+                .cloned()
+                .unwrap_or(Time::START_OF_DAY);
+            if let Some(graph) = cache.graphs.get(&(constraints, slice)) {
+                return graph.clone();
+            }
+            slice
+        };
+
+        // Preparing the graph takes a while, so don't hold up other searches meanwhile
+        let base = match constraints {
+            PathConstraints::Pedestrian => unreachable!(),
+            PathConstraints::Car => &self.car_graph,
+            PathConstraints::Bike => &self.bike_graph,
+            PathConstraints::Bus => &self.bus_graph,
+            PathConstraints::Train => &self.train_graph,
+        };
+        let graph = Arc::new(VehiclePathfinder::new_at(map, base, slice));
+        self.lazy
+            .lock()
+            .unwrap()
+            .graphs
+            .entry((constraints, slice))
+            .or_insert(graph)
+            .clone()
+    }
+
     pub fn pathfind(&self, req: PathRequest, map: &Map) -> Option<Path> {
         if req.start.lane() == req.end.lane() && req.constraints == PathConstraints::Pedestrian {
             return Some(one_step_walking_path(&req, map));
@@ -330,6 +436,9 @@ impl ContractionHierarchyPathfinder {
     }
 
     pub fn apply_edits(&mut self, map: &Map, timer: &mut Timer) {
+        // Prepare these again when they're needed
+        *self.lazy.get_mut().unwrap() = LazyGraphs::default();
+
         timer.start("apply edits to car pathfinding");
         self.car_graph.apply_edits(map);
         timer.stop("apply edits to car pathfinding");
@@ -355,3 +464,28 @@ impl ContractionHierarchyPathfinder {
         timer.stop("apply edits to pedestrian using transit pathfinding");
     }
 }
+
+fn restriction_changes(map: &Map) -> Vec<Time> {
+    let mut times = map.time_restriction_changes();
+    for r in map.all_roads() {
+        let scopes = r.turn_restrictions.iter().map(|(_, _, scope)| scope).chain(
+            r.complicated_turn_restrictions
+                .iter()
+                .map(|(_, _, _, scope)| scope),
+        );
+        for scope in scopes {
+            for window in &scope.windows {
+                times.insert(window.start);
+                times.insert(window.end);
+            }
+        }
+    }
+    // A window might end at midnight the next day
+    let day = Duration::hours(24);
+    times
+        .into_iter()
+        .map(|t| Time::START_OF_DAY + (t - Time::START_OF_DAY) % day)
+        .collect::<BTreeSet<_>>()
+        .into_iter()
+        .collect()
+}
@@ -1,14 +1,22 @@
 //! Pathfinding without needing to build a separate contraction hierarchy.
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use petgraph::graphmap::DiGraphMap;
+use petgraph::visit::EdgeFiltered;
+
+use geom::{Duration, Time};
 
 use crate::pathfind::driving::driving_cost;
 use crate::pathfind::walking::{
-    one_step_walking_path, walking_cost, walking_path_to_steps, WalkingNode,
+    one_step_walking_path, sidewalk_cost, transit_ride, walking_cost, walking_path_to_steps,
+    WalkingNode,
+};
+use crate::pathfind::TimeRestrictions;
+use crate::{
+    BusRouteID, BusStopID, Lane, LaneID, Map, Path, PathConstraints, PathRequest, PathStep,
+    Position, TurnID,
 };
-use crate::{LaneID, Map, Path, PathConstraints, PathRequest, PathStep, TurnID};
 
 // TODO These should maybe keep the DiGraphMaps as state. It's cheap to recalculate it for edits.
 
@@ -22,7 +30,7 @@ pub fn pathfind(req: PathRequest, map: &Map) -> Option<Path> {
     }
 
     let graph = build_graph_for_vehicles(map, req.constraints);
-    calc_path(graph, req, map)
+    calc_path(&graph, |_| true, &req, map, |_| 0.0)
 }
 
 pub fn build_graph_for_vehicles(
@@ -41,30 +49,89 @@ pub fn build_graph_for_vehicles(
     graph
 }
 
+/// Every turn between lanes that aren't sidewalks, even if nobody can use it right now. Searches
+/// skip whatever isn't open as they go, so this can be built once and reused.
+pub fn build_graph_for_all_vehicles(map: &Map) -> DiGraphMap<LaneID, TurnID> {
+    let mut graph: DiGraphMap<LaneID, TurnID> = DiGraphMap::new();
+    for l in map.all_lanes() {
+        if !l.is_walkable() {
+            for (turn, _) in map.get_next_turns_and_lanes(l.id, l.dst_i) {
+                graph.add_edge(turn.id.src, turn.id.dst, turn.id);
+            }
+        }
+    }
+    graph
+}
+
+/// Like `pathfind_at`, but the banned lanes can't be used at all, and entering some lanes costs
+/// extra. The lanes where the path starts and ends are never banned. Only for cars. The graph
+/// comes from `build_graph_for_all_vehicles`.
 pub fn pathfind_avoiding_lanes(
     req: PathRequest,
-    avoid: BTreeSet<LaneID>,
+    departure: Time,
+    banned: BTreeSet<LaneID>,
+    extra_costs: BTreeMap<LaneID, Duration>,
+    graph: &DiGraphMap<LaneID, TurnID>,
     map: &Map,
 ) -> Option<Path> {
     assert_eq!(req.constraints, PathConstraints::Car);
+    let restrictions = TimeRestrictions::new(&req, map);
+    let usable = |l: &Lane| {
+        restrictions.allows(l, departure)
+            && (!banned.contains(&l.id) || l.id == req.start.lane() || l.id == req.end.lane())
+    };
+    calc_path(
+        graph,
+        // The search only reaches lanes through turns checked here, and the start is always
+        // usable, so only check where each turn leads.
+        |turn| usable(map.get_l(turn.dst)) && restrictions.allows_turn(map.get_t(turn), departure),
+        &req,
+        map,
+        |l| {
+            extra_costs
+                .get(&l)
+                .map(|dt| dt.inner_seconds())
+                .unwrap_or(0.0)
+        },
+    )
+}
+
+/// Only uses lanes that are open to the vehicle at one time of day.
+pub fn pathfind_at(req: PathRequest, time: Time, map: &Map) -> Option<Path> {
+    let restrictions = TimeRestrictions::new(&req, map);
     let mut graph: DiGraphMap<LaneID, TurnID> = DiGraphMap::new();
     for l in map.all_lanes() {
-        if req.constraints.can_use(l, map) && !avoid.contains(&l.id) {
-            for turn in map.get_turns_for(l.id, req.constraints) {
-                graph.add_edge(turn.id.src, turn.id.dst, turn.id);
+        if restrictions.allows(l, time) {
+            for (turn, dst) in map.get_next_turns_and_lanes(l.id, l.dst_i) {
+                if restrictions.allows(dst, time) && restrictions.allows_turn(turn, time) {
+                    graph.add_edge(turn.id.src, turn.id.dst, turn.id);
+                }
             }
         }
     }
 
-    calc_path(graph, req, map)
+    calc_path(&graph, |_| true, &req, map, |_| 0.0)
 }
 
-fn calc_path(graph: DiGraphMap<LaneID, TurnID>, req: PathRequest, map: &Map) -> Option<Path> {
+/// Only turns passing `usable` are used, and `extra_cost` is added for entering each lane.
+fn calc_path<U: Fn(TurnID) -> bool, F: Fn(LaneID) -> f64>(
+    graph: &DiGraphMap<LaneID, TurnID>,
+    usable: U,
+    req: &PathRequest,
+    map: &Map,
+    extra_cost: F,
+) -> Option<Path> {
+    let graph = EdgeFiltered::from_fn(graph, |(_, _, turn): (LaneID, LaneID, &TurnID)| {
+        usable(*turn)
+    });
     let (_, path) = petgraph::algo::astar(
         &graph,
         req.start.lane(),
         |l| l == req.end.lane(),
-        |(_, _, turn)| driving_cost(map.get_l(turn.src), map.get_t(*turn), req.constraints, map),
+        |(_, _, turn)| {
+            driving_cost(map.get_l(turn.src), map.get_t(*turn), req.constraints, map)
+                + extra_cost(turn.dst)
+        },
         |_| 0.0,
     )?;
     let mut steps = Vec::new();
@@ -88,11 +155,10 @@ pub fn build_graph_for_pedestrians(map: &Map) -> DiGraphMap<WalkingNode, usize>
     let mut graph: DiGraphMap<WalkingNode, usize> = DiGraphMap::new();
     for l in map.all_lanes() {
         if l.is_walkable() {
-            let cost = walking_cost(l.length());
             let n1 = WalkingNode::SidewalkEndpoint(l.id, true);
             let n2 = WalkingNode::SidewalkEndpoint(l.id, false);
-            graph.add_edge(n1, n2, cost);
-            graph.add_edge(n2, n1, cost);
+            graph.add_edge(n1, n2, sidewalk_cost(l, true));
+            graph.add_edge(n2, n1, sidewalk_cost(l, false));
 
             for turn in map.get_turns_for(l.id, PathConstraints::Pedestrian) {
                 graph.add_edge(
@@ -123,3 +189,106 @@ fn pathfind_walking(req: PathRequest, map: &Map) -> Option<Vec<WalkingNode>> {
     )?;
     Some(path)
 }
+
+/// Like `SidewalkPathfinder::should_use_transit`, but searching a graph built just for this.
+pub fn should_use_transit(
+    map: &Map,
+    start: Position,
+    end: Position,
+) -> Option<(BusStopID, Option<BusStopID>, BusRouteID)> {
+    let mut graph = build_graph_for_pedestrians(map);
+    add_transit_to_graph(&mut graph, map);
+
+    let closest_start = WalkingNode::closest(start, map);
+    let end = WalkingNode::end_transit(end, map);
+    let (_, path) = petgraph::algo::astar(
+        &graph,
+        closest_start,
+        |n| n == end,
+        |(_, _, cost)| *cost,
+        |_| 0,
+    )?;
+    transit_ride(&path, map)
+}
+
+/// Connects bus stops to sidewalks and to each other along each route, like the contraction
+/// hierarchy does.
+fn add_transit_to_graph(graph: &mut DiGraphMap<WalkingNode, usize>, map: &Map) {
+    for stop in map.all_bus_stops().values() {
+        let lane = map.get_l(stop.sidewalk_pos.lane());
+        for endpt in &[true, false] {
+            let cost = if *endpt {
+                walking_cost(lane.length() - stop.sidewalk_pos.dist_along())
+            } else {
+                walking_cost(stop.sidewalk_pos.dist_along())
+            };
+            // Same penalty as the contraction hierarchy, so paths don't pass through a stop
+            // uselessly
+            let penalty = 100;
+            let sidewalk = WalkingNode::SidewalkEndpoint(lane.id, *endpt);
+            graph.add_edge(sidewalk, WalkingNode::RideBus(stop.id), cost + penalty);
+            graph.add_edge(WalkingNode::RideBus(stop.id), sidewalk, cost + penalty);
+        }
+    }
+
+    let bus_graph = build_graph_for_vehicles(map, PathConstraints::Bus);
+    let train_graph = build_graph_for_vehicles(map, PathConstraints::Train);
+    for route in map.all_bus_routes() {
+        let vehicle_graph = match route.route_type {
+            PathConstraints::Bus => &bus_graph,
+            PathConstraints::Train => &train_graph,
+            _ => unreachable!(),
+        };
+        for pair in route.stops.windows(2) {
+            let (stop1, stop2) = (map.get_bs(pair[0]), map.get_bs(pair[1]));
+            if let Some(cost) = driving_cost_between(
+                vehicle_graph,
+                stop1.driving_pos,
+                stop2.driving_pos,
+                route.route_type,
+                map,
+            ) {
+                graph.add_edge(
+                    WalkingNode::RideBus(stop1.id),
+                    WalkingNode::RideBus(stop2.id),
+                    cost,
+                );
+            }
+        }
+
+        if let Some(l) = route.end_border {
+            let stop1 = map.get_bs(*route.stops.last().unwrap());
+            if let Some(cost) = driving_cost_between(
+                vehicle_graph,
+                stop1.driving_pos,
+                Position::end(l, map),
+                route.route_type,
+                map,
+            ) {
+                graph.add_edge(
+                    WalkingNode::RideBus(stop1.id),
+                    WalkingNode::LeaveMap(map.get_l(l).dst_i),
+                    cost,
+                );
+            }
+        }
+    }
+}
+
+/// The cost of driving between two places, rounded like the contraction hierarchy's weights.
+fn driving_cost_between(
+    graph: &DiGraphMap<LaneID, TurnID>,
+    start: Position,
+    end: Position,
+    constraints: PathConstraints,
+    map: &Map,
+) -> Option<usize> {
+    let (cost, _) = petgraph::algo::astar(
+        graph,
+        start.lane(),
+        |l| l == end.lane(),
+        |(_, _, turn)| driving_cost(map.get_l(turn.src), map.get_t(*turn), constraints, map),
+        |_| 0.0,
+    )?;
+    Some(cost.round() as usize)
+}
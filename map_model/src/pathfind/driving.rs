@@ -1,15 +1,18 @@
 //! Pathfinding for cars, bikes, buses, and trains using contraction hierarchies
 
 use std::cell::RefCell;
+use std::collections::{BTreeMap, BTreeSet};
 
 use fast_paths::{deserialize_32, serialize_32, FastGraph, InputGraph, PathCalculator};
 use serde::{Deserialize, Serialize};
 use thread_local::ThreadLocal;
 
 use abstutil::MultiMap;
+use geom::Time;
 
 use crate::pathfind::node_map::{deserialize_nodemap, NodeMap};
 use crate::pathfind::uber_turns::{IntersectionCluster, UberTurn};
+use crate::pathfind::TimeRestrictions;
 use crate::{Lane, LaneID, Map, Path, PathConstraints, PathRequest, PathStep, Turn, TurnID};
 
 #[derive(Serialize, Deserialize)]
@@ -54,7 +57,7 @@ impl VehiclePathfinder {
             }
         }
 
-        let input_graph = make_input_graph(map, &nodes, &uber_turns, constraints);
+        let input_graph = make_input_graph(map, &nodes, &uber_turns, constraints, None);
 
         // All VehiclePathfinders have the same nodes (lanes), so if we're not the first being
         // built, seed from the node ordering.
@@ -74,6 +77,27 @@ impl VehiclePathfinder {
         }
     }
 
+    /// Like `new`, but only using lanes and turns open at one time of day. The rest of the graph
+    /// matches `base`, so its node ordering makes this quicker to prepare.
+    pub fn new_at(map: &Map, base: &VehiclePathfinder, time: Time) -> VehiclePathfinder {
+        let input_graph = make_input_graph(
+            map,
+            &base.nodes,
+            &base.uber_turns,
+            base.constraints,
+            Some(time),
+        );
+        let node_ordering = base.graph.get_node_ordering();
+        let graph = fast_paths::prepare_with_order(&input_graph, &node_ordering).unwrap();
+        VehiclePathfinder {
+            graph,
+            nodes: base.nodes.clone(),
+            uber_turns: base.uber_turns.clone(),
+            constraints: base.constraints,
+            path_calc: ThreadLocal::new(),
+        }
+    }
+
     pub fn pathfind(&self, req: &PathRequest, map: &Map) -> Option<(Path, usize)> {
         assert!(!map.get_l(req.start.lane()).is_walkable());
         let mut calc = self
@@ -85,9 +109,81 @@ impl VehiclePathfinder {
             self.nodes.get(Node::Lane(req.start.lane())),
             self.nodes.get(Node::Lane(req.end.lane())),
         )?;
+        let (mut steps, uber_turns) = self.translate(&raw_path, map);
+        steps.push(PathStep::Lane(req.end.lane()));
+        Some((
+            Path::new(map, steps, req.end.dist_along(), uber_turns),
+            raw_path.get_weight(),
+        ))
+    }
+
+    /// For a graph from `new_at`. The trip may use the lanes where it starts and ends, and roads
+    /// near them, even if they're closed at this time. The graph leaves those out, so search them
+    /// separately, then connect through the graph.
+    pub fn pathfind_at(&self, req: &PathRequest, time: Time, map: &Map) -> Option<Path> {
+        let exemptions = TimeRestrictions::new(req, map);
+        let everywhere = TimeRestrictions::everywhere(req.constraints, map);
+        let from_start = local_search(req.start.lane(), true, &exemptions, time, map);
+        let to_end = local_search(req.end.lane(), false, &exemptions, time, map);
+
+        // Staying on the local roads the whole way might be best
+        let mut best: Option<(usize, Vec<PathStep>, Vec<UberTurn>)> =
+            from_start.get(&req.end.lane()).map(|(cost, _)| {
+                let mut steps = local_steps(req.end.lane(), &from_start, true);
+                steps.push(PathStep::Lane(req.end.lane()));
+                (*cost, steps, Vec::new())
+            });
+
+        let sources: Vec<(usize, usize)> = from_start
+            .iter()
+            .filter(|(l, _)| everywhere.allows(map.get_l(**l), time))
+            .map(|(l, (cost, _))| (self.nodes.get(Node::Lane(*l)), *cost))
+            .collect();
+        let targets: Vec<(usize, usize)> = to_end
+            .iter()
+            .filter(|(l, _)| everywhere.allows(map.get_l(**l), time))
+            .map(|(l, (cost, _))| (self.nodes.get(Node::Lane(*l)), *cost))
+            .collect();
+        if !sources.is_empty() && !targets.is_empty() {
+            let mut calc = self
+                .path_calc
+                .get_or(|| RefCell::new(fast_paths::create_calculator(&self.graph)))
+                .borrow_mut();
+            if let Some(raw_path) =
+                calc.calc_path_multiple_sources_and_targets(&self.graph, sources, targets)
+            {
+                if best
+                    .as_ref()
+                    .map(|(cost, _, _)| raw_path.get_weight() < *cost)
+                    .unwrap_or(true)
+                {
+                    let nodes = self.nodes.translate(&raw_path);
+                    let (first, last) = match (nodes[0], nodes[nodes.len() - 1]) {
+                        (Node::Lane(l1), Node::Lane(l2)) => (l1, l2),
+                        _ => unreachable!(),
+                    };
+                    let mut steps = local_steps(first, &from_start, true);
+                    let (middle, uber_turns) = self.translate(&raw_path, map);
+                    steps.extend(middle);
+                    steps.extend(local_steps(last, &to_end, false));
+                    best = Some((raw_path.get_weight(), steps, uber_turns));
+                }
+            }
+        }
+
+        let (_, steps, uber_turns) = best?;
+        Some(Path::new(map, steps, req.end.dist_along(), uber_turns))
+    }
+
+    /// Every step along a path through the graph, besides the last lane
+    fn translate(
+        &self,
+        raw_path: &fast_paths::ShortestPath,
+        map: &Map,
+    ) -> (Vec<PathStep>, Vec<UberTurn>) {
         let mut steps = Vec::new();
         let mut uber_turns = Vec::new();
-        for pair in self.nodes.translate(&raw_path).windows(2) {
+        for pair in self.nodes.translate(raw_path).windows(2) {
             match (pair[0], pair[1]) {
                 (Node::Lane(l1), Node::Lane(l2)) => {
                     steps.push(PathStep::Lane(l1));
@@ -114,11 +210,7 @@ impl VehiclePathfinder {
                 (Node::UberTurn(_), Node::UberTurn(_)) => unreachable!(),
             }
         }
-        steps.push(PathStep::Lane(req.end.lane()));
-        Some((
-            Path::new(map, steps, req.end.dist_along(), uber_turns),
-            raw_path.get_weight(),
-        ))
+        (steps, uber_turns)
     }
 
     pub fn apply_edits(&mut self, map: &Map) {
@@ -126,32 +218,42 @@ impl VehiclePathfinder {
         // the node ordering.
         // TODO Make sure the result of this is deterministic and equivalent to computing from
         // scratch.
-        let input_graph = make_input_graph(map, &self.nodes, &self.uber_turns, self.constraints);
+        let input_graph =
+            make_input_graph(map, &self.nodes, &self.uber_turns, self.constraints, None);
         let node_ordering = self.graph.get_node_ordering();
         self.graph = fast_paths::prepare_with_order(&input_graph, &node_ordering).unwrap();
     }
 }
 
+/// With a time, only lanes and turns open then are included.
 fn make_input_graph(
     map: &Map,
     nodes: &NodeMap<Node>,
     uber_turns: &Vec<UberTurn>,
     constraints: PathConstraints,
+    time: Option<Time>,
 ) -> InputGraph {
     let mut input_graph = InputGraph::new();
+    let restrictions = TimeRestrictions::everywhere(constraints, map);
 
     // From some lanes, instead of adding edges to turns, add edges to these (indexed) uber-turns.
     let mut uber_turn_entrances: MultiMap<LaneID, usize> = MultiMap::new();
     for (idx, ut) in uber_turns.iter().enumerate() {
         // But actually, make sure this uber-turn only contains lanes that can be used by this
-        // vehicle.
+        // vehicle, and that no turn restriction just for this vehicle bans it.
         // TODO Need to test editing lanes inside an IntersectionCluster very carefully. See Mercer
         // and Dexter.
-        if ut
-            .path
-            .iter()
-            .all(|t| constraints.can_use(map.get_l(t.dst), map))
-        {
+        let usable = match time {
+            Some(time) => ut.path.iter().all(|t| {
+                restrictions.allows(map.get_l(t.dst), time)
+                    && restrictions.allows_turn(map.get_t(*t), time)
+            }),
+            None => ut
+                .path
+                .iter()
+                .all(|t| constraints.can_use(map.get_l(t.dst), map)),
+        };
+        if usable && !ut.is_restricted(constraints, time, map) {
             uber_turn_entrances.insert(ut.entry(), idx);
         } else {
             // Similar to the hack below for unused lanes
@@ -169,16 +271,32 @@ fn make_input_graph(
     for l in map.all_lanes() {
         let from = nodes.get(Node::Lane(l.id));
         let mut any = false;
-        if constraints.can_use(l, map)
-            && map
-                .get_r(l.parent)
-                .access_restrictions
-                .allow_through_traffic
-                .contains(constraints)
-        {
+        let usable = match time {
+            Some(time) => restrictions.allows(l, time),
+            None => {
+                constraints.can_use(l, map)
+                    && map
+                        .get_r(l.parent)
+                        .access_restrictions
+                        .allow_through_traffic
+                        .contains(constraints)
+            }
+        };
+        if usable {
             let indices = uber_turn_entrances.get(l.id);
             if indices.is_empty() {
-                for turn in map.get_turns_for(l.id, constraints) {
+                let turns = match time {
+                    Some(time) => map
+                        .get_next_turns_and_lanes(l.id, l.dst_i)
+                        .into_iter()
+                        .filter(|(turn, dst)| {
+                            restrictions.allows(dst, time) && restrictions.allows_turn(turn, time)
+                        })
+                        .map(|(turn, _)| turn)
+                        .collect(),
+                    None => map.get_turns_for(l.id, constraints),
+                };
+                for turn in turns {
                     any = true;
                     input_graph.add_edge(
                         from,
@@ -218,6 +336,102 @@ fn make_input_graph(
     input_graph
 }
 
+/// Dijkstra's from the lane where a trip starts (or backwards from where it ends), only over the
+/// roads the trip may use no matter what. Returns every lane reached, the cost to get there, and
+/// the turn used to enter it (or leave it, going backwards).
+fn local_search(
+    from: LaneID,
+    forwards: bool,
+    restrictions: &TimeRestrictions,
+    time: Time,
+    map: &Map,
+) -> BTreeMap<LaneID, (usize, Option<TurnID>)> {
+    let constraints = restrictions.constraints;
+    let mut found: BTreeMap<LaneID, (usize, Option<TurnID>)> = BTreeMap::new();
+    let mut done: BTreeSet<LaneID> = BTreeSet::new();
+    let mut queue: BTreeSet<(usize, LaneID)> = BTreeSet::new();
+    found.insert(from, (0, None));
+    queue.insert((0, from));
+    while let Some((cost, l)) = queue.iter().next().cloned() {
+        queue.remove(&(cost, l));
+        done.insert(l);
+
+        let next: Vec<(&Turn, LaneID, usize)> = if forwards {
+            map.get_next_turns_and_lanes(l, map.get_l(l).dst_i)
+                .into_iter()
+                .map(|(turn, dst)| {
+                    (
+                        turn,
+                        dst.id,
+                        driving_cost(map.get_l(l), turn, constraints, map),
+                    )
+                })
+                .map(|(turn, dst, cost)| (turn, dst, round(cost)))
+                .collect()
+        } else {
+            map.get_turns_to_lane(l)
+                .into_iter()
+                .filter(|turn| map.get_l(turn.id.src).dst_i == turn.id.parent)
+                .map(|turn| {
+                    let src = map.get_l(turn.id.src);
+                    (
+                        turn,
+                        src.id,
+                        round(driving_cost(src, turn, constraints, map)),
+                    )
+                })
+                .collect()
+        };
+        for (turn, other, step_cost) in next {
+            let lane = map.get_l(other);
+            if done.contains(&other)
+                || !restrictions.is_local(lane.parent)
+                || !restrictions.allows(lane, time)
+                || !restrictions.allows_turn(turn, time)
+            {
+                continue;
+            }
+            let new_cost = cost + step_cost;
+            if let Some((old_cost, _)) = found.get(&other) {
+                if *old_cost <= new_cost {
+                    continue;
+                }
+                queue.remove(&(*old_cost, other));
+            }
+            found.insert(other, (new_cost, Some(turn.id)));
+            queue.insert((new_cost, other));
+        }
+    }
+    found
+}
+
+/// Going forwards, the steps from the start up to (but not including) this lane. Going
+/// backwards, the steps from this lane to the end.
+fn local_steps(
+    l: LaneID,
+    found: &BTreeMap<LaneID, (usize, Option<TurnID>)>,
+    forwards: bool,
+) -> Vec<PathStep> {
+    let mut steps = Vec::new();
+    let mut current = l;
+    if forwards {
+        while let Some(turn) = found[&current].1 {
+            steps.push(PathStep::Turn(turn));
+            steps.push(PathStep::Lane(turn.src));
+            current = turn.src;
+        }
+        steps.reverse();
+    } else {
+        steps.push(PathStep::Lane(l));
+        while let Some(turn) = found[&current].1 {
+            steps.push(PathStep::Turn(turn));
+            steps.push(PathStep::Lane(turn.dst));
+            current = turn.dst;
+        }
+    }
+    steps
+}
+
 /// Different unit based on constraints.
 pub fn driving_cost(lane: &Lane, turn: &Turn, constraints: PathConstraints, map: &Map) -> f64 {
     // TODO Could cost turns differently.
@@ -231,8 +445,9 @@ pub fn driving_cost(lane: &Lane, turn: &Turn, constraints: PathConstraints, map:
         }
         PathConstraints::Bike => {
             // Speed limits don't matter, bikes are usually constrained by their own speed limit.
-            let dist = lane.length() + turn.geom.length();
-            // TODO Elevation gain is bad, loss is good.
+            // Hills do, so measure how far somebody could bike on flat ground in the same time.
+            let dist = lane.length() / lane.speed_factor(PathConstraints::Bike, false)
+                + turn.geom.length();
             // TODO If we're on a driving lane, higher speed limit is worse.
             // TODO Bike lanes next to parking is dangerous.
 
@@ -243,7 +458,8 @@ pub fn driving_cost(lane: &Lane, turn: &Turn, constraints: PathConstraints, map:
             } else if lane.is_bus() {
                 1.1
             } else {
-                assert!(lane.is_driving());
+                // Usually a driving lane, but maybe some other type that only becomes a driving
+                // lane during part of the day
                 1.5
             };
 
@@ -257,7 +473,7 @@ pub fn driving_cost(lane: &Lane, turn: &Turn, constraints: PathConstraints, map:
             let lt_penalty = if lane.is_bus() {
                 1.0
             } else {
-                assert!(lane.is_driving());
+                // Same as for bikes, this might only be a driving lane part of the day
                 1.1
             };
             (lt_penalty * (t1 + t2)).inner_seconds()
@@ -1,21 +1,24 @@
 //! Everything related to pathfinding through a map for different types of agents.
 
-use std::collections::{BTreeSet, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 use std::fmt;
+use std::sync::Arc;
 
 use enumset::EnumSetType;
 use serde::{Deserialize, Serialize};
 
 use abstutil::Timer;
-use geom::{Distance, PolyLine, EPSILON_DIST};
+use geom::{Distance, Duration, PolyLine, Time, EPSILON_DIST};
 
 pub use self::ch::ContractionHierarchyPathfinder;
 pub use self::dijkstra::{build_graph_for_pedestrians, build_graph_for_vehicles};
 pub use self::driving::driving_cost;
-pub use self::walking::{walking_cost, WalkingNode};
+pub use self::travel_times::TravelTimes;
+pub use self::walking::{sidewalk_cost, walking_cost, WalkingNode};
+use crate::raw::RestrictionScope;
 use crate::{
-    osm, BusRouteID, BusStopID, Lane, LaneID, LaneType, Map, Position, Traversable, TurnID,
-    UberTurn,
+    osm, BusRouteID, BusStopID, Lane, LaneID, LaneType, Map, ParkingLotID, Position, RoadID,
+    Traversable, Turn, TurnID, UberTurn,
 };
 
 mod ch;
@@ -23,6 +26,7 @@ mod dijkstra;
 mod driving;
 mod node_map;
 // TODO tmp
+mod travel_times;
 pub mod uber_turns;
 mod walking;
 
@@ -254,6 +258,26 @@ impl Path {
         self.steps.push_back(step);
     }
 
+    /// Replace everything from the current step onwards with a different path. The new path must
+    /// start at the current step and end on the same lane; the original end distance is kept.
+    /// Progress along the original path is remembered.
+    pub fn reroute(&mut self, new: Path, map: &Map) {
+        assert!(self.currently_inside_ut.is_none());
+        assert_eq!(self.steps[0], new.steps[0]);
+        assert_eq!(self.steps.back(), new.steps.back());
+        for step in self.steps.drain(..) {
+            self.total_length -= step.as_traversable().length(map);
+            match step {
+                PathStep::Lane(_) | PathStep::ContraflowLane(_) => self.total_lanes -= 1,
+                _ => {}
+            }
+        }
+        for step in new.steps {
+            self.add(step, map);
+        }
+        self.uber_turns = new.uber_turns;
+    }
+
     // TODO This is a brittle, tied to exactly what opportunistically_lanechange does.
     pub fn approaching_uber_turn(&self) -> bool {
         if self.steps.len() < 5 || self.uber_turns.is_empty() {
@@ -463,15 +487,34 @@ impl PathConstraints {
         }
     }
 
+    /// Uses the lane's usual type; see `can_use_at` for lanes that change type during the day.
     // TODO Handle private zones here?
     pub fn can_use(self, l: &Lane, map: &Map) -> bool {
+        self.can_use_lane_type(l.lane_type, l, map)
+    }
+
+    /// Can the lane be used at some time of day?
+    pub fn can_use_at(self, l: &Lane, map: &Map, time: Time) -> bool {
+        self.can_use_lane_type(l.lane_type_at(time), l, map)
+    }
+
+    /// Can the lane be used during any part of the day?
+    pub fn can_ever_use(self, l: &Lane, map: &Map) -> bool {
+        l.all_lane_types()
+            .into_iter()
+            .any(|lt| self.can_use_lane_type(lt, l, map))
+    }
+
+    fn can_use_lane_type(self, lt: LaneType, l: &Lane, map: &Map) -> bool {
         match self {
-            PathConstraints::Pedestrian => l.is_walkable(),
-            PathConstraints::Car => l.is_driving(),
+            PathConstraints::Pedestrian => lt == LaneType::Sidewalk || lt == LaneType::Shoulder,
+            PathConstraints::Car => lt == LaneType::Driving,
             PathConstraints::Bike => {
-                if l.is_biking() {
+                if lt == LaneType::Biking {
                     true
-                } else if l.is_driving() || (l.is_bus() && map.config.bikes_can_use_bus_lanes) {
+                } else if lt == LaneType::Driving
+                    || (lt == LaneType::Bus && map.config.bikes_can_use_bus_lanes)
+                {
                     let road = map.get_r(l.parent);
                     !road.osm_tags.is("bicycle", "no")
                         && !road
@@ -481,8 +524,8 @@ impl PathConstraints {
                     false
                 }
             }
-            PathConstraints::Bus => l.is_driving() || l.is_bus(),
-            PathConstraints::Train => l.is_light_rail(),
+            PathConstraints::Bus => lt == LaneType::Driving || lt == LaneType::Bus,
+            PathConstraints::Train => lt == LaneType::LightRail,
         }
     }
 
@@ -568,23 +611,114 @@ fn validate_continuity(map: &Map, steps: &Vec<PathStep>) {
 }
 
 fn validate_restrictions(map: &Map, steps: &Vec<PathStep>) {
-    for triple in steps.windows(5) {
-        if let (PathStep::Lane(l1), PathStep::Lane(l2), PathStep::Lane(l3)) =
-            (triple[0], triple[2], triple[4])
-        {
-            let from = map.get_parent(l1);
-            let via = map.get_l(l2).parent;
-            let to = map.get_l(l3).parent;
-
-            for (dont_via, dont_to) in &from.complicated_turn_restrictions {
-                if via == *dont_via && to == *dont_to {
-                    panic!(
-                        "Some path does illegal uber-turn: {} -> {} -> {}",
-                        l1, l2, l3
-                    );
-                }
+    let roads = roads_along(steps, map);
+    if breaks_complicated_restriction(&roads, map, |scope| scope.is_unconditional()) {
+        panic!("Some path does illegal uber-turn through {:?}", roads);
+    }
+}
+
+/// Every road a vehicle path crosses, in order.
+fn roads_along<'a, I: IntoIterator<Item = &'a PathStep>>(steps: I, map: &Map) -> Vec<RoadID> {
+    steps
+        .into_iter()
+        .filter_map(|step| match step {
+            PathStep::Lane(l) => Some(map.get_l(*l).parent),
+            _ => None,
+        })
+        .collect()
+}
+
+/// Does following this sequence of roads break a turn restriction via entire roads? The caller
+/// decides which restrictions count.
+pub(crate) fn breaks_complicated_restriction<F: Fn(&RestrictionScope) -> bool>(
+    roads: &[RoadID],
+    map: &Map,
+    applies: F,
+) -> bool {
+    (0..roads.len()).any(|idx| {
+        map.get_r(roads[idx])
+            .broken_complicated_restrictions(&roads[idx + 1..])
+            .into_iter()
+            .any(&applies)
+    })
+}
+
+/// Decides which lanes and turns a vehicle may use at some time of day, considering lane types,
+/// access restrictions, and turn restrictions that only apply during part of the day. A trip can
+/// always use the lanes where it starts and ends, and roads in the same zone as them.
+pub(crate) struct TimeRestrictions<'a> {
+    constraints: PathConstraints,
+    endpoints: Vec<LaneID>,
+    local_roads: BTreeSet<RoadID>,
+    map: &'a Map,
+}
+
+impl<'a> TimeRestrictions<'a> {
+    pub fn new(req: &PathRequest, map: &'a Map) -> TimeRestrictions<'a> {
+        let endpoints = vec![req.start.lane(), req.end.lane()];
+        let mut local_roads = BTreeSet::new();
+        for l in &endpoints {
+            let r = map.get_parent(*l);
+            local_roads.insert(r.id);
+            if let Some(zone) = r.get_zone(map) {
+                local_roads.extend(zone.members.iter().cloned());
             }
         }
+        TimeRestrictions {
+            constraints: req.constraints,
+            endpoints,
+            local_roads,
+            map,
+        }
+    }
+
+    /// Without any exemptions for where a trip starts or ends.
+    pub fn everywhere(constraints: PathConstraints, map: &'a Map) -> TimeRestrictions<'a> {
+        TimeRestrictions {
+            constraints,
+            endpoints: Vec::new(),
+            local_roads: BTreeSet::new(),
+            map,
+        }
+    }
+
+    /// Is the road one of the few that a trip can use no matter what, because it starts or ends
+    /// there or in the same zone?
+    pub fn is_local(&self, r: RoadID) -> bool {
+        self.local_roads.contains(&r)
+    }
+
+    pub fn allows(&self, l: &Lane, time: Time) -> bool {
+        if self.endpoints.contains(&l.id) {
+            return true;
+        }
+        self.constraints.can_use_at(l, self.map, time)
+            && (self.local_roads.contains(&l.parent)
+                || self
+                    .map
+                    .get_r(l.parent)
+                    .access_restrictions
+                    .allow_through_traffic_at(time)
+                    .contains(self.constraints))
+    }
+
+    /// Searches going lane-by-lane can't express turn restrictions via entire roads, so while one
+    /// of those is in effect, this just bans entering the via roads from the restricted road.
+    pub fn allows_turn(&self, t: &Turn, time: Time) -> bool {
+        if self.map.is_turn_restricted(t, self.constraints, Some(time)) {
+            return false;
+        }
+        let to = self.map.get_l(t.id.dst).parent;
+        !self
+            .map
+            .get_parent(t.id.src)
+            .complicated_turn_restrictions
+            .iter()
+            .any(|(_, via, _, scope)| {
+                via[0] == to
+                    && !scope.windows.is_empty()
+                    && scope.applies(self.constraints, Some(time))
+            })
     }
 }
 
@@ -607,10 +741,48 @@ impl Pathfinder {
     pub fn pathfind_avoiding_lanes(
         &self,
         req: PathRequest,
-        avoid: BTreeSet<LaneID>,
+        departure: Time,
+        banned: BTreeSet<LaneID>,
+        extra_costs: BTreeMap<LaneID, Duration>,
         map: &Map,
     ) -> Option<Path> {
-        dijkstra::pathfind_avoiding_lanes(req, avoid, map)
+        match self {
+            Pathfinder::Dijkstra => {
+                let graph = dijkstra::build_graph_for_all_vehicles(map);
+                dijkstra::pathfind_avoiding_lanes(req, departure, banned, extra_costs, &graph, map)
+            }
+            Pathfinder::CH(ref p) => {
+                p.pathfind_avoiding_lanes(req, departure, banned, extra_costs, map)
+            }
+        }
+    }
+
+    /// The usual path is only checked against time-of-day restrictions. If it breaks any, search
+    /// again using only lanes and turns open at that time of day.
+    pub fn pathfind_at(&self, req: PathRequest, departure: Time, map: &Map) -> Option<Path> {
+        let path = self.pathfind(req.clone(), map)?;
+        if req.constraints == PathConstraints::Pedestrian {
+            return Some(path);
+        }
+        let restrictions = TimeRestrictions::new(&req, map);
+        let ok = path.get_steps().iter().all(|step| match step {
+            PathStep::Lane(l) => restrictions.allows(map.get_l(*l), departure),
+            PathStep::Turn(t) => {
+                !map.is_turn_restricted(map.get_t(*t), req.constraints, Some(departure))
+            }
+            PathStep::ContraflowLane(_) => true,
+        }) && !breaks_complicated_restriction(
+            &roads_along(path.get_steps(), map),
+            map,
+            |scope| scope.applies(req.constraints, Some(departure)),
+        );
+        if ok {
+            return Some(path);
+        }
+        match self {
+            Pathfinder::Dijkstra => dijkstra::pathfind_at(req, departure, map),
+            Pathfinder::CH(ref p) => p.pathfind_at(req, departure, map),
+        }
     }
 
     // TODO Consider returning the walking-only path in the failure case, to avoid wasting work
@@ -621,12 +793,66 @@ impl Pathfinder {
         end: Position,
     ) -> Option<(BusStopID, Option<BusStopID>, BusRouteID)> {
         match self {
-            // TODO Implement this
-            Pathfinder::Dijkstra => None,
+            Pathfinder::Dijkstra => dijkstra::should_use_transit(map, start, end),
             Pathfinder::CH(ref p) => p.should_use_transit(map, start, end),
         }
     }
 
+    /// Should somebody drive to a parking lot, then ride transit the rest of the way? Only lots
+    /// within walking distance of some stop and roughly on the way to the end are considered. If
+    /// so, says (lot, stop1, optional stop 2, route), like `should_use_transit`.
+    pub fn should_use_park_and_ride(
+        &self,
+        map: &Map,
+        start: Position,
+        end: Position,
+    ) -> Option<(ParkingLotID, BusStopID, Option<BusStopID>, BusRouteID)> {
+        // Checking each lot is expensive, so only try the few with the smallest detour
+        const MAX_LOTS: usize = 3;
+
+        let lots = match self {
+            Pathfinder::Dijkstra => Arc::new(find_park_and_ride_lots(map)),
+            Pathfinder::CH(ref p) => p.park_and_ride_lots(map),
+        };
+        let start_pt = start.pt(map);
+        let end_pt = end.pt(map);
+        let direct = start_pt.dist_to(end_pt);
+        let mut candidates: Vec<(Distance, ParkingLotID)> = lots
+            .keys()
+            .map(|id| map.get_pl(*id))
+            .filter(|pl| {
+                pl.driving_pos.lane() != start.lane()
+                    && pl.sidewalk_pos.pt(map).dist_to(end_pt) < direct
+            })
+            .map(|pl| {
+                let pt = pl.driving_pos.pt(map);
+                (start_pt.dist_to(pt) + pt.dist_to(end_pt), pl.id)
+            })
+            .collect();
+        candidates.sort();
+
+        for (_, id) in candidates.into_iter().take(MAX_LOTS) {
+            let pl = map.get_pl(id);
+            let drive = PathRequest {
+                start,
+                end: pl.driving_pos,
+                constraints: PathConstraints::Car,
+            };
+            if map.pathfind(drive).is_none() {
+                continue;
+            }
+            if let Some((stop1, maybe_stop2, route)) =
+                self.should_use_transit(map, pl.sidewalk_pos, end)
+            {
+                // Walking to some faraway stop defeats the point of parking here
+                if lots[&id].contains(&stop1) {
+                    return Some((id, stop1, maybe_stop2, route));
+                }
+            }
+        }
+        None
+    }
+
     pub fn apply_edits(&mut self, map: &Map, timer: &mut Timer) {
         match self {
             Pathfinder::Dijkstra => {}
@@ -634,3 +860,27 @@ impl Pathfinder {
         }
     }
 }
+
+/// Parking lots within walking distance of some stop, and those stops. Only these lots are worth
+/// considering for park-and-ride.
+fn find_park_and_ride_lots(map: &Map) -> BTreeMap<ParkingLotID, Vec<BusStopID>> {
+    const MAX_WALK_TO_STOP: Distance = Distance::const_meters(500.0);
+
+    let mut lots = BTreeMap::new();
+    for pl in map.all_parking_lots() {
+        if pl.capacity() == 0 {
+            continue;
+        }
+        let pt = pl.sidewalk_pos.pt(map);
+        let stops: Vec<BusStopID> = map
+            .all_bus_stops()
+            .values()
+            .filter(|bs| bs.sidewalk_pos.pt(map).dist_to(pt) <= MAX_WALK_TO_STOP)
+            .map(|bs| bs.id)
+            .collect();
+        if !stops.is_empty() {
+            lots.insert(pl.id, stops);
+        }
+    }
+    lots
+}
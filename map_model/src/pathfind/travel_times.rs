@@ -0,0 +1,160 @@
+//! Time-dependent pathfinding for vehicles, using travel times observed in a previous simulation
+//! instead of free-flow speeds.
+
+use std::cmp::Reverse;
+use std::collections::{BTreeMap, BinaryHeap, HashMap};
+
+use serde::{Deserialize, Serialize};
+
+use abstutil::{deserialize_btreemap, serialize_btreemap};
+use geom::{Distance, Duration, Time};
+
+use crate::pathfind::TimeRestrictions;
+use crate::{LaneID, Map, Path, PathConstraints, PathRequest, PathStep, Traversable, TurnID};
+
+/// Observations are grouped into buckets of this size, starting at midnight.
+const BUCKET_SIZE: Duration = Duration::const_seconds(15.0 * 60.0);
+
+/// How long it took vehicles to cross lanes and turns, depending on the time of day. A lane's
+/// travel time includes any time spent queueing at the end of it.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct TravelTimes {
+    /// (Total time, number of observations)
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    observations: BTreeMap<(Traversable, usize), (Duration, usize)>,
+}
+
+impl TravelTimes {
+    pub fn new() -> TravelTimes {
+        TravelTimes {
+            observations: BTreeMap::new(),
+        }
+    }
+
+    /// Some vehicle started crossing something at `entered`, and took `dt` to finish.
+    pub fn record(&mut self, on: Traversable, entered: Time, dt: Duration) {
+        let entry = self
+            .observations
+            .entry((on, bucket(entered)))
+            .or_insert((Duration::ZERO, 0));
+        entry.0 += dt;
+        entry.1 += 1;
+    }
+
+    /// The mean observed time to cross something, starting at some time. None if nobody crossed
+    /// it during that part of the day.
+    pub fn get(&self, on: Traversable, entered: Time) -> Option<Duration> {
+        let (total, count) = self.observations.get(&(on, bucket(entered)))?;
+        Some(*total / (*count as f64))
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.observations.is_empty()
+    }
+
+    /// Blend these travel times with newer observations, weighting the new ones by `weight`,
+    /// between 0 and 1. Where only one side has an observation, it's used directly. This is the
+    /// averaging step of the method of successive averages.
+    pub fn blend(&self, newer: &TravelTimes, weight: f64) -> TravelTimes {
+        let mut result = TravelTimes::new();
+        for (key, (total, count)) in &self.observations {
+            result
+                .observations
+                .insert(*key, (*total / (*count as f64), 1));
+        }
+        for (key, (total, count)) in &newer.observations {
+            let new = *total / (*count as f64);
+            let entry = result.observations.entry(*key).or_insert((new, 1));
+            entry.0 = (1.0 - weight) * entry.0 + weight * new;
+        }
+        result
+    }
+
+    /// Find the fastest path for a vehicle departing at some time, expecting the observed travel
+    /// times at the moment each lane and turn is reached. Where nothing was observed, assume
+    /// vehicles travel at the speed limit. Time-of-day restrictions are checked when each lane and
+    /// turn is reached. Like the Dijkstra fallback, this doesn't handle uber-turns.
+    pub fn pathfind(&self, req: PathRequest, departure: Time, map: &Map) -> Option<Path> {
+        assert!(req.constraints != PathConstraints::Pedestrian);
+        let start = req.start.lane();
+        let end = req.end.lane();
+
+        // Only part of the first lane has to be crossed
+        let start_lane = map.get_l(start);
+        let first_lane_fraction = if start_lane.length() == Distance::ZERO {
+            0.0
+        } else {
+            (start_lane.length() - req.start.dist_along()) / start_lane.length()
+        };
+        let start_cost = first_lane_fraction * self.lane_time(start, departure, map);
+        let restrictions = TimeRestrictions::new(&req, map);
+
+        // When does a vehicle reach the start of each lane?
+        let mut arrivals: HashMap<LaneID, Time> = HashMap::new();
+        let mut backrefs: HashMap<LaneID, TurnID> = HashMap::new();
+        let mut queue: BinaryHeap<Reverse<(Time, LaneID)>> = BinaryHeap::new();
+        arrivals.insert(start, departure);
+        queue.push(Reverse((departure, start)));
+
+        while let Some(Reverse((time, l))) = queue.pop() {
+            if arrivals[&l] < time {
+                continue;
+            }
+            if l == end {
+                break;
+            }
+            let leave_lane = if l == start {
+                time + start_cost
+            } else {
+                time + self.lane_time(l, time, map)
+            };
+            for (turn, next_lane) in map.get_next_turns_and_lanes(l, map.get_l(l).dst_i) {
+                let next = next_lane.id;
+                let arrive = leave_lane + self.turn_time(turn.id, leave_lane, map);
+                if !restrictions.allows_turn(turn, leave_lane)
+                    || !restrictions.allows(next_lane, arrive)
+                {
+                    continue;
+                }
+                if arrivals.get(&next).map(|t| arrive < *t).unwrap_or(true) {
+                    arrivals.insert(next, arrive);
+                    backrefs.insert(next, turn.id);
+                    queue.push(Reverse((arrive, next)));
+                }
+            }
+        }
+
+        if !arrivals.contains_key(&end) {
+            return None;
+        }
+        let mut steps = vec![PathStep::Lane(end)];
+        let mut current = end;
+        while current != start {
+            let turn = backrefs[&current];
+            steps.push(PathStep::Turn(turn));
+            steps.push(PathStep::Lane(turn.src));
+            current = turn.src;
+        }
+        steps.reverse();
+        Some(Path::new(map, steps, req.end.dist_along(), Vec::new()))
+    }
+
+    fn lane_time(&self, l: LaneID, entered: Time, map: &Map) -> Duration {
+        self.get(Traversable::Lane(l), entered).unwrap_or_else(|| {
+            let lane = map.get_l(l);
+            lane.length() / map.get_r(lane.parent).speed_limit
+        })
+    }
+
+    fn turn_time(&self, t: TurnID, entered: Time, map: &Map) -> Duration {
+        self.get(Traversable::Turn(t), entered)
+            .unwrap_or_else(|| map.get_t(t).geom.length() / map.get_parent(t.dst).speed_limit)
+    }
+}
+
+fn bucket(t: Time) -> usize {
+    ((t - Time::START_OF_DAY) / BUCKET_SIZE) as usize
+}
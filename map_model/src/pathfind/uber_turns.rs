@@ -7,9 +7,12 @@ use petgraph::graphmap::UnGraphMap;
 use serde::{Deserialize, Serialize};
 
 use abstutil::MultiMap;
-use geom::{Angle, Distance, PolyLine, Pt2D};
+use geom::{Angle, Distance, PolyLine, Pt2D, Time};
 
-use crate::{DirectedRoadID, Direction, IntersectionID, LaneID, Map, TurnID};
+use crate::pathfind::breaks_complicated_restriction;
+use crate::{
+    DirectedRoadID, Direction, IntersectionID, LaneID, Map, PathConstraints, RoadID, TurnID,
+};
 
 /// This only applies to VehiclePathfinder; walking through these intersections is nothing special.
 // TODO I haven't seen any cases yet with "interior" intersections. Some stuff might break.
@@ -42,10 +45,12 @@ impl IntersectionCluster {
         // Then look for intersections with complicated turn restrictions.
         let mut graph: UnGraphMap<IntersectionID, ()> = UnGraphMap::new();
         for from in map.all_roads() {
-            for (via, _) in &from.complicated_turn_restrictions {
+            for (_, via, _, _) in &from.complicated_turn_restrictions {
                 // Each of these tells us 2 intersections to group together
-                let r = map.get_r(*via);
-                graph.add_edge(r.src_i, r.dst_i, ());
+                for r in via {
+                    let r = map.get_r(*r);
+                    graph.add_edge(r.src_i, r.dst_i, ());
+                }
             }
         }
         for intersections in petgraph::algo::kosaraju_scc(&graph) {
@@ -114,27 +119,13 @@ impl IntersectionCluster {
             uber_turns.extend(flood(entrance, map, &exits));
         }
 
-        // Filter illegal paths
-        let mut all_restrictions = Vec::new();
-        for from in map.all_roads() {
-            for (via, to) in &from.complicated_turn_restrictions {
-                all_restrictions.push((from.id, *via, *to));
-            }
-        }
-
-        // Filter out the restricted ones!
+        // Filter out the restricted ones! Restrictions that only apply to some vehicles or during
+        // part of the day are handled by the pathfinders.
         let mut illegal = Vec::new();
         uber_turns.retain(|ut| {
-            let mut ok = true;
-            for pair in ut.path.windows(2) {
-                let r1 = map.get_l(pair[0].src).parent;
-                let r2 = map.get_l(pair[0].dst).parent;
-                let r3 = map.get_l(pair[1].dst).parent;
-                if all_restrictions.contains(&(r1, r2, r3)) {
-                    ok = false;
-                    break;
-                }
-            }
+            let ok = !breaks_complicated_restriction(&ut.roads(map), map, |scope| {
+                scope.is_unconditional()
+            });
             if ok {
                 true
             } else {
@@ -242,6 +233,28 @@ impl UberTurn {
         self.path.last().unwrap().dst
     }
 
+    /// Every road crossed, starting with the entry's
+    pub fn roads(&self, map: &Map) -> Vec<RoadID> {
+        let mut roads = vec![map.get_l(self.entry()).parent];
+        for t in &self.path {
+            roads.push(map.get_l(t.dst).parent);
+        }
+        roads
+    }
+
+    /// Does a turn restriction via entire roads that only applies to some vehicles or during part
+    /// of the day ban this uber-turn? With no time, only restrictions that apply all day count.
+    pub fn is_restricted(
+        &self,
+        constraints: PathConstraints,
+        time: Option<Time>,
+        map: &Map,
+    ) -> bool {
+        breaks_complicated_restriction(&self.roads(map), map, |scope| {
+            scope.applies(constraints, time)
+        })
+    }
+
     pub fn geom(&self, map: &Map) -> PolyLine {
         let mut pl = map.get_t(self.path[0]).geom.clone();
         let mut first = true;
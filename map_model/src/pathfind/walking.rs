@@ -13,7 +13,7 @@ use geom::{Distance, Speed};
 use crate::pathfind::driving::VehiclePathfinder;
 use crate::pathfind::node_map::{deserialize_nodemap, NodeMap};
 use crate::{
-    BusRoute, BusRouteID, BusStopID, IntersectionID, LaneID, Map, Path, PathConstraints,
+    BusRoute, BusRouteID, BusStopID, IntersectionID, Lane, LaneID, Map, Path, PathConstraints,
     PathRequest, PathStep, Position,
 };
 
@@ -45,7 +45,8 @@ impl WalkingNode {
         WalkingNode::SidewalkEndpoint(pos.lane(), dst_i)
     }
 
-    fn end_transit(pos: Position, map: &Map) -> WalkingNode {
+    /// Where a path that might use transit ends. Buses leaving the map go to a border.
+    pub fn end_transit(pos: Position, map: &Map) -> WalkingNode {
         let l = map.get_l(pos.lane());
         if map.get_i(l.src_i).is_outgoing_border() {
             if pos.dist_along() == Distance::ZERO {
@@ -131,8 +132,8 @@ impl SidewalkPathfinder {
         Some(self.nodes.translate(&raw_path))
     }
 
-    /// Attempt the pathfinding and see if we should ride a bus. If so, says (stop1, optional stop
-    /// 2, route). If there's no stop 2, then ride the bus off the border.
+    /// Attempt the pathfinding and see if we should ride a bus. If so, says what to ride, like
+    /// `transit_ride`.
     pub fn should_use_transit(
         &self,
         map: &Map,
@@ -152,74 +153,82 @@ impl SidewalkPathfinder {
                 println!("- {:?}", n);
             }
         }
+        transit_ride(&nodes, map)
+    }
+}
 
-        let mut first_stop = None;
-        let mut last_stop = None;
-        let mut possible_routes: Vec<&BusRoute> = Vec::new();
-        for n in &nodes {
-            match n {
-                WalkingNode::RideBus(stop2) => {
-                    if first_stop.is_none() {
-                        first_stop = Some(*stop2);
-                        possible_routes = map.get_routes_serving_stop(*stop2);
-                        assert!(!possible_routes.is_empty());
-                    } else {
-                        // Keep riding the same route?
-                        // We need to do this check, because some transfers might be instantaneous
-                        // at the same stop and involve no walking.
-                        // Also need to make sure the stops are in the proper order. We might have
-                        // a transfer, then try to hop on the first route again, but starting from
-                        // a different point.
-                        let stop1 = first_stop.unwrap();
-                        let mut filtered = possible_routes.clone();
-                        filtered.retain(|r| {
-                            let idx1 = r.stops.iter().position(|s| *s == stop1).unwrap();
-                            let idx2 = r.stops.iter().position(|s| s == stop2);
-                            idx2.map(|idx2| idx1 < idx2).unwrap_or(false)
-                        });
-                        if filtered.is_empty() {
-                            // Aha, a transfer!
-                            return Some((
-                                first_stop.unwrap(),
-                                // TODO I thought this should be impossible, but huge_seattle hits
-                                // it. Workaround for now by just walking.
-                                Some(last_stop?),
-                                possible_routes[0].id,
-                            ));
-                        }
-                        last_stop = Some(*stop2);
-                        possible_routes = filtered;
+/// Given a walking path that might use transit, says (stop1, optional stop 2, route) for the first
+/// ride, if there is one. If there's no stop 2, then ride the bus off the border.
+pub fn transit_ride(
+    nodes: &[WalkingNode],
+    map: &Map,
+) -> Option<(BusStopID, Option<BusStopID>, BusRouteID)> {
+    let mut first_stop = None;
+    let mut last_stop = None;
+    let mut possible_routes: Vec<&BusRoute> = Vec::new();
+    for n in nodes {
+        match n {
+            WalkingNode::RideBus(stop2) => {
+                if first_stop.is_none() {
+                    first_stop = Some(*stop2);
+                    possible_routes = map.get_routes_serving_stop(*stop2);
+                    assert!(!possible_routes.is_empty());
+                } else {
+                    // Keep riding the same route?
+                    // We need to do this check, because some transfers might be instantaneous
+                    // at the same stop and involve no walking.
+                    // Also need to make sure the stops are in the proper order. We might have
+                    // a transfer, then try to hop on the first route again, but starting from
+                    // a different point.
+                    let stop1 = first_stop.unwrap();
+                    let mut filtered = possible_routes.clone();
+                    filtered.retain(|r| {
+                        let idx1 = r.stops.iter().position(|s| *s == stop1).unwrap();
+                        let idx2 = r.stops.iter().position(|s| s == stop2);
+                        idx2.map(|idx2| idx1 < idx2).unwrap_or(false)
+                    });
+                    if filtered.is_empty() {
+                        // Aha, a transfer!
+                        return Some((
+                            first_stop.unwrap(),
+                            // TODO I thought this should be impossible, but huge_seattle hits
+                            // it. Workaround for now by just walking.
+                            Some(last_stop?),
+                            possible_routes[0].id,
+                        ));
                     }
+                    last_stop = Some(*stop2);
+                    possible_routes = filtered;
                 }
-                WalkingNode::LeaveMap(i) => {
-                    // Make sure the route actually leaves via the correct border!
-                    if let Some(r) = possible_routes.iter().find(|r| {
-                        r.end_border
-                            .map(|l| map.get_l(l).dst_i == *i)
-                            .unwrap_or(false)
-                    }) {
-                        return Some((first_stop.unwrap(), None, r.id));
-                    }
-                    // We can get close to the border, but should hop off at some stop.
+            }
+            WalkingNode::LeaveMap(i) => {
+                // Make sure the route actually leaves via the correct border!
+                if let Some(r) = possible_routes.iter().find(|r| {
+                    r.end_border
+                        .map(|l| map.get_l(l).dst_i == *i)
+                        .unwrap_or(false)
+                }) {
+                    return Some((first_stop.unwrap(), None, r.id));
+                }
+                // We can get close to the border, but should hop off at some stop.
+                return Some((
+                    first_stop.unwrap(),
+                    Some(last_stop.expect("impossible transit transfer")),
+                    possible_routes[0].id,
+                ));
+            }
+            WalkingNode::SidewalkEndpoint(_, _) => {
+                if let Some(stop1) = first_stop {
                     return Some((
-                        first_stop.unwrap(),
+                        stop1,
                         Some(last_stop.expect("impossible transit transfer")),
                         possible_routes[0].id,
                     ));
                 }
-                WalkingNode::SidewalkEndpoint(_, _) => {
-                    if let Some(stop1) = first_stop {
-                        return Some((
-                            stop1,
-                            Some(last_stop.expect("impossible transit transfer")),
-                            possible_routes[0].id,
-                        ));
-                    }
-                }
             }
         }
-        None
     }
+    None
 }
 
 fn make_input_graph(
@@ -239,15 +248,12 @@ fn make_input_graph(
                 .allow_through_traffic
                 .contains(PathConstraints::Pedestrian)
         {
-            let mut cost = walking_cost(l.length());
             // TODO Tune this penalty, along with many others.
-            if l.is_shoulder() {
-                cost *= 2;
-            }
+            let penalty = if l.is_shoulder() { 2 } else { 1 };
             let n1 = nodes.get(WalkingNode::SidewalkEndpoint(l.id, true));
             let n2 = nodes.get(WalkingNode::SidewalkEndpoint(l.id, false));
-            input_graph.add_edge(n1, n2, cost);
-            input_graph.add_edge(n2, n1, cost);
+            input_graph.add_edge(n1, n2, penalty * sidewalk_cost(l, true));
+            input_graph.add_edge(n2, n1, penalty * sidewalk_cost(l, false));
         }
     }
 
@@ -391,6 +397,12 @@ pub fn walking_cost(dist: Distance) -> usize {
     (time.inner_seconds().round() as usize).max(1)
 }
 
+/// The cost of walking the full length of a sidewalk in one direction, accounting for hills.
+/// Contraflow means walking from dst_i to src_i.
+pub fn sidewalk_cost(lane: &Lane, contraflow: bool) -> usize {
+    walking_cost(lane.length() / lane.speed_factor(PathConstraints::Pedestrian, contraflow))
+}
+
 pub fn walking_path_to_steps(path: Vec<WalkingNode>, map: &Map) -> Vec<PathStep> {
     let mut steps: Vec<PathStep> = Vec::new();
 
@@ -8,12 +8,15 @@ use std::fmt;
 use petgraph::graphmap::DiGraphMap;
 use serde::{Deserialize, Serialize};
 
+use enumset::EnumSet;
+
 use abstutil::{deserialize_btreemap, serialize_btreemap, MapName, Tags, Timer};
-use geom::{Circle, Distance, GPSBounds, PolyLine, Polygon, Pt2D};
+use geom::{Circle, Distance, GPSBounds, PolyLine, Polygon, Pt2D, Time};
 
 use crate::make::initial::lane_specs::get_lane_specs_ltr;
 use crate::{
     osm, Amenity, AreaType, Direction, DrivingSide, IntersectionType, LaneType, MapConfig,
+    PathConstraints, TimeWindow,
 };
 
 #[derive(Debug, Serialize, Deserialize)]
@@ -38,6 +41,7 @@ pub struct RawMap {
     pub areas: Vec<RawArea>,
     pub parking_lots: Vec<RawParkingLot>,
     pub parking_aisles: Vec<(osm::WayID, Vec<Pt2D>)>,
+    pub bike_share_stations: Vec<RawBikeShareStation>,
 
     pub boundary_polygon: Polygon,
     pub gps_bounds: GPSBounds,
@@ -94,6 +98,7 @@ impl RawMap {
             areas: Vec::new(),
             parking_lots: Vec::new(),
             parking_aisles: Vec::new(),
+            bike_share_stations: Vec::new(),
             // Some nonsense thing
             boundary_polygon: Polygon::rectangle(1.0, 1.0),
             gps_bounds: GPSBounds::new(),
@@ -248,9 +253,16 @@ pub struct RawRoad {
     /// happened.
     pub center_points: Vec<Pt2D>,
     pub osm_tags: Tags,
-    pub turn_restrictions: Vec<(RestrictionType, OriginalRoad)>,
-    /// (via, to). For turn restrictions where 'via' is an entire road. Only BanTurns.
-    pub complicated_turn_restrictions: Vec<(OriginalRoad, OriginalRoad)>,
+    /// (restriction, to, scope). Applies at the intersection shared with 'to'.
+    pub turn_restrictions: Vec<(RestrictionType, OriginalRoad, RestrictionScope)>,
+    /// (restriction, via, to, scope). For turn restrictions where 'via' is a chain of entire
+    /// roads, in order.
+    pub complicated_turn_restrictions: Vec<(
+        RestrictionType,
+        Vec<OriginalRoad>,
+        OriginalRoad,
+        RestrictionScope,
+    )>,
 }
 
 impl RawRoad {
@@ -336,6 +348,17 @@ pub struct RawParkingLot {
     pub osm_tags: Tags,
 }
 
+#[derive(Serialize, Deserialize, Debug, Clone)]
+pub struct RawBikeShareStation {
+    /// The OSM node or the station ID from the bike share system's feed
+    pub external_id: String,
+    pub name: String,
+    pub point: Pt2D,
+    pub capacity: usize,
+    /// If unknown, stations start the day half full.
+    pub initial_bikes: Option<usize>,
+}
+
 #[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub enum RestrictionType {
     BanTurns,
@@ -345,6 +368,72 @@ pub enum RestrictionType {
 #[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Hash)]
 pub struct TurnRestriction(pub OriginalRoad, pub RestrictionType, pub OriginalRoad);
 
+/// Which vehicles a turn restriction applies to, and when.
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq)]
+pub struct RestrictionScope {
+    /// These vehicles may ignore the restriction
+    pub except: EnumSet<PathConstraints>,
+    /// If empty, the restriction applies all day
+    pub windows: Vec<TimeWindow>,
+}
+
+impl RestrictionScope {
+    pub fn always() -> RestrictionScope {
+        RestrictionScope {
+            except: EnumSet::new(),
+            windows: Vec::new(),
+        }
+    }
+
+    /// Does the restriction apply to everybody all day?
+    pub fn is_unconditional(&self) -> bool {
+        self.except.is_empty() && self.windows.is_empty()
+    }
+
+    /// Does the restriction apply to this type of vehicle? With no time, only restrictions that
+    /// apply all day count.
+    pub fn applies(&self, constraints: PathConstraints, time: Option<Time>) -> bool {
+        if self.except.contains(constraints) {
+            return false;
+        }
+        match time {
+            Some(t) => self.windows.is_empty() || self.windows.iter().any(|w| w.contains(t)),
+            None => self.windows.is_empty(),
+        }
+    }
+}
+
+impl fmt::Display for RestrictionScope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let only = EnumSet::all() - self.except;
+        if only.len() == 1 {
+            write!(f, " only for {:?}", only.iter().next().unwrap())?;
+        } else if !self.except.is_empty() {
+            write!(
+                f,
+                " except {}",
+                self.except
+                    .iter()
+                    .map(|c| format!("{:?}", c))
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )?;
+        }
+        if !self.windows.is_empty() {
+            write!(
+                f,
+                " during {}",
+                self.windows
+                    .iter()
+                    .map(|w| w.to_string())
+                    .collect::<Vec<_>>()
+                    .join(", ")
+            )?;
+        }
+        Ok(())
+    }
+}
+
 impl RestrictionType {
     pub fn new(restriction: &str) -> Option<RestrictionType> {
         // TODO There's a huge space of things not represented yet: time conditions, bus-only, no
@@ -361,6 +450,65 @@ impl RestrictionType {
             None
         }
     }
+
+    /// Interprets every restriction tagged on a relation: the plain `restriction`, times of day
+    /// from `restriction:conditional`, and ones for a single type of vehicle, like
+    /// `restriction:bicycle`. Vehicles listed in `except` are exempt from all of them.
+    pub fn all_from_osm(tags: &Tags) -> Vec<(RestrictionType, RestrictionScope)> {
+        let mut except = EnumSet::new();
+        if let Some(list) = tags.get("except") {
+            for vehicle in list.split(';') {
+                if let Some(c) = osm_vehicle(vehicle.trim()) {
+                    except |= c;
+                }
+            }
+        }
+
+        let mut results = Vec::new();
+        if let Some(rt) = tags
+            .get("restriction")
+            .and_then(|x| RestrictionType::new(x))
+        {
+            results.push((
+                rt,
+                RestrictionScope {
+                    except,
+                    windows: Vec::new(),
+                },
+            ));
+        }
+        if let Some(value) = tags.get("restriction:conditional") {
+            for (restriction, windows) in TimeWindow::parse_osm_conditional(value) {
+                if let Some(rt) = RestrictionType::new(&restriction) {
+                    results.push((rt, RestrictionScope { except, windows }));
+                }
+            }
+        }
+        for vehicle in &["bicycle", "bus", "psv", "motorcar"] {
+            if let Some(rt) = tags
+                .get(&format!("restriction:{}", vehicle))
+                .and_then(|x| RestrictionType::new(x))
+            {
+                results.push((
+                    rt,
+                    RestrictionScope {
+                        except: EnumSet::all() - osm_vehicle(vehicle).unwrap(),
+                        windows: Vec::new(),
+                    },
+                ));
+            }
+        }
+        results
+    }
+}
+
+fn osm_vehicle(x: &str) -> Option<PathConstraints> {
+    match x {
+        "bicycle" => Some(PathConstraints::Bike),
+        "bus" | "psv" => Some(PathConstraints::Bus),
+        "motorcar" | "motor_vehicle" => Some(PathConstraints::Car),
+        _ => None,
+    }
 }
 
 #[derive(Debug, Serialize, Deserialize)]
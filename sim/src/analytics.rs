@@ -1,16 +1,18 @@
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
 
 use serde::{Deserialize, Serialize};
 
 use abstutil::Counter;
 use geom::{Distance, Duration, Time};
 use map_model::{
-    BusRouteID, BusStopID, CompressedMovementID, IntersectionID, LaneID, Map, MovementID,
-    ParkingLotID, Path, PathRequest, RoadID, Traversable, TurnID,
+    BikeShareStationID, BuildingID, BusRouteID, BusStopID, CompressedMovementID, IntersectionID,
+    LaneID, Map, MovementID, ParkingLotID, Path, PathRequest, RoadID, TravelTimes, Traversable,
+    TurnID,
 };
 
 use crate::{
     AgentID, AgentType, AlertLocation, CarID, Event, ParkingSpot, TripID, TripMode, TripPhaseType,
+    VehicleType,
 };
 
 /// As a simulation runs, different pieces emit Events. The Analytics object listens to these,
@@ -34,11 +36,37 @@ pub struct Analytics {
     /// in time.
     pub demand: BTreeMap<MovementID, usize>,
 
+    /// When each transit vehicle was scheduled to begin its route
+    pub bus_dispatches: Vec<(Time, CarID, BusRouteID)>,
+    /// Per transit route, how long after being dispatched vehicles should reach each stop.
+    /// Recorded the first time a vehicle serving the route is dispatched.
+    pub transit_stop_offsets: BTreeMap<BusRouteID, Vec<Duration>>,
     // TODO Reconsider this one
     pub bus_arrivals: Vec<(Time, CarID, BusRouteID, BusStopID)>,
     /// For each passenger boarding, how long did they wait at the stop?
     pub passengers_boarding: BTreeMap<BusStopID, Vec<(Time, BusRouteID, Duration)>>,
     pub passengers_alighting: BTreeMap<BusStopID, Vec<(Time, BusRouteID)>>,
+    /// Every time a transit vehicle leaves a stop, how many passengers are aboard
+    pub transit_loads: Vec<(Time, CarID, BusRouteID, BusStopID, usize)>,
+    /// Every time somebody couldn't get on a full transit vehicle
+    pub passengers_denied: BTreeMap<BusStopID, Vec<(Time, BusRouteID)>>,
+    /// Every time somebody gets in or out of a carpool or ride-hailing vehicle, how many
+    /// passengers are riding
+    pub carpool_occupancy: Vec<(Time, CarID, usize)>,
+    /// For each ride-hailing pickup, how long since the ride was requested?
+    pub ride_hail_waits: Vec<(Time, TripID, Duration)>,
+    /// Every time a ride-hailing vehicle finishes driving somewhere, how far did it go, and was it
+    /// carrying a passenger? Empty trips are deadheading.
+    pub ride_hail_distance: Vec<(Time, CarID, Distance, bool)>,
+    /// Every time a delivery truck stops at a building: the lane it stopped in, whether it
+    /// double-parked there, and how long it stayed
+    pub curbside_loading: Vec<(Time, CarID, BuildingID, LaneID, bool, Duration)>,
+    /// Per bike share station, every time somebody takes a bike (true) or returns one (false), and
+    /// how many bikes are left there afterwards
+    pub bike_share_docks: BTreeMap<BikeShareStationID, Vec<(Time, bool, usize)>>,
+    /// Per bike share station, every time somebody had to go elsewhere because there were no
+    /// bikes (true) or no free docks (false)
+    pub bike_share_unavailable: BTreeMap<BikeShareStationID, Vec<(Time, bool)>>,
 
     pub started_trips: BTreeMap<TripID, Time>,
     /// Finish time, ID, mode, trip duration if successful (or None if cancelled)
@@ -57,8 +85,13 @@ pub struct Analytics {
     pub trip_log: Vec<(Time, TripID, Option<PathRequest>, TripPhaseType)>,
 
     // TODO Transit riders aren't represented here yet, just the vehicle they're riding.
-    /// Only for traffic signals. The u8 is the movement index from a CompressedMovementID.
-    pub intersection_delays: BTreeMap<IntersectionID, Vec<(u8, Time, Duration, AgentType)>>,
+    /// Only for traffic signals. The u8 is the movement index from a CompressedMovementID. The
+    /// bool is true if an actuated stage let the agent through, to compare against fixed timing.
+    pub intersection_delays: BTreeMap<IntersectionID, Vec<(u8, Time, Duration, AgentType, bool)>>,
+    /// Every time a traffic signal held a green (false) or ended a stage early (true) for a
+    /// transit vehicle, by how much, and roughly how much delay that saved the vehicle
+    pub transit_signal_priority:
+        BTreeMap<IntersectionID, Vec<(Time, CarID, bool, Duration, Duration)>>,
 
     /// Per parking lane or lot, when does a spot become filled (true) or free (false)
     pub parking_lane_changes: BTreeMap<LaneID, Vec<(Time, bool)>>,
@@ -66,6 +99,18 @@ pub struct Analytics {
 
     pub(crate) alerts: Vec<(Time, AlertLocation, String)>,
 
+    /// Every time a driver switched routes partway through their trip, and how many congested
+    /// intersections they avoided
+    pub reroutes: Vec<(Time, TripID, usize)>,
+
+    /// How long cars took to cross each lane and turn, through the day. This is large, so it's
+    /// not saved with prebaked results.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub travel_times: TravelTimes,
+    /// When each car entered the lane or turn they're currently on
+    #[serde(skip_serializing, skip_deserializing)]
+    entered_traversable: BTreeMap<CarID, (Traversable, Time)>,
+
     /// After we restore from a savestate, don't record anything. This is only going to make sense
     /// if savestates are only used for quickly previewing against prebaked results, where we have
     /// the full Analytics anyway.
@@ -79,18 +124,32 @@ impl Analytics {
             intersection_thruput: TimeSeriesCount::new(),
             traffic_signal_thruput: TimeSeriesCount::new(),
             demand: BTreeMap::new(),
+            bus_dispatches: Vec::new(),
+            transit_stop_offsets: BTreeMap::new(),
             bus_arrivals: Vec::new(),
             passengers_boarding: BTreeMap::new(),
             passengers_alighting: BTreeMap::new(),
+            transit_loads: Vec::new(),
+            passengers_denied: BTreeMap::new(),
+            carpool_occupancy: Vec::new(),
+            ride_hail_waits: Vec::new(),
+            ride_hail_distance: Vec::new(),
+            curbside_loading: Vec::new(),
+            bike_share_docks: BTreeMap::new(),
+            bike_share_unavailable: BTreeMap::new(),
             started_trips: BTreeMap::new(),
             finished_trips: Vec::new(),
             trip_intersection_delays: BTreeMap::new(),
             lane_speed_percentage: BTreeMap::new(),
             trip_log: Vec::new(),
             intersection_delays: BTreeMap::new(),
+            transit_signal_priority: BTreeMap::new(),
             parking_lane_changes: BTreeMap::new(),
             parking_lot_changes: BTreeMap::new(),
             alerts: Vec::new(),
+            reroutes: Vec::new(),
+            travel_times: TravelTimes::new(),
+            entered_traversable: BTreeMap::new(),
             record_anything,
         }
     }
@@ -100,6 +159,8 @@ impl Analytics {
             return;
         }
 
+        self.record_travel_times(&ev, time);
+
         // Throughput
         if let Event::AgentEntersTraversable(a, to, passengers) = ev {
             match to {
@@ -128,7 +189,9 @@ impl Analytics {
                     }
 
                     if let Some(id) = map.get_movement(t) {
-                        *self.demand.entry(id).or_insert(0) -= 1;
+                        // Agents that started before analytics were reset weren't counted
+                        let demand = self.demand.entry(id).or_insert(0);
+                        *demand = demand.saturating_sub(1);
 
                         let m = map.get_traffic_signal(t.parent).compressed_id(t);
                         self.traffic_signal_thruput.record(time, m, a.to_type(), 1);
@@ -154,10 +217,61 @@ impl Analytics {
         }
 
         // Bus arrivals
+        if let Event::BusDispatched(bus, route) = ev {
+            self.bus_dispatches.push((time, bus, route));
+        }
         if let Event::BusArrivedAtStop(bus, route, stop) = ev {
             self.bus_arrivals.push((time, bus, route, stop));
         }
 
+        // Carpool occupancy
+        match ev {
+            Event::PassengerBoardsCar(_, car, _, _, riding)
+            | Event::PassengerAlightsCar(_, car, _, riding) => {
+                self.carpool_occupancy.push((time, car, riding));
+            }
+            _ => {}
+        }
+
+        // Ride-hailing
+        match ev {
+            Event::RideHailPickup(trip, _, wait) => {
+                self.ride_hail_waits.push((time, trip, wait));
+            }
+            Event::RideHailVehicleMoved(car, dist, occupied) => {
+                self.ride_hail_distance.push((time, car, dist, occupied));
+            }
+            _ => {}
+        }
+
+        if let Event::TruckLoading(car, b, l, double_parked, dwell) = ev {
+            self.curbside_loading
+                .push((time, car, b, l, double_parked, dwell));
+        }
+
+        // Bike share
+        match ev {
+            Event::BikeShareUndocked(_, station, bikes) => {
+                self.bike_share_docks
+                    .entry(station)
+                    .or_insert_with(Vec::new)
+                    .push((time, true, bikes));
+            }
+            Event::BikeShareDocked(_, station, bikes) => {
+                self.bike_share_docks
+                    .entry(station)
+                    .or_insert_with(Vec::new)
+                    .push((time, false, bikes));
+            }
+            Event::BikeShareUnavailable(_, station, no_bikes) => {
+                self.bike_share_unavailable
+                    .entry(station)
+                    .or_insert_with(Vec::new)
+                    .push((time, no_bikes));
+            }
+            _ => {}
+        }
+
         // Passengers boarding/alighting
         if let Event::PassengerBoardsTransit(_, _, route, stop, waiting) = ev {
             self.passengers_boarding
@@ -171,6 +285,15 @@ impl Analytics {
                 .or_insert_with(Vec::new)
                 .push((time, route));
         }
+        if let Event::PassengerDeniedBoarding(_, _, route, stop) = ev {
+            self.passengers_denied
+                .entry(stop)
+                .or_insert_with(Vec::new)
+                .push((time, route));
+        }
+        if let Event::BusDepartedFromStop(bus, route, stop, load) = ev {
+            self.transit_loads.push((time, bus, route, stop, load));
+        }
 
         // Started trips
         if let Event::TripPhaseStarting(id, _, _, _) = ev {
@@ -211,7 +334,7 @@ impl Analytics {
                             .insert(turn_id, delay.inner_seconds() as u8);
                     }
                 }
-                AgentID::BusPassenger(_, _) => {}
+                AgentID::BusPassenger(_, _) | AgentID::CarPassenger(_, _) => {}
             }
         }
         // Lane Speed
@@ -226,11 +349,24 @@ impl Analytics {
         }
 
         // Intersection delays
-        if let Event::IntersectionDelayMeasured(id, delay, agent) = ev {
+        if let Event::IntersectionDelayMeasured(id, delay, agent, actuated) = ev {
             self.intersection_delays
                 .entry(id.i)
                 .or_insert_with(Vec::new)
-                .push((id.idx, time, delay, agent.to_type()));
+                .push((id.idx, time, delay, agent.to_type(), actuated));
+        }
+        if let Event::TransitSignalPriority {
+            i,
+            bus,
+            early_green,
+            dt,
+            saved,
+        } = ev
+        {
+            self.transit_signal_priority
+                .entry(i)
+                .or_insert_with(Vec::new)
+                .push((time, bus, early_green, dt, saved));
         }
 
         // Parking spot changes
@@ -277,6 +413,24 @@ impl Analytics {
             Event::PathAmended(path) => {
                 self.record_demand(&path, map);
             }
+            Event::TripRerouted {
+                trip,
+                avoided,
+                old_path,
+                new_path,
+                ..
+            } => {
+                self.reroutes.push((time, trip, avoided.len()));
+                for step in old_path.get_steps() {
+                    if let Traversable::Turn(t) = step.as_traversable() {
+                        if let Some(id) = map.get_movement(t) {
+                            let demand = self.demand.entry(id).or_insert(0);
+                            *demand = demand.saturating_sub(1);
+                        }
+                    }
+                }
+                self.record_demand(&new_path, map);
+            }
             Event::Alert(loc, msg) => {
                 self.alerts.push((time, loc, msg));
             }
@@ -284,6 +438,39 @@ impl Analytics {
         }
     }
 
+    /// Only cars are measured; bikes and buses are much slower for other reasons.
+    fn record_travel_times(&mut self, ev: &Event, time: Time) {
+        match *ev {
+            Event::AgentEntersTraversable(AgentID::Car(car), to, _) => {
+                if car.1 != VehicleType::Car {
+                    return;
+                }
+                if let Some((from, entered)) = self.entered_traversable.insert(car, (to, time)) {
+                    // Cars reuse their ID for later trips, so make sure they moved directly from
+                    // the last thing to this one. Otherwise they stopped somewhere in between.
+                    let connected = match (from, to) {
+                        (Traversable::Lane(l), Traversable::Turn(t)) => t.src == l,
+                        (Traversable::Turn(t), Traversable::Lane(l)) => t.dst == l,
+                        _ => false,
+                    };
+                    if connected {
+                        self.travel_times.record(from, entered, time - entered);
+                    }
+                }
+            }
+            // The car stopped or vanished partway along a lane. If it later continues from the
+            // same lane, the time spent parked or off the map isn't travel time.
+            Event::CarReachedParkingSpot(car, _)
+            | Event::CarLeftParkingSpot(car, _)
+            | Event::TruckLoading(car, _, _, _, _)
+            | Event::PersonLeavesMap(_, Some(AgentID::Car(car)), _)
+            | Event::PersonEntersMap(_, AgentID::Car(car), _) => {
+                self.entered_traversable.remove(&car);
+            }
+            _ => {}
+        }
+    }
+
     pub fn record_demand(&mut self, path: &Path, map: &Map) {
         for step in path.get_steps() {
             if let Traversable::Turn(t) = step.as_traversable() {
@@ -294,6 +481,18 @@ impl Analytics {
         }
     }
 
+    /// Like `record_demand`, but for an agent that's already partway along its path. The current
+    /// step was already counted when the agent entered it.
+    pub fn record_remaining_demand(&mut self, path: &Path, map: &Map) {
+        for step in path.get_steps().iter().skip(1) {
+            if let Traversable::Turn(t) = step.as_traversable() {
+                if let Some(id) = map.get_movement(t) {
+                    *self.demand.entry(id).or_insert(0) += 1;
+                }
+            }
+        }
+    }
+
     // TODO If these ever need to be speeded up, just cache the histogram and index in the events
     // list.
 
@@ -435,6 +634,315 @@ impl Analytics {
         pts
     }
 
+    /// The average delay through a traffic signal while actuated (or fixed) stages were running,
+    /// if anybody went through then.
+    pub fn avg_signal_delay(&self, i: IntersectionID, actuated: bool) -> Option<Duration> {
+        let mut total = Duration::ZERO;
+        let mut count = 0;
+        for (_, _, dt, _, was_actuated) in self.intersection_delays.get(&i)? {
+            if *was_actuated == actuated {
+                total += *dt;
+                count += 1;
+            }
+        }
+        if count == 0 {
+            None
+        } else {
+            Some(total / (count as f64))
+        }
+    }
+
+    /// For every lane that delivery trucks double-parked in, the total time it was blocked.
+    pub fn double_parking_per_lane(&self) -> BTreeMap<LaneID, Duration> {
+        let mut per_lane = BTreeMap::new();
+        for (_, _, _, l, double_parked, dwell) in &self.curbside_loading {
+            if *double_parked {
+                *per_lane.entry(*l).or_insert(Duration::ZERO) += *dwell;
+            }
+        }
+        per_lane
+    }
+
+    /// For every stop along a transit route, summarize how crowded vehicles were when leaving it.
+    pub fn transit_load_profile(&self, route: BusRouteID, map: &Map) -> Vec<StopLoad> {
+        let mut results = Vec::new();
+        for stop in &map.get_br(route).stops {
+            let mut load = StopLoad {
+                stop: *stop,
+                departures: 0,
+                boardings: self
+                    .passengers_boarding
+                    .get(stop)
+                    .map(|list| list.iter().filter(|(_, r, _)| *r == route).count())
+                    .unwrap_or(0),
+                alightings: self
+                    .passengers_alighting
+                    .get(stop)
+                    .map(|list| list.iter().filter(|(_, r)| *r == route).count())
+                    .unwrap_or(0),
+                denied: self
+                    .passengers_denied
+                    .get(stop)
+                    .map(|list| list.iter().filter(|(_, r)| *r == route).count())
+                    .unwrap_or(0),
+                total_load: 0,
+                max_load: 0,
+            };
+            for (_, _, r, s, passengers) in &self.transit_loads {
+                if *r == route && s == stop {
+                    load.departures += 1;
+                    load.total_load += *passengers;
+                    load.max_load = load.max_load.max(*passengers);
+                }
+            }
+            results.push(load);
+        }
+        results
+    }
+
+    /// For every vehicle that's served a transit route, how many passengers were aboard when it
+    /// left each stop.
+    pub fn transit_vehicle_loads(
+        &self,
+        route: BusRouteID,
+    ) -> BTreeMap<CarID, Vec<(Time, BusStopID, usize)>> {
+        let mut per_vehicle = BTreeMap::new();
+        for (t, bus, r, stop, passengers) in &self.transit_loads {
+            if *r == route {
+                per_vehicle
+                    .entry(*bus)
+                    .or_insert_with(Vec::new)
+                    .push((*t, *stop, *passengers));
+            }
+        }
+        per_vehicle
+    }
+
+    /// Every time a vehicle serving this route reached a stop, compared against when it was
+    /// scheduled to arrive there: the route's timetabled departure plus the expected time to reach
+    /// the stop. Vehicles that were dispatched before analytics started recording are skipped.
+    pub fn transit_schedule_adherence(
+        &self,
+        route: BusRouteID,
+        map: &Map,
+    ) -> Vec<ScheduledArrival> {
+        let br = map.get_br(route);
+        let offsets = match self.transit_stop_offsets.get(&route) {
+            Some(offsets) => offsets,
+            None => {
+                return Vec::new();
+            }
+        };
+        // Match each dispatch to the latest departure in the timetable at or before it
+        let dispatched: BTreeMap<CarID, Time> = self
+            .bus_dispatches
+            .iter()
+            .filter(|(_, _, r)| *r == route)
+            .filter_map(|(t, bus, _)| {
+                let idx = br.spawn_times.iter().rposition(|spawn| spawn <= t)?;
+                Some((*bus, br.spawn_times[idx]))
+            })
+            .collect();
+        // Routes may visit the same stop twice, so track how far along each vehicle is
+        let mut next_idx: BTreeMap<CarID, usize> = BTreeMap::new();
+
+        let mut results = Vec::new();
+        for (t, bus, r, stop) in &self.bus_arrivals {
+            if *r != route {
+                continue;
+            }
+            let start = if let Some(t) = dispatched.get(bus) {
+                *t
+            } else {
+                continue;
+            };
+            let idx = next_idx.entry(*bus).or_insert(0);
+            if let Some(offset) = br.stops[*idx..]
+                .iter()
+                .position(|s| s == stop)
+                .map(|i| *idx + i)
+            {
+                results.push(ScheduledArrival {
+                    bus: *bus,
+                    stop: *stop,
+                    scheduled: start + offsets[offset],
+                    actual: *t,
+                });
+                *idx = offset + 1;
+            }
+        }
+        results
+    }
+
+    /// Summarize schedule adherence and headways at every stop along a transit route.
+    pub fn transit_route_performance(&self, route: BusRouteID, map: &Map) -> RoutePerformance {
+        let arrivals = self.transit_schedule_adherence(route, map);
+        let mut stops = Vec::new();
+        for stop in &map.get_br(route).stops {
+            let mut perf = StopPerformance {
+                stop: *stop,
+                arrivals: 0,
+                early: 0,
+                on_time: 0,
+                late: 0,
+                total_delay: Duration::ZERO,
+                max_delay: Duration::ZERO,
+                headways: Vec::new(),
+                scheduled_headways: Vec::new(),
+                bunched: 0,
+            };
+            let mut last: Option<&ScheduledArrival> = None;
+            // If a route visits a stop twice, both visits count here
+            let mut here: Vec<&ScheduledArrival> =
+                arrivals.iter().filter(|a| a.stop == *stop).collect();
+            here.sort_by_key(|a| a.actual);
+            for a in here {
+                perf.arrivals += 1;
+                let delay = a.delay();
+                if delay < Duration::ZERO - ON_TIME_EARLY {
+                    perf.early += 1;
+                } else if delay > ON_TIME_LATE {
+                    perf.late += 1;
+                } else {
+                    perf.on_time += 1;
+                }
+                perf.total_delay += delay;
+                perf.max_delay = perf.max_delay.max(delay);
+
+                if let Some(prev) = last {
+                    let headway = a.actual - prev.actual;
+                    let scheduled = a.scheduled - prev.scheduled;
+                    if headway < BUNCHING_THRESHOLD * scheduled {
+                        perf.bunched += 1;
+                    }
+                    perf.headways.push(headway);
+                    perf.scheduled_headways.push(scheduled);
+                }
+                last = Some(a);
+            }
+            stops.push(perf);
+        }
+        RoutePerformance {
+            route,
+            arrivals,
+            stops,
+        }
+    }
+
+    /// Summarizes how every bike share station has been used so far, to find stations that need
+    /// rebalancing or more docks.
+    pub fn bike_share_station_stats(
+        &self,
+        now: Time,
+        map: &Map,
+    ) -> BTreeMap<BikeShareStationID, BikeShareStationStats> {
+        let mut results = BTreeMap::new();
+        for station in map.all_bike_share_stations() {
+            let mut stats = BikeShareStationStats {
+                pickups: 0,
+                dropoffs: 0,
+                no_bikes: 0,
+                no_docks: 0,
+                time_empty: Duration::ZERO,
+                time_full: Duration::ZERO,
+                min_bikes: station.initial_bikes,
+                max_bikes: station.initial_bikes,
+            };
+
+            // Track how long the station spent empty or full between every change
+            let mut last_change = Time::START_OF_DAY;
+            let mut bikes = station.initial_bikes;
+            let mut add_interval = |stats: &mut BikeShareStationStats, bikes: usize, t: Time| {
+                if bikes == 0 {
+                    stats.time_empty += t - last_change;
+                } else if bikes >= station.capacity {
+                    stats.time_full += t - last_change;
+                }
+                last_change = t;
+            };
+            if let Some(changes) = self.bike_share_docks.get(&station.id) {
+                for (t, undocked, after) in changes {
+                    add_interval(&mut stats, bikes, *t);
+                    if *undocked {
+                        stats.pickups += 1;
+                    } else {
+                        stats.dropoffs += 1;
+                    }
+                    bikes = *after;
+                    stats.min_bikes = stats.min_bikes.min(bikes);
+                    stats.max_bikes = stats.max_bikes.max(bikes);
+                }
+            }
+            add_interval(&mut stats, bikes, now);
+
+            for (_, no_bikes) in self
+                .bike_share_unavailable
+                .get(&station.id)
+                .unwrap_or(&Vec::new())
+            {
+                if *no_bikes {
+                    stats.no_bikes += 1;
+                } else {
+                    stats.no_docks += 1;
+                }
+            }
+            results.insert(station.id, stats);
+        }
+        results
+    }
+
+    /// Summarizes transit signal priority at one traffic signal: how often it kicked in, and how
+    /// long transit vehicles and everybody on the cross streets waited, and roughly how much delay
+    /// priority saved transit. Movements that no transit vehicle has used count as the cross
+    /// streets.
+    pub fn transit_priority_stats(&self, i: IntersectionID) -> TransitPriorityStats {
+        let mut stats = TransitPriorityStats {
+            green_extensions: 0,
+            early_greens: 0,
+            time_extended: Duration::ZERO,
+            time_cut_short: Duration::ZERO,
+            transit_crossings: 0,
+            transit_delay: Duration::ZERO,
+            transit_delay_saved: Duration::ZERO,
+            cross_street_crossings: 0,
+            cross_street_delay: Duration::ZERO,
+        };
+        // Each record is one stage held or cut short.
+        for (_, _, early_green, dt, saved) in
+            self.transit_signal_priority.get(&i).unwrap_or(&Vec::new())
+        {
+            if *early_green {
+                stats.early_greens += 1;
+                stats.time_cut_short += *dt;
+            } else {
+                stats.green_extensions += 1;
+                stats.time_extended += *dt;
+            }
+            stats.transit_delay_saved += *saved;
+        }
+
+        let delays = self
+            .intersection_delays
+            .get(&i)
+            .cloned()
+            .unwrap_or_default();
+        let transit_movements: BTreeSet<u8> = delays
+            .iter()
+            .filter(|(_, _, _, agent, _)| *agent == AgentType::Bus || *agent == AgentType::Train)
+            .map(|(idx, _, _, _, _)| *idx)
+            .collect();
+        for (idx, _, delay, agent, _) in delays {
+            if agent == AgentType::Bus || agent == AgentType::Train {
+                stats.transit_crossings += 1;
+                stats.transit_delay += delay;
+            } else if !transit_movements.contains(&idx) {
+                stats.cross_street_crossings += 1;
+                stats.cross_street_delay += delay;
+            }
+        }
+        stats
+    }
+
     /// Returns the free spots over time
     pub fn parking_lane_availability(
         &self,
@@ -512,6 +1020,191 @@ pub struct TripPhase {
     pub phase_type: TripPhaseType,
 }
 
+/// How one bike share station has been used through the day
+#[derive(Clone, Debug, PartialEq)]
+pub struct BikeShareStationStats {
+    pub pickups: usize,
+    pub dropoffs: usize,
+    /// How many people showed up to an empty station
+    pub no_bikes: usize,
+    /// How many people couldn't return their bike, because every dock was full
+    pub no_docks: usize,
+    pub time_empty: Duration,
+    pub time_full: Duration,
+    pub min_bikes: usize,
+    pub max_bikes: usize,
+}
+
+impl BikeShareStationStats {
+    /// Positive if more bikes were returned than taken, so a rebalancing crew would have to move
+    /// bikes away from here.
+    pub fn net_flow(&self) -> isize {
+        (self.dropoffs as isize) - (self.pickups as isize)
+    }
+}
+
+/// How transit signal priority has worked out at one traffic signal
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct TransitPriorityStats {
+    pub green_extensions: usize,
+    pub early_greens: usize,
+    pub time_extended: Duration,
+    pub time_cut_short: Duration,
+    pub transit_crossings: usize,
+    /// The total delay over all transit crossings
+    pub transit_delay: Duration,
+    /// Roughly how much more transit vehicles would've waited without priority: the red they
+    /// would've hit when the green was held, and the time a stage was cut short by
+    pub transit_delay_saved: Duration,
+    pub cross_street_crossings: usize,
+    /// The total delay over all cross street crossings
+    pub cross_street_delay: Duration,
+}
+
+impl TransitPriorityStats {
+    pub fn avg_transit_delay(&self) -> Option<Duration> {
+        if self.transit_crossings == 0 {
+            None
+        } else {
+            Some(self.transit_delay / (self.transit_crossings as f64))
+        }
+    }
+
+    pub fn avg_cross_street_delay(&self) -> Option<Duration> {
+        if self.cross_street_crossings == 0 {
+            None
+        } else {
+            Some(self.cross_street_delay / (self.cross_street_crossings as f64))
+        }
+    }
+}
+
+/// Arrivals no more than this early count as on-time
+const ON_TIME_EARLY: Duration = Duration::const_seconds(60.0);
+/// Arrivals no more than this late count as on-time
+const ON_TIME_LATE: Duration = Duration::const_seconds(5.0 * 60.0);
+/// If a vehicle arrives sooner after the previous one than this fraction of the scheduled gap
+/// between them, the two are bunched.
+const BUNCHING_THRESHOLD: f64 = 0.25;
+
+/// When a transit vehicle reached a stop, and when it was supposed to
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ScheduledArrival {
+    pub bus: CarID,
+    pub stop: BusStopID,
+    pub scheduled: Time,
+    pub actual: Time,
+}
+
+impl ScheduledArrival {
+    /// Negative if the vehicle was early
+    pub fn delay(&self) -> Duration {
+        self.actual - self.scheduled
+    }
+}
+
+/// How closely one transit route has kept to its schedule
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct RoutePerformance {
+    pub route: BusRouteID,
+    pub arrivals: Vec<ScheduledArrival>,
+    /// In order along the route
+    pub stops: Vec<StopPerformance>,
+}
+
+impl RoutePerformance {
+    /// The percentage of arrivals at any stop that were on-time, from 0 to 1. None if nothing has
+    /// arrived yet.
+    pub fn on_time_pct(&self) -> Option<f64> {
+        let arrivals: usize = self.stops.iter().map(|s| s.arrivals).sum();
+        if arrivals == 0 {
+            return None;
+        }
+        let on_time: usize = self.stops.iter().map(|s| s.on_time).sum();
+        Some((on_time as f64) / (arrivals as f64))
+    }
+
+    pub fn bunched(&self) -> usize {
+        self.stops.iter().map(|s| s.bunched).sum()
+    }
+}
+
+/// Schedule adherence and headways at one stop along a transit route. Arrivals up to 1 minute
+/// early or 5 minutes late count as on-time.
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct StopPerformance {
+    pub stop: BusStopID,
+    pub arrivals: usize,
+    pub early: usize,
+    pub on_time: usize,
+    pub late: usize,
+    /// Early arrivals subtract from this
+    pub total_delay: Duration,
+    pub max_delay: Duration,
+    /// The actual time between consecutive arrivals
+    pub headways: Vec<Duration>,
+    /// The scheduled time between the same consecutive arrivals
+    pub scheduled_headways: Vec<Duration>,
+    /// How many times a vehicle arrived right behind the previous one
+    pub bunched: usize,
+}
+
+impl StopPerformance {
+    pub fn avg_delay(&self) -> Option<Duration> {
+        if self.arrivals == 0 {
+            None
+        } else {
+            Some(self.total_delay / (self.arrivals as f64))
+        }
+    }
+
+    /// How irregular the headways are, as the coefficient of variation: the standard deviation
+    /// over the mean. 0 means vehicles arrived perfectly evenly spaced. None if there aren't at
+    /// least two headways.
+    pub fn headway_variation(&self) -> Option<f64> {
+        if self.headways.len() < 2 {
+            return None;
+        }
+        let n = self.headways.len() as f64;
+        let mean = self.headways.iter().map(|h| h.inner_seconds()).sum::<f64>() / n;
+        if mean == 0.0 {
+            return None;
+        }
+        let variance = self
+            .headways
+            .iter()
+            .map(|h| (h.inner_seconds() - mean).powi(2))
+            .sum::<f64>()
+            / n;
+        Some(variance.sqrt() / mean)
+    }
+}
+
+/// How crowded transit vehicles were when leaving one stop along a route
+#[derive(Clone, Debug, PartialEq)]
+pub struct StopLoad {
+    pub stop: BusStopID,
+    /// How many times a vehicle left this stop
+    pub departures: usize,
+    pub boardings: usize,
+    pub alightings: usize,
+    /// How many times somebody couldn't get on, because the vehicle was full
+    pub denied: usize,
+    /// The sum of passengers aboard over all departures
+    pub total_load: usize,
+    pub max_load: usize,
+}
+
+impl StopLoad {
+    pub fn avg_load(&self) -> f64 {
+        if self.departures == 0 {
+            0.0
+        } else {
+            (self.total_load as f64) / (self.departures as f64)
+        }
+    }
+}
+
 /// See https://github.com/dabreegster/abstreet/issues/85
 #[derive(Clone, Serialize, Deserialize)]
 pub struct TimeSeriesCount<X: Ord + Clone> {
@@ -654,3 +1347,62 @@ impl Window {
         self.times.len()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parked_time_isnt_travel_time() {
+        let car = CarID(0, VehicleType::Car);
+        let i1 = IntersectionID(0);
+        let i2 = IntersectionID(1);
+        let l1 = LaneID(0);
+        let l2 = LaneID(1);
+        let l3 = LaneID(2);
+        let t1 = TurnID {
+            parent: i1,
+            src: l1,
+            dst: l2,
+        };
+        let t2 = TurnID {
+            parent: i2,
+            src: l2,
+            dst: l3,
+        };
+        let start = Time::START_OF_DAY + Duration::hours(7);
+        let mut analytics = Analytics::new(true);
+        let enter = |analytics: &mut Analytics, on: Traversable, dt: f64| {
+            analytics.record_travel_times(
+                &Event::AgentEntersTraversable(AgentID::Car(car), on, None),
+                start + Duration::seconds(dt),
+            );
+        };
+
+        // Cross the first lane in 10s, then park on the next lane
+        enter(&mut analytics, Traversable::Lane(l1), 0.0);
+        enter(&mut analytics, Traversable::Turn(t1), 10.0);
+        enter(&mut analytics, Traversable::Lane(l2), 15.0);
+        analytics.record_travel_times(
+            &Event::CarReachedParkingSpot(car, ParkingSpot::Onstreet(l2, 0)),
+            start + Duration::seconds(20.0),
+        );
+        // An hour later, leave and drive off the lane
+        analytics.record_travel_times(
+            &Event::CarLeftParkingSpot(car, ParkingSpot::Onstreet(l2, 0)),
+            start + Duration::seconds(3600.0),
+        );
+        enter(&mut analytics, Traversable::Turn(t2), 3610.0);
+
+        let times = &analytics.travel_times;
+        assert_eq!(
+            times.get(Traversable::Lane(l1), start),
+            Some(Duration::seconds(10.0))
+        );
+        assert_eq!(
+            times.get(Traversable::Turn(t1), start),
+            Some(Duration::seconds(5.0))
+        );
+        assert_eq!(times.get(Traversable::Lane(l2), start), None);
+    }
+}
@@ -0,0 +1,130 @@
+use std::sync::Arc;
+
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use serde::Serialize;
+
+use abstutil::{Parallelism, Timer};
+use geom::{Duration, Time};
+use map_model::{Map, PathConstraints, PathRequest, TravelTimes};
+
+use crate::{Scenario, Sim, SimOptions, TripPhaseType};
+
+/// Describes one day simulated by `iterative_assignment`.
+#[derive(Clone, Debug, Serialize)]
+pub struct AssignmentIteration {
+    /// How many drivers started driving
+    pub num_drivers: usize,
+    /// How many of those drivers would pick a different route, given the travel times observed
+    /// so far
+    pub num_changed_routes: usize,
+    /// The total time spent by all finished trips
+    pub total_trip_time: Duration,
+}
+
+/// Find a dynamic user equilibrium, where drivers can't find a faster route given the congestion
+/// they'll encounter. This repeatedly simulates the full day. Each time, drivers route using the
+/// travel times observed so far, averaged across all previous days (the method of successive
+/// averages). This stops after `max_iterations`, or once the fraction of drivers who'd change
+/// their route drops to `tolerance`.
+///
+/// Returns the final travel times, which can be passed to `SimOptions` to use in other runs, and
+/// a summary of every iteration.
+pub fn iterative_assignment(
+    map: &Map,
+    scenario: &Scenario,
+    opts: SimOptions,
+    rng_seed: u64,
+    max_iterations: usize,
+    tolerance: f64,
+    timer: &mut Timer,
+) -> (TravelTimes, Vec<AssignmentIteration>) {
+    let mut travel_times = opts.travel_times.clone();
+    let mut iterations = Vec::new();
+    for iteration in 0..max_iterations {
+        timer.start(format!("assignment iteration {}", iteration + 1));
+
+        let mut sim = Sim::new(
+            map,
+            SimOptions {
+                travel_times: travel_times.clone(),
+                // The observed travel times come from analytics
+                skip_analytics: false,
+                ..opts.clone()
+            },
+            timer,
+        );
+        let mut rng = XorShiftRng::seed_from_u64(rng_seed);
+        scenario.instantiate(&mut sim, map, &mut rng, timer);
+        sim.timed_step(map, Duration::hours(24), &mut None, timer);
+        let analytics = sim.get_analytics();
+
+        let observed = &analytics.travel_times;
+        let next = match travel_times {
+            Some(ref prev) => prev.blend(observed, 1.0 / (iteration as f64 + 1.0)),
+            None => observed.clone(),
+        };
+
+        // Would drivers pick a different route now?
+        let requests: Vec<(Time, PathRequest)> = analytics
+            .trip_log
+            .iter()
+            .filter_map(|(t, _, req, phase)| match (req, phase) {
+                (Some(req), TripPhaseType::Driving) if req.constraints == PathConstraints::Car => {
+                    Some((*t, req.clone()))
+                }
+                _ => None,
+            })
+            .collect();
+        let num_drivers = requests.len();
+        let prev = &travel_times;
+        let next_ref = &next;
+        let changed = timer.parallelize(
+            "check for better routes",
+            Parallelism::Fastest,
+            requests,
+            |(t, req)| {
+                let old = match prev {
+                    Some(ref times) => times.pathfind(req.clone(), t, map),
+                    None => map.pathfind(req.clone()),
+                };
+                let new = next_ref.pathfind(req, t, map);
+                match (old, new) {
+                    (Some(old), Some(new)) => old.get_steps() != new.get_steps(),
+                    _ => false,
+                }
+            },
+        );
+        let num_changed_routes = changed.into_iter().filter(|x| *x).count();
+
+        let mut total_trip_time = Duration::ZERO;
+        for (_, _, _, maybe_dt) in &analytics.finished_trips {
+            if let Some(dt) = maybe_dt {
+                total_trip_time += *dt;
+            }
+        }
+
+        timer.note(format!(
+            "Iteration {}: {} of {} drivers would change routes",
+            iteration + 1,
+            num_changed_routes,
+            num_drivers
+        ));
+        iterations.push(AssignmentIteration {
+            num_drivers,
+            num_changed_routes,
+            total_trip_time,
+        });
+        travel_times = Some(Arc::new(next));
+        timer.stop(format!("assignment iteration {}", iteration + 1));
+
+        if num_drivers == 0 || (num_changed_routes as f64) / (num_drivers as f64) <= tolerance {
+            break;
+        }
+    }
+
+    (
+        travel_times.map(|t| (*t).clone()).unwrap_or_default(),
+        iterations,
+    )
+}
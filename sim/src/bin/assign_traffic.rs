@@ -0,0 +1,49 @@
+//! Repeatedly simulates a scenario until drivers stop finding faster routes, then writes the
+//! observed travel times. Pass the output to other tools with `--travel_times`, so drivers route
+//! around the congestion they'll encounter.
+
+use abstutil::Timer;
+use map_model::Map;
+use sim::{Scenario, SimFlags};
+
+fn main() {
+    let mut args = abstutil::CmdArgs::new();
+    let iterations = args
+        .optional_parse("--iterations", |s| s.parse::<usize>())
+        .unwrap_or(10);
+    let tolerance = args
+        .optional_parse("--tolerance", |s| s.parse::<f64>())
+        .unwrap_or(0.01);
+    let output = args
+        .optional("--output")
+        .unwrap_or_else(|| "travel_times.bin".to_string());
+    let flags = SimFlags::from_args(&mut args);
+    args.done();
+
+    let mut timer = Timer::new("assign traffic");
+    let mut scenario: Scenario = abstutil::read_binary(flags.load.clone(), &mut timer);
+    let map = Map::new(scenario.map_name.path(), &mut timer);
+    for m in &flags.modifiers {
+        scenario = m.apply(&map, scenario);
+    }
+
+    let (travel_times, summary) = sim::iterative_assignment(
+        &map,
+        &scenario,
+        flags.opts,
+        flags.rng_seed,
+        iterations,
+        tolerance,
+        &mut timer,
+    );
+    for (idx, iteration) in summary.into_iter().enumerate() {
+        println!(
+            "Iteration {}: {} of {} drivers would change routes, total trip time {}",
+            idx + 1,
+            iteration.num_changed_routes,
+            iteration.num_drivers,
+            iteration.total_trip_time
+        );
+    }
+    abstutil::write_binary(output, &travel_times);
+}
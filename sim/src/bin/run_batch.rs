@@ -0,0 +1,200 @@
+//! Runs many simulations in parallel, varying the map, scenario, edits, RNG seed, and options, and
+//! writes a CSV summarizing each run. Use this for parameter sweeps.
+//!
+//! The manifest is a JSON list of runs, like:
+//!
+//! ```json
+//! [
+//!   {
+//!     "name": "baseline",
+//!     "map": { "city": "seattle", "map": "montlake" },
+//!     "scenario": "weekday"
+//!   },
+//!   {
+//!     "name": "no_lc",
+//!     "map": { "city": "seattle", "map": "montlake" },
+//!     "scenario": "weekday",
+//!     "modifiers": [],
+//!     "edits": null,
+//!     "rng_seed": 7,
+//!     "opts": { "recalc_lanechanging": false }
+//!   }
+//! ]
+//! ```
+//!
+//! Only `name`, `map`, and `scenario` are required. `edits` is a `PermanentMapEdits` and `opts` is
+//! a partial `SimOptions`; anything not specified keeps its default.
+
+use std::fs::File;
+use std::io::Write;
+
+use rand::SeedableRng;
+use rand_xorshift::XorShiftRng;
+use serde::Deserialize;
+
+use abstutil::{MapName, Parallelism, Timer};
+use geom::{Duration, Histogram, Statistic};
+use map_model::{Map, PermanentMapEdits};
+use sim::{Scenario, ScenarioModifier, Sim, SimFlags, SimOptions, TripMode};
+
+#[derive(Deserialize)]
+struct Run {
+    name: String,
+    map: MapName,
+    scenario: String,
+    #[serde(default)]
+    modifiers: Vec<ScenarioModifier>,
+    #[serde(default)]
+    edits: Option<PermanentMapEdits>,
+    #[serde(default = "default_rng_seed")]
+    rng_seed: u64,
+    #[serde(default)]
+    opts: SimOptions,
+}
+
+fn default_rng_seed() -> u64 {
+    SimFlags::RNG_SEED
+}
+
+struct Summary {
+    name: String,
+    /// Per mode, the durations of finished trips
+    trip_times: Vec<(TripMode, Histogram<Duration>)>,
+    cancelled_trips: usize,
+    /// Summed over all agents crossing traffic signals
+    total_intersection_delay: Duration,
+}
+
+fn main() {
+    let mut args = abstutil::CmdArgs::new();
+    let manifest_path = args.required("--manifest");
+    let output = args
+        .optional("--output")
+        .unwrap_or_else(|| "batch_results.csv".to_string());
+    let hours = Duration::hours(
+        args.optional_parse("--hours", |s| s.parse::<usize>())
+            .unwrap_or(24),
+    );
+    let parallelism = if args.enabled("--use_all_cpus") {
+        Parallelism::Fastest
+    } else {
+        // Every run loads its own copy of the map, so be careful about memory
+        Parallelism::Polite
+    };
+    args.done();
+
+    let mut timer = Timer::new("run batch");
+    let runs: Vec<Run> = abstutil::read_json(manifest_path, &mut timer);
+    let summaries = timer.parallelize("run simulations", parallelism, runs, |run| {
+        run_one(run, hours)
+    });
+
+    let mut f = File::create(&output).unwrap();
+    writeln!(f, "{}", header()).unwrap();
+    for summary in summaries {
+        writeln!(f, "{}", summary.to_row()).unwrap();
+    }
+    println!("Wrote {}", output);
+}
+
+fn run_one(run: Run, hours: Duration) -> Summary {
+    let mut timer = Timer::throwaway();
+    let mut map = Map::new(run.map.path(), &mut timer);
+    if let Some(perma) = run.edits.clone() {
+        let edits = perma
+            .to_edits(&map)
+            .unwrap_or_else(|err| panic!("Bad edits for run {}: {}", run.name, err));
+        map.must_apply_edits(edits, &mut timer);
+        map.recalculate_pathfinding_after_edits(&mut timer);
+    }
+
+    let mut scenario: Scenario =
+        abstutil::read_binary(abstutil::path_scenario(&run.map, &run.scenario), &mut timer);
+    for m in &run.modifiers {
+        scenario = m.apply(&map, scenario);
+    }
+
+    let mut opts = run.opts;
+    opts.run_name = run.name.clone();
+    let mut sim = Sim::new(&map, opts, &mut timer);
+    let mut rng = XorShiftRng::seed_from_u64(run.rng_seed);
+    scenario.instantiate(&mut sim, &map, &mut rng, &mut timer);
+    sim.timed_step(&map, hours, &mut None, &mut timer);
+
+    let analytics = sim.get_analytics();
+    let mut trip_times: Vec<(TripMode, Histogram<Duration>)> = TripMode::all()
+        .into_iter()
+        .map(|mode| (mode, Histogram::new()))
+        .collect();
+    let mut cancelled_trips = 0;
+    for (_, _, mode, maybe_dt) in &analytics.finished_trips {
+        if let Some(dt) = maybe_dt {
+            for (m, hgram) in &mut trip_times {
+                if m == mode {
+                    hgram.add(*dt);
+                }
+            }
+        } else {
+            cancelled_trips += 1;
+        }
+    }
+    let mut total_intersection_delay = Duration::ZERO;
+    for delays in analytics.intersection_delays.values() {
+        for (_, _, dt, _, _) in delays {
+            total_intersection_delay += *dt;
+        }
+    }
+
+    Summary {
+        name: run.name,
+        trip_times,
+        cancelled_trips,
+        total_intersection_delay,
+    }
+}
+
+const STATS: [(Statistic, &str); 4] = [
+    (Statistic::P50, "p50"),
+    (Statistic::P90, "p90"),
+    (Statistic::P99, "p99"),
+    (Statistic::Max, "max"),
+];
+
+/// Run names can contain anything, including commas and quotes.
+fn quote(x: &str) -> String {
+    format!("\"{}\"", x.replace('"', "\"\""))
+}
+
+fn header() -> String {
+    let mut cols = vec!["run".to_string()];
+    for mode in TripMode::all() {
+        let mode = format!("{:?}", mode).to_lowercase();
+        cols.push(format!("{}_finished", mode));
+        for (_, stat) in &STATS {
+            cols.push(format!("{}_{}_seconds", mode, stat));
+        }
+    }
+    cols.push("cancelled_trips".to_string());
+    cols.push("total_intersection_delay_seconds".to_string());
+    cols.join(",")
+}
+
+impl Summary {
+    fn to_row(&self) -> String {
+        let mut cols = vec![quote(&self.name)];
+        for (_, hgram) in &self.trip_times {
+            cols.push(hgram.count().to_string());
+            for (stat, _) in &STATS {
+                cols.push(
+                    hgram
+                        .select(*stat)
+                        .map(|dt| dt.inner_seconds().to_string())
+                        .unwrap_or_else(String::new),
+                );
+            }
+        }
+        cols.push(self.cancelled_trips.to_string());
+        cols.push(self.total_intersection_delay.inner_seconds().to_string());
+        cols.join(",")
+    }
+}
@@ -3,7 +3,9 @@ use std::collections::{BTreeMap, BTreeSet};
 use serde::{Deserialize, Serialize};
 
 use geom::{Duration, Time};
-use map_model::{LaneID, Map, Path, PathConstraints, PathRequest, PathStep, TurnID};
+use map_model::{
+    AccessRestrictions, LaneID, Map, Path, PathConstraints, PathRequest, PathStep, TurnID,
+};
 
 use crate::mechanics::IntersectionSimState;
 use crate::{CarID, SimOptions, VehicleType};
@@ -12,7 +14,7 @@ use crate::{CarID, SimOptions, VehicleType};
 type ZoneIdx = usize;
 
 /// Some roads (grouped into zones) may have a cap on the number of vehicles that can enter per
-/// hour. CapSimState enforces this, just for driving trips.
+/// hour, maybe only during part of the day. CapSimState enforces this, just for driving trips.
 #[derive(Serialize, Deserialize, Clone)]
 pub(crate) struct CapSimState {
     lane_to_zone: BTreeMap<LaneID, ZoneIdx>,
@@ -22,7 +24,7 @@ pub(crate) struct CapSimState {
 
 #[derive(Serialize, Deserialize, Clone)]
 struct Zone {
-    cap: usize,
+    restrictions: AccessRestrictions,
     entered_in_last_hour: BTreeSet<CarID>,
     // TODO Maybe want sliding windows or something else
     hour_started: Time,
@@ -38,7 +40,7 @@ impl CapSimState {
                 .map(|delay_threshold| AvoidCongestion { delay_threshold }),
         };
         for z in map.all_zones() {
-            if let Some(cap) = z.restrictions.cap_vehicles_per_hour {
+            if z.restrictions.ever_capped() {
                 let idx = sim.zones.len();
                 for r in &z.members {
                     for l in map.get_r(*r).all_lanes() {
@@ -48,7 +50,7 @@ impl CapSimState {
                     }
                 }
                 sim.zones.push(Zone {
-                    cap,
+                    restrictions: z.restrictions.clone(),
                     entered_in_last_hour: BTreeSet::new(),
                     hour_started: Time::START_OF_DAY,
                 });
@@ -65,13 +67,19 @@ impl CapSimState {
             if let PathStep::Lane(l) = step {
                 if let Some(idx) = self.lane_to_zone.get(l) {
                     let zone = &mut self.zones[*idx];
+                    let cap = match zone.restrictions.cap_vehicles_per_hour_at(now) {
+                        Some(cap) => cap,
+                        None => {
+                            continue;
+                        }
+                    };
 
                     if now - zone.hour_started >= Duration::hours(1) {
                         zone.hour_started = Time::START_OF_DAY + Duration::hours(now.get_parts().0);
                         zone.entered_in_last_hour.clear();
                     }
 
-                    if zone.entered_in_last_hour.len() >= zone.cap
+                    if zone.entered_in_last_hour.len() >= cap
                         && !zone.entered_in_last_hour.contains(&car)
                     {
                         return false;
@@ -111,17 +119,39 @@ impl CapSimState {
         // TODO Make the responses configurable: cancel the trip, reroute, delay an hour, switch
         // modes. Where should this policy be specified? Is it simulation-wide?
 
-        let mut avoid_lanes: BTreeSet<LaneID> = BTreeSet::new();
+        map.pathfind_avoiding_lanes(
+            req.clone(),
+            now,
+            self.capped_lanes(now, car),
+            BTreeMap::new(),
+        )
+        .ok_or_else(|| format!("no path avoiding caps: {}", req))
+    }
+
+    /// Lanes in zones that have already reached their cap, which this car hasn't entered yet.
+    pub fn capped_lanes(&self, now: Time, car: CarID) -> BTreeSet<LaneID> {
+        let mut lanes = BTreeSet::new();
+        if car.1 != VehicleType::Car {
+            return lanes;
+        }
         for (l, idx) in &self.lane_to_zone {
             let zone = &self.zones[*idx];
-            if zone.entered_in_last_hour.len() >= zone.cap
-                && !zone.entered_in_last_hour.contains(&car)
-            {
-                avoid_lanes.insert(*l);
+            if let Some(cap) = zone.restrictions.cap_vehicles_per_hour_at(now) {
+                if zone.entered_in_last_hour.len() >= cap
+                    && !zone.entered_in_last_hour.contains(&car)
+                {
+                    lanes.insert(*l);
+                }
             }
         }
-        map.pathfind_avoiding_lanes(req.clone(), avoid_lanes)
-            .ok_or_else(|| format!("no path avoiding caps: {}", req))
+        lanes
+    }
+
+    /// A driver switched to a new path partway through their trip, avoiding the `capped_lanes`.
+    /// Count them in any other capped zones the new path enters.
+    pub fn rerouted(&mut self, now: Time, car: CarID, path: &Path) {
+        // The new path doesn't use any full zones, so this always succeeds
+        self.allow_trip(now, car, path);
     }
 
     pub fn get_cap_counter(&self, l: LaneID) -> usize {
@@ -0,0 +1,128 @@
+use std::collections::BTreeSet;
+use std::fs::File;
+use std::io::{BufReader, BufWriter, Write};
+use std::sync::{Arc, Mutex};
+
+use serde::{Deserialize, Serialize};
+
+use geom::Time;
+use map_model::IntersectionID;
+
+use crate::{AgentID, Event};
+
+/// Configures an event log, which streams every `Event` emitted by the simulation to a file, so
+/// that a full day can be analyzed offline.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct EventLogOptions {
+    pub path: String,
+    pub format: EventLogFormat,
+    pub filter: EventFilter,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub enum EventLogFormat {
+    /// One JSON object per line, like `{"time": 3600.0, "event": {"TripCancelled": [5, "Walk"]}}`
+    Json,
+    /// A stream of bincode-encoded `(Time, Event)` pairs. Use `read_binary_event_log` to load
+    /// this.
+    Binary,
+}
+
+/// Decides which events are written. Each restriction that's present must match; an empty filter
+/// keeps everything.
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct EventFilter {
+    /// Only keep events with these variant names, like "TripFinished"
+    pub variants: Option<BTreeSet<String>>,
+    /// Only keep events directly involving one of these agents
+    pub agents: Option<BTreeSet<AgentID>>,
+    /// Only keep events happening at one of these intersections
+    pub intersections: Option<BTreeSet<IntersectionID>>,
+}
+
+impl EventFilter {
+    pub fn matches(&self, ev: &Event) -> bool {
+        if let Some(ref variants) = self.variants {
+            if !variants.contains(ev.variant_name()) {
+                return false;
+            }
+        }
+        if let Some(ref agents) = self.agents {
+            if !ev.agents().into_iter().any(|a| agents.contains(&a)) {
+                return false;
+            }
+        }
+        if let Some(ref intersections) = self.intersections {
+            match ev.intersection() {
+                Some(i) if intersections.contains(&i) => {}
+                _ => {
+                    return false;
+                }
+            }
+        }
+        true
+    }
+}
+
+#[derive(Serialize)]
+struct JsonRecord<'a> {
+    time: Time,
+    event: &'a Event,
+}
+
+/// The open file that events are appended to.
+#[derive(Clone)]
+pub(crate) struct EventLog {
+    format: EventLogFormat,
+    filter: EventFilter,
+    // Cloning a Sim shares the same file, instead of reopening and truncating it.
+    file: Arc<Mutex<BufWriter<File>>>,
+}
+
+impl EventLog {
+    pub fn new(opts: EventLogOptions) -> EventLog {
+        let file = File::create(&opts.path)
+            .unwrap_or_else(|err| panic!("Can't create event log {}: {}", opts.path, err));
+        EventLog {
+            format: opts.format,
+            filter: opts.filter,
+            file: Arc::new(Mutex::new(BufWriter::new(file))),
+        }
+    }
+
+    pub fn handle_event(&mut self, time: Time, ev: &Event) {
+        if !self.filter.matches(ev) {
+            return;
+        }
+        let mut file = self.file.lock().unwrap();
+        match self.format {
+            EventLogFormat::Json => {
+                serde_json::to_writer(&mut *file, &JsonRecord { time, event: ev }).unwrap();
+                writeln!(file).unwrap();
+            }
+            EventLogFormat::Binary => {
+                bincode::serialize_into(&mut *file, &(time, ev)).unwrap();
+            }
+        }
+    }
+
+    pub fn flush(&mut self) {
+        self.file.lock().unwrap().flush().unwrap();
+    }
+}
+
+/// Loads everything from an event log written with `EventLogFormat::Binary`.
+pub fn read_binary_event_log(path: &str) -> Result<Vec<(Time, Event)>, String> {
+    let file = File::open(path).map_err(|err| format!("Can't open {}: {}", path, err))?;
+    let len = file.metadata().map_err(|err| err.to_string())?.len();
+    let mut reader = BufReader::new(file);
+    let mut events = Vec::new();
+    let mut offset = 0;
+    while offset < len {
+        let record: (Time, Event) =
+            bincode::deserialize_from(&mut reader).map_err(|err| err.to_string())?;
+        offset += bincode::serialized_size(&record).map_err(|err| err.to_string())?;
+        events.push(record);
+    }
+    Ok(events)
+}
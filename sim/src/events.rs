@@ -1,9 +1,9 @@
 use serde::{Deserialize, Serialize};
 
-use geom::{Duration, Speed};
+use geom::{Distance, Duration, Speed};
 use map_model::{
-    BuildingID, BusRouteID, BusStopID, CompressedMovementID, IntersectionID, LaneID, Map, Path,
-    PathRequest, Traversable, TurnID,
+    BikeShareStationID, BuildingID, BusRouteID, BusStopID, CompressedMovementID, IntersectionID,
+    LaneID, Map, Path, PathRequest, Traversable, TurnID,
 };
 
 use crate::{AgentID, CarID, ParkingSpot, PedestrianID, PersonID, TripID, TripMode};
@@ -21,11 +21,40 @@ pub enum Event {
     CarReachedParkingSpot(CarID, ParkingSpot),
     CarLeftParkingSpot(CarID, ParkingSpot),
 
+    /// A transit vehicle begins its route at a time from the schedule. It may not appear until a
+    /// bit later, if there's no room for it to spawn.
+    BusDispatched(CarID, BusRouteID),
     BusArrivedAtStop(CarID, BusRouteID, BusStopID),
-    BusDepartedFromStop(CarID, BusRouteID, BusStopID),
+    /// How many passengers are aboard when leaving?
+    BusDepartedFromStop(CarID, BusRouteID, BusStopID, usize),
     /// How long waiting at the stop?
     PassengerBoardsTransit(PersonID, CarID, BusRouteID, BusStopID, Duration),
     PassengerAlightsTransit(PersonID, CarID, BusRouteID, BusStopID),
+    /// Somebody waiting at a stop couldn't get on, because the vehicle was full. They'll wait for
+    /// the next one.
+    PassengerDeniedBoarding(PedestrianID, CarID, BusRouteID, BusStopID),
+    /// Somebody got into a carpool or ride-hailing vehicle at a building. How long did they wait,
+    /// and how many passengers (not counting the driver) are in the car now?
+    PassengerBoardsCar(PersonID, CarID, BuildingID, Duration, usize),
+    /// Somebody got out of a carpool or ride-hailing vehicle at a building. How many passengers
+    /// are still in the car?
+    PassengerAlightsCar(PersonID, CarID, BuildingID, usize),
+    /// A ride-hailing vehicle picked somebody up. How long since they requested the ride?
+    RideHailPickup(TripID, CarID, Duration),
+    /// A ride-hailing vehicle finished driving somewhere. True if it was carrying a passenger,
+    /// false if it was deadheading to a pickup or staging spot.
+    RideHailVehicleMoved(CarID, Distance, bool),
+    /// A delivery truck stopped to load or unload at a building, for some duration. The lane is
+    /// where it stopped: a parking lane, or a driving lane if it double-parked and is blocking
+    /// traffic.
+    TruckLoading(CarID, BuildingID, LaneID, bool, Duration),
+    /// Somebody took a bike from a bike share station. How many bikes are left there?
+    BikeShareUndocked(TripID, BikeShareStationID, usize),
+    /// Somebody returned a bike to a bike share station. How many bikes are there now?
+    BikeShareDocked(TripID, BikeShareStationID, usize),
+    /// Somebody had to go to a different bike share station, because this one had no bikes (true)
+    /// or no free docks (false).
+    BikeShareUnavailable(TripID, BikeShareStationID, bool),
 
     PersonEntersBuilding(PersonID, BuildingID),
     PersonLeavesBuilding(PersonID, BuildingID),
@@ -40,7 +69,8 @@ pub enum Event {
     /// If the agent is a transit vehicle, then include a count of how many passengers are on
     /// board.
     AgentEntersTraversable(AgentID, Traversable, Option<usize>),
-    IntersectionDelayMeasured(CompressedMovementID, Duration, AgentID),
+    /// True if an actuated stage let the agent through
+    IntersectionDelayMeasured(CompressedMovementID, Duration, AgentID, bool),
 
     TripFinished {
         trip: TripID,
@@ -58,10 +88,120 @@ pub enum Event {
     /// Just use for parking replanning. Not happy about copying the full path in here, but the way
     /// to plumb info into Analytics is Event.
     PathAmended(Path),
+    /// A driver switched routes partway through their trip to avoid congestion. Includes the
+    /// delayed intersections the old route would've crossed, and the remaining steps of the old
+    /// and new routes.
+    TripRerouted {
+        trip: TripID,
+        car: CarID,
+        avoided: Vec<IntersectionID>,
+        old_path: Path,
+        new_path: Path,
+    },
+
+    /// A traffic signal changed its timing for a transit vehicle, either holding the green longer
+    /// for it to arrive, or cutting the current stage short so it gets a green sooner.
+    TransitSignalPriority {
+        i: IntersectionID,
+        bus: CarID,
+        early_green: bool,
+        dt: Duration,
+        /// Roughly how much sooner the vehicle gets through than without priority
+        saved: Duration,
+    },
 
     Alert(AlertLocation, String),
 }
 
+impl Event {
+    /// The name of this event's variant, used to filter what gets written to an event log.
+    pub fn variant_name(&self) -> &'static str {
+        match self {
+            Event::CarReachedParkingSpot(_, _) => "CarReachedParkingSpot",
+            Event::CarLeftParkingSpot(_, _) => "CarLeftParkingSpot",
+            Event::BusDispatched(_, _) => "BusDispatched",
+            Event::BusArrivedAtStop(_, _, _) => "BusArrivedAtStop",
+            Event::BusDepartedFromStop(_, _, _, _) => "BusDepartedFromStop",
+            Event::PassengerBoardsTransit(_, _, _, _, _) => "PassengerBoardsTransit",
+            Event::PassengerAlightsTransit(_, _, _, _) => "PassengerAlightsTransit",
+            Event::PassengerDeniedBoarding(_, _, _, _) => "PassengerDeniedBoarding",
+            Event::PassengerBoardsCar(_, _, _, _, _) => "PassengerBoardsCar",
+            Event::PassengerAlightsCar(_, _, _, _) => "PassengerAlightsCar",
+            Event::RideHailPickup(_, _, _) => "RideHailPickup",
+            Event::RideHailVehicleMoved(_, _, _) => "RideHailVehicleMoved",
+            Event::TruckLoading(_, _, _, _, _) => "TruckLoading",
+            Event::BikeShareUndocked(_, _, _) => "BikeShareUndocked",
+            Event::BikeShareDocked(_, _, _) => "BikeShareDocked",
+            Event::BikeShareUnavailable(_, _, _) => "BikeShareUnavailable",
+            Event::PersonEntersBuilding(_, _) => "PersonEntersBuilding",
+            Event::PersonLeavesBuilding(_, _) => "PersonLeavesBuilding",
+            Event::PersonLeavesMap(_, _, _) => "PersonLeavesMap",
+            Event::PersonEntersMap(_, _, _) => "PersonEntersMap",
+            Event::PedReachedParkingSpot(_, _) => "PedReachedParkingSpot",
+            Event::BikeStoppedAtSidewalk(_, _) => "BikeStoppedAtSidewalk",
+            Event::AgentEntersTraversable(_, _, _) => "AgentEntersTraversable",
+            Event::IntersectionDelayMeasured(_, _, _, _) => "IntersectionDelayMeasured",
+            Event::TripFinished { .. } => "TripFinished",
+            Event::TripCancelled(_, _) => "TripCancelled",
+            Event::TripPhaseStarting(_, _, _, _) => "TripPhaseStarting",
+            Event::TripIntersectionDelay(_, _, _, _) => "TripIntersectionDelay",
+            Event::LaneSpeedPercentage(_, _, _, _) => "LaneSpeedPercentage",
+            Event::PathAmended(_) => "PathAmended",
+            Event::TripRerouted { .. } => "TripRerouted",
+            Event::TransitSignalPriority { .. } => "TransitSignalPriority",
+            Event::Alert(_, _) => "Alert",
+        }
+    }
+
+    /// The agents directly involved in this event. Events that only refer to a person or trip
+    /// don't list anything.
+    pub fn agents(&self) -> Vec<AgentID> {
+        match self {
+            Event::CarReachedParkingSpot(car, _)
+            | Event::CarLeftParkingSpot(car, _)
+            | Event::BusDispatched(car, _)
+            | Event::BusArrivedAtStop(car, _, _)
+            | Event::BusDepartedFromStop(car, _, _, _)
+            | Event::BikeStoppedAtSidewalk(car, _)
+            | Event::RideHailPickup(_, car, _)
+            | Event::RideHailVehicleMoved(car, _, _)
+            | Event::TruckLoading(car, _, _, _, _)
+            | Event::TripRerouted { car, .. }
+            | Event::TransitSignalPriority { bus: car, .. } => vec![AgentID::Car(*car)],
+            Event::PassengerBoardsTransit(person, bus, _, _, _)
+            | Event::PassengerAlightsTransit(person, bus, _, _) => {
+                vec![AgentID::Car(*bus), AgentID::BusPassenger(*person, *bus)]
+            }
+            Event::PassengerBoardsCar(person, car, _, _, _)
+            | Event::PassengerAlightsCar(person, car, _, _) => {
+                vec![AgentID::Car(*car), AgentID::CarPassenger(*person, *car)]
+            }
+            Event::PassengerDeniedBoarding(ped, bus, _, _) => {
+                vec![AgentID::Car(*bus), AgentID::Pedestrian(*ped)]
+            }
+            Event::PedReachedParkingSpot(ped, _) => vec![AgentID::Pedestrian(*ped)],
+            Event::PersonLeavesMap(_, Some(a), _)
+            | Event::PersonEntersMap(_, a, _)
+            | Event::AgentEntersTraversable(a, _, _)
+            | Event::IntersectionDelayMeasured(_, _, a, _)
+            | Event::TripIntersectionDelay(_, _, a, _) => vec![*a],
+            _ => Vec::new(),
+        }
+    }
+
+    /// The intersection where this event happened, if it's tied to one.
+    pub fn intersection(&self) -> Option<IntersectionID> {
+        match self {
+            Event::PersonLeavesMap(_, _, i) | Event::PersonEntersMap(_, _, i) => Some(*i),
+            Event::AgentEntersTraversable(_, Traversable::Turn(t), _) => Some(t.parent),
+            Event::IntersectionDelayMeasured(movement, _, _, _) => Some(movement.i),
+            Event::TripIntersectionDelay(_, t, _, _) => Some(t.parent),
+            Event::Alert(AlertLocation::Intersection(i), _) => Some(*i),
+            _ => None,
+        }
+    }
+}
+
 #[derive(Debug, PartialEq, Clone, Serialize, Deserialize)]
 pub enum AlertLocation {
     Nil,
@@ -79,6 +219,11 @@ pub enum TripPhaseType {
     WaitingForBus(BusRouteID, BusStopID),
     /// What stop did they board at?
     RidingBus(BusRouteID, BusStopID, CarID),
+    /// Waiting inside a building for a carpool to pick them up
+    WaitingForRide(BuildingID),
+    RidingInCar(CarID),
+    /// A delivery truck loading or unloading at a building
+    Delivering(BuildingID),
     Cancelled,
     Finished,
     DelayedStart,
@@ -95,6 +240,9 @@ impl TripPhaseType {
                 format!("Waiting for bus {}", map.get_br(r).full_name)
             }
             TripPhaseType::RidingBus(r, _, _) => format!("Riding bus {}", map.get_br(r).full_name),
+            TripPhaseType::WaitingForRide(_) => "Waiting to be picked up".to_string(),
+            TripPhaseType::RidingInCar(car) => format!("Riding in {}", car),
+            TripPhaseType::Delivering(b) => format!("Making a delivery at {}", b),
             TripPhaseType::Cancelled => "Trip was cancelled due to some bug".to_string(),
             TripPhaseType::Finished => "Trip finished".to_string(),
             TripPhaseType::DelayedStart => "Delayed by a previous trip taking too long".to_string(),
@@ -2,12 +2,12 @@
 //! https://dabreegster.github.io/abstreet/trafficsim/index.html.
 //!
 //! The simulation is very roughly layered into two pieces: the low-level "mechanics" of simulating
-//! individual agents over time, and higher-level systems like TripManager and TransitSimState that
-//! glue together individual goals executed by the agents.
+//! individual agents over time, and higher-level systems like TripManager, TransitSimState, and
+//! RideHailSimState that glue together individual goals executed by the agents.
 //!
 //! Helpful terminology:
-//! - sov = single occupancy vehicle, a car with just a driver and no passengers. (Car passengers
-//!   are not currently modelled)
+//! - sov = single occupancy vehicle, a car with just a driver and no passengers. People sharing a
+//!   car use TripMode::Carpool instead.
 
 #[macro_use]
 extern crate log;
@@ -19,8 +19,8 @@ use serde::{Deserialize, Serialize};
 use abstutil::{deserialize_usize, serialize_usize};
 use geom::{Distance, Speed, Time};
 use map_model::{
-    BuildingID, BusRouteID, BusStopID, DirectedRoadID, IntersectionID, LaneID, Map, ParkingLotID,
-    Path, PathConstraints, PathRequest, Position,
+    BikeShareStationID, BuildingID, BusRouteID, BusStopID, DirectedRoadID, IntersectionID, LaneID,
+    Map, ParkingLotID, Path, PathConstraints, PathRequest, Position,
 };
 
 pub use crate::render::{
@@ -28,37 +28,49 @@ pub use crate::render::{
     UnzoomedAgent,
 };
 
-pub use self::analytics::{Analytics, TripPhase};
+pub use self::analytics::{
+    Analytics, BikeShareStationStats, RoutePerformance, ScheduledArrival, StopLoad,
+    StopPerformance, TransitPriorityStats, TripPhase,
+};
+pub use self::assignment::{iterative_assignment, AssignmentIteration};
 pub(crate) use self::cap::CapSimState;
-pub(crate) use self::events::Event;
-pub use self::events::{AlertLocation, TripPhaseType};
+pub(crate) use self::event_log::EventLog;
+pub use self::event_log::{read_binary_event_log, EventFilter, EventLogFormat, EventLogOptions};
+pub use self::events::{AlertLocation, Event, TripPhaseType};
 pub(crate) use self::make::TripSpec;
 pub use self::make::{
     BorderSpawnOverTime, ExternalPerson, ExternalTrip, ExternalTripEndpoint, IndividTrip,
     PersonSpec, Scenario, ScenarioGenerator, ScenarioModifier, SimFlags, SpawnOverTime,
     TripPurpose,
 };
+pub use self::mechanics::KinematicLimits;
 pub(crate) use self::mechanics::{
     DrivingSimState, IntersectionSimState, ParkingSim, ParkingSimState, WalkingSimState,
 };
 pub(crate) use self::pandemic::PandemicModel;
 pub(crate) use self::recorder::TrafficRecorder;
+pub(crate) use self::ridehail::{RideHailSimState, RideRequest};
+pub use self::router::ReroutePolicy;
 pub(crate) use self::router::{ActionAtEnd, Router};
 pub(crate) use self::scheduler::{Command, Scheduler};
 pub use self::sim::{AgentProperties, AlertHandler, DelayCause, Sim, SimCallback, SimOptions};
+pub use self::transit::TransitCrowding;
 pub(crate) use self::transit::TransitSimState;
 pub use self::trips::{CommutersVehiclesCounts, Person, PersonState, TripInfo, TripResult};
 pub use self::trips::{TripEndpoint, TripMode};
-pub(crate) use self::trips::{TripLeg, TripManager};
+pub(crate) use self::trips::{TripLeg, TripManager, MAX_BIKE_SHARE_WALK};
 
 mod analytics;
+mod assignment;
 mod cap;
+mod event_log;
 mod events;
 mod make;
 mod mechanics;
 mod pandemic;
 mod recorder;
 mod render;
+mod ridehail;
 mod router;
 mod scheduler;
 mod sim;
@@ -70,6 +82,10 @@ pub(crate) const BIKE_LENGTH: Distance = Distance::const_meters(1.8);
 // These two must be < PARKING_SPOT_LENGTH
 pub(crate) const MIN_CAR_LENGTH: Distance = Distance::const_meters(4.5);
 pub(crate) const MAX_CAR_LENGTH: Distance = Distance::const_meters(6.5);
+// A delivery van. Delivery trucks load at curbside parking spots, so this must also be <
+// PARKING_SPOT_LENGTH. Garages and lots only leave room for a little more than MAX_CAR_LENGTH, so
+// trucks only park onstreet.
+pub(crate) const TRUCK_LENGTH: Distance = Distance::const_meters(7.0);
 // Note this is more than MAX_CAR_LENGTH
 pub(crate) const BUS_LENGTH: Distance = Distance::const_meters(12.5);
 pub(crate) const LIGHT_RAIL_LENGTH: Distance = Distance::const_meters(60.0);
@@ -102,6 +118,7 @@ impl fmt::Display for CarID {
             VehicleType::Bus => write!(f, "Bus #{}", self.0),
             VehicleType::Train => write!(f, "Train #{}", self.0),
             VehicleType::Bike => write!(f, "Bike #{}", self.0),
+            VehicleType::Truck => write!(f, "Truck #{}", self.0),
         }
     }
 }
@@ -127,6 +144,8 @@ pub enum AgentID {
     Pedestrian(PedestrianID),
     // TODO Rename...
     BusPassenger(PersonID, CarID),
+    /// Somebody riding in a carpool, not driving
+    CarPassenger(PersonID, CarID),
 }
 
 impl AgentID {
@@ -140,13 +159,14 @@ impl AgentID {
     pub fn to_type(self) -> AgentType {
         match self {
             AgentID::Car(c) => match c.1 {
-                VehicleType::Car => AgentType::Car,
+                VehicleType::Car | VehicleType::Truck => AgentType::Car,
                 VehicleType::Bike => AgentType::Bike,
                 VehicleType::Bus => AgentType::Bus,
                 VehicleType::Train => AgentType::Train,
             },
             AgentID::Pedestrian(_) => AgentType::Pedestrian,
             AgentID::BusPassenger(_, _) => AgentType::TransitRider,
+            AgentID::CarPassenger(_, _) => AgentType::CarPassenger,
         }
     }
 
@@ -155,6 +175,7 @@ impl AgentID {
             AgentID::Car(c) => Some(c.1),
             AgentID::Pedestrian(_) => None,
             AgentID::BusPassenger(_, _) => None,
+            AgentID::CarPassenger(_, _) => None,
         }
     }
 }
@@ -165,6 +186,7 @@ impl fmt::Display for AgentID {
             AgentID::Car(id) => write!(f, "AgentID({})", id),
             AgentID::Pedestrian(id) => write!(f, "AgentID({})", id),
             AgentID::BusPassenger(person, bus) => write!(f, "AgentID({} on {})", person, bus),
+            AgentID::CarPassenger(person, car) => write!(f, "AgentID({} in {})", person, car),
         }
     }
 }
@@ -177,6 +199,7 @@ pub enum AgentType {
     Train,
     Pedestrian,
     TransitRider,
+    CarPassenger,
 }
 
 impl AgentType {
@@ -188,6 +211,7 @@ impl AgentType {
             AgentType::Train,
             AgentType::Pedestrian,
             AgentType::TransitRider,
+            AgentType::CarPassenger,
         ]
     }
 
@@ -199,6 +223,7 @@ impl AgentType {
             AgentType::Train => "Train",
             AgentType::Pedestrian => "Pedestrian",
             AgentType::TransitRider => "Transit rider",
+            AgentType::CarPassenger => "Car passenger",
         }
     }
 
@@ -210,6 +235,7 @@ impl AgentType {
             AgentType::Train => "trains",
             AgentType::Pedestrian => "pedestrians",
             AgentType::TransitRider => "transit riders",
+            AgentType::CarPassenger => "car passengers",
         }
     }
 
@@ -220,6 +246,7 @@ impl AgentType {
             AgentType::Bus | AgentType::Train => unreachable!(),
             AgentType::Pedestrian => "walking",
             AgentType::TransitRider => "riding transit",
+            AgentType::CarPassenger => "riding in a car",
         }
     }
 }
@@ -274,6 +301,8 @@ pub enum VehicleType {
     Bus,
     Train,
     Bike,
+    /// Makes deliveries; otherwise acts like a car
+    Truck,
 }
 
 impl fmt::Display for VehicleType {
@@ -283,6 +312,7 @@ impl fmt::Display for VehicleType {
             VehicleType::Bus => write!(f, "bus"),
             VehicleType::Train => write!(f, "train"),
             VehicleType::Bike => write!(f, "bike"),
+            VehicleType::Truck => write!(f, "truck"),
         }
     }
 }
@@ -294,6 +324,7 @@ impl VehicleType {
             VehicleType::Bus => PathConstraints::Bus,
             VehicleType::Train => PathConstraints::Train,
             VehicleType::Bike => PathConstraints::Bike,
+            VehicleType::Truck => PathConstraints::Car,
         }
     }
 
@@ -303,6 +334,7 @@ impl VehicleType {
             VehicleType::Bus => true,
             VehicleType::Train => true,
             VehicleType::Bike => false,
+            VehicleType::Truck => false,
         }
     }
 }
@@ -358,6 +390,9 @@ pub struct ParkedCar {
 pub(crate) enum DrivingGoal {
     ParkNear(BuildingID),
     Border(IntersectionID, LaneID),
+    /// Only cars, to transfer to transit at a park-and-ride. If the lot is full, park near the
+    /// building instead.
+    ParkInLot(ParkingLotID, BuildingID),
 }
 
 impl DrivingGoal {
@@ -387,6 +422,13 @@ impl DrivingGoal {
                 }
             },
             DrivingGoal::Border(_, l) => Some(Position::end(*l, map)),
+            DrivingGoal::ParkInLot(pl, _) => match constraints {
+                PathConstraints::Car => Some(map.get_pl(*pl).driving_pos),
+                PathConstraints::Bike
+                | PathConstraints::Bus
+                | PathConstraints::Train
+                | PathConstraints::Pedestrian => unreachable!(),
+            },
         }
     }
 
@@ -402,6 +444,7 @@ impl DrivingGoal {
             DrivingGoal::Border(i, last_lane) => {
                 Router::end_at_border(owner, path, map.get_l(*last_lane).length(), *i)
             }
+            DrivingGoal::ParkInLot(pl, b) => Router::park_in_lot(owner, path, *pl, *b, map),
         }
     }
 }
@@ -424,6 +467,7 @@ pub(crate) enum SidewalkPOI {
     Border(IntersectionID),
     /// The bikeable position
     BikeRack(Position),
+    BikeShareStation(BikeShareStationID),
     SuddenlyAppear,
 }
 
@@ -465,6 +509,13 @@ impl SidewalkSpot {
         })
     }
 
+    pub fn bike_share_station(station: BikeShareStationID, map: &Map) -> SidewalkSpot {
+        SidewalkSpot {
+            connection: SidewalkPOI::BikeShareStation(station),
+            sidewalk_pos: map.get_bss(station).sidewalk_pos,
+        }
+    }
+
     pub fn bus_stop(stop: BusStopID, map: &Map) -> SidewalkSpot {
         SidewalkSpot {
             sidewalk_pos: map.get_bs(stop).sidewalk_pos,
@@ -59,6 +59,7 @@ impl ScenarioModifier {
                         if let Some(to_mode) = *to_mode {
                             trip.mode = to_mode;
                             trip.modified = true;
+                            trip.carpool_driver = None;
                         } else {
                             trip.modified = true;
                             trip.cancelled = true;
@@ -68,6 +69,10 @@ impl ScenarioModifier {
                         }
                     }
                 }
+                // People switching to or from carpools need to find new partners
+                if from_modes.contains(&TripMode::Carpool) || *to_mode == Some(TripMode::Carpool) {
+                    s.match_carpools(map);
+                }
                 s
             }
             ScenarioModifier::AddExtraTrips(name) => {
@@ -75,9 +80,13 @@ impl ScenarioModifier {
                     abstutil::path_scenario(map.get_name(), name),
                     &mut Timer::throwaway(),
                 );
+                let offset = s.people.len();
                 for mut p in other.people {
                     for trip in &mut p.trips {
                         trip.modified = true;
+                        if let Some((driver, t)) = trip.carpool_driver {
+                            trip.carpool_driver = Some((driver + offset, t));
+                        }
                     }
                     s.people.push(p);
                 }
@@ -118,14 +127,19 @@ impl ScenarioModifier {
 // needing LOTS of cars.
 fn repeat_days(mut s: Scenario, days: usize) -> Scenario {
     s.scenario_name = format!("{} (repeated {} days)", s.scenario_name, days);
+    let trips_per_person: Vec<usize> = s.people.iter().map(|p| p.trips.len()).collect();
     for person in &mut s.people {
         let mut trips = Vec::new();
         let mut offset = Duration::ZERO;
-        for _ in 0..days {
+        for day in 0..days {
             for trip in &person.trips {
                 let mut new = trip.clone();
                 new.depart += offset;
                 new.modified = true;
+                // Ride with the same driver every day
+                if let Some((p, t)) = trip.carpool_driver {
+                    new.carpool_driver = Some((p, day * trips_per_person[p] + t));
+                }
                 trips.push(new);
             }
             offset += Duration::hours(24);
@@ -7,13 +7,13 @@ use rand_xorshift::XorShiftRng;
 use serde::{Deserialize, Serialize};
 
 use abstutil::{prettyprint_usize, Counter, MapName, Parallelism, Timer};
-use geom::{Distance, Speed, Time};
+use geom::{Distance, Duration, Speed, Time};
 use map_model::{BuildingID, Map, OffstreetParking, RoadID};
 
 use crate::make::fork_rng;
 use crate::{
     OrigPersonID, ParkingSpot, Sim, TripEndpoint, TripInfo, TripMode, TripSpec, Vehicle,
-    VehicleSpec, VehicleType, BIKE_LENGTH, MAX_CAR_LENGTH, MIN_CAR_LENGTH,
+    VehicleSpec, VehicleType, BIKE_LENGTH, MAX_CAR_LENGTH, MIN_CAR_LENGTH, TRUCK_LENGTH,
 };
 
 /// A Scenario describes all the input to a simulation. Usually a scenario covers one day.
@@ -46,6 +46,12 @@ pub struct IndividTrip {
     pub cancelled: bool,
     /// Did a ScenarioModifier affect this?
     pub modified: bool,
+    /// For carpool passengers, the person driving them and the index of the driver's trip. The
+    /// driver's trip must also use TripMode::Carpool.
+    pub carpool_driver: Option<(usize, usize)>,
+    /// For TripMode::Delivery, buildings to stop at (in order) and how long to load or unload at
+    /// each, before continuing to the destination.
+    pub delivery_stops: Vec<(BuildingID, Duration)>,
 }
 
 impl IndividTrip {
@@ -62,6 +68,8 @@ impl IndividTrip {
             purpose,
             cancelled: false,
             modified: false,
+            carpool_driver: None,
+            delivery_stops: Vec::new(),
         }
     }
 }
@@ -141,7 +149,13 @@ impl Scenario {
         timer.start_iter("trips for People", self.people.len());
         let mut parked_cars: Vec<(Vehicle, BuildingID)> = Vec::new();
         let mut schedule_trips = Vec::new();
-        for p in &self.people {
+        // (person, trip) for every trip scheduled so far
+        let mut scheduled_idx = Vec::new();
+        // For each carpool driver's (person, trip), the index of passenger trips
+        let mut carpools: BTreeMap<(usize, usize), Vec<usize>> = BTreeMap::new();
+        // For each delivery trip scheduled, the index of the trip and its stops
+        let mut deliveries: Vec<(usize, Vec<(BuildingID, Duration)>)> = Vec::new();
+        for (p_idx, p) in self.people.iter().enumerate() {
             timer.next();
 
             if let Err(err) = p.check_schedule() {
@@ -155,25 +169,52 @@ impl Scenario {
                 parked_cars.push((person.vehicles[idx].clone(), b));
             }
             let mut from = p.origin.clone();
-            for (t, maybe_idx) in p.trips.iter().zip(vehicle_foreach_trip) {
+            for (t_idx, (t, maybe_idx)) in p.trips.iter().zip(vehicle_foreach_trip).enumerate() {
                 // The RNG call might change over edits for picking the spawning lane from a border
                 // with multiple choices for a vehicle type.
                 let mut tmp_rng = fork_rng(rng);
-                let spec = match TripSpec::maybe_new(
-                    from.clone(),
-                    t.destination.clone(),
-                    t.mode,
-                    maybe_idx.map(|idx| person.vehicles[idx].id),
-                    retry_if_no_room,
-                    &mut tmp_rng,
-                    map,
-                ) {
-                    Ok(spec) => spec,
-                    Err(error) => TripSpec::SpawningFailure {
-                        use_vehicle: maybe_idx.map(|idx| person.vehicles[idx].id),
-                        error,
-                    },
+                let spec = if t.carpool_driver.is_some() {
+                    match (
+                        self.valid_carpool_driver(p_idx, t_idx),
+                        &from,
+                        &t.destination,
+                    ) {
+                        (Some(driver), TripEndpoint::Bldg(start), TripEndpoint::Bldg(goal)) => {
+                            carpools
+                                .entry(driver)
+                                .or_insert_with(Vec::new)
+                                .push(schedule_trips.len());
+                            TripSpec::RidingCarpool {
+                                start: *start,
+                                goal: *goal,
+                            }
+                        }
+                        _ => TripSpec::SpawningFailure {
+                            use_vehicle: None,
+                            error: "carpool passenger doesn't have a valid driver".to_string(),
+                        },
+                    }
+                } else {
+                    match TripSpec::maybe_new(
+                        from.clone(),
+                        t.destination.clone(),
+                        t.mode,
+                        maybe_idx.map(|idx| person.vehicles[idx].id),
+                        retry_if_no_room,
+                        &mut tmp_rng,
+                        map,
+                    ) {
+                        Ok(spec) => spec,
+                        Err(error) => TripSpec::SpawningFailure {
+                            use_vehicle: maybe_idx.map(|idx| person.vehicles[idx].id),
+                            error,
+                        },
+                    }
                 };
+                if t.mode == TripMode::Delivery && !t.delivery_stops.is_empty() {
+                    deliveries.push((schedule_trips.len(), t.delivery_stops.clone()));
+                }
+                scheduled_idx.push((p_idx, t_idx));
                 schedule_trips.push((
                     person.id,
                     spec,
@@ -207,7 +248,24 @@ impl Scenario {
         parked_cars.shuffle(rng);
         seed_parked_cars(parked_cars, sim, map, rng, timer);
 
-        sim.spawn_trips(results, map, timer);
+        let ids = sim.spawn_trips(results, map, timer);
+        if !carpools.is_empty() {
+            let lookup: BTreeMap<(usize, usize), usize> = scheduled_idx
+                .into_iter()
+                .enumerate()
+                .map(|(idx, key)| (key, idx))
+                .collect();
+            for (driver, passengers) in carpools {
+                sim.new_carpool(
+                    ids[lookup[&driver]],
+                    passengers.into_iter().map(|idx| ids[idx]).collect(),
+                    map,
+                );
+            }
+        }
+        for (idx, stops) in deliveries {
+            sim.new_delivery_tour(ids[idx], stops);
+        }
         timer.stop(format!("Instantiating {}", self.scenario_name));
     }
 
@@ -236,6 +294,14 @@ impl Scenario {
         }
     }
 
+    fn truck() -> VehicleSpec {
+        VehicleSpec {
+            vehicle_type: VehicleType::Truck,
+            length: TRUCK_LENGTH,
+            max_speed: None,
+        }
+    }
+
     fn rand_bike(rng: &mut XorShiftRng) -> VehicleSpec {
         let max_speed = Some(Scenario::rand_speed(
             rng,
@@ -287,13 +353,33 @@ impl Scenario {
 
     pub fn remove_weird_schedules(mut self) -> Scenario {
         let orig = self.people.len();
-        self.people.retain(|person| match person.check_schedule() {
-            Ok(()) => true,
-            Err(err) => {
-                println!("{}", err);
-                false
+        // Removing people shifts indices, so fix up carpool links
+        let mut new_idx = Vec::new();
+        let mut kept = 0;
+        for person in &self.people {
+            match person.check_schedule() {
+                Ok(()) => {
+                    new_idx.push(Some(kept));
+                    kept += 1;
+                }
+                Err(err) => {
+                    println!("{}", err);
+                    new_idx.push(None);
+                }
             }
+        }
+        let mut idx = 0;
+        self.people.retain(|_| {
+            idx += 1;
+            new_idx[idx - 1].is_some()
         });
+        for person in &mut self.people {
+            for trip in &mut person.trips {
+                if let Some((p, t)) = trip.carpool_driver {
+                    trip.carpool_driver = new_idx.get(p).cloned().flatten().map(|p| (p, t));
+                }
+            }
+        }
         println!(
             "{} of {} people have nonsense schedules",
             prettyprint_usize(orig - self.people.len()),
@@ -301,6 +387,113 @@ impl Scenario {
         );
         self
     }
+
+    /// If the trip is a carpool passenger and the driver's trip makes sense, returns the driver's
+    /// person and trip index.
+    fn valid_carpool_driver(&self, person: usize, trip: usize) -> Option<(usize, usize)> {
+        let passenger = &self.people[person];
+        let (driver_idx, driver_trip) = passenger.trips[trip].carpool_driver?;
+        let driver = self.people.get(driver_idx)?;
+        let t = driver.trips.get(driver_trip)?;
+        if driver_idx == person
+            || passenger.trips[trip].mode != TripMode::Carpool
+            || t.mode != TripMode::Carpool
+            || t.carpool_driver.is_some()
+            || t.cancelled
+        {
+            return None;
+        }
+        match (
+            passenger.trip_start(trip),
+            &passenger.trips[trip].destination,
+            driver.trip_start(driver_trip),
+        ) {
+            (TripEndpoint::Bldg(_), TripEndpoint::Bldg(_), TripEndpoint::Bldg(_)) => {
+                Some((driver_idx, driver_trip))
+            }
+            _ => None,
+        }
+    }
+
+    /// Group people taking similar carpool trips around the same time. Trips that start and end
+    /// near each other and depart within 15 minutes of each other share a car; the first person
+    /// to leave drives, picking up to 3 passengers. Existing links that don't make sense anymore
+    /// are removed first.
+    pub fn match_carpools(&mut self, map: &Map) {
+        let max_passengers = 3;
+        let max_wait = Duration::minutes(15);
+        let cell_size = Distance::meters(500.0);
+
+        for p in 0..self.people.len() {
+            for t in 0..self.people[p].trips.len() {
+                if self.people[p].trips[t].carpool_driver.is_some()
+                    && self.valid_carpool_driver(p, t).is_none()
+                {
+                    self.people[p].trips[t].carpool_driver = None;
+                }
+            }
+        }
+
+        let mut num_passengers: Counter<(usize, usize)> = Counter::new();
+        for person in &self.people {
+            for trip in &person.trips {
+                if let Some(driver) = trip.carpool_driver {
+                    num_passengers.inc(driver);
+                }
+            }
+        }
+
+        let cell = |b: BuildingID| {
+            let pt = map.get_b(b).polygon.center();
+            (
+                (pt.x() / cell_size.inner_meters()) as isize,
+                (pt.y() / cell_size.inner_meters()) as isize,
+            )
+        };
+        let mut groups: BTreeMap<_, Vec<(Time, usize, usize)>> = BTreeMap::new();
+        for (p, person) in self.people.iter().enumerate() {
+            for (t, trip) in person.trips.iter().enumerate() {
+                if trip.mode != TripMode::Carpool || trip.carpool_driver.is_some() || trip.cancelled
+                {
+                    continue;
+                }
+                if let (TripEndpoint::Bldg(b1), TripEndpoint::Bldg(b2)) =
+                    (person.trip_start(t), &trip.destination)
+                {
+                    groups
+                        .entry((cell(*b1), cell(*b2)))
+                        .or_insert_with(Vec::new)
+                        .push((trip.depart, p, t));
+                }
+            }
+        }
+
+        for (_, mut trips) in groups {
+            trips.sort();
+            let mut matched = BTreeSet::new();
+            for (idx, (depart, p1, t1)) in trips.iter().enumerate() {
+                if matched.contains(&(*p1, *t1)) {
+                    continue;
+                }
+                for (depart2, p2, t2) in &trips[idx + 1..] {
+                    if *depart2 - *depart > max_wait
+                        || num_passengers.get((*p1, *t1)) >= max_passengers
+                    {
+                        break;
+                    }
+                    if p1 == p2
+                        || matched.contains(&(*p2, *t2))
+                        || num_passengers.get((*p2, *t2)) > 0
+                    {
+                        continue;
+                    }
+                    self.people[*p2].trips[*t2].carpool_driver = Some((*p1, *t1));
+                    num_passengers.inc((*p1, *t1));
+                    matched.insert((*p2, *t2));
+                }
+            }
+        }
+    }
 }
 
 fn seed_parked_cars(
@@ -364,7 +557,10 @@ fn seed_parked_cars(
         if !ok {
             continue;
         }
-        if let Some(spot) = find_spot_near_building(b, &mut open_spots_per_road, map) {
+        // Trucks don't fit in garages or lots
+        let onstreet_only = vehicle.length > MAX_CAR_LENGTH;
+        if let Some(spot) = find_spot_near_building(b, onstreet_only, &mut open_spots_per_road, map)
+        {
             seeded += 1;
             sim.seed_parked_car(vehicle, spot);
         } else {
@@ -383,6 +579,7 @@ fn seed_parked_cars(
 // spot.
 fn find_spot_near_building(
     b: BuildingID,
+    onstreet_only: bool,
     open_spots_per_road: &mut BTreeMap<RoadID, Vec<(ParkingSpot, Option<BuildingID>)>>,
     map: &Map,
 ) -> Option<ParkingSpot> {
@@ -401,14 +598,14 @@ fn find_spot_near_building(
             // TODO With some probability, skip this available spot and park farther away
             if let Some(idx) = spots
                 .iter()
-                .position(|(_, restriction)| restriction == &Some(b))
+                .position(|(_, restriction)| !onstreet_only && restriction == &Some(b))
             {
                 return Some(spots.remove(idx).0);
             }
-            if let Some(idx) = spots
-                .iter()
-                .position(|(_, restriction)| restriction.is_none())
-            {
+            if let Some(idx) = spots.iter().position(|(spot, restriction)| {
+                restriction.is_none()
+                    && (!onstreet_only || matches!(spot, ParkingSpot::Onstreet(_, _)))
+            }) {
                 return Some(spots.remove(idx).0);
             }
         }
@@ -423,6 +620,15 @@ fn find_spot_near_building(
 }
 
 impl PersonSpec {
+    /// Where the trip at this index begins
+    fn trip_start(&self, idx: usize) -> &TripEndpoint {
+        if idx == 0 {
+            &self.origin
+        } else {
+            &self.trips[idx - 1].destination
+        }
+    }
+
     /// Verify that a person's trips make sense
     fn check_schedule(&self) -> Result<(), String> {
         for pair in self.trips.windows(2) {
@@ -470,7 +676,11 @@ impl PersonSpec {
         let mut from = self.origin.clone();
         for trip in &self.trips {
             let use_for_trip = match trip.mode {
-                TripMode::Walk | TripMode::Transit => None,
+                TripMode::Walk | TripMode::Transit | TripMode::RideHail | TripMode::BikeShare => {
+                    None
+                }
+                // Riding in somebody else's car
+                TripMode::Carpool if trip.carpool_driver.is_some() => None,
                 TripMode::Bike => {
                     if bike_idx.is_none() {
                         bike_idx = Some(vehicle_specs.len());
@@ -478,23 +688,40 @@ impl PersonSpec {
                     }
                     bike_idx
                 }
-                TripMode::Drive => {
+                // After a park-and-ride trip, the car is really in some lot. The trip back starting
+                // from the destination will find it there.
+                TripMode::Drive
+                | TripMode::Carpool
+                | TripMode::Delivery
+                | TripMode::ParkAndRide => {
                     let need_parked_at = match from {
                         TripEndpoint::Bldg(b) => Some(b),
                         _ => None,
                     };
+                    let vehicle_type = if trip.mode == TripMode::Delivery {
+                        VehicleType::Truck
+                    } else {
+                        VehicleType::Car
+                    };
 
-                    // Any available cars in the right spot?
+                    // Any available cars (or trucks) in the right spot?
                     let idx = if let Some(idx) = car_locations
                         .iter()
-                        .find(|(_, parked_at)| *parked_at == need_parked_at)
+                        .find(|(idx, parked_at)| {
+                            *parked_at == need_parked_at
+                                && vehicle_specs[*idx].vehicle_type == vehicle_type
+                        })
                         .map(|(idx, _)| *idx)
                     {
                         idx
                     } else {
                         // Need a new car, starting in the right spot
                         let idx = vehicle_specs.len();
-                        vehicle_specs.push(Scenario::rand_car(rng));
+                        vehicle_specs.push(if vehicle_type == VehicleType::Truck {
+                            Scenario::truck()
+                        } else {
+                            Scenario::rand_car(rng)
+                        });
                         if let Some(b) = need_parked_at {
                             cars_initially_parked_at.push((idx, b));
                         }
@@ -1,15 +1,21 @@
 //! Intermediate structures used to instantiate a Scenario. Badly needs simplification:
 //! https://github.com/dabreegster/abstreet/issues/258
 
+use std::collections::BTreeSet;
+
 use rand::seq::SliceRandom;
 use rand_xorshift::XorShiftRng;
 use serde::{Deserialize, Serialize};
 
-use map_model::{BuildingID, BusRouteID, BusStopID, Map, PathConstraints, PathRequest, Position};
+use geom::Pt2D;
+use map_model::{
+    BikeShareStationID, BuildingID, BusRouteID, BusStopID, Map, ParkingLotID, PathConstraints,
+    PathRequest, Position,
+};
 
 use crate::{
     CarID, DrivingGoal, PersonID, SidewalkSpot, TripEndpoint, TripInfo, TripLeg, TripMode,
-    VehicleType, SPAWN_DIST,
+    VehicleType, MAX_BIKE_SHARE_WALK, SPAWN_DIST,
 };
 
 // TODO Some of these fields are unused now that we separately pass TripEndpoint
@@ -50,6 +56,26 @@ pub(crate) enum TripSpec {
         stop1: BusStopID,
         maybe_stop2: Option<BusStopID>,
     },
+    /// Wait inside to be picked up by somebody else's car
+    RidingCarpool { start: BuildingID, goal: BuildingID },
+    /// Wait inside to be picked up by a ride-hailing vehicle
+    UsingRideHail { start: BuildingID, goal: BuildingID },
+    UsingBikeShare {
+        start: SidewalkSpot,
+        goal: SidewalkSpot,
+        station1: BikeShareStationID,
+        station2: BikeShareStationID,
+    },
+    /// Drive to a parking lot, ride transit from there, then walk to the goal. If the car was left
+    /// at a different lot earlier, this is the trip back instead: ride transit back to the lot,
+    /// then drive to the goal. That's only decided when the trip starts.
+    UsingParkAndRide {
+        car: CarID,
+        start_bldg: BuildingID,
+        goal: BuildingID,
+        /// (lot, stop1, optional stop 2, route). If no lot is worth using from here, just drive.
+        park_and_ride: Option<(ParkingLotID, BusStopID, Option<BusStopID>, BusRouteID)>,
+    },
 }
 
 impl TripSpec {
@@ -114,7 +140,7 @@ impl TripSpec {
                     DrivingGoal::ParkNear(b) => {
                         legs.push(TripLeg::Walk(SidewalkSpot::building(*b, map)));
                     }
-                    DrivingGoal::Border(_, _) => {}
+                    DrivingGoal::Border(_, _) | DrivingGoal::ParkInLot(_, _) => {}
                 }
             }
             TripSpec::JustWalking { start, goal, .. } => {
@@ -139,6 +165,7 @@ impl TripSpec {
                             goal,
                         })
                     }
+                    DrivingGoal::ParkInLot(_, _) => unreachable!(),
                 };
 
                 if let Some(start_spot) = SidewalkSpot::bike_rack(*start, map) {
@@ -167,7 +194,7 @@ impl TripSpec {
                         DrivingGoal::ParkNear(b) => {
                             legs.push(TripLeg::Walk(SidewalkSpot::building(*b, map)));
                         }
-                        DrivingGoal::Border(_, _) => {}
+                        DrivingGoal::Border(_, _) | DrivingGoal::ParkInLot(_, _) => {}
                     }
                 } else if backup_plan.is_some() {
                     info!("Can't start biking from {}. Walking instead", start);
@@ -204,6 +231,40 @@ impl TripSpec {
                     ];
                 }
             }
+            TripSpec::RidingCarpool { goal, .. } | TripSpec::UsingRideHail { goal, .. } => {
+                legs.push(TripLeg::RideInCar(*goal));
+            }
+            TripSpec::UsingBikeShare {
+                goal,
+                station1,
+                station2,
+                ..
+            } => {
+                legs = vec![
+                    TripLeg::Walk(SidewalkSpot::bike_share_station(*station1, map)),
+                    TripLeg::RideBikeShare(*station1, *station2, BTreeSet::new()),
+                    TripLeg::Walk(goal.clone()),
+                ];
+            }
+            TripSpec::UsingParkAndRide {
+                car,
+                goal,
+                park_and_ride,
+                ..
+            } => {
+                legs.push(TripLeg::Walk(SidewalkSpot::deferred_parking_spot()));
+                if let Some((lot, stop1, maybe_stop2, route)) = park_and_ride {
+                    legs.push(TripLeg::Drive(*car, DrivingGoal::ParkInLot(*lot, *goal)));
+                    legs.push(TripLeg::Walk(SidewalkSpot::bus_stop(*stop1, map)));
+                    legs.push(TripLeg::RideBus(*route, *maybe_stop2));
+                    if maybe_stop2.is_some() {
+                        legs.push(TripLeg::Walk(SidewalkSpot::building(*goal, map)));
+                    }
+                } else {
+                    legs.push(TripLeg::Drive(*car, DrivingGoal::ParkNear(*goal)));
+                    legs.push(TripLeg::Walk(SidewalkSpot::building(*goal, map)));
+                }
+            }
         };
 
         (person, info, self, legs)
@@ -230,7 +291,7 @@ impl TripSpec {
             }
             TripSpec::SpawningFailure { .. } => None,
             // We don't know where the parked car will be
-            TripSpec::UsingParkedCar { .. } => None,
+            TripSpec::UsingParkedCar { .. } | TripSpec::UsingParkAndRide { .. } => None,
             TripSpec::JustWalking { start, goal, .. } => Some(PathRequest {
                 start: start.sidewalk_pos,
                 end: goal.sidewalk_pos,
@@ -246,6 +307,15 @@ impl TripSpec {
                 end: SidewalkSpot::bus_stop(*stop1, map).sidewalk_pos,
                 constraints: PathConstraints::Pedestrian,
             }),
+            TripSpec::UsingBikeShare {
+                start, station1, ..
+            } => Some(PathRequest {
+                start: start.sidewalk_pos,
+                end: map.get_bss(*station1).sidewalk_pos,
+                constraints: PathConstraints::Pedestrian,
+            }),
+            // Somebody else is driving
+            TripSpec::RidingCarpool { .. } | TripSpec::UsingRideHail { .. } => None,
         }
     }
 
@@ -261,16 +331,18 @@ impl TripSpec {
         map: &Map,
     ) -> Result<TripSpec, String> {
         Ok(match mode {
-            TripMode::Drive | TripMode::Bike => {
-                let constraints = if mode == TripMode::Drive {
-                    PathConstraints::Car
-                } else {
+            // Carpool passengers are handled separately; this is just for the driver. Delivery
+            // stops are also handled separately.
+            TripMode::Drive | TripMode::Carpool | TripMode::Delivery | TripMode::Bike => {
+                let constraints = if mode == TripMode::Bike {
                     PathConstraints::Bike
+                } else {
+                    PathConstraints::Car
                 };
                 let goal = to.driving_goal(constraints, map)?;
                 match from {
                     TripEndpoint::Bldg(start_bldg) => {
-                        if mode == TripMode::Drive {
+                        if mode != TripMode::Bike {
                             TripSpec::UsingParkedCar {
                                 start_bldg,
                                 goal,
@@ -307,6 +379,25 @@ impl TripSpec {
                     },
                 }
             }
+            TripMode::RideHail => match (&from, &to) {
+                (TripEndpoint::Bldg(start), TripEndpoint::Bldg(goal)) => {
+                    for b in vec![*start, *goal] {
+                        if map.get_b(b).driving_connection(map).is_none() {
+                            return Err(format!("{} isn't connected to any road for cars", b));
+                        }
+                    }
+                    TripSpec::UsingRideHail {
+                        start: *start,
+                        goal: *goal,
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "ride-hailing trips must go between buildings, not {:?} to {:?}",
+                        from, to
+                    ));
+                }
+            },
             TripMode::Walk => TripSpec::JustWalking {
                 start: from.start_sidewalk_spot(map)?,
                 goal: to.end_sidewalk_spot(map)?,
@@ -330,10 +421,64 @@ impl TripSpec {
                     TripSpec::JustWalking { start, goal }
                 }
             }
+            TripMode::BikeShare => {
+                let start = from.start_sidewalk_spot(map)?;
+                let goal = to.end_sidewalk_spot(map)?;
+                match (
+                    closest_bike_share_station(start.sidewalk_pos.pt(map), map),
+                    closest_bike_share_station(goal.sidewalk_pos.pt(map), map),
+                ) {
+                    (Some(station1), Some(station2))
+                        if map.get_bss(station1).biking_pos.lane()
+                            != map.get_bss(station2).biking_pos.lane() =>
+                    {
+                        TripSpec::UsingBikeShare {
+                            start,
+                            goal,
+                            station1,
+                            station2,
+                        }
+                    }
+                    // No stations close enough, or it's not worth biking between them
+                    _ => TripSpec::JustWalking { start, goal },
+                }
+            }
+            TripMode::ParkAndRide => match (&from, &to) {
+                (TripEndpoint::Bldg(start_bldg), TripEndpoint::Bldg(goal)) => {
+                    let start = map
+                        .get_b(*start_bldg)
+                        .driving_connection(map)
+                        .ok_or_else(|| {
+                            format!("{} isn't connected to any road for cars", start_bldg)
+                        })?
+                        .0;
+                    TripSpec::UsingParkAndRide {
+                        car: use_vehicle.unwrap(),
+                        start_bldg: *start_bldg,
+                        goal: *goal,
+                        park_and_ride: map
+                            .should_use_park_and_ride(start, map.get_b(*goal).sidewalk_pos),
+                    }
+                }
+                _ => {
+                    return Err(format!(
+                        "park-and-ride trips must go between buildings, not {:?} to {:?}",
+                        from, to
+                    ));
+                }
+            },
         })
     }
 }
 
+fn closest_bike_share_station(pt: Pt2D, map: &Map) -> Option<BikeShareStationID> {
+    map.all_bike_share_stations()
+        .iter()
+        .filter(|s| s.point.dist_to(pt) <= MAX_BIKE_SHARE_WALK)
+        .min_by_key(|s| s.point.dist_to(pt))
+        .map(|s| s.id)
+}
+
 impl TripEndpoint {
     fn start_sidewalk_spot(&self, map: &Map) -> Result<SidewalkSpot, String> {
         match self {
@@ -2,9 +2,10 @@ use std::collections::VecDeque;
 
 use serde::{Deserialize, Serialize};
 
-use geom::{Distance, Duration, PolyLine, Time};
-use map_model::{Direction, Map, Traversable};
+use geom::{Distance, Duration, PolyLine, Speed, Time};
+use map_model::{Direction, Map, PathConstraints, Traversable};
 
+use crate::mechanics::kinematics::{KinematicLimits, SpeedProfile};
 use crate::{
     CarStatus, DistanceInterval, DrawCarInput, ParkingSpot, PersonID, Router, TimeInterval,
     TransitSimState, TripID, Vehicle, VehicleType,
@@ -21,6 +22,13 @@ pub(crate) struct Car {
     pub trip_and_person: Option<(TripID, PersonID)>,
     pub started_at: Time,
     pub total_blocked_time: Duration,
+    /// The last time this car considered switching routes to avoid congestion
+    pub last_reroute_check: Option<Time>,
+    /// If present, the car speeds up and slows down gradually.
+    pub kinematics: Option<KinematicLimits>,
+    /// How fast the car was going at the end of its last Crossing state. Only used with
+    /// kinematics.
+    pub last_speed: Speed,
 
     /// In reverse order -- most recently left is first. The sum length of these must be >=
     /// vehicle.length.
@@ -30,30 +38,76 @@ pub(crate) struct Car {
 impl Car {
     /// Assumes the current head of the path is the thing to cross.
     pub fn crossing_state(&self, start_dist: Distance, start_time: Time, map: &Map) -> CarState {
-        let dist_int = DistanceInterval::new_driving(
-            start_dist,
-            if self.router.last_step() {
-                self.router.get_end_dist()
-            } else {
-                self.router.head().length(map)
-            },
-        );
-        self.crossing_state_with_end_dist(dist_int, start_time, map)
+        let (end_dist, stop_at_end) = if self.router.last_step() {
+            (self.router.get_end_dist(), true)
+        } else {
+            (self.router.head().length(map), false)
+        };
+        self.crossing_state_with_end_dist(
+            DistanceInterval::new_driving(start_dist, end_dist),
+            start_time,
+            stop_at_end,
+            map,
+        )
     }
 
     pub fn crossing_state_with_end_dist(
         &self,
         dist_int: DistanceInterval,
         start_time: Time,
+        stop_at_end: bool,
         map: &Map,
     ) -> CarState {
-        let on = self.router.head();
-        let mut speed = on.speed_limit(map);
-        if let Some(s) = self.vehicle.max_speed {
-            speed = speed.min(s);
+        let speed = self.max_speed_along(self.router.head(), map);
+        if let Some(limits) = self.kinematics {
+            let profile = SpeedProfile::new(
+                dist_int.end - dist_int.start,
+                self.current_speed(start_time),
+                speed,
+                limits,
+                stop_at_end,
+            );
+            return CarState::Crossing(
+                TimeInterval::new(start_time, start_time + profile.total_time()),
+                dist_int,
+                Some(profile),
+            );
         }
         let dt = (dist_int.end - dist_int.start) / speed;
-        CarState::Crossing(TimeInterval::new(start_time, start_time + dt), dist_int)
+        CarState::Crossing(
+            TimeInterval::new(start_time, start_time + dt),
+            dist_int,
+            None,
+        )
+    }
+
+    /// The speed limit, or the vehicle's own top speed if that's lower. Bikes slow down going
+    /// uphill and speed up going downhill.
+    pub fn max_speed_along(&self, on: Traversable, map: &Map) -> Speed {
+        let speed_limit = on.speed_limit(map);
+        let factor = match on {
+            Traversable::Lane(l) if self.vehicle.vehicle_type == VehicleType::Bike => {
+                map.get_l(l).speed_factor(PathConstraints::Bike, false)
+            }
+            _ => 1.0,
+        };
+        speed_limit.min(self.vehicle.max_speed.unwrap_or(speed_limit) * factor)
+    }
+
+    /// How fast is the car moving right now? Only meaningful with kinematics.
+    fn current_speed(&self, now: Time) -> Speed {
+        match self.state {
+            CarState::Crossing(ref time_int, _, Some(ref profile)) => {
+                profile.speed_at(now - time_int.start)
+            }
+            // If the car wasn't actually blocked, it keeps moving
+            CarState::Queued { blocked_since } | CarState::WaitingToAdvance { blocked_since }
+                if blocked_since == now =>
+            {
+                self.last_speed
+            }
+            _ => Speed::ZERO,
+        }
     }
 
     pub fn get_draw_car(
@@ -214,7 +268,7 @@ impl Car {
             status: match self.state {
                 CarState::Queued { .. } => CarStatus::Moving,
                 CarState::WaitingToAdvance { .. } => CarStatus::Moving,
-                CarState::Crossing(_, _) => CarStatus::Moving,
+                CarState::Crossing(_, _, _) => CarStatus::Moving,
                 CarState::Unparking(_, _, _) => CarStatus::Moving,
                 CarState::Parking(_, _, _) => CarStatus::Moving,
                 // Changing color for idling buses is helpful
@@ -253,7 +307,9 @@ impl Car {
 /// state machine encoded here.
 #[derive(Debug, Serialize, Deserialize, Clone)]
 pub(crate) enum CarState {
-    Crossing(TimeInterval, DistanceInterval),
+    /// The speed profile is only present with kinematics; otherwise the car moves at a constant
+    /// speed.
+    Crossing(TimeInterval, DistanceInterval, Option<SpeedProfile>),
     Queued {
         blocked_since: Time,
     },
@@ -269,7 +325,7 @@ pub(crate) enum CarState {
 impl CarState {
     pub fn get_end_time(&self) -> Time {
         match self {
-            CarState::Crossing(ref time_int, _) => time_int.end,
+            CarState::Crossing(ref time_int, _, _) => time_int.end,
             CarState::Queued { .. } => unreachable!(),
             CarState::WaitingToAdvance { .. } => unreachable!(),
             CarState::Unparking(_, _, ref time_int) => time_int.end,
@@ -4,19 +4,21 @@ use serde::{Deserialize, Serialize};
 
 use abstutil::{deserialize_hashmap, serialize_hashmap, FixedMap, IndexableKey};
 use geom::{Distance, Duration, PolyLine, Speed, Time};
-use map_model::{IntersectionID, LaneID, Map, Path, PathStep, Traversable};
+use map_model::{IntersectionID, LaneID, Map, Path, PathStep, Position, Traversable, TurnID};
 
 use crate::mechanics::car::{Car, CarState};
-use crate::mechanics::Queue;
+use crate::mechanics::{KinematicLimits, Queue};
 use crate::sim::Ctx;
 use crate::{
     ActionAtEnd, AgentID, AgentProperties, CarID, Command, CreateCar, DelayCause, DistanceInterval,
     DrawCarInput, Event, IntersectionSimState, ParkedCar, ParkingSim, ParkingSpot, PersonID,
-    SimOptions, TimeInterval, TransitSimState, TripID, TripManager, UnzoomedAgent, Vehicle,
-    WalkingSimState, FOLLOWING_DISTANCE,
+    ReroutePolicy, RideHailSimState, SimOptions, TimeInterval, TransitSimState, TripID,
+    TripManager, UnzoomedAgent, Vehicle, VehicleType, WalkingSimState, FOLLOWING_DISTANCE,
 };
 
-const TIME_TO_WAIT_AT_BUS_STOP: Duration = Duration::const_seconds(10.0);
+/// Carpools stop at least this long for passengers to get in and out, and check this often if
+/// anybody they're picking up is ready yet.
+const TIME_TO_WAIT_AT_CARPOOL_STOP: Duration = Duration::const_seconds(30.0);
 
 // TODO Do something else.
 pub const BLIND_RETRY_TO_CREEP_FORWARDS: Duration = Duration::const_seconds(0.1);
@@ -42,6 +44,8 @@ pub(crate) struct DrivingSimState {
 
     recalc_lanechanging: bool,
     handle_uber_turns: bool,
+    reroute: Option<ReroutePolicy>,
+    kinematics: Option<BTreeMap<VehicleType, KinematicLimits>>,
 
     time_to_unpark_onstreet: Duration,
     time_to_park_onstreet: Duration,
@@ -58,6 +62,8 @@ impl DrivingSimState {
             events: Vec::new(),
             recalc_lanechanging: opts.recalc_lanechanging,
             handle_uber_turns: opts.handle_uber_turns,
+            reroute: opts.reroute.clone(),
+            kinematics: opts.kinematics.clone(),
 
             time_to_unpark_onstreet: Duration::seconds(10.0),
             time_to_park_onstreet: Duration::seconds(15.0),
@@ -70,7 +76,7 @@ impl DrivingSimState {
         }
 
         for l in map.all_lanes() {
-            if l.lane_type.is_for_moving_vehicles() {
+            if l.ever_for_moving_vehicles() {
                 let q = Queue::new(Traversable::Lane(l.id), map);
                 sim.queues.insert(q.id, q);
             }
@@ -115,8 +121,26 @@ impl DrivingSimState {
                 last_steps: VecDeque::new(),
                 started_at: now,
                 total_blocked_time: Duration::ZERO,
+                last_reroute_check: None,
+                kinematics: None,
+                last_speed: Speed::ZERO,
                 trip_and_person: params.trip_and_person,
             };
+            if let Some(ref limits) = self.kinematics {
+                let vt = car.vehicle.vehicle_type;
+                car.kinematics = Some(
+                    limits
+                        .get(&vt)
+                        .cloned()
+                        .unwrap_or_else(|| KinematicLimits::default_for(vt)),
+                );
+            }
+            // Vehicles appearing at the edge of the map were already moving
+            if params.maybe_parked_car.is_none()
+                && ctx.map.get_i(ctx.map.get_l(first_lane).src_i).is_border()
+            {
+                car.last_speed = car.max_speed_along(Traversable::Lane(first_lane), ctx.map);
+            }
             if let Some(p) = params.maybe_parked_car {
                 let delay = match p.spot {
                     ParkingSpot::Onstreet(_, _) => self.time_to_unpark_onstreet,
@@ -210,6 +234,7 @@ impl DrivingSimState {
         ctx: &mut Ctx,
         trips: &mut TripManager,
         transit: &mut TransitSimState,
+        ride_hail: &mut RideHailSimState,
         walking: &mut WalkingSimState,
     ) {
         let mut need_distances = {
@@ -226,7 +251,8 @@ impl DrivingSimState {
             // checker, temporarily move one of them out of the map.
             let mut car = self.cars.remove(&id).unwrap();
             // Responsibility of update_car to manage scheduling stuff!
-            need_distances = self.update_car_without_distances(&mut car, now, ctx, transit);
+            need_distances =
+                self.update_car_without_distances(&mut car, now, ctx, trips, transit, ride_hail);
             self.cars.insert(id, car);
         }
 
@@ -243,9 +269,9 @@ impl DrivingSimState {
             // checker, temporarily move one of them out of the map.
             let mut car = self.cars.remove(&id).unwrap();
             // Responsibility of update_car_with_distances to manage scheduling stuff!
-            if self
-                .update_car_with_distances(&mut car, &dists, idx, now, ctx, trips, transit, walking)
-            {
+            if self.update_car_with_distances(
+                &mut car, &dists, idx, now, ctx, trips, transit, ride_hail, walking,
+            ) {
                 self.cars.insert(id, car);
             } else {
                 self.delete_car_internal(&mut car, dists, idx, now, ctx);
@@ -261,20 +287,21 @@ impl DrivingSimState {
         car: &mut Car,
         now: Time,
         ctx: &mut Ctx,
+        trips: &mut TripManager,
         transit: &mut TransitSimState,
+        ride_hail: &mut RideHailSimState,
     ) -> bool {
         match car.state {
-            CarState::Crossing(time_int, dist_int) => {
+            CarState::Crossing(time_int, dist_int, profile) => {
+                if let Some(profile) = profile {
+                    car.last_speed = profile.final_speed();
+                }
                 let time_cross = now - time_int.start;
                 if time_cross > Duration::ZERO {
                     let avg_speed = Speed::from_dist_time(dist_int.length(), time_cross);
 
                     let route = car.router.head();
-                    let max_speed = route.speed_limit(ctx.map).min(
-                        car.vehicle
-                            .max_speed
-                            .unwrap_or(Speed::meters_per_second(100.0)),
-                    );
+                    let max_speed = car.max_speed_along(route, ctx.map);
 
                     if let Some((trip, _)) = car.trip_and_person {
                         if let Traversable::Lane(lane) = route {
@@ -293,9 +320,43 @@ impl DrivingSimState {
                 if queue.cars[0] == car.vehicle.id && queue.laggy_head.is_none() {
                     // Want to re-run, but no urgency about it happening immediately.
                     car.state = CarState::WaitingToAdvance { blocked_since: now };
+                    if let Some(ref policy) = self.reroute {
+                        if let Some((trip, _)) = car.trip_and_person {
+                            let due = car
+                                .last_reroute_check
+                                .map(|t| now - t >= policy.min_interval)
+                                .unwrap_or(true);
+                            if due {
+                                car.last_reroute_check = Some(now);
+                                let delayed = ctx
+                                    .intersections
+                                    .delayed_intersections(now, policy.delay_threshold)
+                                    .into_iter()
+                                    .map(|(i, since)| (i, now - since))
+                                    .collect();
+                                if let Some((avoided, old_path)) = car.router.maybe_reroute(
+                                    now,
+                                    &delayed,
+                                    ctx.cap.capped_lanes(now, car.vehicle.id),
+                                    policy.max_detour,
+                                    ctx.map,
+                                ) {
+                                    ctx.cap.rerouted(now, car.vehicle.id, car.router.get_path());
+                                    self.events.push(Event::TripRerouted {
+                                        trip,
+                                        car: car.vehicle.id,
+                                        avoided,
+                                        old_path,
+                                        new_path: car.router.get_path().clone(),
+                                    });
+                                }
+                            }
+                        }
+                    }
                     if self.recalc_lanechanging {
                         car.router.opportunistically_lanechange(
                             &self.queues,
+                            now,
                             ctx.map,
                             self.handle_uber_turns,
                         );
@@ -323,7 +384,30 @@ impl DrivingSimState {
                     .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
             }
             CarState::IdlingAtStop(dist, _) => {
-                car.router = transit.bus_departed_from_stop(car.vehicle.id, ctx.map);
+                if car.vehicle.vehicle_type.is_transit() {
+                    car.router = transit.bus_departed_from_stop(car.vehicle.id, ctx.map);
+                } else {
+                    let pos = Position::new(car.router.head().as_lane(), dist);
+                    let maybe_router = if ride_hail.is_fleet_vehicle(car.vehicle.id) {
+                        ride_hail.car_leaving_stop(now, car.vehicle.id, pos, trips, ctx)
+                    } else if car.vehicle.vehicle_type == VehicleType::Truck {
+                        Some(trips.truck_done_loading(now, &car.vehicle, pos, ctx))
+                    } else {
+                        trips.car_leaving_carpool_stop(now, car.vehicle.id, pos, ctx)
+                    };
+                    if let Some(router) = maybe_router {
+                        car.router = router;
+                    } else {
+                        // Keep waiting for somebody to show up
+                        car.state = CarState::IdlingAtStop(
+                            dist,
+                            TimeInterval::new(now, now + TIME_TO_WAIT_AT_CARPOOL_STOP),
+                        );
+                        ctx.scheduler
+                            .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+                        return false;
+                    }
+                }
                 self.events
                     .push(Event::PathAmended(car.router.get_path().clone()));
                 car.state = car.crossing_state(dist, now, ctx.map);
@@ -360,7 +444,7 @@ impl DrivingSimState {
                         CarState::WaitingToAdvance { .. } => unreachable!(),
                         // They weren't blocked. Note that there's no way the Crossing state could
                         // jump forwards here; the leader is still in front of them.
-                        CarState::Crossing(_, _)
+                        CarState::Crossing(_, _, _)
                         | CarState::Unparking(_, _, _)
                         | CarState::Parking(_, _, _)
                         | CarState::IdlingAtStop(_, _) => {}
@@ -375,14 +459,10 @@ impl DrivingSimState {
                 assert!(from != goto);
 
                 if let Traversable::Turn(t) = goto {
-                    let mut speed = goto.speed_limit(ctx.map);
-                    if let Some(s) = car.vehicle.max_speed {
-                        speed = speed.min(s);
-                    }
                     if !ctx.intersections.maybe_start_turn(
                         AgentID::Car(car.vehicle.id),
                         t,
-                        speed,
+                        car.max_speed_along(goto, ctx.map),
                         now,
                         ctx.map,
                         ctx.scheduler,
@@ -444,6 +524,7 @@ impl DrivingSimState {
                             car.vehicle.length + FOLLOWING_DISTANCE,
                         ),
                         now,
+                        false,
                         ctx.map,
                     )
                     .get_end_time(),
@@ -471,12 +552,13 @@ impl DrivingSimState {
         ctx: &mut Ctx,
         trips: &mut TripManager,
         transit: &mut TransitSimState,
+        ride_hail: &mut RideHailSimState,
         walking: &mut WalkingSimState,
     ) -> bool {
         let our_dist = dists[idx].1;
 
         match car.state {
-            CarState::Crossing(_, _)
+            CarState::Crossing(_, _, _)
             | CarState::Unparking(_, _, _)
             | CarState::IdlingAtStop(_, _)
             | CarState::WaitingToAdvance { .. } => unreachable!(),
@@ -557,10 +639,12 @@ impl DrivingSimState {
                     }
                     Some(ActionAtEnd::BusAtStop) => {
                         car.total_blocked_time += now - blocked_since;
-                        if transit.bus_arrived_at_stop(now, car.vehicle.id, trips, walking, ctx) {
+                        if let Some(dwell) =
+                            transit.bus_arrived_at_stop(now, car.vehicle.id, trips, walking, ctx)
+                        {
                             car.state = CarState::IdlingAtStop(
                                 our_dist,
-                                TimeInterval::new(now, now + TIME_TO_WAIT_AT_BUS_STOP),
+                                TimeInterval::new(now, now + dwell),
                             );
                             ctx.scheduler
                                 .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
@@ -570,6 +654,62 @@ impl DrivingSimState {
                             false
                         }
                     }
+                    Some(ActionAtEnd::CarpoolStop) => {
+                        car.total_blocked_time += now - blocked_since;
+                        let distance_crossed = car.router.get_path().total_length();
+                        if ride_hail.is_fleet_vehicle(car.vehicle.id) {
+                            if !ride_hail.car_reached_stop(
+                                now,
+                                car.vehicle.id,
+                                distance_crossed,
+                                trips,
+                                ctx,
+                            ) {
+                                // Idling at a staging spot
+                                return false;
+                            }
+                        } else {
+                            trips.car_reached_carpool_stop(
+                                now,
+                                car.vehicle.id,
+                                distance_crossed,
+                                ctx,
+                            );
+                        }
+                        car.state = CarState::IdlingAtStop(
+                            our_dist,
+                            TimeInterval::new(now, now + TIME_TO_WAIT_AT_CARPOOL_STOP),
+                        );
+                        ctx.scheduler
+                            .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+                        true
+                    }
+                    Some(ActionAtEnd::StartLoading(Some(spot))) => {
+                        car.total_blocked_time += now - blocked_since;
+                        car.state = CarState::Parking(
+                            our_dist,
+                            spot,
+                            TimeInterval::new(now, now + self.time_to_park_onstreet),
+                        );
+                        ctx.parking.reserve_spot(spot, car.vehicle.id);
+                        ctx.scheduler
+                            .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+                        true
+                    }
+                    Some(ActionAtEnd::StartLoading(None)) => {
+                        // Double-park, blocking the lane until the delivery is done
+                        car.total_blocked_time += now - blocked_since;
+                        let dwell = trips.truck_double_parked(
+                            car.vehicle.id,
+                            car.router.head().as_lane(),
+                            car.router.get_path().total_length(),
+                        );
+                        car.state =
+                            CarState::IdlingAtStop(our_dist, TimeInterval::new(now, now + dwell));
+                        ctx.scheduler
+                            .push(car.state.get_end_time(), Command::UpdateCar(car.vehicle.id));
+                        true
+                    }
                     None => {
                         ctx.scheduler.push(
                             now + BLIND_RETRY_TO_REACH_END_DIST,
@@ -581,7 +721,7 @@ impl DrivingSimState {
                         /*
                         // If this car wasn't blocked at all, when would it reach its goal?
                         let ideal_end_time = match car.crossing_state(our_dist, now, map) {
-                            CarState::Crossing(time_int, _) => time_int.end,
+                            CarState::Crossing(time_int, _, _) => time_int.end,
                             _ => unreachable!(),
                         };
                         if ideal_end_time == now {
@@ -694,7 +834,7 @@ impl DrivingSimState {
                         Command::UpdateCar(follower_id),
                     );
                 }
-                CarState::Crossing(_, _) => {
+                CarState::Crossing(_, _, _) => {
                     // If the follower was still Crossing, they might not've been blocked by leader
                     // yet. In that case, recalculating their Crossing state is a no-op.
                     follower.state = follower.crossing_state(follower_dist, now, ctx.map);
@@ -758,6 +898,7 @@ impl DrivingSimState {
                         self.cars[&id].vehicle.length + FOLLOWING_DISTANCE,
                     ),
                     now,
+                    false,
                     ctx.map,
                 )
                 .get_end_time();
@@ -827,6 +968,7 @@ impl DrivingSimState {
                                 if self.recalc_lanechanging && !ctx.handling_live_edits {
                                     follower.router.opportunistically_lanechange(
                                         &self.queues,
+                                        now,
                                         ctx.map,
                                         self.handle_uber_turns,
                                     );
@@ -838,7 +980,7 @@ impl DrivingSimState {
                         CarState::WaitingToAdvance { .. } => unreachable!(),
                         // They weren't blocked. Note that there's no way the Crossing state could
                         // jump forwards here; the leader vanished from the end of the traversable.
-                        CarState::Crossing(_, _)
+                        CarState::Crossing(_, _, _)
                         | CarState::Unparking(_, _, _)
                         | CarState::Parking(_, _, _)
                         | CarState::IdlingAtStop(_, _) => {}
@@ -860,7 +1002,7 @@ impl DrivingSimState {
         // Calculate all queues that should exist now.
         let mut new_queues = HashSet::new();
         for l in map.all_lanes() {
-            if l.lane_type.is_for_moving_vehicles() {
+            if l.ever_for_moving_vehicles() {
                 new_queues.insert(Traversable::Lane(l.id));
             }
         }
@@ -1164,6 +1306,44 @@ impl DrivingSimState {
         }
     }
 
+    /// Every vehicle moving along this lane (not idling at a stop), the turn it'll make at the end
+    /// of it, if any, and how long it'd take to reach the end at full speed
+    pub fn vehicles_approaching(
+        &self,
+        now: Time,
+        l: LaneID,
+        map: &Map,
+    ) -> Vec<(CarID, Option<TurnID>, Duration)> {
+        let mut results = Vec::new();
+        if let Some(queue) = self.queues.get(&Traversable::Lane(l)) {
+            for (id, front) in queue.get_car_positions(now, &self.cars, &self.queues) {
+                let car = &self.cars[&id];
+                if let CarState::IdlingAtStop(_, _) = car.state {
+                    continue;
+                }
+                let eta = (queue.geom_len - front) / car.max_speed_along(Traversable::Lane(l), map);
+                results.push((
+                    id,
+                    car.router.maybe_next().and_then(|t| t.maybe_turn()),
+                    eta,
+                ));
+            }
+        }
+        results
+    }
+
+    /// Is there a vehicle on this lane whose front is at least this far along?
+    pub fn vehicle_detected(&self, now: Time, l: LaneID, dist: Distance) -> bool {
+        if let Some(queue) = self.queues.get(&Traversable::Lane(l)) {
+            queue
+                .get_car_positions(now, &self.cars, &self.queues)
+                .into_iter()
+                .any(|(_, front)| front >= dist)
+        } else {
+            false
+        }
+    }
+
     pub fn debug_queue_lengths(&self, l: LaneID) -> Option<(Distance, Distance)> {
         let queue = self.queues.get(&Traversable::Lane(l))?;
         Some((queue.reserved_length, queue.geom_len))
@@ -5,20 +5,28 @@ use serde::{Deserialize, Serialize};
 use abstutil::{
     deserialize_btreemap, prettyprint_usize, retain_btreeset, serialize_btreemap, FixedMap,
 };
-use geom::{Duration, Time};
+use geom::{Distance, Duration, Time};
 use map_model::{
-    ControlStopSign, ControlTrafficSignal, Intersection, IntersectionID, LaneID, Map, PhaseType,
-    Traversable, TurnID, TurnPriority, TurnType,
+    BusRouteID, ControlStopSign, ControlTrafficSignal, Intersection, IntersectionID, LaneID, Map,
+    MovementID, PhaseType, Traversable, TurnID, TurnPriority, TurnType,
 };
 
-use crate::mechanics::car::Car;
-use crate::mechanics::Queue;
+use crate::mechanics::car::{Car, CarState};
+use crate::mechanics::{DrivingSimState, Queue};
 use crate::{
     AgentID, AlertLocation, CarID, Command, DelayCause, Event, Scheduler, SimOptions, Speed,
 };
 
 const WAIT_AT_STOP_SIGN: Duration = Duration::const_seconds(0.5);
 const WAIT_BEFORE_YIELD_AT_TRAFFIC_SIGNAL: Duration = Duration::const_seconds(0.2);
+/// At roundabouts and yield signs, don't enter if a vehicle with priority will reach the
+/// intersection and cross our path within this long.
+const CRITICAL_GAP: Duration = Duration::const_seconds(4.0);
+/// How far back from the stop line the loop detectors for actuated signals reach
+const DETECTOR_LENGTH: Distance = Distance::const_meters(20.0);
+/// When holding the green for a transit vehicle with signal priority, leave it this much longer
+/// than the vehicle needs to reach and cross the intersection at full speed
+const TRANSIT_PRIORITY_SLACK: Duration = Duration::const_seconds(2.0);
 
 /// Manages conflicts at intersections. When an agent has reached the end of a lane, they call
 /// maybe_start_turn to make a Request. Based on the intersection type (stop sign, traffic signal,
@@ -39,6 +47,12 @@ pub(crate) struct IntersectionSimState {
     // (x, y) means x is blocked by y. It's a many-to-many relationship. TODO Better data
     // structure.
     blocked_by: BTreeSet<(CarID, CarID)>,
+    /// The route of every transit vehicle, for transit signal priority
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    transit_routes: BTreeMap<CarID, BusRouteID>,
     events: Vec<Event>,
 
     // Count how many calls to maybe_start_turn there are aside from the initial call. Break down
@@ -70,7 +84,29 @@ struct State {
 #[derive(Clone, Serialize, Deserialize)]
 struct SignalState {
     current_stage: usize,
+    #[serde(default = "start_of_day")]
+    stage_started_at: Time,
     stage_ends_at: Time,
+    // Only signals with actuated stages have these.
+    #[serde(default)]
+    detectors: Vec<LoopDetector>,
+    /// Transit signal priority only holds or cuts short each stage once
+    #[serde(default)]
+    extended_for_transit: bool,
+    #[serde(default)]
+    cut_short_for_transit: bool,
+}
+
+fn start_of_day() -> Time {
+    Time::START_OF_DAY
+}
+
+/// A virtual loop detector on an approach lane to an actuated signal. It detects any vehicle whose
+/// front is in the last stretch of the lane before the stop line.
+#[derive(Clone, Serialize, Deserialize)]
+struct LoopDetector {
+    lane: LaneID,
+    starts_at: Distance,
 }
 
 #[derive(PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize, Clone, Debug)]
@@ -90,6 +126,7 @@ impl IntersectionSimState {
             handle_uber_turns: opts.handle_uber_turns,
             disable_turn_conflicts: opts.disable_turn_conflicts,
             blocked_by: BTreeSet::new(),
+            transit_routes: BTreeMap::new(),
             events: Vec::new(),
 
             total_repeat_requests: 0,
@@ -163,6 +200,11 @@ impl IntersectionSimState {
     /// turn.
     pub fn vehicle_gone(&mut self, car: CarID) {
         retain_btreeset(&mut self.blocked_by, |(c1, c2)| *c1 != car && *c2 != car);
+        self.transit_routes.remove(&car);
+    }
+
+    pub fn transit_vehicle_created(&mut self, car: CarID, route: BusRouteID) {
+        self.transit_routes.insert(car, route);
     }
 
     pub fn agent_deleted_mid_turn(&mut self, agent: AgentID, turn: TurnID) {
@@ -246,14 +288,17 @@ impl IntersectionSimState {
         id: IntersectionID,
         map: &Map,
         scheduler: &mut Scheduler,
+        driving: &DrivingSimState,
     ) {
         let state = self.state.get_mut(&id).unwrap();
         let signal_state = state.signal.as_mut().unwrap();
         let signal = map.get_traffic_signal(id);
+        let transit_routes = &self.transit_routes;
 
         // Switch to a new stage?
         assert_eq!(now, signal_state.stage_ends_at);
-        let old_stage = &signal.stages[signal_state.current_stage];
+        let old_stage_idx = signal_state.current_stage;
+        let old_stage = &signal.stages[old_stage_idx];
         match old_stage.phase_type {
             PhaseType::Fixed(_) => {
                 signal_state.current_stage += 1;
@@ -275,15 +320,152 @@ impl IntersectionSimState {
                     ));
                 }
             }
+            PhaseType::Actuated(timing) => {
+                // Keep extending the green while vehicles show up for the protected movements,
+                // until maxing out. Pedestrians waiting for a protected crosswalk can already go,
+                // so they don't extend anything.
+                let max_out = signal_state.stage_started_at + timing.max_green;
+                if now < max_out
+                    && !signal_state.cut_short_for_transit
+                    && has_demand(
+                        old_stage
+                            .protected_movements
+                            .iter()
+                            .filter(|m| !m.crosswalk),
+                        signal,
+                        &BTreeMap::new(),
+                        &signal_state.detectors,
+                        driving,
+                        now,
+                    )
+                {
+                    signal_state.stage_ends_at = max_out.min(now + timing.extension);
+                    scheduler.push(signal_state.stage_ends_at, Command::UpdateIntersection(id));
+                    // Somebody might've declined to start a turn because the stage was about to
+                    // end.
+                    self.wakeup_waiting(now, id, scheduler, map);
+                    return;
+                }
+                signal_state.current_stage += 1;
+            }
         }
+
+        // Before moving on, hold the green for any transit vehicle with priority that can make it
+        // through before the latest the stage can run.
+        if signal_state.current_stage != old_stage_idx
+            && !signal_state.extended_for_transit
+            && !signal_state.cut_short_for_transit
+        {
+            if let Some(ref tsp) = signal.transit_priority {
+                let full_duration = match old_stage.phase_type {
+                    PhaseType::Actuated(timing) => timing.max_green,
+                    ref x => x.simple_duration(),
+                };
+                let latest_end = signal_state.stage_started_at + full_duration + tsp.max_extension;
+                let next_stage = &signal.stages[signal_state.current_stage % signal.stages.len()];
+                if now < latest_end {
+                    let approaching = map.get_i(id).incoming_lanes.iter().find_map(|l| {
+                        driving
+                            .vehicles_approaching(now, *l, map)
+                            .into_iter()
+                            .find_map(|(car, turn, eta)| {
+                                let t = turn?;
+                                if !transit_routes
+                                    .get(&car)
+                                    .map(|r| tsp.applies_to(*r))
+                                    .unwrap_or(false)
+                                    || old_stage.get_priority_of_turn(t, signal)
+                                        == TurnPriority::Banned
+                                    || next_stage.get_priority_of_turn(t, signal)
+                                        != TurnPriority::Banned
+                                {
+                                    return None;
+                                }
+                                let cross = map.get_t(t).geom.length()
+                                    / Traversable::Turn(t).speed_limit(map);
+                                let hold = eta + cross + TRANSIT_PRIORITY_SLACK;
+                                if now + hold > latest_end {
+                                    return None;
+                                }
+                                // Without the extension, the vehicle would arrive partway through
+                                // the following stages that don't allow its turn.
+                                let mut red = Duration::ZERO;
+                                for offset in 0..signal.stages.len() - 1 {
+                                    let stage = &signal.stages[(signal_state.current_stage
+                                        + offset)
+                                        % signal.stages.len()];
+                                    if stage.get_priority_of_turn(t, signal) != TurnPriority::Banned
+                                    {
+                                        break;
+                                    }
+                                    red += stage.phase_type.simple_duration();
+                                }
+                                let saved = if red > eta { red - eta } else { Duration::ZERO };
+                                Some((car, hold, saved))
+                            })
+                    });
+                    if let Some((bus, hold, saved)) = approaching {
+                        signal_state.current_stage = old_stage_idx;
+                        signal_state.extended_for_transit = true;
+                        signal_state.stage_ends_at = now + hold;
+                        self.events.push(Event::TransitSignalPriority {
+                            i: id,
+                            bus,
+                            early_green: false,
+                            dt: signal_state.stage_ends_at - now,
+                            saved,
+                        });
+                        scheduler.push(signal_state.stage_ends_at, Command::UpdateIntersection(id));
+                        self.wakeup_waiting(now, id, scheduler, map);
+                        return;
+                    }
+                }
+            }
+        }
+
         if signal_state.current_stage == signal.stages.len() {
             signal_state.current_stage = 0;
         }
 
+        // Skip actuated stages that nobody is waiting for. If there's no demand for any of them,
+        // rest in the stage that just ended.
+        if !signal_state.detectors.is_empty() {
+            let mut found = false;
+            for _ in 0..signal.stages.len() {
+                let stage = &signal.stages[signal_state.current_stage];
+                if !matches!(stage.phase_type, PhaseType::Actuated(_))
+                    || has_demand(
+                        stage
+                            .protected_movements
+                            .iter()
+                            .chain(stage.yield_movements.iter()),
+                        signal,
+                        &state.waiting,
+                        &signal_state.detectors,
+                        driving,
+                        now,
+                    )
+                {
+                    found = true;
+                    break;
+                }
+                signal_state.current_stage += 1;
+                if signal_state.current_stage == signal.stages.len() {
+                    signal_state.current_stage = 0;
+                }
+            }
+            if !found {
+                signal_state.current_stage = old_stage_idx;
+            }
+        }
+
+        signal_state.stage_started_at = now;
         signal_state.stage_ends_at = now
             + signal.stages[signal_state.current_stage]
                 .phase_type
                 .simple_duration();
+        signal_state.extended_for_transit = false;
+        signal_state.cut_short_for_transit = false;
         scheduler.push(signal_state.stage_ends_at, Command::UpdateIntersection(id));
         self.wakeup_waiting(now, id, scheduler, map);
     }
@@ -361,7 +543,11 @@ impl IntersectionSimState {
         } else if let Some(ref signal) = map.maybe_get_traffic_signal(turn.parent) {
             self.traffic_signal_policy(&req, map, signal, speed, now, Some(scheduler))
         } else if let Some(ref sign) = map.maybe_get_stop_sign(turn.parent) {
-            self.stop_sign_policy(&req, map, sign, now, scheduler)
+            if map.get_i(turn.parent).is_roundabout() {
+                self.yield_policy(&req, map, sign, now, scheduler, readonly_pair)
+            } else {
+                self.stop_sign_policy(&req, map, sign, now, scheduler)
+            }
         } else {
             unreachable!()
         };
@@ -451,10 +637,21 @@ impl IntersectionSimState {
         // SharedSidewalkCorner are always no-conflict, immediate turns; they're not interesting.
         if !shared_sidewalk_corner {
             if let Some(ts) = map.maybe_get_traffic_signal(state.id) {
+                let actuated = state
+                    .signal
+                    .as_ref()
+                    .map(|s| {
+                        matches!(
+                            ts.stages[s.current_stage].phase_type,
+                            PhaseType::Actuated(_)
+                        )
+                    })
+                    .unwrap_or(false);
                 self.events.push(Event::IntersectionDelayMeasured(
                     ts.compressed_id(turn),
                     delay,
                     agent,
+                    actuated,
                 ));
             }
         }
@@ -484,6 +681,7 @@ impl IntersectionSimState {
                 state.signal.as_mut(),
             ) {
                 (Some(ts), Some(signal_state)) => {
+                    signal_state.detectors = LoopDetector::for_signal(ts, map);
                     if signal_state.current_stage >= ts.stages.len() {
                         // Just jump back to the first one. Shrug.
                         signal_state.current_stage = 0;
@@ -719,6 +917,78 @@ impl IntersectionSimState {
         true
     }
 
+    /// Roads that must yield don't stop first. They wait until there's a big enough gap in the
+    /// traffic with priority -- nobody on a road with priority about to cross their path.
+    fn yield_policy(
+        &mut self,
+        req: &Request,
+        map: &Map,
+        sign: &ControlStopSign,
+        now: Time,
+        scheduler: &mut Scheduler,
+        cars_and_queues: Option<(&FixedMap<CarID, Car>, &HashMap<Traversable, Queue>)>,
+    ) -> bool {
+        if sign.get_priority(req.turn, map) != TurnPriority::Yield {
+            return true;
+        }
+        let (cars, queues) = if let Some(pair) = cars_and_queues {
+            pair
+        } else {
+            return true;
+        };
+        let turn = map.get_t(req.turn);
+
+        // When does the next vehicle with priority that would cut us off arrive?
+        let mut next_arrival: Option<Time> = None;
+        for (r, cfg) in &sign.roads {
+            if cfg.must_stop {
+                continue;
+            }
+            for l in &map.get_i(req.turn.parent).incoming_lanes {
+                if map.get_l(*l).parent != *r {
+                    continue;
+                }
+                let queue = if let Some(q) = queues.get(&Traversable::Lane(*l)) {
+                    q
+                } else {
+                    continue;
+                };
+                for id in &queue.cars {
+                    let car = &cars[id];
+                    // Somebody stopped at the intersection must be blocked by something, or
+                    // they'd have gone already. Like for stop signs, don't wait for them.
+                    let arrival = match car.state {
+                        CarState::Crossing(ref time_int, _, _) => time_int.end,
+                        _ => continue,
+                    };
+                    if arrival > now + CRITICAL_GAP {
+                        continue;
+                    }
+                    if let Some(t) = car.router.maybe_next().and_then(|t| t.maybe_turn()) {
+                        if t.parent == req.turn.parent && map.get_t(t).conflicts_with(turn) {
+                            next_arrival = Some(
+                                next_arrival
+                                    .map(|x: Time| x.max(arrival))
+                                    .unwrap_or(arrival),
+                            );
+                        }
+                    }
+                }
+            }
+        }
+
+        if let Some(t) = next_arrival {
+            // Try again once they've arrived. If they start their turn, we'll be blocked until
+            // they finish and wake us up.
+            scheduler.push(
+                t.max(now + WAIT_BEFORE_YIELD_AT_TRAFFIC_SIGNAL),
+                Command::update_agent(req.agent),
+            );
+            return false;
+        }
+        true
+    }
+
     fn traffic_signal_policy(
         &mut self,
         req: &Request,
@@ -733,13 +1003,19 @@ impl IntersectionSimState {
         let state = &self.state[&req.turn.parent];
         let signal_state = state.signal.as_ref().unwrap();
         let stage = &signal.stages[signal_state.current_stage];
-        let full_stage_duration = stage.phase_type.simple_duration();
+        let full_stage_duration = match stage.phase_type {
+            PhaseType::Actuated(timing) => timing.max_green,
+            ref x => x.simple_duration(),
+        };
         let remaining_stage_time = signal_state.stage_ends_at - now;
         let our_time = state.waiting[req];
 
         // Can't go at all this stage.
         let our_priority = stage.get_priority_of_turn(req.turn, signal);
         if our_priority == TurnPriority::Banned {
+            if let Some(s) = scheduler {
+                self.maybe_transit_early_green(req, signal, now, s);
+            }
             return false;
         }
 
@@ -757,6 +1033,34 @@ impl IntersectionSimState {
             return false;
         }
 
+        if let Some(ref timing) = signal.pedestrian_timing {
+            let movement = signal.turn_to_movement(req.turn);
+            if movement.crosswalk {
+                // Don't start crossing once the DON'T WALK starts flashing; wait for the next
+                // cycle instead.
+                let clearance = signal.clearance_interval(&movement).unwrap();
+                if now + clearance > signal_state.stage_ends_at {
+                    return false;
+                }
+            } else {
+                // During the leading pedestrian interval, hold back vehicles crossing any
+                // crosswalk that's green.
+                let lpi_ends = signal_state.stage_started_at + timing.leading_interval;
+                if now < lpi_ends
+                    && stage
+                        .protected_movements
+                        .iter()
+                        .filter(|m| m.crosswalk)
+                        .any(|m| signal.movements[m].conflicts_with(&signal.movements[&movement]))
+                {
+                    if let Some(s) = scheduler {
+                        s.push(lpi_ends, Command::update_agent(req.agent));
+                    }
+                    return false;
+                }
+            }
+        }
+
         // Previously: A yield loses to a conflicting Priority turn.
         // But similar to the description in stop_sign_policy, this caused unnecessary gridlock.
         // Priority vehicles getting scheduled first just requires a little tweak in
@@ -787,6 +1091,90 @@ impl IntersectionSimState {
         true
     }
 
+    /// If a transit vehicle with priority is waiting at a red light and the next stage would let
+    /// it go, end the current stage early.
+    fn maybe_transit_early_green(
+        &mut self,
+        req: &Request,
+        signal: &ControlTrafficSignal,
+        now: Time,
+        scheduler: &mut Scheduler,
+    ) {
+        let tsp = if let Some(ref tsp) = signal.transit_priority {
+            tsp
+        } else {
+            return;
+        };
+        let bus = match req.agent {
+            AgentID::Car(car) => car,
+            _ => return,
+        };
+        if !self
+            .transit_routes
+            .get(&bus)
+            .map(|r| tsp.applies_to(*r))
+            .unwrap_or(false)
+        {
+            return;
+        }
+
+        let signal_state = self
+            .state
+            .get_mut(&req.turn.parent)
+            .unwrap()
+            .signal
+            .as_mut()
+            .unwrap();
+        if signal_state.extended_for_transit || signal_state.cut_short_for_transit {
+            return;
+        }
+        let next_stage = &signal.stages[(signal_state.current_stage + 1) % signal.stages.len()];
+        if next_stage.get_priority_of_turn(req.turn, signal) == TurnPriority::Banned {
+            return;
+        }
+        // Don't cut the stage shorter than pedestrians need to finish crossing. With explicit
+        // pedestrian timing, keep the leading interval, minimum WALK, and clearance, and give
+        // anybody who just started crossing the full clearance interval.
+        let mut min_end = signal_state.stage_started_at
+            + signal.get_min_crossing_time(signal_state.current_stage);
+        if let Some(ref timing) = signal.pedestrian_timing {
+            if let Some(clearance) = signal.stages[signal_state.current_stage]
+                .protected_movements
+                .iter()
+                .filter(|m| m.crosswalk)
+                .filter_map(|m| signal.clearance_interval(m))
+                .max()
+            {
+                min_end = min_end
+                    .max(
+                        signal_state.stage_started_at
+                            + timing.leading_interval
+                            + timing.min_walk
+                            + clearance,
+                    )
+                    .max(now + clearance);
+            }
+        }
+        let earliest_end = (signal_state.stage_ends_at - tsp.max_early_green)
+            .max(min_end)
+            .max(now);
+        if earliest_end >= signal_state.stage_ends_at {
+            return;
+        }
+        // The vehicle is already waiting for the next stage, so it gets through that much sooner.
+        let dt = signal_state.stage_ends_at - earliest_end;
+        self.events.push(Event::TransitSignalPriority {
+            i: signal.id,
+            bus,
+            early_green: true,
+            dt,
+            saved: dt,
+        });
+        signal_state.stage_ends_at = earliest_end;
+        signal_state.cut_short_for_transit = true;
+        scheduler.update(earliest_end, Command::UpdateIntersection(signal.id));
+    }
+
     // If true, the request can go.
     fn handle_accepted_conflicts(
         &mut self,
@@ -889,12 +1277,16 @@ impl IntersectionSimState {
 
 impl SignalState {
     fn new(id: IntersectionID, now: Time, map: &Map, scheduler: &mut Scheduler) -> SignalState {
+        let signal = map.get_traffic_signal(id);
         let mut state = SignalState {
             current_stage: 0,
+            stage_started_at: now,
             stage_ends_at: now,
+            detectors: LoopDetector::for_signal(signal, map),
+            extended_for_transit: false,
+            cut_short_for_transit: false,
         };
 
-        let signal = map.get_traffic_signal(id);
         // What stage are we starting with?
         let mut offset = (now - Time::START_OF_DAY) + signal.offset;
         loop {
@@ -908,6 +1300,7 @@ impl SignalState {
                     state.current_stage = 0;
                 }
             } else {
+                state.stage_started_at = now - offset;
                 state.stage_ends_at = now + dt - offset;
                 break;
             }
@@ -917,6 +1310,59 @@ impl SignalState {
     }
 }
 
+impl LoopDetector {
+    /// Covers every approach lane for vehicles, if the signal has any actuated stages.
+    fn for_signal(signal: &ControlTrafficSignal, map: &Map) -> Vec<LoopDetector> {
+        if !signal
+            .stages
+            .iter()
+            .any(|stage| matches!(stage.phase_type, PhaseType::Actuated(_)))
+        {
+            return Vec::new();
+        }
+        map.get_i(signal.id)
+            .incoming_lanes
+            .iter()
+            .map(|l| map.get_l(*l))
+            .filter(|lane| lane.ever_for_moving_vehicles())
+            .map(|lane| LoopDetector {
+                lane: lane.id,
+                starts_at: (lane.length() - DETECTOR_LENGTH).max(Distance::ZERO),
+            })
+            .collect()
+    }
+}
+
+/// Is anybody waiting for or approaching any of these movements? Vehicles are picked up by the
+/// loop detectors on their approach lane, even before they reach the stop line. Pedestrians only
+/// count once they're waiting to cross.
+fn has_demand<'a, I: Iterator<Item = &'a MovementID>>(
+    movements: I,
+    signal: &ControlTrafficSignal,
+    waiting: &BTreeMap<Request, Time>,
+    detectors: &[LoopDetector],
+    driving: &DrivingSimState,
+    now: Time,
+) -> bool {
+    for m in movements {
+        let movement = &signal.movements[m];
+        if waiting
+            .keys()
+            .any(|req| movement.members.contains(&req.turn))
+        {
+            return true;
+        }
+        for detector in detectors {
+            if movement.members.iter().any(|t| t.src == detector.lane)
+                && driving.vehicle_detected(now, detector.lane, detector.starts_at)
+            {
+                return true;
+            }
+        }
+    }
+    false
+}
+
 fn allow_block_the_box(i: &Intersection) -> bool {
     // Degenerate intersections are often just artifacts of how roads are split up in OSM. Allow
     // vehicles to get stuck in them, since the only possible thing they could block is pedestrians
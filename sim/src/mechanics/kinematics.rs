@@ -0,0 +1,235 @@
+//! By default, vehicles instantly switch between being stopped and moving at their maximum speed.
+//! Optionally, they can speed up and slow down gradually instead. This still fits the discrete-event
+//! model: every Crossing state describes exactly how speed changes over its whole duration, so the
+//! end time is known up-front.
+
+use std::collections::BTreeMap;
+
+use serde::{Deserialize, Serialize};
+
+use geom::{Distance, Duration, Speed};
+
+use crate::VehicleType;
+
+/// How quickly a vehicle can change speed, in meters per second squared.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub struct KinematicLimits {
+    pub acceleration: f64,
+    pub deceleration: f64,
+}
+
+impl KinematicLimits {
+    /// Comfortable limits for each type of vehicle, roughly following traffic engineering
+    /// references.
+    pub fn default_for(vehicle_type: VehicleType) -> KinematicLimits {
+        let (acceleration, deceleration) = match vehicle_type {
+            VehicleType::Car => (2.5, 3.0),
+            VehicleType::Bus => (1.2, 1.5),
+            VehicleType::Train => (1.0, 1.2),
+            VehicleType::Bike => (1.0, 2.0),
+            VehicleType::Truck => (1.5, 2.0),
+        };
+        KinematicLimits {
+            acceleration,
+            deceleration,
+        }
+    }
+
+    pub fn defaults() -> BTreeMap<VehicleType, KinematicLimits> {
+        vec![
+            VehicleType::Car,
+            VehicleType::Bus,
+            VehicleType::Train,
+            VehicleType::Bike,
+            VehicleType::Truck,
+        ]
+        .into_iter()
+        .map(|vt| (vt, KinematicLimits::default_for(vt)))
+        .collect()
+    }
+}
+
+/// How a vehicle's speed changes while it crosses some distance. First it speeds up (or slows
+/// down) from its initial speed to a peak, then cruises, then maybe slows down to a stop. All
+/// values are in meters and seconds.
+#[derive(Clone, Copy, Debug, PartialEq, Serialize, Deserialize)]
+pub(crate) struct SpeedProfile {
+    dist: f64,
+    initial_speed: f64,
+    peak_speed: f64,
+    /// Negative when the vehicle starts above the peak speed
+    first_accel: f64,
+    first_time: f64,
+    cruise_time: f64,
+    deceleration: f64,
+    final_time: f64,
+}
+
+impl SpeedProfile {
+    /// Plan how to cross `dist`, starting at `initial_speed` and never going faster than
+    /// `max_speed`. If `stop_at_end`, the vehicle must be stopped at the end; otherwise it just
+    /// tries to reach `max_speed`.
+    pub fn new(
+        dist: Distance,
+        initial_speed: Speed,
+        max_speed: Speed,
+        limits: KinematicLimits,
+        stop_at_end: bool,
+    ) -> SpeedProfile {
+        let d = dist.inner_meters().max(0.0);
+        let v0 = initial_speed.inner_meters_per_second().max(0.0);
+        let vmax = max_speed.inner_meters_per_second();
+        let a = limits.acceleration;
+        let b = limits.deceleration;
+
+        let mut profile = SpeedProfile {
+            dist: d,
+            initial_speed: v0,
+            peak_speed: v0,
+            first_accel: 0.0,
+            first_time: 0.0,
+            cruise_time: 0.0,
+            deceleration: 0.0,
+            final_time: 0.0,
+        };
+        if d == 0.0 {
+            return profile;
+        }
+
+        if !stop_at_end {
+            let (accel, peak) = if v0 <= vmax {
+                (a, vmax.min((v0 * v0 + 2.0 * a * d).sqrt()))
+            } else {
+                (-b, vmax.max((v0 * v0 - 2.0 * b * d).max(0.0).sqrt()))
+            };
+            profile.first_accel = accel;
+            profile.peak_speed = peak;
+            profile.first_time = (peak - v0) / accel;
+            let first_dist = (peak * peak - v0 * v0) / (2.0 * accel);
+            if peak > 0.0 {
+                profile.cruise_time = ((d - first_dist) / peak).max(0.0);
+            }
+            return profile;
+        }
+
+        if v0 * v0 / (2.0 * b) >= d {
+            // Too fast to stop comfortably, so brake harder.
+            profile.deceleration = v0 * v0 / (2.0 * d);
+            profile.final_time = 2.0 * d / v0;
+            return profile;
+        }
+        // The fastest speed from which it's still possible to stop in time
+        let reachable = ((2.0 * a * b * d + b * v0 * v0) / (a + b)).sqrt();
+        let peak = vmax.min(reachable);
+        let (accel, first_dist) = if peak >= v0 {
+            (a, (peak * peak - v0 * v0) / (2.0 * a))
+        } else {
+            (-b, (v0 * v0 - peak * peak) / (2.0 * b))
+        };
+        profile.first_accel = accel;
+        profile.peak_speed = peak;
+        profile.first_time = (peak - v0) / accel;
+        profile.deceleration = b;
+        profile.final_time = peak / b;
+        let final_dist = peak * peak / (2.0 * b);
+        profile.cruise_time = ((d - first_dist - final_dist) / peak).max(0.0);
+        profile
+    }
+
+    pub fn total_time(&self) -> Duration {
+        Duration::seconds(self.first_time + self.cruise_time + self.final_time)
+    }
+
+    /// How much of the distance has been covered after some time, from 0 to 1.
+    pub fn percent_dist(&self, elapsed: Duration) -> f64 {
+        if self.dist == 0.0 {
+            return 1.0;
+        }
+        let t = elapsed.inner_seconds().max(0.0);
+        let first_dist = self.initial_speed * self.first_time
+            + 0.5 * self.first_accel * self.first_time * self.first_time;
+        let covered = if t <= self.first_time {
+            self.initial_speed * t + 0.5 * self.first_accel * t * t
+        } else if t <= self.first_time + self.cruise_time {
+            first_dist + self.peak_speed * (t - self.first_time)
+        } else {
+            let t = (t - self.first_time - self.cruise_time).min(self.final_time);
+            first_dist + self.peak_speed * self.cruise_time + self.peak_speed * t
+                - 0.5 * self.deceleration * t * t
+        };
+        (covered / self.dist).max(0.0).min(1.0)
+    }
+
+    pub fn speed_at(&self, elapsed: Duration) -> Speed {
+        let t = elapsed.inner_seconds().max(0.0);
+        let speed = if t <= self.first_time {
+            self.initial_speed + self.first_accel * t
+        } else if t <= self.first_time + self.cruise_time {
+            self.peak_speed
+        } else {
+            let t = (t - self.first_time - self.cruise_time).min(self.final_time);
+            self.peak_speed - self.deceleration * t
+        };
+        Speed::meters_per_second(speed.max(0.0))
+    }
+
+    pub fn final_speed(&self) -> Speed {
+        self.speed_at(self.total_time())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn check(profile: SpeedProfile, final_speed: f64) {
+        let total = profile.total_time();
+        assert!((profile.percent_dist(total) - 1.0).abs() < 0.001);
+        assert!((profile.final_speed().inner_meters_per_second() - final_speed).abs() < 0.01);
+        // Distance never goes backwards
+        let mut last = 0.0;
+        for i in 0..=100 {
+            let pct = profile.percent_dist((i as f64) / 100.0 * total);
+            assert!(pct >= last);
+            last = pct;
+        }
+    }
+
+    #[test]
+    fn test_speed_profiles() {
+        let limits = KinematicLimits::default_for(VehicleType::Car);
+        let max = Speed::meters_per_second(15.0);
+
+        // Long enough to reach the max speed and cruise
+        let p = SpeedProfile::new(Distance::meters(200.0), Speed::ZERO, max, limits, false);
+        assert_eq!(p.peak_speed, 15.0);
+        assert!(p.total_time() > Distance::meters(200.0) / max);
+        check(p, 15.0);
+
+        // Too short to reach the max speed
+        let p = SpeedProfile::new(Distance::meters(5.0), Speed::ZERO, max, limits, false);
+        assert!(p.peak_speed < 15.0);
+        check(p, p.peak_speed);
+
+        // Stop at the end
+        let p = SpeedProfile::new(Distance::meters(200.0), max, max, limits, true);
+        check(p, 0.0);
+        let p = SpeedProfile::new(Distance::meters(10.0), Speed::ZERO, max, limits, true);
+        check(p, 0.0);
+
+        // Too fast to stop comfortably
+        let p = SpeedProfile::new(Distance::meters(5.0), max, max, limits, true);
+        assert!(p.deceleration > limits.deceleration);
+        check(p, 0.0);
+
+        // Slow down to a lower speed limit
+        let p = SpeedProfile::new(
+            Distance::meters(100.0),
+            max,
+            Speed::meters_per_second(10.0),
+            limits,
+            false,
+        );
+        check(p, 10.0);
+    }
+}
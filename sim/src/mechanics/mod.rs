@@ -1,5 +1,6 @@
 pub(crate) use self::driving::DrivingSimState;
 pub(crate) use self::intersection::IntersectionSimState;
+pub use self::kinematics::KinematicLimits;
 pub(crate) use self::parking::{ParkingSim, ParkingSimState};
 pub(crate) use self::queue::Queue;
 pub(crate) use self::walking::WalkingSimState;
@@ -7,6 +8,7 @@ pub(crate) use self::walking::WalkingSimState;
 mod car;
 mod driving;
 mod intersection;
+mod kinematics;
 mod parking;
 mod queue;
 mod walking;
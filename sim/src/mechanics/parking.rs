@@ -7,13 +7,16 @@ use abstutil::{
     deserialize_btreemap, deserialize_multimap, retain_btreemap, serialize_btreemap,
     serialize_multimap, MultiMap, Timer,
 };
-use geom::{Distance, PolyLine, Pt2D};
+use geom::{Distance, PolyLine, Pt2D, Time};
 use map_model::{
     BuildingID, Lane, LaneID, LaneType, Map, OffstreetParking, ParkingLotID, PathConstraints,
     PathStep, Position, Traversable, TurnID,
 };
 
-use crate::{CarID, CarStatus, DrawCarInput, Event, ParkedCar, ParkingSpot, PersonID, Vehicle};
+use crate::{
+    CarID, CarStatus, DrawCarInput, Event, ParkedCar, ParkingSpot, PersonID, Vehicle,
+    MAX_CAR_LENGTH,
+};
 
 /// Manages the state of parked cars. There are two implementations:
 /// - NormalParkingSimState allows only one vehicle per ParkingSpot defined in the map
@@ -69,6 +72,9 @@ pub trait ParkingSim {
     fn collect_events(&mut self) -> Vec<Event>;
     fn all_parked_car_positions(&self, map: &Map) -> Vec<(Position, PersonID)>;
     fn bldg_to_parked_cars(&self, b: BuildingID) -> Vec<CarID>;
+    /// Some on-street parking lanes become other types of lanes during part of the day. Nobody new
+    /// can park there then, but cars already parked stay put.
+    fn update_time_restrictions(&mut self, now: Time, map: &Map);
 }
 
 #[enum_dispatch]
@@ -123,6 +129,8 @@ pub struct NormalParkingSimState {
         deserialize_with = "deserialize_multimap"
     )]
     driving_to_parking_lanes: MultiMap<LaneID, LaneID>,
+    // Parking lanes that don't allow parking right now
+    closed_onstreet_lanes: BTreeSet<LaneID>,
 
     // Off-street
     num_spots_per_offstreet: BTreeMap<BuildingID, usize>,
@@ -153,6 +161,7 @@ impl NormalParkingSimState {
 
             onstreet_lanes: BTreeMap::new(),
             driving_to_parking_lanes: MultiMap::new(),
+            closed_onstreet_lanes: BTreeSet::new(),
             num_spots_per_offstreet: BTreeMap::new(),
             driving_to_offstreet: MultiMap::new(),
             num_spots_per_lot: BTreeMap::new(),
@@ -232,6 +241,9 @@ impl ParkingSim for NormalParkingSimState {
 
     fn get_free_onstreet_spots(&self, l: LaneID) -> Vec<ParkingSpot> {
         let mut spots: Vec<ParkingSpot> = Vec::new();
+        if self.closed_onstreet_lanes.contains(&l) {
+            return spots;
+        }
         if let Some(lane) = self.onstreet_lanes.get(&l) {
             for spot in lane.spots() {
                 if self.is_free(spot) {
@@ -420,6 +432,9 @@ impl ParkingSim for NormalParkingSimState {
         let mut candidates = Vec::new();
 
         for l in self.driving_to_parking_lanes.get(driving_pos.lane()) {
+            if self.closed_onstreet_lanes.contains(l) {
+                continue;
+            }
             for spot in self.onstreet_lanes[l].spots() {
                 if self.is_free(spot)
                     && driving_pos.dist_along()
@@ -430,29 +445,32 @@ impl ParkingSim for NormalParkingSimState {
             }
         }
 
-        for (b, bldg_dist) in self.driving_to_offstreet.get(driving_pos.lane()) {
-            if let OffstreetParking::Private(_, _) = map.get_b(*b).parking {
-                if target != *b {
-                    continue;
+        // Trucks don't fit in garages or lots
+        if vehicle.length <= MAX_CAR_LENGTH {
+            for (b, bldg_dist) in self.driving_to_offstreet.get(driving_pos.lane()) {
+                if let OffstreetParking::Private(_, _) = map.get_b(*b).parking {
+                    if target != *b {
+                        continue;
+                    }
                 }
-            }
-            if driving_pos.dist_along() < *bldg_dist {
-                for idx in 0..self.num_spots_per_offstreet[b] {
-                    let spot = ParkingSpot::Offstreet(*b, idx);
-                    if self.is_free(spot) {
-                        candidates.push(spot);
+                if driving_pos.dist_along() < *bldg_dist {
+                    for idx in 0..self.num_spots_per_offstreet[b] {
+                        let spot = ParkingSpot::Offstreet(*b, idx);
+                        if self.is_free(spot) {
+                            candidates.push(spot);
+                        }
                     }
                 }
             }
-        }
 
-        for pl in self.driving_to_lots.get(driving_pos.lane()) {
-            let lot_dist = map.get_pl(*pl).driving_pos.dist_along();
-            if driving_pos.dist_along() < lot_dist {
-                for idx in 0..self.num_spots_per_lot[&pl] {
-                    let spot = ParkingSpot::Lot(*pl, idx);
-                    if self.is_free(spot) {
-                        candidates.push(spot);
+            for pl in self.driving_to_lots.get(driving_pos.lane()) {
+                let lot_dist = map.get_pl(*pl).driving_pos.dist_along();
+                if driving_pos.dist_along() < lot_dist {
+                    for idx in 0..self.num_spots_per_lot[&pl] {
+                        let spot = ParkingSpot::Lot(*pl, idx);
+                        if self.is_free(spot) {
+                            candidates.push(spot);
+                        }
                     }
                 }
             }
@@ -611,6 +629,15 @@ impl ParkingSim for NormalParkingSimState {
         }
         cars
     }
+
+    fn update_time_restrictions(&mut self, now: Time, map: &Map) {
+        self.closed_onstreet_lanes = self
+            .onstreet_lanes
+            .keys()
+            .filter(|l| map.get_l(**l).lane_type_at(now) != LaneType::Parking)
+            .cloned()
+            .collect();
+    }
 }
 
 #[derive(Serialize, Deserialize, Clone)]
@@ -1001,4 +1028,6 @@ impl ParkingSim for InfiniteParkingSimState {
         }
         cars
     }
+
+    fn update_time_restrictions(&mut self, _: Time, _: &Map) {}
 }
@@ -5,8 +5,8 @@ use serde::{Deserialize, Serialize};
 use abstutil::{deserialize_multimap, serialize_multimap, FixedMap, IndexableKey, MultiMap};
 use geom::{Distance, Duration, Line, PolyLine, Speed, Time};
 use map_model::{
-    BuildingID, BusRouteID, DrivingSide, Map, ParkingLotID, Path, PathStep, Traversable,
-    SIDEWALK_THICKNESS,
+    BuildingID, BusRouteID, DrivingSide, Map, ParkingLotID, Path, PathConstraints, PathStep,
+    Traversable, SIDEWALK_THICKNESS,
 };
 
 use crate::sim::Ctx;
@@ -209,6 +209,19 @@ impl WalkingSimState {
                             ctx.scheduler
                                 .push(ped.state.get_end_time(), Command::UpdatePed(ped.id));
                         }
+                        SidewalkPOI::BikeShareStation(station) => {
+                            // Unlocking a bike from the dock takes about as long as getting out
+                            // your own bike
+                            let pt1 = ped.goal.sidewalk_pos.pt(ctx.map);
+                            let pt2 = ctx.map.get_bss(station).biking_pos.pt(ctx.map);
+                            ped.state = PedState::StartingToBike(
+                                ped.goal.clone(),
+                                Line::must_new(pt1, pt2),
+                                TimeInterval::new(now, now + TIME_TO_START_BIKING),
+                            );
+                            ctx.scheduler
+                                .push(ped.state.get_end_time(), Command::UpdatePed(ped.id));
+                        }
                         SidewalkPOI::SuddenlyAppear => unreachable!(),
                         SidewalkPOI::DeferredParkingSpot => unreachable!(),
                     }
@@ -397,6 +410,9 @@ impl WalkingSimState {
         let p = self.peds.get(&id)?;
         Some(&p.path)
     }
+    pub fn get_all_walking_paths(&self) -> Vec<&Path> {
+        self.peds.values().map(|p| &p.path).collect()
+    }
 
     pub fn get_unzoomed_agents(&self, now: Time, map: &Map) -> Vec<UnzoomedAgent> {
         let mut peds = Vec::new();
@@ -559,7 +575,7 @@ impl WalkingSimState {
                 SidewalkPOI::BusStop(_) => {
                     cnts.walking_to_from_transit += 1;
                 }
-                SidewalkPOI::BikeRack(_) => {
+                SidewalkPOI::BikeRack(_) | SidewalkPOI::BikeShareStation(_) => {
                     cnts.walking_to_from_bike += 1;
                 }
                 _ => match p.start.connection {
@@ -569,7 +585,7 @@ impl WalkingSimState {
                     SidewalkPOI::BusStop(_) => {
                         cnts.walking_to_from_transit += 1;
                     }
-                    SidewalkPOI::BikeRack(_) => {
+                    SidewalkPOI::BikeRack(_) | SidewalkPOI::BikeShareStation(_) => {
                         cnts.walking_to_from_bike += 1;
                     }
                     _ => {
@@ -609,8 +625,21 @@ impl Pedestrian {
                 PathStep::Turn(t) => map.get_t(t).geom.length(),
             }
         };
+        // Hills change how fast people walk along sidewalks
+        let speed = match self.path.current_step() {
+            PathStep::Lane(l) => {
+                self.speed
+                    * map
+                        .get_l(l)
+                        .speed_factor(PathConstraints::Pedestrian, false)
+            }
+            PathStep::ContraflowLane(l) => {
+                self.speed * map.get_l(l).speed_factor(PathConstraints::Pedestrian, true)
+            }
+            PathStep::Turn(_) => self.speed,
+        };
         let dist_int = DistanceInterval::new_walking(start_dist, end_dist);
-        let time_int = TimeInterval::new(start_time, start_time + dist_int.length() / self.speed);
+        let time_int = TimeInterval::new(start_time, start_time + dist_int.length() / speed);
         PedState::Crossing(dist_int, time_int)
     }
 
@@ -732,7 +761,10 @@ impl Pedestrian {
                 PedState::WaitingToTurn(_, _) => Some(self.path.next_step().as_turn()),
                 _ => None,
             },
-            preparing_bike: matches!(self.state, PedState::StartingToBike(_, _, _) | PedState::FinishingBiking(_, _, _)),
+            preparing_bike: matches!(
+                self.state,
+                PedState::StartingToBike(_, _, _) | PedState::FinishingBiking(_, _, _)
+            ),
             waiting_for_bus: matches!(self.state, PedState::WaitingForBus(_, _)),
             on,
         }
@@ -0,0 +1,411 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use serde::{Deserialize, Serialize};
+
+use abstutil::{deserialize_btreemap, serialize_btreemap};
+use geom::{Distance, Duration, Time};
+use map_model::{BuildingID, Map, PathConstraints, PathRequest, Position};
+
+use crate::sim::Ctx;
+use crate::{
+    CarID, Command, CreateCar, Event, PersonID, Router, TripID, TripManager, Vehicle, VehicleSpec,
+    VehicleType, MAX_CAR_LENGTH, MIN_CAR_LENGTH,
+};
+
+/// If nobody has been dispatched to pick somebody up after this long, they give up.
+const MAX_RIDE_HAIL_WAIT: Duration = Duration::const_seconds(30.0 * 60.0);
+
+/// Somebody waiting inside a building for a ride-hailing vehicle.
+#[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
+pub(crate) struct RideRequest {
+    pub trip: TripID,
+    pub person: PersonID,
+    pub pickup: BuildingID,
+    pub dropoff: BuildingID,
+    pub requested: Time,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+struct FleetVehicle {
+    vehicle: Vehicle,
+    state: FleetState,
+}
+
+#[derive(Serialize, Deserialize, Clone)]
+enum FleetState {
+    /// Off the road at a staging spot, waiting to be dispatched
+    Idle(usize),
+    /// Driving empty to a staging spot
+    Repositioning(usize),
+    /// Driving empty to pick somebody up
+    EnRoute(RideRequest),
+    /// Stopped at the curb while somebody gets in
+    PickingUp(RideRequest),
+    /// Driving somebody to their destination
+    CarryingPassenger(RideRequest),
+    /// Stopped at the curb with nothing to do yet
+    Available,
+}
+
+/// Manages a fleet of ride-hailing vehicles that nobody owns. Vehicles start idle at staging spots
+/// spread around the map. When somebody requests a ride, the closest idle vehicle with a route to
+/// them is dispatched. Otherwise, the request waits for the next vehicle that can reach them to
+/// drop somebody off, up to `MAX_RIDE_HAIL_WAIT`. With nobody left to pick up, vehicles reposition
+/// to the staging spot with the most demand relative to the number of vehicles already there.
+///
+/// Vehicles pick their routes like any other driver, using observed travel times and avoiding
+/// capped zones.
+///
+/// Driving without a passenger (to a pickup or a staging spot) is deadheading.
+#[derive(Serialize, Deserialize, Clone)]
+pub(crate) struct RideHailSimState {
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    vehicles: BTreeMap<CarID, FleetVehicle>,
+    staging: Vec<Position>,
+    /// How many rides were requested closest to each staging spot
+    demand: Vec<usize>,
+    /// Nobody has been dispatched for these yet
+    pending: VecDeque<RideRequest>,
+
+    events: Vec<Event>,
+}
+
+impl RideHailSimState {
+    pub fn new(map: &Map, fleet_size: usize, trips: &mut TripManager) -> RideHailSimState {
+        let mut state = RideHailSimState {
+            vehicles: BTreeMap::new(),
+            staging: Vec::new(),
+            demand: Vec::new(),
+            pending: VecDeque::new(),
+            events: Vec::new(),
+        };
+        if fleet_size == 0 {
+            return state;
+        }
+
+        // Spread staging spots evenly over driving lanes with room for a vehicle to appear
+        let lanes: Vec<_> = map
+            .all_lanes()
+            .iter()
+            .filter(|l| l.is_driving() && l.length() > MAX_CAR_LENGTH * 2.0)
+            .collect();
+        if lanes.is_empty() {
+            return state;
+        }
+        for idx in 0..fleet_size {
+            let lane = lanes[idx * lanes.len() / fleet_size];
+            state
+                .staging
+                .push(Position::new(lane.id, lane.length() / 2.0));
+            state.demand.push(0);
+
+            let vehicle = VehicleSpec {
+                vehicle_type: VehicleType::Car,
+                length: MIN_CAR_LENGTH,
+                max_speed: None,
+            }
+            .make(CarID(trips.new_car_id(), VehicleType::Car), None);
+            state.vehicles.insert(
+                vehicle.id,
+                FleetVehicle {
+                    vehicle,
+                    state: FleetState::Idle(idx),
+                },
+            );
+        }
+        state
+    }
+
+    pub fn is_fleet_vehicle(&self, car: CarID) -> bool {
+        self.vehicles.contains_key(&car)
+    }
+
+    pub fn request_ride(
+        &mut self,
+        now: Time,
+        req: RideRequest,
+        trips: &mut TripManager,
+        ctx: &mut Ctx,
+    ) {
+        if self.vehicles.is_empty() {
+            trips.cancel_trip(
+                now,
+                req.trip,
+                "there aren't any ride-hailing vehicles".to_string(),
+                None,
+                ctx,
+            );
+            return;
+        }
+
+        let pickup_pt = ctx.map.get_b(req.pickup).polygon.center();
+        let staging = &self.staging;
+        let closest_staging = (0..staging.len())
+            .min_by_key(|idx| staging[*idx].pt(ctx.map).dist_to(pickup_pt))
+            .unwrap();
+        self.demand[closest_staging] += 1;
+
+        let end = match pickup_pos(req.pickup, ctx.map) {
+            Some(end) => end,
+            None => {
+                trips.cancel_trip(
+                    now,
+                    req.trip,
+                    format!("no ride-hailing vehicle can reach {}", req.pickup),
+                    None,
+                    ctx,
+                );
+                return;
+            }
+        };
+
+        // The closest idle vehicle might not have a route, like when it's staged just past the
+        // pickup on the same lane, so fall back to the next closest.
+        let mut idle: Vec<(CarID, Position)> = self
+            .vehicles
+            .iter()
+            .filter_map(|(id, v)| match v.state {
+                FleetState::Idle(idx) => Some((*id, staging[idx])),
+                _ => None,
+            })
+            .collect();
+        idle.sort_by_key(|(_, pos)| pos.pt(ctx.map).dist_to(pickup_pt));
+        for (car, start) in idle {
+            if let Some((path_req, router)) =
+                route(now, car, start, end, Some(req.trip), trips, ctx)
+            {
+                let fleet = self.vehicles.get_mut(&car).unwrap();
+                ctx.scheduler.push(
+                    now,
+                    Command::SpawnCar(
+                        CreateCar {
+                            vehicle: fleet.vehicle.clone(),
+                            router,
+                            req: path_req,
+                            start_dist: start.dist_along(),
+                            maybe_parked_car: None,
+                            trip_and_person: None,
+                            maybe_route: None,
+                        },
+                        true,
+                    ),
+                );
+                fleet.state = FleetState::EnRoute(req);
+                return;
+            }
+        }
+
+        // Every vehicle is busy or can't get there from where it's idling. Wait for one to free up
+        // somewhere else.
+        ctx.scheduler.push(
+            req.requested + MAX_RIDE_HAIL_WAIT,
+            Command::ExpireRideRequest(req.trip),
+        );
+        self.pending.push_back(req);
+    }
+
+    /// Nobody was dispatched for a ride in time, so the person waiting gives up.
+    pub fn expire_request(
+        &mut self,
+        now: Time,
+        trip: TripID,
+        trips: &mut TripManager,
+        ctx: &mut Ctx,
+    ) {
+        if let Some(idx) = self.pending.iter().position(|req| req.trip == trip) {
+            self.pending.remove(idx);
+            trips.cancel_trip(
+                now,
+                trip,
+                format!(
+                    "no ride-hailing vehicle came after waiting {}",
+                    MAX_RIDE_HAIL_WAIT
+                ),
+                None,
+                ctx,
+            );
+        }
+    }
+
+    /// A fleet vehicle reached the end of its route. Returns false if it should vanish, because
+    /// it's idling at a staging spot.
+    pub fn car_reached_stop(
+        &mut self,
+        now: Time,
+        car: CarID,
+        distance_crossed: Distance,
+        trips: &mut TripManager,
+        ctx: &mut Ctx,
+    ) -> bool {
+        let fleet = self.vehicles.get_mut(&car).unwrap();
+        match std::mem::replace(&mut fleet.state, FleetState::Available) {
+            FleetState::EnRoute(req) => {
+                self.events
+                    .push(Event::RideHailVehicleMoved(car, distance_crossed, false));
+                fleet.state = FleetState::PickingUp(req);
+                true
+            }
+            FleetState::CarryingPassenger(req) => {
+                self.events
+                    .push(Event::RideHailVehicleMoved(car, distance_crossed, true));
+                trips.ride_hail_dropoff(now, req.trip, car, distance_crossed, ctx);
+                true
+            }
+            FleetState::Repositioning(idx) => {
+                self.events
+                    .push(Event::RideHailVehicleMoved(car, distance_crossed, false));
+                // Only stick around if there's somebody waiting that this vehicle can reach. Travel
+                // times just change which path is picked, and zone caps are checked once the
+                // vehicle really leaves, so any path will do here.
+                let here = self.staging[idx];
+                if self.pending.iter().any(|req| {
+                    pickup_pos(req.pickup, ctx.map)
+                        .filter(|end| can_loop_to(here, *end))
+                        .and_then(|end| {
+                            ctx.map.pathfind_at(
+                                PathRequest {
+                                    start: here,
+                                    end,
+                                    constraints: PathConstraints::Car,
+                                },
+                                now,
+                            )
+                        })
+                        .is_some()
+                }) {
+                    true
+                } else {
+                    self.vehicles.get_mut(&car).unwrap().state = FleetState::Idle(idx);
+                    false
+                }
+            }
+            FleetState::Idle(_) | FleetState::PickingUp(_) | FleetState::Available => {
+                unreachable!()
+            }
+        }
+    }
+
+    /// A fleet vehicle stopped at the curb is ready to leave. Returns None if it has nowhere to go
+    /// yet and should keep waiting.
+    pub fn car_leaving_stop(
+        &mut self,
+        now: Time,
+        car: CarID,
+        pos: Position,
+        trips: &mut TripManager,
+        ctx: &mut Ctx,
+    ) -> Option<Router> {
+        let fleet = self.vehicles.get_mut(&car).unwrap();
+        if let FleetState::PickingUp(req) =
+            std::mem::replace(&mut fleet.state, FleetState::Available)
+        {
+            let end = ctx.map.get_b(req.dropoff).driving_connection(ctx.map);
+            if let Some((path_req, router)) =
+                end.and_then(|(end, _)| route(now, car, pos, end, Some(req.trip), trips, ctx))
+            {
+                let wait = now - req.requested;
+                trips.ride_hail_pickup(req.trip, car, req.pickup, wait, path_req);
+                self.events.push(Event::RideHailPickup(req.trip, car, wait));
+                fleet.state = FleetState::CarryingPassenger(req);
+                return Some(router);
+            }
+            trips.cancel_trip(
+                now,
+                req.trip,
+                format!("ride-hailing vehicle can't reach {}", req.dropoff),
+                None,
+                ctx,
+            );
+        }
+
+        // Serve the longest wait this vehicle can reach. Other vehicles might reach the rest.
+        for idx in 0..self.pending.len() {
+            if let Some((_, router)) =
+                route_to_pickup(now, car, pos, &self.pending[idx], trips, ctx)
+            {
+                let req = self.pending.remove(idx).unwrap();
+                ctx.scheduler.cancel(Command::ExpireRideRequest(req.trip));
+                self.vehicles.get_mut(&car).unwrap().state = FleetState::EnRoute(req);
+                return Some(router);
+            }
+        }
+
+        // Nobody to pick up, so reposition. Prefer spots where lots of people have asked for
+        // rides, but not many vehicles are waiting already.
+        let mut vehicles_per_staging = vec![0; self.staging.len()];
+        for v in self.vehicles.values() {
+            if let FleetState::Idle(idx) | FleetState::Repositioning(idx) = v.state {
+                vehicles_per_staging[idx] += 1;
+            }
+        }
+        let here = pos.pt(ctx.map);
+        let mut candidates: Vec<usize> = (0..self.staging.len()).collect();
+        candidates.sort_by_key(|idx| {
+            (
+                std::cmp::Reverse(self.demand[*idx] * 100 / (1 + vehicles_per_staging[*idx])),
+                vehicles_per_staging[*idx],
+                self.staging[*idx].pt(ctx.map).dist_to(here),
+            )
+        });
+        for idx in candidates {
+            if let Some((_, router)) = route(now, car, pos, self.staging[idx], None, trips, ctx) {
+                self.vehicles.get_mut(&car).unwrap().state = FleetState::Repositioning(idx);
+                return Some(router);
+            }
+        }
+        None
+    }
+
+    pub fn collect_events(&mut self) -> Vec<Event> {
+        std::mem::replace(&mut self.events, Vec::new())
+    }
+}
+
+fn pickup_pos(b: BuildingID, map: &Map) -> Option<Position> {
+    map.get_b(b).driving_connection(map).map(|(pos, _)| pos)
+}
+
+fn route_to_pickup(
+    now: Time,
+    car: CarID,
+    start: Position,
+    req: &RideRequest,
+    trips: &mut TripManager,
+    ctx: &mut Ctx,
+) -> Option<(PathRequest, Router)> {
+    pickup_pos(req.pickup, ctx.map)
+        .and_then(|end| route(now, car, start, end, Some(req.trip), trips, ctx))
+}
+
+/// A path can't loop back to an earlier spot on the same lane
+fn can_loop_to(start: Position, end: Position) -> bool {
+    start.lane() != end.lane() || start.dist_along() <= end.dist_along()
+}
+
+/// `trip` is the person being picked up or driven, if any.
+fn route(
+    now: Time,
+    car: CarID,
+    start: Position,
+    end: Position,
+    trip: Option<TripID>,
+    trips: &mut TripManager,
+    ctx: &mut Ctx,
+) -> Option<(PathRequest, Router)> {
+    if !can_loop_to(start, end) {
+        return None;
+    }
+    let req = PathRequest {
+        start,
+        end,
+        constraints: PathConstraints::Car,
+    };
+    let path = trips.ride_hail_path(now, car, &req, trip, ctx)?;
+    Some((
+        req,
+        Router::stop_for_passengers(car, path, end.dist_along()),
+    ))
+}
@@ -1,14 +1,14 @@
 //! For vehicles only, not pedestrians. Follows a Path from map_model, but can opportunistically
 //! lane-change to avoid a slow lane, can can handle re-planning to look for available parking.
 
-use std::collections::HashMap;
+use std::collections::{BTreeMap, BTreeSet, HashMap};
 
 use serde::{Deserialize, Serialize};
 
-use geom::Distance;
+use geom::{Distance, Duration, Time};
 use map_model::{
-    BuildingID, IntersectionID, LaneID, Map, Path, PathConstraints, PathRequest, PathStep,
-    Position, Traversable, TurnID,
+    BuildingID, IntersectionID, LaneID, Map, ParkingLotID, Path, PathConstraints, PathRequest,
+    PathStep, Position, Traversable, TurnID,
 };
 
 use crate::mechanics::Queue;
@@ -25,6 +25,19 @@ pub(crate) struct Router {
     owner: CarID,
 }
 
+/// Lets drivers switch to a different route partway through their trip, when the rest of their
+/// path passes through a congested intersection.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct ReroutePolicy {
+    /// An intersection is congested when some agent has been waiting there at least this long.
+    pub delay_threshold: Duration,
+    /// Each driver reconsiders their route at most this often.
+    pub min_interval: Duration,
+    /// Only switch to a new route if it's at most this many times longer than the rest of the
+    /// current route.
+    pub max_detour: f64,
+}
+
 #[derive(Debug)]
 pub(crate) enum ActionAtEnd {
     VanishAtBorder(IntersectionID),
@@ -32,6 +45,9 @@ pub(crate) enum ActionAtEnd {
     GotoLaneEnd,
     StopBiking(SidewalkSpot),
     BusAtStop,
+    CarpoolStop,
+    /// Park in this spot to load, or double-park if there's no spot or it's been taken.
+    StartLoading(Option<ParkingSpot>),
     GiveUpOnParking,
 }
 
@@ -55,6 +71,23 @@ enum Goal {
     FollowBusRoute {
         end_dist: Distance,
     },
+    /// Pull over next to a building to pick up or drop off carpool passengers
+    StopForPassengers {
+        end_dist: Distance,
+    },
+    /// Stop near a building to load or unload a delivery. If the spot is still free when the
+    /// truck arrives, it parks there; otherwise it stops in the lane.
+    Deliver {
+        end_dist: Distance,
+        spot: Option<ParkingSpot>,
+    },
+    /// Park in one particular lot, to transfer to transit. If the lot is full by the time the car
+    /// arrives, drive the rest of the way and park near the fallback building instead.
+    ParkInLot {
+        lot: ParkingLotID,
+        end_dist: Distance,
+        fallback: BuildingID,
+    },
 }
 
 impl Router {
@@ -111,6 +144,45 @@ impl Router {
         }
     }
 
+    pub fn stop_for_passengers(owner: CarID, path: Path, end_dist: Distance) -> Router {
+        Router {
+            path,
+            goal: Goal::StopForPassengers { end_dist },
+            owner,
+        }
+    }
+
+    pub fn deliver(
+        owner: CarID,
+        path: Path,
+        end_dist: Distance,
+        spot: Option<ParkingSpot>,
+    ) -> Router {
+        Router {
+            path,
+            goal: Goal::Deliver { end_dist, spot },
+            owner,
+        }
+    }
+
+    pub fn park_in_lot(
+        owner: CarID,
+        path: Path,
+        lot: ParkingLotID,
+        fallback: BuildingID,
+        map: &Map,
+    ) -> Router {
+        Router {
+            path,
+            goal: Goal::ParkInLot {
+                lot,
+                end_dist: map.get_pl(lot).driving_pos.dist_along(),
+                fallback,
+            },
+            owner,
+        }
+    }
+
     pub fn head(&self) -> Traversable {
         self.path.current_step().as_traversable()
     }
@@ -139,6 +211,9 @@ impl Router {
             } => stuck_end_dist.unwrap_or_else(|| spot.unwrap().1),
             Goal::BikeThenStop { ref goal } => goal.sidewalk_pos.dist_along(),
             Goal::FollowBusRoute { end_dist } => end_dist,
+            Goal::StopForPassengers { end_dist } => end_dist,
+            Goal::Deliver { end_dist, .. } => end_dist,
+            Goal::ParkInLot { end_dist, .. } => end_dist,
         }
     }
 
@@ -171,7 +246,11 @@ impl Router {
         // Sanity check laws haven't been broken
         if let Traversable::Lane(l) = self.head() {
             let lane = map.get_l(l);
-            if !vehicle.vehicle_type.to_constraints().can_use(lane, map) {
+            if !vehicle
+                .vehicle_type
+                .to_constraints()
+                .can_ever_use(lane, map)
+            {
                 panic!(
                     "{} just wound up on {}, a {:?} (check the OSM tags)",
                     vehicle.id, l, lane.lane_type
@@ -325,12 +404,90 @@ impl Router {
                     None
                 }
             }
+            Goal::StopForPassengers { end_dist } => {
+                if end_dist == front {
+                    Some(ActionAtEnd::CarpoolStop)
+                } else {
+                    None
+                }
+            }
+            Goal::Deliver { end_dist, spot } => {
+                if end_dist == front {
+                    Some(ActionAtEnd::StartLoading(
+                        spot.filter(|s| parking.is_free(*s)),
+                    ))
+                } else {
+                    None
+                }
+            }
+            Goal::ParkInLot {
+                lot,
+                end_dist,
+                fallback,
+            } => {
+                if end_dist != front {
+                    return None;
+                }
+                if let Some(spot) = parking.get_free_lot_spots(lot).into_iter().next() {
+                    return Some(ActionAtEnd::StartParking(spot));
+                }
+
+                // Drive the rest of the way, then look for parking like usual
+                let current_lane = self.path.current_step().as_lane();
+                let target_lane = map.find_driving_lane_near_building(fallback);
+                if current_lane != target_lane {
+                    let req = PathRequest {
+                        start: Position::new(current_lane, front),
+                        end: Position::start(target_lane),
+                        constraints: PathConstraints::Car,
+                    };
+                    if let Some(path) = map.pathfind(req) {
+                        for step in path.get_steps().iter().skip(1) {
+                            self.path.add(*step, map);
+                        }
+                        events.push(Event::PathAmended(self.path.clone()));
+                    } else {
+                        if let Some((_, p)) = trip_and_person {
+                            events.push(Event::Alert(
+                                AlertLocation::Person(p),
+                                format!(
+                                    "{} reached {}, but it's full, and {} is unreachable",
+                                    vehicle.id, lot, fallback
+                                ),
+                            ));
+                        }
+                        return Some(ActionAtEnd::GiveUpOnParking);
+                    }
+                }
+                if let Some((_, p)) = trip_and_person {
+                    events.push(Event::Alert(
+                        AlertLocation::Person(p),
+                        format!(
+                            "{} reached {}, but it's full, so it's driving to {} instead",
+                            vehicle.id, lot, fallback
+                        ),
+                    ));
+                }
+
+                self.goal = Goal::ParkNearBuilding {
+                    target: fallback,
+                    spot: None,
+                    stuck_end_dist: None,
+                    started_looking: false,
+                };
+                if current_lane == target_lane {
+                    self.maybe_handle_end(front, vehicle, parking, map, trip_and_person, events)
+                } else {
+                    Some(ActionAtEnd::GotoLaneEnd)
+                }
+            }
         }
     }
 
     pub fn opportunistically_lanechange(
         &mut self,
         queues: &HashMap<Traversable, Queue>,
+        now: Time,
         map: &Map,
         handle_uber_turns: bool,
     ) {
@@ -356,13 +513,17 @@ impl Router {
         let parent = map.get_parent(orig_target_lane);
         let next_parent = map.get_l(next_lane).src_i;
 
-        // Look for other candidates, and assign a cost to each.
+        // Look for other candidates, and assign a cost to each. Lanes that only open to us during
+        // part of the day count, but the original lane is always fine.
         let constraints = self.owner.1.to_constraints();
         let dir = parent.dir(orig_target_lane);
         let (_, turn1, best_lane, turn2) = parent
             .lanes_ltr()
             .into_iter()
-            .filter(|(l, d, _)| dir == *d && constraints.can_use(map.get_l(*l), map))
+            .filter(|(l, d, _)| {
+                dir == *d
+                    && (*l == orig_target_lane || constraints.can_use_at(map.get_l(*l), map, now))
+            })
             .filter_map(|(l, _, _)| {
                 let t1 = TurnID {
                     parent: current_turn.parent,
@@ -424,6 +585,102 @@ impl Router {
         self.path.modify_step(3, PathStep::Turn(turn2.id), map);
     }
 
+    /// Called when the car reaches the end of a lane. If the rest of the path crosses any of the
+    /// `delayed` intersections (besides the one just ahead, which can't be avoided anymore), look
+    /// for a different path, counting the current delay at each of them as an extra cost. The
+    /// new path obeys the usual restrictions at the current time and never uses the `banned`
+    /// lanes. Returns the delayed intersections that the new path avoids, and the remaining steps
+    /// of the old path, if the path changes.
+    pub fn maybe_reroute(
+        &mut self,
+        now: Time,
+        delayed: &BTreeMap<IntersectionID, Duration>,
+        banned: BTreeSet<LaneID>,
+        max_detour: f64,
+        map: &Map,
+    ) -> Option<(Vec<IntersectionID>, Path)> {
+        // Only people driving to their destination; not buses or bikes, and not anybody already
+        // searching for parking.
+        if self.owner.1 != VehicleType::Car && self.owner.1 != VehicleType::Truck {
+            return None;
+        }
+        match self.goal {
+            Goal::EndAtBorder { .. } | Goal::ParkInLot { .. } => {}
+            Goal::ParkNearBuilding {
+                started_looking, ..
+            } if !started_looking => {}
+            _ => {
+                return None;
+            }
+        }
+        // Uber-turns have to be followed exactly.
+        if self.path.currently_inside_ut().is_some() || self.path.about_to_start_ut().is_some() {
+            return None;
+        }
+
+        let steps = self.path.get_steps();
+        let (current, end) = match (steps[0], steps[steps.len() - 1]) {
+            (PathStep::Lane(l1), PathStep::Lane(l2)) if l1 != l2 => (l1, l2),
+            _ => {
+                return None;
+            }
+        };
+        // The path doesn't cross the intersection at the end of the last lane.
+        let crossing_delays: Vec<IntersectionID> = steps
+            .iter()
+            .skip(1)
+            .take(steps.len() - 2)
+            .filter_map(|step| match step {
+                PathStep::Lane(l) => Some(map.get_l(*l).dst_i),
+                _ => None,
+            })
+            .filter(|i| delayed.contains_key(i))
+            .collect();
+        if crossing_delays.is_empty() {
+            return None;
+        }
+
+        let mut extra_costs = BTreeMap::new();
+        for (i, delay) in delayed {
+            for l in &map.get_i(*i).incoming_lanes {
+                if *l != current && *l != end {
+                    extra_costs.insert(*l, *delay);
+                }
+            }
+        }
+        let req = PathRequest {
+            start: Position::new(current, map.get_l(current).length()),
+            // The path keeps its original end distance, so this doesn't matter
+            end: Position::new(end, map.get_l(end).length()),
+            constraints: PathConstraints::Car,
+        };
+        let new_path = map.pathfind_avoiding_lanes(req, now, banned, extra_costs)?;
+        let remaining_length = self.path.total_length() - self.path.crossed_so_far();
+        if new_path.total_length() > max_detour * remaining_length {
+            return None;
+        }
+        // Waiting out some delays might still be better than a long detour
+        let still_crossed: BTreeSet<IntersectionID> = new_path
+            .get_steps()
+            .iter()
+            .filter_map(|step| match step {
+                PathStep::Turn(t) => Some(t.parent),
+                _ => None,
+            })
+            .collect();
+        let avoided: Vec<IntersectionID> = crossing_delays
+            .into_iter()
+            .filter(|i| !still_crossed.contains(i))
+            .collect();
+        if avoided.is_empty() {
+            return None;
+        }
+
+        let old_path = self.path.clone();
+        self.path.reroute(new_path, map);
+        Some((avoided, old_path))
+    }
+
     pub fn replace_path_for_serialization(&mut self, path: Path) -> Path {
         std::mem::replace(&mut self.path, path)
     }
@@ -9,7 +9,8 @@ use geom::{Duration, Histogram, Time};
 use map_model::{BusRouteID, IntersectionID, Path, PathRequest};
 
 use crate::{
-    pandemic, AgentID, CarID, CreateCar, CreatePedestrian, PedestrianID, TripID, TripSpec,
+    pandemic, AgentID, CarID, CreateCar, CreatePedestrian, PedestrianID, RideRequest, TripID,
+    TripSpec,
 };
 
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
@@ -27,6 +28,11 @@ pub(crate) enum Command {
     Pandemic(pandemic::Cmd),
     /// The Time is redundant, just used to dedupe commands
     StartBus(BusRouteID, Time),
+    RequestRide(RideRequest),
+    /// Give up waiting for a ride-hailing vehicle, if nobody has been dispatched yet
+    ExpireRideRequest(TripID),
+    /// Some lane types or access restrictions change at this time of day
+    UpdateTimeRestrictions,
 }
 
 impl Command {
@@ -34,7 +40,7 @@ impl Command {
         match id {
             AgentID::Car(c) => Command::UpdateCar(c),
             AgentID::Pedestrian(p) => Command::UpdatePed(p),
-            AgentID::BusPassenger(_, _) => unreachable!(),
+            AgentID::BusPassenger(_, _) | AgentID::CarPassenger(_, _) => unreachable!(),
         }
     }
 
@@ -50,6 +56,9 @@ impl Command {
             Command::Callback(_) => CommandType::Callback,
             Command::Pandemic(ref p) => CommandType::Pandemic(p.clone()),
             Command::StartBus(r, t) => CommandType::StartBus(*r, *t),
+            Command::RequestRide(ref req) => CommandType::RequestRide(req.trip),
+            Command::ExpireRideRequest(trip) => CommandType::ExpireRideRequest(*trip),
+            Command::UpdateTimeRestrictions => CommandType::TimeRestrictions,
         }
     }
 
@@ -65,6 +74,9 @@ impl Command {
             Command::Callback(_) => SimpleCommandType::Callback,
             Command::Pandemic(_) => SimpleCommandType::Pandemic,
             Command::StartBus(_, _) => SimpleCommandType::StartBus,
+            Command::RequestRide(_) => SimpleCommandType::RequestRide,
+            Command::ExpireRideRequest(_) => SimpleCommandType::ExpireRideRequest,
+            Command::UpdateTimeRestrictions => SimpleCommandType::TimeRestrictions,
         }
     }
 }
@@ -81,6 +93,9 @@ enum CommandType {
     Callback,
     Pandemic(pandemic::Cmd),
     StartBus(BusRouteID, Time),
+    RequestRide(TripID),
+    ExpireRideRequest(TripID),
+    TimeRestrictions,
 }
 
 /// A more compressed form of CommandType, just used for keeping stats on event processing.
@@ -94,6 +109,9 @@ enum SimpleCommandType {
     Callback,
     Pandemic,
     StartBus,
+    RequestRide,
+    ExpireRideRequest,
+    TimeRestrictions,
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Clone)]
@@ -1,7 +1,8 @@
 // This file has a jumbled mess of queries, setup, and mutating methods.
 
-use std::collections::{BTreeSet, HashSet};
+use std::collections::{BTreeMap, BTreeSet, HashSet};
 use std::panic;
+use std::sync::Arc;
 
 use instant::Instant;
 use rand::SeedableRng;
@@ -11,18 +12,19 @@ use serde::{Deserialize, Serialize};
 use abstutil::{prettyprint_usize, serialized_size_bytes, CmdArgs, MapName, Parallelism, Timer};
 use geom::{Distance, Duration, Speed, Time};
 use map_model::{
-    BuildingID, BusRoute, IntersectionID, LaneID, Map, ParkingLotID, Path, PathConstraints,
-    PathRequest, Position, Traversable,
+    BuildingID, BusRoute, BusRouteID, IntersectionID, LaneID, Map, ParkingLotID, Path,
+    PathConstraints, PathRequest, Position, TravelTimes, Traversable,
 };
 
 pub use self::queries::{AgentProperties, DelayCause};
 use crate::{
     AgentID, AlertLocation, Analytics, CapSimState, CarID, Command, CreateCar, DrivingSimState,
-    Event, IntersectionSimState, OrigPersonID, PandemicModel, ParkedCar, ParkingSim,
-    ParkingSimState, ParkingSpot, Person, PersonID, Router, Scheduler, SidewalkPOI, SidewalkSpot,
-    TrafficRecorder, TransitSimState, TripID, TripInfo, TripLeg, TripManager, TripPhaseType,
-    TripSpec, Vehicle, VehicleSpec, VehicleType, WalkingSimState, BUS_LENGTH, LIGHT_RAIL_LENGTH,
-    MIN_CAR_LENGTH, SPAWN_DIST,
+    Event, EventFilter, EventLog, EventLogFormat, EventLogOptions, IntersectionSimState,
+    KinematicLimits, OrigPersonID, PandemicModel, ParkedCar, ParkingSim, ParkingSimState,
+    ParkingSpot, Person, PersonID, ReroutePolicy, RideHailSimState, Router, Scheduler, SidewalkPOI,
+    SidewalkSpot, TrafficRecorder, TransitCrowding, TransitSimState, TripID, TripInfo, TripLeg,
+    TripManager, TripPhaseType, TripSpec, Vehicle, VehicleSpec, VehicleType, WalkingSimState,
+    BUS_LENGTH, LIGHT_RAIL_LENGTH, MIN_CAR_LENGTH, SPAWN_DIST,
 };
 
 mod queries;
@@ -38,6 +40,7 @@ pub struct Sim {
     walking: WalkingSimState,
     intersections: IntersectionSimState,
     transit: TransitSimState,
+    ride_hail: RideHailSimState,
     cap: CapSimState,
     trips: TripManager,
     #[serde(skip_serializing, skip_deserializing)]
@@ -63,6 +66,8 @@ pub struct Sim {
 
     #[serde(skip_serializing, skip_deserializing)]
     alerts: AlertHandler,
+    #[serde(skip_serializing, skip_deserializing)]
+    event_log: Option<EventLog>,
 }
 
 pub(crate) struct Ctx<'a> {
@@ -76,7 +81,8 @@ pub(crate) struct Ctx<'a> {
 }
 
 /// Options controlling the traffic simulation.
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(default)]
 pub struct SimOptions {
     /// Used to distinguish savestates for running the same scenario.
     pub run_name: String,
@@ -97,7 +103,9 @@ pub struct SimOptions {
     /// intersections with short roads. "Locks" the entire movement before starting, and ignores
     /// red lights after starting.
     pub handle_uber_turns: bool,
-    /// Enable an experimental SEIR pandemic model.
+    /// Enable an experimental SEIR pandemic model. This can only be set from code or the command
+    /// line, not when deserializing options.
+    #[serde(skip_serializing, skip_deserializing)]
     pub enable_pandemic_model: Option<XorShiftRng>,
     /// When a warning is encountered during simulation, specifies how to respond.
     pub alerts: AlertHandler,
@@ -114,6 +122,25 @@ pub struct SimOptions {
     /// Don't collect any analytics. Only useful for benchmarking and debugging gridlock more
     /// quickly.
     pub skip_analytics: bool,
+    /// If present, write every event matching a filter to a file as the simulation runs.
+    pub event_log: Option<EventLogOptions>,
+    /// If present, drivers pick routes using travel times observed in a previous simulation,
+    /// instead of assuming free-flow speeds. This can only be set from code or the command line.
+    #[serde(skip_serializing, skip_deserializing)]
+    pub travel_times: Option<Arc<TravelTimes>>,
+    /// If present, drivers may switch routes partway through their trip to avoid congested
+    /// intersections.
+    pub reroute: Option<ReroutePolicy>,
+    /// If present, vehicles speed up and slow down gradually, within these limits. Vehicle types
+    /// not listed use `KinematicLimits::default_for`. Otherwise, vehicles instantly change between
+    /// being stopped and moving at their maximum speed.
+    pub kinematics: Option<BTreeMap<VehicleType, KinematicLimits>>,
+    /// How many ride-hailing vehicles serve trips using TripMode::RideHail. With none, those trips
+    /// are cancelled.
+    pub ride_hail_fleet: usize,
+    /// If present, transit vehicles have a limited capacity, and stay at stops longer when more
+    /// people get on and off. Otherwise, everybody fits and vehicles wait a fixed time at stops.
+    pub transit_crowding: Option<TransitCrowding>,
 }
 
 impl std::default::Default for SimOptions {
@@ -152,11 +179,85 @@ impl SimOptions {
             cancel_drivers_delay_threshold: args
                 .optional_parse("--cancel_drivers_delay_threshold", Duration::parse),
             skip_analytics: args.enabled("--skip_analytics"),
+            event_log: args.optional("--event_log").map(|path| EventLogOptions {
+                path,
+                format: if args.enabled("--event_log_binary") {
+                    EventLogFormat::Binary
+                } else {
+                    EventLogFormat::Json
+                },
+                filter: EventFilter {
+                    variants: args
+                        .optional("--event_log_variants")
+                        .map(|x| x.split(',').map(|v| v.to_string()).collect()),
+                    agents: None,
+                    intersections: args.optional("--event_log_intersections").map(|x| {
+                        x.split(',')
+                            .map(|i| {
+                                IntersectionID(i.parse::<usize>().unwrap_or_else(|_| {
+                                    panic!("Bad --event_log_intersections={}", x)
+                                }))
+                            })
+                            .collect()
+                    }),
+                },
+            }),
+            travel_times: args
+                .optional("--travel_times")
+                .map(|path| Arc::new(abstutil::read_binary(path, &mut Timer::throwaway()))),
+            reroute: args
+                .optional_parse("--reroute_delay_threshold", Duration::parse)
+                .map(|delay_threshold| ReroutePolicy {
+                    delay_threshold,
+                    min_interval: args
+                        .optional_parse("--reroute_interval", Duration::parse)
+                        .unwrap_or(Duration::minutes(5)),
+                    max_detour: args
+                        .optional_parse("--reroute_max_detour", |s| s.parse::<f64>())
+                        .unwrap_or(2.0),
+                }),
+            kinematics: if args.enabled("--kinematics") {
+                Some(KinematicLimits::defaults())
+            } else {
+                None
+            },
+            ride_hail_fleet: args
+                .optional_parse("--ride_hail_fleet", |s| s.parse::<usize>())
+                .unwrap_or(0),
+            transit_crowding: {
+                // Overriding the capacity of some routes implies crowding
+                let route_capacity = args.optional("--transit_route_capacity").map(|x| {
+                    x.split(',')
+                        .map(|pair| {
+                            let mut parts = pair.split(':');
+                            match (
+                                parts.next().and_then(|r| r.parse::<usize>().ok()),
+                                parts.next().and_then(|n| n.parse::<usize>().ok()),
+                                parts.next(),
+                            ) {
+                                (Some(r), Some(n), None) => (BusRouteID(r), n),
+                                _ => panic!(
+                                    "Bad --transit_route_capacity={}. Must be \
+                                     route:capacity,route:capacity,...",
+                                    x
+                                ),
+                            }
+                        })
+                        .collect::<BTreeMap<_, _>>()
+                });
+                if args.enabled("--transit_crowding") || route_capacity.is_some() {
+                    let mut crowding = TransitCrowding::defaults();
+                    crowding.route_capacity = route_capacity.unwrap_or_default();
+                    Some(crowding)
+                } else {
+                    None
+                }
+            },
         }
     }
 }
 
-#[derive(Clone)]
+#[derive(Clone, Serialize, Deserialize)]
 pub enum AlertHandler {
     /// Just print the alert to STDOUT
     Print,
@@ -187,6 +288,12 @@ impl SimOptions {
             disable_turn_conflicts: false,
             cancel_drivers_delay_threshold: None,
             skip_analytics: false,
+            event_log: None,
+            travel_times: None,
+            reroute: None,
+            kinematics: None,
+            ride_hail_fleet: 0,
+            transit_crowding: None,
         }
     }
 }
@@ -195,14 +302,16 @@ impl SimOptions {
 impl Sim {
     pub fn new(map: &Map, opts: SimOptions, timer: &mut Timer) -> Sim {
         let mut scheduler = Scheduler::new();
-        Sim {
+        let mut trips = TripManager::new(opts.travel_times.clone());
+        let mut sim = Sim {
             driving: DrivingSimState::new(map, &opts),
             parking: ParkingSimState::new(map, opts.infinite_parking, timer),
             walking: WalkingSimState::new(),
             intersections: IntersectionSimState::new(map, &mut scheduler, &opts),
-            transit: TransitSimState::new(map),
+            transit: TransitSimState::new(map, opts.transit_crowding.clone()),
+            ride_hail: RideHailSimState::new(map, opts.ride_hail_fleet, &mut trips),
             cap: CapSimState::new(map, &opts),
-            trips: TripManager::new(),
+            trips,
             pandemic: if let Some(rng) = opts.enable_pandemic_model {
                 Some(PandemicModel::new(rng))
             } else {
@@ -219,7 +328,32 @@ impl Sim {
 
             analytics: Analytics::new(!opts.skip_analytics),
             recorder: None,
-        }
+            event_log: opts.event_log.map(EventLog::new),
+        };
+        sim.parking.update_time_restrictions(sim.time, map);
+        sim.schedule_time_restrictions(map);
+        sim
+    }
+
+    /// Wake up the next time some lane type or access restriction changes.
+    fn schedule_time_restrictions(&mut self, map: &Map) {
+        let changes = map.time_restriction_changes();
+        let first = match changes.iter().next() {
+            Some(t) => *t,
+            None => {
+                self.scheduler.cancel(Command::UpdateTimeRestrictions);
+                return;
+            }
+        };
+        // The changes repeat every day
+        let day = Duration::hours(24);
+        let today = Time::START_OF_DAY + day * ((self.time - Time::START_OF_DAY) / day).floor();
+        let next = changes
+            .into_iter()
+            .map(|t| today + (t - Time::START_OF_DAY))
+            .find(|t| *t > self.time)
+            .unwrap_or(today + day + (first - Time::START_OF_DAY));
+        self.scheduler.update(next, Command::UpdateTimeRestrictions);
     }
 
     pub(crate) fn spawn_trips(
@@ -227,12 +361,14 @@ impl Sim {
         input: Vec<(PersonID, TripInfo, TripSpec, Vec<TripLeg>)>,
         map: &Map,
         timer: &mut Timer,
-    ) {
+    ) -> Vec<TripID> {
+        let mut ids = Vec::new();
         timer.start_iter("spawn trips", input.len());
         for (p, info, spec, legs) in input {
             timer.next();
 
             let trip = self.trips.new_trip(p, info.clone(), legs);
+            ids.push(trip);
             if let Some(msg) = info.cancellation_reason {
                 self.trips.cancel_unstarted_trip(trip, msg);
             } else {
@@ -246,6 +382,17 @@ impl Sim {
         }
 
         self.dispatch_events(Vec::new(), map);
+        ids
+    }
+
+    /// The driver's trip will pick up and drop off all of the passengers. All of these trips must
+    /// already be spawned.
+    pub(crate) fn new_carpool(&mut self, driver: TripID, passengers: Vec<TripID>, map: &Map) {
+        self.trips.new_carpool(driver, passengers, map);
+    }
+
+    pub(crate) fn new_delivery_tour(&mut self, trip: TripID, stops: Vec<(BuildingID, Duration)>) {
+        self.trips.new_delivery_tour(trip, stops);
     }
 
     pub fn get_free_onstreet_spots(&self, l: LaneID) -> Vec<ParkingSpot> {
@@ -333,9 +480,17 @@ impl Sim {
         }
     }
 
-    fn start_bus(&mut self, route: &BusRoute, map: &Map) {
+    fn start_bus(&mut self, route: &BusRoute, map: &Map) -> CarID {
         // Spawn one bus for the first leg.
         let (req, path) = self.transit.create_empty_route(route, map);
+        // Schedule adherence compares against these, so only work them out once per route
+        if !self.analytics.transit_stop_offsets.contains_key(&route.id) {
+            if let Some(offsets) = self.transit.scheduled_stop_offsets(route.id, map) {
+                self.analytics
+                    .transit_stop_offsets
+                    .insert(route.id, offsets);
+            }
+        }
 
         // For now, no desire for randomness. Caller can pass in list of specs if that ever
         // changes.
@@ -350,6 +505,7 @@ impl Sim {
             max_speed: None,
         }
         .make(CarID(self.trips.new_car_id(), vehicle_type), None);
+        let id = vehicle.id;
         let start_lane = map.get_l(path.current_step().as_lane());
         let start_dist = if map.get_i(start_lane.src_i).is_incoming_border() {
             SPAWN_DIST
@@ -377,6 +533,7 @@ impl Sim {
                 true,
             ),
         );
+        id
     }
 
     pub fn set_name(&mut self, name: String) {
@@ -459,7 +616,7 @@ impl Sim {
                 for step in create_car.router.get_path().get_steps() {
                     match step.as_traversable() {
                         Traversable::Lane(l) => {
-                            if !constraints.can_use(ctx.map.get_l(l), ctx.map) {
+                            if !constraints.can_ever_use(ctx.map.get_l(l), ctx.map) {
                                 ok = false;
                                 break;
                             }
@@ -524,7 +681,7 @@ impl Sim {
                                 trip,
                                 person,
                                 Some(req),
-                                if id.1 == VehicleType::Car {
+                                if id.1 != VehicleType::Bike {
                                     TripPhaseType::Driving
                                 } else {
                                     TripPhaseType::Biking
@@ -543,6 +700,7 @@ impl Sim {
                         }
                         if let Some(route) = maybe_route {
                             self.transit.bus_created(id, route);
+                            self.intersections.transit_vehicle_created(id, route);
                         }
                         self.analytics
                             .record_demand(self.driving.get_path(id).unwrap(), map);
@@ -594,6 +752,7 @@ impl Sim {
                     &mut ctx,
                     &mut self.trips,
                     &mut self.transit,
+                    &mut self.ride_hail,
                     &mut self.walking,
                 );
             }
@@ -610,8 +769,13 @@ impl Sim {
                 );
             }
             Command::UpdateIntersection(i) => {
-                self.intersections
-                    .update_intersection(self.time, i, map, &mut self.scheduler);
+                self.intersections.update_intersection(
+                    self.time,
+                    i,
+                    map,
+                    &mut self.scheduler,
+                    &self.driving,
+                );
             }
             Command::Callback(frequency) => {
                 self.scheduler
@@ -627,7 +791,20 @@ impl Sim {
                     .handle_cmd(self.time, cmd, &mut self.scheduler);
             }
             Command::StartBus(r, _) => {
-                self.start_bus(map.get_br(r), map);
+                let bus = self.start_bus(map.get_br(r), map);
+                events.push(Event::BusDispatched(bus, r));
+            }
+            Command::RequestRide(req) => {
+                self.ride_hail
+                    .request_ride(self.time, req, &mut self.trips, &mut ctx);
+            }
+            Command::ExpireRideRequest(trip) => {
+                self.ride_hail
+                    .expire_request(self.time, trip, &mut self.trips, &mut ctx);
+            }
+            Command::UpdateTimeRestrictions => {
+                self.parking.update_time_restrictions(self.time, map);
+                self.schedule_time_restrictions(map);
             }
         }
 
@@ -640,6 +817,7 @@ impl Sim {
     fn dispatch_events(&mut self, mut events: Vec<Event>, map: &Map) {
         events.extend(self.trips.collect_events());
         events.extend(self.transit.collect_events());
+        events.extend(self.ride_hail.collect_events());
         events.extend(self.driving.collect_events());
         events.extend(self.walking.collect_events());
         events.extend(self.intersections.collect_events());
@@ -651,6 +829,9 @@ impl Sim {
             if let Some(ref mut r) = self.recorder {
                 r.handle_event(self.time, &ev, map, &self.driving, &self.trips);
             }
+            if let Some(ref mut log) = self.event_log {
+                log.handle_event(self.time, &ev);
+            }
 
             self.analytics.event(ev, self.time, map);
         }
@@ -701,6 +882,9 @@ impl Sim {
                 last_update = Instant::now();
             }
         }
+        if let Some(ref mut log) = self.event_log {
+            log.flush();
+        }
         timer.stop(format!("Advance sim to {}", end_time));
     }
     pub fn tiny_step(&mut self, map: &Map, maybe_cb: &mut Option<Box<dyn SimCallback>>) {
@@ -745,6 +929,9 @@ impl Sim {
                 }
             }
         }
+        if let Some(ref mut log) = self.event_log {
+            log.flush();
+        }
     }
 
     pub fn dump_before_abort(&self) {
@@ -797,6 +984,10 @@ impl Sim {
                 "- transit: {} bytes",
                 prettyprint_usize(serialized_size_bytes(&self.transit))
             );
+            println!(
+                "- ride_hail: {} bytes",
+                prettyprint_usize(serialized_size_bytes(&self.ride_hail))
+            );
             println!(
                 "- cap: {} bytes",
                 prettyprint_usize(serialized_size_bytes(&self.cap))
@@ -837,6 +1028,51 @@ impl Sim {
         Ok(sim)
     }
 
+    /// The latest savestate at or before some time, if there is one.
+    pub fn find_savestate_at_or_before(&self, base_time: Time) -> Option<String> {
+        let path = self.save_path(base_time);
+        if abstutil::file_exists(&path) {
+            Some(path)
+        } else {
+            self.find_previous_savestate(base_time)
+        }
+    }
+
+    /// Savestates don't include analytics, so after loading one, nothing is recorded. Start
+    /// recording from the current time. Anything that happened before isn't available, except
+    /// for the demand from agents already moving.
+    pub fn reset_analytics(&mut self, map: &Map) {
+        self.analytics = Analytics::new(true);
+        for path in self
+            .driving
+            .get_all_driving_paths()
+            .into_iter()
+            .chain(self.walking.get_all_walking_paths())
+        {
+            self.analytics.record_remaining_demand(path, map);
+        }
+    }
+
+    /// Savestates don't include the observed travel times that drivers route with or how to handle
+    /// alerts, since those can only be set from code or the command line. After loading one, take
+    /// them from the options the simulation was started with.
+    pub fn restore_unsaved_options(&mut self, opts: &SimOptions) {
+        self.trips.set_travel_times(opts.travel_times.clone());
+        self.alerts = opts.alerts.clone();
+    }
+
+    /// Savestates and forks shouldn't write to the original simulation's event log. Start a new
+    /// one, or stop logging.
+    pub fn set_event_log(&mut self, opts: Option<EventLogOptions>) {
+        self.event_log = opts.map(EventLog::new);
+    }
+
+    /// Savestates don't include the event log. After restoring one in place of another
+    /// simulation, keep appending to that simulation's log.
+    pub fn take_event_log(&mut self, from: &mut Sim) {
+        self.event_log = from.event_log.take();
+    }
+
     pub fn restore_paths(&mut self, map: &Map, timer: &mut Timer) {
         let paths = timer.parallelize(
             "calculate paths",
@@ -900,12 +1136,14 @@ impl Sim {
                     self.trips
                         .trip_abruptly_cancelled(trip, AgentID::Pedestrian(ped));
                 }
-                AgentID::BusPassenger(_, _) => unreachable!(),
+                AgentID::BusPassenger(_, _) | AgentID::CarPassenger(_, _) => unreachable!(),
             }
         }
 
         self.driving.handle_live_edits(map);
         self.intersections.handle_live_edits(map);
+        self.parking.update_time_restrictions(self.time, map);
+        self.schedule_time_restrictions(map);
 
         (num_trips_cancelled, num_parked_cars)
     }
@@ -1043,3 +1281,26 @@ impl Sim {
         self.recorder.take().unwrap().save(map);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_restore_keeps_travel_times() {
+        let map = Map::blank();
+        let mut opts = SimOptions::new("test");
+        opts.travel_times = Some(Arc::new(TravelTimes::new()));
+        opts.alerts = AlertHandler::Silence;
+        let sim = Sim::new(&map, opts.clone(), &mut Timer::throwaway());
+
+        let mut restored: Sim = abstutil::from_binary(&abstutil::to_binary(&sim)).unwrap();
+        assert!(restored.get_travel_times().is_none());
+        restored.restore_unsaved_options(&opts);
+        assert!(Arc::ptr_eq(
+            restored.get_travel_times().unwrap(),
+            opts.travel_times.as_ref().unwrap()
+        ));
+        assert!(matches!(restored.alerts, AlertHandler::Silence));
+    }
+}
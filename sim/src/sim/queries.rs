@@ -1,12 +1,13 @@
 //! All sorts of read-only queries about a simulation
 
 use std::collections::{BTreeMap, HashSet};
+use std::sync::Arc;
 
 use abstutil::Counter;
 use geom::{Distance, Duration, PolyLine, Pt2D, Time};
 use map_model::{
     BuildingID, BusRouteID, BusStopID, IntersectionID, Lane, LaneID, Map, Path, Position,
-    Traversable, TurnID,
+    TravelTimes, Traversable, TurnID,
 };
 
 use crate::analytics::Window;
@@ -70,7 +71,7 @@ impl Sim {
             AgentID::Pedestrian(id) => self.walking.agent_properties(id, self.time),
             AgentID::Car(id) => self.driving.agent_properties(id, self.time),
             // TODO Harder to measure some of this stuff
-            AgentID::BusPassenger(_, _) => AgentProperties {
+            AgentID::BusPassenger(_, _) | AgentID::CarPassenger(_, _) => AgentProperties {
                 total_time: Duration::ZERO,
                 waiting_here: Duration::ZERO,
                 total_waiting: Duration::ZERO,
@@ -84,6 +85,11 @@ impl Sim {
         self.transit.get_passengers(car).len()
     }
 
+    /// None means unlimited
+    pub fn transit_capacity(&self, car: CarID) -> Option<usize> {
+        self.transit.get_capacity(car)
+    }
+
     pub fn bus_route_id(&self, maybe_bus: CarID) -> Option<BusRouteID> {
         if maybe_bus.1 == VehicleType::Bus || maybe_bus.1 == VehicleType::Train {
             Some(self.transit.bus_route(maybe_bus))
@@ -185,6 +191,7 @@ impl Sim {
             VehicleType::Bike,
             VehicleType::Bus,
             VehicleType::Train,
+            VehicleType::Truck,
         ] {
             let id = CarID(idx, *vt);
             if self.driving.does_car_exist(id) {
@@ -192,10 +199,12 @@ impl Sim {
             }
         }
 
-        let id = CarID(idx, VehicleType::Car);
-        // Only cars can be parked.
-        if self.parking.lookup_parked_car(id).is_some() {
-            return Some(id);
+        // Only cars and trucks can be parked.
+        for vt in &[VehicleType::Car, VehicleType::Truck] {
+            let id = CarID(idx, *vt);
+            if self.parking.lookup_parked_car(id).is_some() {
+                return Some(id);
+            }
         }
 
         None
@@ -205,7 +214,7 @@ impl Sim {
         match id {
             AgentID::Car(car) => self.driving.get_path(car),
             AgentID::Pedestrian(ped) => self.walking.get_path(ped),
-            AgentID::BusPassenger(_, _) => None,
+            AgentID::BusPassenger(_, _) | AgentID::CarPassenger(_, _) => None,
         }
     }
     pub fn get_all_driving_paths(&self) -> Vec<&Path> {
@@ -221,7 +230,7 @@ impl Sim {
         match id {
             AgentID::Car(car) => self.driving.trace_route(self.time, car, map, dist_ahead),
             AgentID::Pedestrian(ped) => self.walking.trace_route(self.time, ped, map, dist_ahead),
-            AgentID::BusPassenger(_, _) => None,
+            AgentID::BusPassenger(_, _) | AgentID::CarPassenger(_, _) => None,
         }
     }
 
@@ -252,7 +261,9 @@ impl Sim {
                 .canonical_pt(id, map)
                 .or_else(|| Some(self.get_draw_car(id, map)?.body.last_pt())),
             AgentID::Pedestrian(id) => Some(self.get_draw_ped(id, map)?.pos),
-            AgentID::BusPassenger(_, bus) => Some(self.get_draw_car(bus, map)?.body.last_pt()),
+            AgentID::BusPassenger(_, car) | AgentID::CarPassenger(_, car) => {
+                Some(self.get_draw_car(car, map)?.body.last_pt())
+            }
         }
     }
 
@@ -296,6 +307,11 @@ impl Sim {
         &self.analytics
     }
 
+    /// The observed travel times that drivers pick routes with, if any
+    pub fn get_travel_times(&self) -> Option<&Arc<TravelTimes>> {
+        self.trips.get_travel_times().as_ref()
+    }
+
     pub fn find_blockage_front(&self, car: CarID, map: &Map) -> String {
         self.driving
             .find_blockage_front(car, map, &self.intersections)
@@ -3,7 +3,7 @@ use std::collections::{BTreeMap, BTreeSet};
 use serde::{Deserialize, Serialize};
 
 use abstutil::{deserialize_btreemap, serialize_btreemap};
-use geom::Time;
+use geom::{Duration, Time};
 use map_model::{BusRoute, BusRouteID, BusStopID, Map, Path, PathRequest, Position};
 
 use crate::sim::Ctx;
@@ -15,6 +15,59 @@ use crate::{
 // These index stops along a route, not stops along a single sidewalk.
 type StopIdx = usize;
 
+/// Without crowding, vehicles always wait this long at each stop.
+const TIME_TO_WAIT_AT_BUS_STOP: Duration = Duration::const_seconds(10.0);
+
+/// Limits how many passengers fit in transit vehicles, and makes vehicles wait at stops
+/// depending on how many people get on and off.
+#[derive(Clone, Debug, Serialize, Deserialize)]
+pub struct TransitCrowding {
+    /// How many passengers fit in a bus, unless the route overrides it
+    pub bus_capacity: usize,
+    /// How many passengers fit in a train, unless the route overrides it
+    pub train_capacity: usize,
+    #[serde(
+        serialize_with = "serialize_btreemap",
+        deserialize_with = "deserialize_btreemap"
+    )]
+    pub route_capacity: BTreeMap<BusRouteID, usize>,
+    /// Vehicles stay at every stop at least this long, even if nobody gets on or off.
+    pub min_dwell: Duration,
+    pub dwell_per_boarding: Duration,
+    pub dwell_per_alighting: Duration,
+}
+
+impl TransitCrowding {
+    /// Roughly a standard 40-foot bus and a two-car light rail train, with all doors in use.
+    pub fn defaults() -> TransitCrowding {
+        TransitCrowding {
+            bus_capacity: 70,
+            train_capacity: 400,
+            route_capacity: BTreeMap::new(),
+            min_dwell: Duration::seconds(5.0),
+            dwell_per_boarding: Duration::seconds(3.0),
+            dwell_per_alighting: Duration::seconds(2.0),
+        }
+    }
+
+    fn capacity(&self, route: BusRouteID, vehicle_type: VehicleType) -> usize {
+        if let Some(n) = self.route_capacity.get(&route) {
+            return *n;
+        }
+        if vehicle_type == VehicleType::Train {
+            self.train_capacity
+        } else {
+            self.bus_capacity
+        }
+    }
+
+    fn dwell_time(&self, boarded: usize, alighted: usize) -> Duration {
+        self.min_dwell
+            + (boarded as f64) * self.dwell_per_boarding
+            + (alighted as f64) * self.dwell_per_alighting
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 struct Stop {
     id: BusStopID,
@@ -36,9 +89,19 @@ struct Bus {
     route: BusRouteID,
     /// Where does each passenger want to deboard?
     passengers: Vec<(PersonID, Option<BusStopID>)>,
+    /// None means unlimited
+    capacity: Option<usize>,
     state: BusState,
 }
 
+impl Bus {
+    fn is_full(&self) -> bool {
+        self.capacity
+            .map(|cap| self.passengers.len() >= cap)
+            .unwrap_or(false)
+    }
+}
+
 #[derive(Serialize, Deserialize, Clone)]
 enum BusState {
     DrivingToStop(StopIdx),
@@ -49,6 +112,9 @@ enum BusState {
 
 /// Manages public transit vehicles (buses and trains) that follow a route. The transit model is
 /// currently kind of broken, so not describing the state machine yet.
+///
+/// With `TransitCrowding`, vehicles fill up. People who can't fit keep waiting for the next
+/// vehicle, and vehicles stay at stops longer when more people get on and off.
 #[derive(Serialize, Deserialize, Clone)]
 pub(crate) struct TransitSimState {
     #[serde(
@@ -67,12 +133,13 @@ pub(crate) struct TransitSimState {
         deserialize_with = "deserialize_btreemap"
     )]
     peds_waiting: BTreeMap<BusStopID, Vec<(PedestrianID, BusRouteID, Option<BusStopID>, Time)>>,
+    crowding: Option<TransitCrowding>,
 
     events: Vec<Event>,
 }
 
 impl TransitSimState {
-    pub fn new(map: &Map) -> TransitSimState {
+    pub fn new(map: &Map, crowding: Option<TransitCrowding>) -> TransitSimState {
         // Keep this filled out always so get_passengers can return &Vec without a hassle
         let mut peds_waiting = BTreeMap::new();
         for bs in map.all_bus_stops().keys() {
@@ -83,6 +150,7 @@ impl TransitSimState {
             buses: BTreeMap::new(),
             routes: BTreeMap::new(),
             peds_waiting,
+            crowding,
             events: Vec::new(),
         }
     }
@@ -156,6 +224,36 @@ impl TransitSimState {
         self.routes[&bus_route.id].start.clone()
     }
 
+    /// How long after being dispatched a vehicle on this route should reach each stop, driving
+    /// the route's paths at the speed limit and waiting the minimum time at each stop. None if no
+    /// vehicle has started the route yet.
+    pub fn scheduled_stop_offsets(&self, route: BusRouteID, map: &Map) -> Option<Vec<Duration>> {
+        let r = self.routes.get(&route)?;
+        let dwell = match self.crowding {
+            Some(ref crowding) => crowding.min_dwell,
+            None => TIME_TO_WAIT_AT_BUS_STOP,
+        };
+        let free_flow = |path: &Path| -> Duration {
+            path.get_steps()
+                .iter()
+                .map(|step| {
+                    let t = step.as_traversable();
+                    t.length(map) / t.speed_limit(map)
+                })
+                .sum()
+        };
+
+        let mut offsets = Vec::new();
+        let mut total = free_flow(&r.start.1);
+        for stop in &r.stops {
+            offsets.push(total);
+            if let Some((_, ref path)) = stop.next_stop {
+                total += dwell + free_flow(path);
+            }
+        }
+        Some(offsets)
+    }
+
     pub fn bus_created(&mut self, bus: CarID, r: BusRouteID) {
         let route = self.routes.get_mut(&r).unwrap();
         route.active_vehicles.insert(bus);
@@ -165,13 +263,14 @@ impl TransitSimState {
                 car: bus,
                 route: r,
                 passengers: Vec::new(),
+                capacity: self.crowding.as_ref().map(|c| c.capacity(r, bus.1)),
                 state: BusState::DrivingToStop(0),
             },
         );
     }
 
-    /// If true, the bus is idling. If false, the bus actually arrived at a border and should now
-    /// vanish.
+    /// If Some, the bus idles at the stop for that long. If None, the bus actually arrived at a
+    /// border and should now vanish.
     pub fn bus_arrived_at_stop(
         &mut self,
         now: Time,
@@ -179,7 +278,7 @@ impl TransitSimState {
         trips: &mut TripManager,
         walking: &mut WalkingSimState,
         ctx: &mut Ctx,
-    ) -> bool {
+    ) -> Option<Duration> {
         let mut bus = self.buses.get_mut(&id).unwrap();
         match bus.state {
             BusState::DrivingToStop(stop_idx) => {
@@ -190,8 +289,10 @@ impl TransitSimState {
 
                 // Deboard existing passengers.
                 let mut still_riding = Vec::new();
+                let mut alighted = 0;
                 for (person, maybe_stop2) in bus.passengers.drain(..) {
                     if Some(stop1) == maybe_stop2 {
+                        alighted += 1;
                         trips.person_left_bus(now, person, bus.car, ctx);
                         self.events.push(Event::PassengerAlightsTransit(
                             person, bus.car, bus.route, stop1,
@@ -204,10 +305,17 @@ impl TransitSimState {
 
                 // Board new passengers.
                 let mut still_waiting = Vec::new();
+                let mut boarded = 0;
                 for (ped, route, maybe_stop2, started_waiting) in
                     self.peds_waiting.remove(&stop1).unwrap()
                 {
-                    if bus.route == route {
+                    if bus.route == route && bus.is_full() {
+                        // Wait for the next vehicle
+                        self.events
+                            .push(Event::PassengerDeniedBoarding(ped, bus.car, route, stop1));
+                        still_waiting.push((ped, route, maybe_stop2, started_waiting));
+                    } else if bus.route == route {
+                        boarded += 1;
                         let (trip, person) = trips.ped_boarded_bus(
                             now,
                             ped,
@@ -242,7 +350,10 @@ impl TransitSimState {
                     }
                 }
                 self.peds_waiting.insert(stop1, still_waiting);
-                true
+                Some(match self.crowding {
+                    Some(ref crowding) => crowding.dwell_time(boarded, alighted),
+                    None => TIME_TO_WAIT_AT_BUS_STOP,
+                })
             }
             BusState::DrivingOffMap => {
                 self.routes
@@ -261,7 +372,7 @@ impl TransitSimState {
                     }
                     trips.transit_rider_reached_border(now, person, id, ctx);
                 }
-                false
+                None
             }
             BusState::AtStop(_) | BusState::Done => unreachable!(),
         }
@@ -274,8 +385,12 @@ impl TransitSimState {
             BusState::DrivingToStop(_) | BusState::DrivingOffMap | BusState::Done => unreachable!(),
             BusState::AtStop(stop_idx) => {
                 let stop = &route.stops[stop_idx];
-                self.events
-                    .push(Event::BusDepartedFromStop(id, bus.route, stop.id));
+                self.events.push(Event::BusDepartedFromStop(
+                    id,
+                    bus.route,
+                    stop.id,
+                    bus.passengers.len(),
+                ));
                 if let Some((req, path)) = stop.next_stop.clone() {
                     bus.state = BusState::DrivingToStop(stop_idx + 1);
                     Router::follow_bus_route(id, path, req.end.dist_along())
@@ -317,7 +432,10 @@ impl TransitSimState {
         if let Some(route) = self.routes.get(&route_id) {
             for bus in &route.active_vehicles {
                 if let BusState::AtStop(idx) = self.buses[bus].state {
-                    if route.stops[idx].id == stop1 {
+                    if route.stops[idx].id == stop1 && self.buses[bus].is_full() {
+                        self.events
+                            .push(Event::PassengerDeniedBoarding(ped, *bus, route_id, stop1));
+                    } else if route.stops[idx].id == stop1 {
                         self.buses
                             .get_mut(bus)
                             .unwrap()
@@ -363,6 +481,11 @@ impl TransitSimState {
         &self.buses[&bus].passengers
     }
 
+    /// None means unlimited
+    pub fn get_capacity(&self, bus: CarID) -> Option<usize> {
+        self.buses[&bus].capacity
+    }
+
     pub fn bus_route(&self, bus: CarID) -> BusRouteID {
         self.buses[&bus].route
     }
@@ -1,25 +1,40 @@
-use std::collections::{BTreeMap, VecDeque};
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::sync::Arc;
 
 use serde::{Deserialize, Serialize};
 
 use abstutil::{deserialize_btreemap, serialize_btreemap, Counter};
 use geom::{Distance, Duration, Speed, Time};
 use map_model::{
-    BuildingID, BusRouteID, BusStopID, IntersectionID, Map, PathConstraints, PathRequest, Position,
+    BikeShareStationID, BuildingID, BusRouteID, BusStopID, IntersectionID, LaneID, Map, Path,
+    PathConstraints, PathRequest, Position, TravelTimes,
 };
 
 use crate::sim::Ctx;
 use crate::{
     AgentID, AgentType, AlertLocation, CarID, Command, CreateCar, CreatePedestrian, DrivingGoal,
     Event, IndividTrip, OrigPersonID, ParkedCar, ParkingSim, ParkingSpot, PedestrianID, PersonID,
-    PersonSpec, Scenario, Scheduler, SidewalkPOI, SidewalkSpot, TransitSimState, TripID,
-    TripPhaseType, TripPurpose, TripSpec, Vehicle, VehicleSpec, VehicleType, WalkingSimState,
+    PersonSpec, RideRequest, Router, Scenario, Scheduler, SidewalkPOI, SidewalkSpot,
+    TransitSimState, TripID, TripPhaseType, TripPurpose, TripSpec, Vehicle, VehicleSpec,
+    VehicleType, WalkingSimState, BIKE_LENGTH,
 };
 
+/// How long a carpool waits at a stop for passengers who haven't started their trip yet
+const MAX_CARPOOL_WAIT: Duration = Duration::const_seconds(5.0 * 60.0);
+/// Delivery trucks will use a free parking spot at most this far from the building they're
+/// visiting. Otherwise they double-park.
+const MAX_LOADING_DIST: Distance = Distance::const_meters(30.0);
+/// How far somebody will walk to or from a bike share station
+pub(crate) const MAX_BIKE_SHARE_WALK: Distance = Distance::const_meters(800.0);
+
 /// Manages people, each of which executes some trips through the day. Each trip is further broken
 /// down into legs -- for example, a driving trip might start with somebody walking to their car,
 /// driving somewhere, parking, and then walking to their final destination.
 /// https://dabreegster.github.io/abstreet/trafficsim/trips.html describes some of the variations.
+///
+/// Carpools are also managed here. The driver's trip stops at buildings along the way to pick up
+/// and drop off passengers, whose trips just consist of waiting and riding. Delivery trucks
+/// similarly stop at buildings along the way to load and unload.
 //
 // Here be dragons, keep hands and feet inside the ride at all times...
 #[derive(Serialize, Deserialize, Debug, Clone)]
@@ -37,11 +52,24 @@ pub(crate) struct TripManager {
     car_id_counter: usize,
 
     events: Vec<Event>,
+
+    /// Keyed by the driver's trip
+    carpools: BTreeMap<TripID, Carpool>,
+    /// For every carpool passenger's trip, the driver's trip
+    carpool_drivers: BTreeMap<TripID, TripID>,
+    delivery_tours: BTreeMap<TripID, DeliveryTour>,
+    /// How many bikes are docked at each bike share station. Stations nobody has used yet still
+    /// have their initial_bikes.
+    bike_share_bikes: BTreeMap<BikeShareStationID, usize>,
+
+    /// If present, drivers choose routes using these observed travel times.
+    #[serde(skip_serializing, skip_deserializing)]
+    travel_times: Option<Arc<TravelTimes>>,
 }
 
 // Initialization
 impl TripManager {
-    pub fn new() -> TripManager {
+    pub fn new(travel_times: Option<Arc<TravelTimes>>) -> TripManager {
         TripManager {
             trips: Vec::new(),
             people: Vec::new(),
@@ -49,9 +77,24 @@ impl TripManager {
             unfinished_trips: 0,
             car_id_counter: 0,
             events: Vec::new(),
+            carpools: BTreeMap::new(),
+            carpool_drivers: BTreeMap::new(),
+            delivery_tours: BTreeMap::new(),
+            bike_share_bikes: BTreeMap::new(),
+            travel_times,
         }
     }
 
+    /// Savestates don't include observed travel times, so they have to be attached again after
+    /// loading one.
+    pub fn set_travel_times(&mut self, travel_times: Option<Arc<TravelTimes>>) {
+        self.travel_times = travel_times;
+    }
+
+    pub fn get_travel_times(&self) -> &Option<Arc<TravelTimes>> {
+        &self.travel_times
+    }
+
     // TODO assert the specs are correct yo
     pub fn new_person(
         &mut self,
@@ -78,6 +121,8 @@ impl TripManager {
             vehicles,
             delayed_trips: Vec::new(),
             on_bus: None,
+            in_car: None,
+            on_bike_share: None,
         });
         self.get_person(id).unwrap()
     }
@@ -129,6 +174,95 @@ impl TripManager {
         id
     }
 
+    /// The driver will pick up each passenger at the start of their trip, then drop them off at
+    /// the end, before continuing to their own destination. Pickups happen first, each time going
+    /// to the closest remaining building, then dropoffs in the same way. Passengers that can't be
+    /// picked up will have their trip cancelled when it starts.
+    pub fn new_carpool(&mut self, driver: TripID, passengers: Vec<TripID>, map: &Map) {
+        for p in &passengers {
+            self.carpool_drivers.insert(*p, driver);
+        }
+
+        let trip = &self.trips[driver.0];
+        if trip.info.cancellation_reason.is_some() {
+            return;
+        }
+        let car = match trip.legs.iter().find_map(|leg| match leg {
+            TripLeg::Drive(c, _) => Some(*c),
+            _ => None,
+        }) {
+            Some(c) => c,
+            None => {
+                return;
+            }
+        };
+        let start = match trip.info.start {
+            TripEndpoint::Bldg(b) => b,
+            _ => {
+                return;
+            }
+        };
+
+        let mut pick_ups = Vec::new();
+        let mut drop_offs = Vec::new();
+        for p in passengers {
+            let info = &self.trips[p.0].info;
+            if info.cancellation_reason.is_some() {
+                continue;
+            }
+            if let (TripEndpoint::Bldg(b1), TripEndpoint::Bldg(b2)) = (&info.start, &info.end) {
+                if map.get_b(*b1).driving_connection(map).is_some()
+                    && map.get_b(*b2).driving_connection(map).is_some()
+                {
+                    pick_ups.push((p, *b1));
+                    drop_offs.push((p, *b2));
+                }
+            }
+        }
+
+        let mut stops: Vec<CarpoolStop> = Vec::new();
+        let mut at = map.get_b(start).polygon.center();
+        for (mut remaining, pick_up) in vec![(pick_ups, true), (drop_offs, false)] {
+            while !remaining.is_empty() {
+                let idx = (0..remaining.len())
+                    .min_by_key(|idx| at.dist_to(map.get_b(remaining[*idx].1).polygon.center()))
+                    .unwrap();
+                let (p, b) = remaining.remove(idx);
+                at = map.get_b(b).polygon.center();
+                if stops.last().map(|stop| stop.bldg) != Some(b) {
+                    stops.push(CarpoolStop {
+                        bldg: b,
+                        pick_up: Vec::new(),
+                        drop_off: Vec::new(),
+                    });
+                }
+                let stop = stops.last_mut().unwrap();
+                if pick_up {
+                    stop.pick_up.push(p);
+                } else {
+                    stop.drop_off.push(p);
+                }
+            }
+        }
+
+        self.carpools.insert(
+            driver,
+            Carpool {
+                car,
+                stops: stops.into_iter().collect(),
+                waiting: BTreeMap::new(),
+                riding: BTreeSet::new(),
+                arrived_at_stop: None,
+            },
+        );
+    }
+
+    /// The truck will stop at each building in order, then continue to the trip's destination.
+    pub fn new_delivery_tour(&mut self, trip: TripID, stops: Vec<(BuildingID, Duration)>) {
+        self.delivery_tours
+            .insert(trip, DeliveryTour { stops, next: 0 });
+    }
+
     pub fn start_trip(&mut self, now: Time, trip: TripID, spec: TripSpec, ctx: &mut Ctx) {
         assert!(self.trips[trip.0].info.cancellation_reason.is_none());
 
@@ -157,7 +291,10 @@ impl TripManager {
 
         // Defer calculating the path until now, to handle live map edits.
         let maybe_req = spec.get_pathfinding_request(ctx.map);
-        let maybe_path = maybe_req.clone().and_then(|req| ctx.map.pathfind(req));
+        let travel_times = &self.travel_times;
+        let maybe_path = maybe_req
+            .clone()
+            .and_then(|req| pathfind(travel_times, req, now, ctx.map));
 
         match spec {
             TripSpec::VehicleAppearing {
@@ -178,6 +315,20 @@ impl TripManager {
                 assert!(ctx.parking.lookup_parked_car(vehicle.id).is_none());
                 let req = maybe_req.unwrap();
                 let person = person.id;
+
+                // Delivery trucks first head to their first stop.
+                if let Some((req, router)) =
+                    self.next_delivery_leg(now, trip, &vehicle, start_pos, ctx)
+                {
+                    ctx.scheduler.push(
+                        now,
+                        Command::SpawnCar(
+                            CreateCar::for_appearing(vehicle, start_pos, router, req, trip, person),
+                            retry_if_no_room,
+                        ),
+                    );
+                    return;
+                }
                 match maybe_path
                     .ok_or_else(|| {
                         format!(
@@ -377,6 +528,63 @@ impl TripManager {
                     );
                 }
             }
+            TripSpec::RidingCarpool { start, .. } => {
+                assert_eq!(person.state, PersonState::Inside(start));
+                let person = person.id;
+
+                let carpools = &mut self.carpools;
+                let carpool = self
+                    .carpool_drivers
+                    .get(&trip)
+                    .and_then(|driver| carpools.get_mut(driver))
+                    .filter(|carpool| {
+                        carpool
+                            .stops
+                            .iter()
+                            .any(|stop| stop.pick_up.contains(&trip))
+                    });
+                if let Some(carpool) = carpool {
+                    // Wait inside until the car arrives
+                    carpool.waiting.insert(trip, now);
+                    self.people[person.0].state = PersonState::Trip(trip);
+                    self.events.push(Event::TripPhaseStarting(
+                        trip,
+                        person,
+                        None,
+                        TripPhaseType::WaitingForRide(start),
+                    ));
+                } else {
+                    self.cancel_trip(
+                        now,
+                        trip,
+                        "their carpool isn't coming to pick them up".to_string(),
+                        None,
+                        ctx,
+                    );
+                }
+            }
+            TripSpec::UsingRideHail { start, goal } => {
+                assert_eq!(person.state, PersonState::Inside(start));
+                let person = person.id;
+                // Wait inside until a vehicle arrives
+                self.people[person.0].state = PersonState::Trip(trip);
+                self.events.push(Event::TripPhaseStarting(
+                    trip,
+                    person,
+                    None,
+                    TripPhaseType::WaitingForRide(start),
+                ));
+                ctx.scheduler.push(
+                    now,
+                    Command::RequestRide(RideRequest {
+                        trip,
+                        person,
+                        pickup: start,
+                        dropoff: goal,
+                        requested: now,
+                    }),
+                );
+            }
             TripSpec::UsingTransit { start, stop1, .. } => {
                 assert_eq!(
                     person.state,
@@ -431,6 +639,148 @@ impl TripManager {
                     );
                 }
             }
+            TripSpec::UsingBikeShare {
+                start, station1, ..
+            } => {
+                assert_eq!(
+                    person.state,
+                    match start.connection {
+                        SidewalkPOI::Building(b) => PersonState::Inside(b),
+                        SidewalkPOI::Border(i) => {
+                            self.events.push(Event::PersonEntersMap(
+                                person.id,
+                                AgentID::Pedestrian(person.ped),
+                                i,
+                            ));
+                            PersonState::OffMap
+                        }
+                        SidewalkPOI::SuddenlyAppear => {
+                            self.events.push(Event::PersonEntersMap(
+                                person.id,
+                                AgentID::Pedestrian(person.ped),
+                                ctx.map.get_l(start.sidewalk_pos.lane()).src_i,
+                            ));
+                            PersonState::OffMap
+                        }
+                        _ => unreachable!(),
+                    }
+                );
+                person.state = PersonState::Trip(trip);
+
+                let walk_to = SidewalkSpot::bike_share_station(station1, ctx.map);
+                let req = maybe_req.unwrap();
+                if let Some(path) = maybe_path {
+                    ctx.scheduler.push(
+                        now,
+                        Command::SpawnPed(CreatePedestrian {
+                            id: person.ped,
+                            speed: person.ped_speed,
+                            start,
+                            goal: walk_to,
+                            path,
+                            req,
+                            trip,
+                            person: person.id,
+                        }),
+                    );
+                } else {
+                    self.cancel_trip(
+                        now,
+                        trip,
+                        format!("UsingBikeShare trip couldn't find the first path {}", req),
+                        None,
+                        ctx,
+                    );
+                }
+            }
+            TripSpec::UsingParkAndRide {
+                car,
+                start_bldg,
+                goal,
+                park_and_ride,
+            } => {
+                assert_eq!(person.state, PersonState::Inside(start_bldg));
+                person.state = PersonState::Trip(trip);
+                let (ped, speed, person) = (person.ped, person.ped_speed, person.id);
+
+                let parked_car = if let Some(p) = ctx.parking.lookup_parked_car(car) {
+                    p.clone()
+                } else {
+                    self.cancel_trip(
+                        now,
+                        trip,
+                        format!("should have {} parked somewhere, but it's unavailable", car),
+                        None,
+                        ctx,
+                    );
+                    return;
+                };
+                let start = SidewalkSpot::building(start_bldg, ctx.map);
+                let planned_lot = park_and_ride.map(|(lot, _, _, _)| lot);
+                let legs = &mut self.trips[trip.0].legs;
+                let walk_to = match parked_car.spot {
+                    // The car is already in the lot, so skip straight to transit
+                    ParkingSpot::Lot(pl, _) if Some(pl) == planned_lot => {
+                        legs.pop_front();
+                        legs.pop_front();
+                        match legs[0] {
+                            TripLeg::Walk(ref spot) => spot.clone(),
+                            _ => unreachable!(),
+                        }
+                    }
+                    // The car was left at some other lot earlier. Ride transit back to it (unless
+                    // walking is better), then drive to the goal.
+                    ParkingSpot::Lot(pl, _) => {
+                        legs.clear();
+                        let walk_to = match ctx
+                            .map
+                            .should_use_transit(start.sidewalk_pos, ctx.map.get_pl(pl).sidewalk_pos)
+                        {
+                            Some((stop1, Some(stop2), route)) => {
+                                let stop = SidewalkSpot::bus_stop(stop1, ctx.map);
+                                legs.push_back(TripLeg::Walk(stop.clone()));
+                                legs.push_back(TripLeg::RideBus(route, Some(stop2)));
+                                stop
+                            }
+                            _ => SidewalkSpot::parking_spot(parked_car.spot, ctx.map, ctx.parking),
+                        };
+                        legs.push_back(TripLeg::Walk(SidewalkSpot::deferred_parking_spot()));
+                        legs.push_back(TripLeg::Drive(car, DrivingGoal::ParkNear(goal)));
+                        legs.push_back(TripLeg::Walk(SidewalkSpot::building(goal, ctx.map)));
+                        walk_to
+                    }
+                    _ => SidewalkSpot::parking_spot(parked_car.spot, ctx.map, ctx.parking),
+                };
+
+                let req = PathRequest {
+                    start: start.sidewalk_pos,
+                    end: walk_to.sidewalk_pos,
+                    constraints: PathConstraints::Pedestrian,
+                };
+                if let Some(path) = ctx.map.pathfind(req.clone()) {
+                    ctx.scheduler.push(
+                        now,
+                        Command::SpawnPed(CreatePedestrian {
+                            id: ped,
+                            speed,
+                            start,
+                            goal: walk_to,
+                            path,
+                            req,
+                            trip,
+                            person,
+                        }),
+                    );
+                } else {
+                    self.cancel_trip(
+                        now,
+                        trip,
+                        format!("UsingParkAndRide trip couldn't find the first path {}", req),
+                        None,
+                        ctx,
+                    );
+                }
+            }
         }
     }
 
@@ -461,10 +811,32 @@ impl TripManager {
         trip.total_blocked_time += blocked_time;
         trip.total_distance += distance_crossed;
 
+        // A delivery truck parked to load, not at the end of its trip
+        let trip_id = trip.id;
+        if self
+            .delivery_tours
+            .get(&trip_id)
+            .map(|tour| tour.next < tour.stops.len())
+            .unwrap_or(false)
+        {
+            self.truck_parked_to_load(now, trip_id, spot, ctx);
+            return;
+        }
+        let trip = &mut self.trips[trip_id.0];
+
         match trip.legs.pop_front() {
             Some(TripLeg::Drive(c, DrivingGoal::ParkNear(_))) => {
                 assert_eq!(car, c);
             }
+            Some(TripLeg::Drive(c, DrivingGoal::ParkInLot(lot, b))) => {
+                assert_eq!(car, c);
+                // The lot was full, so the car parked near the goal instead. Skip transit.
+                if !matches!(spot, ParkingSpot::Lot(pl, _) if pl == lot) {
+                    trip.legs.clear();
+                    trip.legs
+                        .push_back(TripLeg::Walk(SidewalkSpot::building(b, ctx.map)));
+                }
+            }
             _ => unreachable!(),
         };
 
@@ -541,6 +913,53 @@ impl TripManager {
                 start = Position::new(start.lane(), start.dist_along() + parked_car.vehicle.length);
             }
         }
+
+        // Carpools first head to wherever they're picking somebody up.
+        let trip_id = trip.id;
+        let person = trip.person;
+        if self.carpools.contains_key(&trip_id) {
+            if let Some((req, router)) =
+                self.next_carpool_leg(now, trip_id, parked_car.vehicle.id, start, ctx)
+            {
+                ctx.scheduler.push(
+                    now,
+                    Command::SpawnCar(
+                        CreateCar::for_parked_car(
+                            parked_car,
+                            router,
+                            req,
+                            start.dist_along(),
+                            trip_id,
+                            person,
+                        ),
+                        true,
+                    ),
+                );
+                return;
+            }
+        }
+        // So do delivery trucks.
+        if let Some((req, router)) =
+            self.next_delivery_leg(now, trip_id, &parked_car.vehicle, start, ctx)
+        {
+            ctx.scheduler.push(
+                now,
+                Command::SpawnCar(
+                    CreateCar::for_parked_car(
+                        parked_car,
+                        router,
+                        req,
+                        start.dist_along(),
+                        trip_id,
+                        person,
+                    ),
+                    true,
+                ),
+            );
+            return;
+        }
+        let trip = &mut self.trips[trip_id.0];
+
         let end = drive_to.goal_pos(PathConstraints::Car, ctx.map).unwrap();
         let req = PathRequest {
             start,
@@ -548,9 +967,7 @@ impl TripManager {
             constraints: PathConstraints::Car,
         };
 
-        match ctx
-            .map
-            .pathfind(req.clone())
+        match pathfind(&self.travel_times, req.clone(), now, ctx.map)
             .ok_or_else(|| format!("no path to drive from {} to {}", start, end))
             .and_then(|path| {
                 ctx.cap.validate_path(
@@ -607,6 +1024,11 @@ impl TripManager {
         trip.total_distance += distance_crossed;
 
         trip.assert_walking_leg(spot.clone());
+        if let SidewalkPOI::BikeShareStation(station) = spot.connection {
+            let id = trip.id;
+            self.undock_bike_share(now, id, station, ctx);
+            return;
+        }
         let (bike, drive_to) = match trip.legs[0] {
             TripLeg::Drive(bike, ref to) => (bike, to.clone()),
             _ => unreachable!(),
@@ -691,9 +1113,15 @@ impl TripManager {
         trip.total_blocked_time += blocked_time;
         trip.total_distance += distance_crossed;
 
-        match trip.legs.pop_front() {
-            Some(TripLeg::Drive(c, DrivingGoal::ParkNear(_))) => {
+        match trip.legs[0] {
+            TripLeg::Drive(c, DrivingGoal::ParkNear(_)) => {
                 assert_eq!(c, bike);
+                trip.legs.pop_front();
+            }
+            TripLeg::RideBikeShare(_, station, _) => {
+                let id = trip.id;
+                self.dock_bike_share(now, id, bike, station, map, scheduler);
+                return;
             }
             _ => unreachable!(),
         };
@@ -710,6 +1138,220 @@ impl TripManager {
         }
     }
 
+    fn bikes_at_station(&self, station: BikeShareStationID, map: &Map) -> usize {
+        self.bike_share_bikes
+            .get(&station)
+            .cloned()
+            .unwrap_or_else(|| map.get_bss(station).initial_bikes)
+    }
+
+    /// Somebody reached a bike share station and wants to take a bike. If there aren't any, they
+    /// walk to the closest other station that has one, or give up and walk to their destination.
+    fn undock_bike_share(
+        &mut self,
+        now: Time,
+        id: TripID,
+        station: BikeShareStationID,
+        ctx: &mut Ctx,
+    ) {
+        let dock_at = match self.trips[id.0].legs[0] {
+            TripLeg::RideBikeShare(from, to, _) => {
+                assert_eq!(from, station);
+                to
+            }
+            _ => unreachable!(),
+        };
+        let person = self.trips[id.0].person;
+        let here = SidewalkSpot::bike_share_station(station, ctx.map);
+
+        let bikes = self.bikes_at_station(station, ctx.map);
+        if bikes == 0 {
+            self.events
+                .push(Event::BikeShareUnavailable(id, station, true));
+            let maybe_next = self.closest_bike_share_station(station, dock_at, ctx.map, |s| {
+                self.bikes_at_station(s, ctx.map) > 0
+            });
+            let trip = &mut self.trips[id.0];
+            trip.legs.pop_front();
+            if let Some(next) = maybe_next {
+                trip.legs
+                    .push_front(TripLeg::RideBikeShare(next, dock_at, BTreeSet::new()));
+                trip.legs
+                    .push_front(TripLeg::Walk(SidewalkSpot::bike_share_station(
+                        next, ctx.map,
+                    )));
+            } else {
+                // Just walk the rest of the way
+                self.events.push(Event::Alert(
+                    AlertLocation::Person(person),
+                    format!(
+                        "{} couldn't find a bike share station with bikes near {}, so they're \
+                         walking instead",
+                        person, station
+                    ),
+                ));
+            }
+            if !trip.spawn_ped(
+                now,
+                here,
+                &self.people[person.0],
+                ctx.map,
+                ctx.scheduler,
+                &mut self.events,
+            ) {
+                self.unfinished_trips -= 1;
+            }
+            return;
+        }
+
+        let start = ctx.map.get_bss(station).biking_pos;
+        let req = PathRequest {
+            start,
+            end: ctx.map.get_bss(dock_at).biking_pos,
+            constraints: PathConstraints::Bike,
+        };
+        let path = match ctx.map.pathfind(req.clone()) {
+            Some(path) => path,
+            None => {
+                self.cancel_trip(
+                    now,
+                    id,
+                    format!("no path to bike between bike share stations: {}", req),
+                    None,
+                    ctx,
+                );
+                return;
+            }
+        };
+
+        self.bike_share_bikes.insert(station, bikes - 1);
+        self.events
+            .push(Event::BikeShareUndocked(id, station, bikes - 1));
+        let bike = bike_share_bike(CarID(self.new_car_id(), VehicleType::Bike), person);
+        self.people[person.0].on_bike_share = Some(bike.id);
+        let router = Router::bike_then_stop(
+            bike.id,
+            path,
+            SidewalkSpot::bike_share_station(dock_at, ctx.map),
+        );
+        ctx.scheduler.push(
+            now,
+            Command::SpawnCar(
+                CreateCar::for_appearing(bike, start, router, req, id, person),
+                true,
+            ),
+        );
+    }
+
+    /// Somebody biked to a bike share station. If there's a free dock, they leave the bike and
+    /// walk to their destination. Otherwise they bike to the closest station with room that they
+    /// haven't already found full.
+    fn dock_bike_share(
+        &mut self,
+        now: Time,
+        id: TripID,
+        bike: CarID,
+        station: BikeShareStationID,
+        map: &Map,
+        scheduler: &mut Scheduler,
+    ) {
+        let person = self.trips[id.0].person;
+        let bikes = self.bikes_at_station(station, map);
+        if bikes >= map.get_bss(station).capacity {
+            self.events
+                .push(Event::BikeShareUnavailable(id, station, false));
+            if let TripLeg::RideBikeShare(_, _, ref mut tried) = self.trips[id.0].legs[0] {
+                tried.insert(station);
+            }
+            let tried = match self.trips[id.0].legs[0] {
+                TripLeg::RideBikeShare(_, _, ref tried) => tried,
+                _ => unreachable!(),
+            };
+            let maybe_next = self.closest_bike_share_station(station, station, map, |s| {
+                !tried.contains(&s) && self.bikes_at_station(s, map) < map.get_bss(s).capacity
+            });
+            // If every station nearby is full, just leave the bike here anyway.
+            if let Some(next) = maybe_next {
+                let start = map.get_bss(station).biking_pos;
+                let req = PathRequest {
+                    start,
+                    end: map.get_bss(next).biking_pos,
+                    constraints: PathConstraints::Bike,
+                };
+                if let Some(path) = map.pathfind(req.clone()) {
+                    let trip = &mut self.trips[id.0];
+                    match trip.legs[0] {
+                        TripLeg::RideBikeShare(_, ref mut to, _) => {
+                            *to = next;
+                        }
+                        _ => unreachable!(),
+                    }
+                    let router = Router::bike_then_stop(
+                        bike,
+                        path,
+                        SidewalkSpot::bike_share_station(next, map),
+                    );
+                    scheduler.push(
+                        now,
+                        Command::SpawnCar(
+                            CreateCar::for_appearing(
+                                bike_share_bike(bike, person),
+                                start,
+                                router,
+                                req,
+                                id,
+                                person,
+                            ),
+                            true,
+                        ),
+                    );
+                    return;
+                }
+            }
+        }
+
+        self.bike_share_bikes.insert(station, bikes + 1);
+        self.events
+            .push(Event::BikeShareDocked(id, station, bikes + 1));
+        self.people[person.0].on_bike_share = None;
+        let trip = &mut self.trips[id.0];
+        trip.legs.pop_front();
+        if !trip.spawn_ped(
+            now,
+            SidewalkSpot::bike_share_station(station, map),
+            &self.people[person.0],
+            map,
+            scheduler,
+            &mut self.events,
+        ) {
+            self.unfinished_trips -= 1;
+        }
+    }
+
+    /// Find the closest bike share station to `from` within walking distance, besides `from` and
+    /// `except`, matching some condition.
+    fn closest_bike_share_station<F: Fn(BikeShareStationID) -> bool>(
+        &self,
+        from: BikeShareStationID,
+        except: BikeShareStationID,
+        map: &Map,
+        ok: F,
+    ) -> Option<BikeShareStationID> {
+        let here = map.get_bss(from).point;
+        let avoid_lane = map.get_bss(except).biking_pos.lane();
+        map.all_bike_share_stations()
+            .iter()
+            .filter(|s| {
+                s.id != from
+                    && s.id != except
+                    && s.biking_pos.lane() != avoid_lane
+                    && s.point.dist_to(here) <= MAX_BIKE_SHARE_WALK
+                    && ok(s.id)
+            })
+            .min_by_key(|s| s.point.dist_to(here))
+            .map(|s| s.id)
+    }
+
     pub fn ped_reached_building(
         &mut self,
         now: Time,
@@ -832,9 +1474,29 @@ impl TripManager {
         };
         self.people[person.0].on_bus.take().unwrap();
 
-        if !trip.spawn_ped(
+        // Heading back to a car left at a park-and-ride lot
+        let walk_to = match (&trip.legs[0], trip.legs.get(1)) {
+            (TripLeg::Walk(spot), Some(TripLeg::Drive(car, _)))
+                if spot.connection == SidewalkPOI::DeferredParkingSpot =>
+            {
+                match ctx.parking.lookup_parked_car(*car) {
+                    Some(p) => SidewalkSpot::parking_spot(p.spot, ctx.map, ctx.parking),
+                    None => {
+                        let msg =
+                            format!("should have {} parked somewhere, but it's unavailable", car);
+                        let id = trip.id;
+                        self.cancel_trip(now, id, msg, None, ctx);
+                        return;
+                    }
+                }
+            }
+            (TripLeg::Walk(spot), _) => spot.clone(),
+            _ => unreachable!(),
+        };
+        if !trip.spawn_ped_to(
             now,
             start,
+            walk_to,
             &self.people[trip.person.0],
             ctx.map,
             ctx.scheduler,
@@ -844,6 +1506,498 @@ impl TripManager {
         }
     }
 
+    /// A carpool pulled over next to a building. Drop off anybody getting out here.
+    pub fn car_reached_carpool_stop(
+        &mut self,
+        now: Time,
+        car: CarID,
+        distance_crossed: Distance,
+        ctx: &mut Ctx,
+    ) {
+        let driver = self.active_trip_mode[&AgentID::Car(car)];
+        self.trips[driver.0].total_distance += distance_crossed;
+
+        let carpool = self.carpools.get_mut(&driver).unwrap();
+        carpool.arrived_at_stop = Some(now);
+        for passenger in &carpool.riding {
+            self.trips[passenger.0].total_distance += distance_crossed;
+        }
+        let stop = carpool.stops.front_mut().unwrap();
+        let mut finished = Vec::new();
+        for passenger in stop.drop_off.drain(..) {
+            // They might have missed their pickup
+            if carpool.riding.remove(&passenger) {
+                finished.push((passenger, carpool.riding.len()));
+            }
+        }
+
+        for (id, still_riding) in finished {
+            self.passenger_gets_out_of_car(now, id, car, still_riding, ctx);
+        }
+    }
+
+    /// A carpool is ready to leave a stop. Anybody waiting here gets in, then the car heads to the
+    /// next stop or its final destination. Returns None if the car should keep waiting for
+    /// somebody to show up.
+    pub fn car_leaving_carpool_stop(
+        &mut self,
+        now: Time,
+        car: CarID,
+        pos: Position,
+        ctx: &mut Ctx,
+    ) -> Option<Router> {
+        let driver = self.active_trip_mode[&AgentID::Car(car)];
+        let carpool = self.carpools.get_mut(&driver).unwrap();
+        let trips = &self.trips;
+        let still_coming = carpool.stops[0].pick_up.iter().any(|t| {
+            !carpool.waiting.contains_key(t)
+                && trips[t.0].info.cancellation_reason.is_none()
+                && trips[t.0].finished_at.is_none()
+        });
+        if still_coming && now - carpool.arrived_at_stop.unwrap() < MAX_CARPOOL_WAIT {
+            return None;
+        }
+
+        let stop = carpool.stops.pop_front().unwrap();
+        let mut boarding = Vec::new();
+        for id in stop.pick_up {
+            if let Some(waiting_since) = carpool.waiting.remove(&id) {
+                carpool.riding.insert(id);
+                boarding.push((id, now - waiting_since, carpool.riding.len()));
+            } else {
+                // Too late; don't bother stopping to drop them off.
+                for later in &mut carpool.stops {
+                    later.drop_off.retain(|t| *t != id);
+                }
+            }
+        }
+        for (id, waited, riding) in boarding {
+            self.passenger_gets_in_car(id, car, stop.bldg, waited, riding, None);
+        }
+
+        let person = self.trips[driver.0].person;
+        if let Some((req, router)) = self.next_carpool_leg(now, driver, car, pos, ctx) {
+            self.events.push(Event::TripPhaseStarting(
+                driver,
+                person,
+                Some(req),
+                TripPhaseType::Driving,
+            ));
+            return Some(router);
+        }
+
+        // Everybody's been dropped off, so finish the trip normally.
+        let (req, router) = self.drive_to_final_goal(now, driver, car, pos, ctx);
+        self.events.push(Event::TripPhaseStarting(
+            driver,
+            person,
+            Some(req),
+            TripPhaseType::Driving,
+        ));
+        Some(router)
+    }
+
+    /// After making every stop along the way, a carpool or delivery truck heads to the end of its
+    /// trip.
+    fn drive_to_final_goal(
+        &self,
+        now: Time,
+        trip: TripID,
+        car: CarID,
+        pos: Position,
+        ctx: &mut Ctx,
+    ) -> (PathRequest, Router) {
+        let goal = match self.trips[trip.0].legs[0] {
+            TripLeg::Drive(_, ref goal) => goal.clone(),
+            _ => unreachable!(),
+        };
+        let req = PathRequest {
+            start: pos,
+            end: goal.goal_pos(PathConstraints::Car, ctx.map).unwrap(),
+            constraints: PathConstraints::Car,
+        };
+        let router = match pathfind(&self.travel_times, req.clone(), now, ctx.map) {
+            Some(path) => goal.make_router(car, path, ctx.map),
+            // Just look for parking starting from here
+            None => match goal {
+                DrivingGoal::ParkNear(b) => {
+                    Router::park_near(car, Path::one_step(pos.lane(), ctx.map), b)
+                }
+                DrivingGoal::Border(_, _) | DrivingGoal::ParkInLot(_, _) => panic!(
+                    "{} finished all of its stops, but can't reach {:?} from {}",
+                    car, goal, pos
+                ),
+            },
+        };
+        (req, router)
+    }
+
+    /// A delivery truck is stopping in the lane to load or unload, blocking traffic. Returns how
+    /// long it'll stay.
+    pub fn truck_double_parked(
+        &mut self,
+        car: CarID,
+        lane: LaneID,
+        distance_crossed: Distance,
+    ) -> Duration {
+        let trip = self.active_trip_mode[&AgentID::Car(car)];
+        self.trips[trip.0].total_distance += distance_crossed;
+        self.start_loading(trip, car, lane, true)
+    }
+
+    /// A double-parked delivery truck is done loading and heads to the next stop or its final
+    /// destination.
+    pub fn truck_done_loading(
+        &mut self,
+        now: Time,
+        vehicle: &Vehicle,
+        pos: Position,
+        ctx: &mut Ctx,
+    ) -> Router {
+        let trip = self.active_trip_mode[&AgentID::Car(vehicle.id)];
+        self.delivery_tours.get_mut(&trip).unwrap().next += 1;
+        let (req, router) = match self.next_delivery_leg(now, trip, vehicle, pos, ctx) {
+            Some(pair) => pair,
+            None => self.drive_to_final_goal(now, trip, vehicle.id, pos, ctx),
+        };
+        self.events.push(Event::TripPhaseStarting(
+            trip,
+            self.trips[trip.0].person,
+            Some(req),
+            TripPhaseType::Driving,
+        ));
+        router
+    }
+
+    /// A delivery truck parked next to a building. Once it's done loading, it'll unpark and head
+    /// to the next stop or its final destination.
+    fn truck_parked_to_load(&mut self, now: Time, trip: TripID, spot: ParkingSpot, ctx: &mut Ctx) {
+        let parked_car = ctx.parking.get_car_at_spot(spot).unwrap().clone();
+        let lane = match spot {
+            ParkingSpot::Onstreet(l, _) => l,
+            // Trucks only load at onstreet spots
+            ParkingSpot::Offstreet(_, _) | ParkingSpot::Lot(_, _) => unreachable!(),
+        };
+        let dwell = self.start_loading(trip, parked_car.vehicle.id, lane, false);
+
+        let depart = now + dwell;
+        let start = ctx
+            .parking
+            .spot_to_driving_pos(spot, &parked_car.vehicle, ctx.map);
+        self.delivery_tours.get_mut(&trip).unwrap().next += 1;
+        let (req, router) =
+            match self.next_delivery_leg(depart, trip, &parked_car.vehicle, start, ctx) {
+                Some(pair) => pair,
+                None => self.drive_to_final_goal(depart, trip, parked_car.vehicle.id, start, ctx),
+            };
+        let person = self.trips[trip.0].person;
+        ctx.scheduler.push(
+            depart,
+            Command::SpawnCar(
+                CreateCar::for_parked_car(
+                    parked_car,
+                    router,
+                    req,
+                    start.dist_along(),
+                    trip,
+                    person,
+                ),
+                true,
+            ),
+        );
+    }
+
+    /// Returns how long the truck will spend at the current stop.
+    fn start_loading(
+        &mut self,
+        trip: TripID,
+        car: CarID,
+        lane: LaneID,
+        double_parked: bool,
+    ) -> Duration {
+        let tour = &self.delivery_tours[&trip];
+        let (bldg, dwell) = tour.stops[tour.next];
+        self.events
+            .push(Event::TruckLoading(car, bldg, lane, double_parked, dwell));
+        self.events.push(Event::TripPhaseStarting(
+            trip,
+            self.trips[trip.0].person,
+            None,
+            TripPhaseType::Delivering(bldg),
+        ));
+        dwell
+    }
+
+    /// Find the path from `start` to the next delivery stop. Prefer a free parking spot close to
+    /// the building, but otherwise plan to double-park. Stops that can't be reached are skipped.
+    /// Returns None for trips without deliveries, or when there are no stops left.
+    fn next_delivery_leg(
+        &mut self,
+        now: Time,
+        trip: TripID,
+        vehicle: &Vehicle,
+        start: Position,
+        ctx: &mut Ctx,
+    ) -> Option<(PathRequest, Router)> {
+        loop {
+            let tour = self.delivery_tours.get(&trip)?;
+            let bldg = tour.stops.get(tour.next)?.0;
+            if let Some((curb, _)) = ctx.map.get_b(bldg).driving_connection(ctx.map) {
+                let (end, spot) = match ctx
+                    .parking
+                    .get_all_free_spots(Position::start(curb.lane()), vehicle, bldg, ctx.map)
+                    .into_iter()
+                    .filter(|(spot, pos)| {
+                        matches!(spot, ParkingSpot::Onstreet(_, _))
+                            && (pos.dist_along() - curb.dist_along()).abs() <= MAX_LOADING_DIST
+                    })
+                    .min_by_key(|(_, pos)| (pos.dist_along() - curb.dist_along()).abs())
+                {
+                    Some((spot, pos)) => (pos, Some(spot)),
+                    None => (curb, None),
+                };
+                let req = PathRequest {
+                    start,
+                    end,
+                    constraints: PathConstraints::Car,
+                };
+                // A path can't loop back to an earlier spot on the same lane
+                if start.lane() != end.lane() || start.dist_along() <= end.dist_along() {
+                    let info = &mut self.trips[trip.0].info;
+                    if let Some(Ok(path)) = pathfind(&self.travel_times, req.clone(), now, ctx.map)
+                        .map(|path| {
+                            ctx.cap.validate_path(
+                                &req,
+                                path,
+                                now,
+                                vehicle.id,
+                                &mut info.capped,
+                                ctx.intersections,
+                                ctx.map,
+                            )
+                        })
+                    {
+                        let router = Router::deliver(vehicle.id, path, end.dist_along(), spot);
+                        return Some((req, router));
+                    }
+                }
+            }
+
+            self.events.push(Event::Alert(
+                AlertLocation::Person(self.trips[trip.0].person),
+                format!("{} can't reach its delivery stop at {}", vehicle.id, bldg),
+            ));
+            self.delivery_tours.get_mut(&trip).unwrap().next += 1;
+        }
+    }
+
+    /// Ride-hailing vehicles pick their route like any other driver. `trip` is the person being
+    /// picked up or driven, if any; driving empty to a staging spot doesn't belong to any trip.
+    pub fn ride_hail_path(
+        &mut self,
+        now: Time,
+        car: CarID,
+        req: &PathRequest,
+        trip: Option<TripID>,
+        ctx: &mut Ctx,
+    ) -> Option<Path> {
+        let path = pathfind(&self.travel_times, req.clone(), now, ctx.map)?;
+        let mut deadheading = false;
+        let capped = match trip {
+            Some(id) => &mut self.trips[id.0].info.capped,
+            None => &mut deadheading,
+        };
+        ctx.cap
+            .validate_path(req, path, now, car, capped, ctx.intersections, ctx.map)
+            .ok()
+    }
+
+    /// A ride-hailing vehicle picked up somebody waiting inside a building.
+    pub fn ride_hail_pickup(
+        &mut self,
+        id: TripID,
+        car: CarID,
+        bldg: BuildingID,
+        waited: Duration,
+        req: PathRequest,
+    ) {
+        self.passenger_gets_in_car(id, car, bldg, waited, 1, Some(req));
+    }
+
+    /// A ride-hailing vehicle reached somebody's destination.
+    pub fn ride_hail_dropoff(
+        &mut self,
+        now: Time,
+        id: TripID,
+        car: CarID,
+        distance_crossed: Distance,
+        ctx: &mut Ctx,
+    ) {
+        self.trips[id.0].total_distance += distance_crossed;
+        self.passenger_gets_out_of_car(now, id, car, 0, ctx);
+    }
+
+    /// `riding` counts passengers in the car after this person gets in.
+    fn passenger_gets_in_car(
+        &mut self,
+        id: TripID,
+        car: CarID,
+        bldg: BuildingID,
+        waited: Duration,
+        riding: usize,
+        req: Option<PathRequest>,
+    ) {
+        let trip = &mut self.trips[id.0];
+        // Waiting to be picked up counts as being delayed
+        trip.total_blocked_time += waited;
+        self.active_trip_mode
+            .insert(AgentID::CarPassenger(trip.person, car), id);
+        self.people[trip.person.0].in_car = Some(car);
+        self.events
+            .push(Event::PersonLeavesBuilding(trip.person, bldg));
+        self.events.push(Event::PassengerBoardsCar(
+            trip.person,
+            car,
+            bldg,
+            waited,
+            riding,
+        ));
+        self.events.push(Event::TripPhaseStarting(
+            id,
+            trip.person,
+            req,
+            TripPhaseType::RidingInCar(car),
+        ));
+    }
+
+    /// `still_riding` counts passengers left in the car.
+    fn passenger_gets_out_of_car(
+        &mut self,
+        now: Time,
+        id: TripID,
+        car: CarID,
+        still_riding: usize,
+        ctx: &mut Ctx,
+    ) {
+        let trip = &mut self.trips[id.0];
+        self.active_trip_mode
+            .remove(&AgentID::CarPassenger(trip.person, car))
+            .unwrap();
+        self.people[trip.person.0].in_car = None;
+        let bldg = match trip.legs.pop_front() {
+            Some(TripLeg::RideInCar(b)) => b,
+            _ => unreachable!(),
+        };
+        self.events.push(Event::PassengerAlightsCar(
+            trip.person,
+            car,
+            bldg,
+            still_riding,
+        ));
+        self.people[trip.person.0].state = PersonState::Inside(bldg);
+        self.events
+            .push(Event::PersonEntersBuilding(trip.person, bldg));
+        self.trip_finished(now, id, ctx);
+    }
+
+    /// Find the path from `start` to the next carpool stop. If a stop can't be reached, skip it
+    /// and cancel the trips of anybody getting in or out there. Returns None when there are no
+    /// stops left.
+    fn next_carpool_leg(
+        &mut self,
+        now: Time,
+        driver: TripID,
+        car: CarID,
+        start: Position,
+        ctx: &mut Ctx,
+    ) -> Option<(PathRequest, Router)> {
+        loop {
+            let bldg = self.carpools.get(&driver)?.stops.front()?.bldg;
+            let maybe_req = ctx
+                .map
+                .get_b(bldg)
+                .driving_connection(ctx.map)
+                .map(|(end, _)| PathRequest {
+                    start,
+                    end,
+                    constraints: PathConstraints::Car,
+                })
+                // A path can't loop back to an earlier spot on the same lane
+                .filter(|req| {
+                    req.start.lane() != req.end.lane()
+                        || req.start.dist_along() <= req.end.dist_along()
+                });
+            if let Some(req) = maybe_req {
+                let trip = &mut self.trips[driver.0];
+                if let Some(Ok(path)) =
+                    pathfind(&self.travel_times, req.clone(), now, ctx.map).map(|path| {
+                        ctx.cap.validate_path(
+                            &req,
+                            path,
+                            now,
+                            car,
+                            &mut trip.info.capped,
+                            ctx.intersections,
+                            ctx.map,
+                        )
+                    })
+                {
+                    let router = Router::stop_for_passengers(car, path, req.end.dist_along());
+                    return Some((req, router));
+                }
+            }
+
+            let stop = self
+                .carpools
+                .get_mut(&driver)
+                .unwrap()
+                .stops
+                .pop_front()
+                .unwrap();
+            for passenger in stop.pick_up.into_iter().chain(stop.drop_off) {
+                self.cancel_carpool_passenger(
+                    now,
+                    driver,
+                    passenger,
+                    format!("their carpool couldn't reach {}", bldg),
+                    ctx,
+                );
+            }
+        }
+    }
+
+    /// Passengers who haven't started their trip yet will notice the ride isn't coming when they
+    /// do.
+    fn cancel_carpool_passenger(
+        &mut self,
+        now: Time,
+        driver: TripID,
+        id: TripID,
+        reason: String,
+        ctx: &mut Ctx,
+    ) {
+        let carpool = self.carpools.get_mut(&driver).unwrap();
+        for stop in &mut carpool.stops {
+            stop.pick_up.retain(|t| *t != id);
+            stop.drop_off.retain(|t| *t != id);
+        }
+        let trip = &self.trips[id.0];
+        if carpool.riding.remove(&id) {
+            self.active_trip_mode
+                .remove(&AgentID::CarPassenger(trip.person, carpool.car))
+                .unwrap();
+            self.people[trip.person.0].in_car = None;
+        } else if carpool.waiting.remove(&id).is_some() {
+            // They never left the building
+            if let TripEndpoint::Bldg(b) = trip.info.start {
+                self.people[trip.person.0].state = PersonState::Inside(b);
+            }
+        } else {
+            return;
+        }
+        self.cancel_trip(now, id, reason, None, ctx);
+    }
+
     pub fn ped_reached_border(
         &mut self,
         now: Time,
@@ -1027,7 +2181,9 @@ impl TripManager {
 
         // Don't forget the car!
         if let Some(vehicle) = abandoned_vehicle {
-            if vehicle.vehicle_type == VehicleType::Car {
+            if vehicle.vehicle_type == VehicleType::Car
+                || vehicle.vehicle_type == VehicleType::Truck
+            {
                 if let TripEndpoint::Bldg(b) = trip.info.end {
                     let driving_lane = ctx.map.find_driving_lane_near_building(b);
                     if let Some(spot) = ctx
@@ -1076,6 +2232,28 @@ impl TripManager {
                 }
             }
         }
+        // A bike share bike just vanishes.
+        self.people[person.0].on_bike_share = None;
+
+        // Anybody waiting for or riding in this person's carpool is out of luck too.
+        if let Some(carpool) = self.carpools.get(&id) {
+            let passengers: Vec<TripID> = carpool
+                .waiting
+                .keys()
+                .chain(carpool.riding.iter())
+                .cloned()
+                .collect();
+            for passenger in passengers {
+                self.cancel_carpool_passenger(
+                    now,
+                    id,
+                    passenger,
+                    "their carpool was cancelled".to_string(),
+                    ctx,
+                );
+            }
+            self.carpools.remove(&id);
+        }
 
         self.start_delayed_trip(now, person, ctx);
     }
@@ -1118,6 +2296,20 @@ impl TripManager {
             TripLeg::Walk(_) => AgentID::Pedestrian(person.ped),
             TripLeg::Drive(c, _) => AgentID::Car(*c),
             TripLeg::RideBus(_, _) => AgentID::BusPassenger(person.id, person.on_bus.unwrap()),
+            TripLeg::RideInCar(_) => match person.in_car {
+                Some(car) => AgentID::CarPassenger(person.id, car),
+                // Still waiting to be picked up
+                None => {
+                    return TripResult::ModeChange;
+                }
+            },
+            TripLeg::RideBikeShare(_, _, _) => match person.on_bike_share {
+                Some(bike) => AgentID::Car(bike),
+                // The bike hasn't appeared yet
+                None => {
+                    return TripResult::ModeChange;
+                }
+            },
         };
         if self.active_trip_mode.get(&a) == Some(&id) {
             TripResult::Ok(a)
@@ -1172,6 +2364,7 @@ impl TripManager {
             cyclists: 0,
 
             sov_drivers: 0,
+            car_passengers: 0,
 
             buses,
             trains,
@@ -1182,7 +2375,7 @@ impl TripManager {
         for a in self.active_trip_mode.keys() {
             match a {
                 AgentID::Car(c) => match c.1 {
-                    VehicleType::Car => {
+                    VehicleType::Car | VehicleType::Truck => {
                         cnt.sov_drivers += 1;
                     }
                     VehicleType::Bike => {
@@ -1197,8 +2390,11 @@ impl TripManager {
                     VehicleType::Train => {
                         cnt.train_riders += 1;
                     }
-                    VehicleType::Car | VehicleType::Bike => unreachable!(),
+                    VehicleType::Car | VehicleType::Bike | VehicleType::Truck => unreachable!(),
                 },
+                AgentID::CarPassenger(_, _) => {
+                    cnt.car_passengers += 1;
+                }
                 // These're counted separately
                 AgentID::Pedestrian(_) => {}
             }
@@ -1279,10 +2475,14 @@ impl TripManager {
                     let agent_type = match t.info.mode {
                         TripMode::Walk => AgentType::Pedestrian,
                         TripMode::Bike => AgentType::Bike,
-                        TripMode::Drive => AgentType::Car,
+                        TripMode::Drive
+                        | TripMode::Carpool
+                        | TripMode::RideHail
+                        | TripMode::Delivery
+                        | TripMode::ParkAndRide => AgentType::Car,
                         // TODO Not true for long. People will be able to spawn at borders already
                         // on a bus.
-                        TripMode::Transit => AgentType::Pedestrian,
+                        TripMode::Transit | TripMode::BikeShare => AgentType::Pedestrian,
                     };
                     times.push((t.info.departure, agent_type));
                 }
@@ -1305,12 +2505,25 @@ impl TripManager {
                     .iter()
                     .map(|t| {
                         let trip = &self.trips[t.0];
-                        IndividTrip::new(
+                        let mut individ = IndividTrip::new(
                             trip.info.departure,
                             trip.info.purpose,
                             trip.info.end.clone(),
                             trip.info.mode,
-                        )
+                        );
+                        // People are numbered the same way in the scenario
+                        individ.carpool_driver = self.carpool_drivers.get(t).map(|driver| {
+                            let driver = &self.trips[driver.0];
+                            let person = &self.people[driver.person.0];
+                            (
+                                person.id.0,
+                                person.trips.iter().position(|x| *x == driver.id).unwrap(),
+                            )
+                        });
+                        if let Some(tour) = self.delivery_tours.get(t) {
+                            individ.delivery_stops = tour.stops.clone();
+                        }
+                        individ
                     })
                     .collect(),
             });
@@ -1361,7 +2574,20 @@ impl Trip {
             TripLeg::Walk(ref to) => to.clone(),
             _ => unreachable!(),
         };
+        self.spawn_ped_to(now, start, walk_to, person, map, scheduler, events)
+    }
 
+    /// Like spawn_ped, but the walking leg's goal isn't known yet.
+    fn spawn_ped_to(
+        &self,
+        now: Time,
+        start: SidewalkSpot,
+        walk_to: SidewalkSpot,
+        person: &Person,
+        map: &Map,
+        scheduler: &mut Scheduler,
+        events: &mut Vec<Event>,
+    ) -> bool {
         let req = PathRequest {
             start: start.sidewalk_pos,
             end: walk_to.sidewalk_pos,
@@ -1415,6 +2641,15 @@ pub(crate) enum TripLeg {
     Drive(CarID, DrivingGoal),
     /// Maybe get off at a stop, maybe ride off-map
     RideBus(BusRouteID, Option<BusStopID>),
+    /// Get picked up at the start of the trip, dropped off at this building
+    RideInCar(BuildingID),
+    /// Take a bike from the first bike share station, then return it at the second. Stations
+    /// already found full when trying to dock are remembered, so riders don't bounce between them.
+    RideBikeShare(
+        BikeShareStationID,
+        BikeShareStationID,
+        BTreeSet<BikeShareStationID>,
+    ),
 }
 
 #[derive(Serialize, Deserialize, PartialEq, Eq, Debug, Clone, Copy, PartialOrd, Ord)]
@@ -1423,6 +2658,18 @@ pub enum TripMode {
     Bike,
     Transit,
     Drive,
+    /// Either driving other people or riding along with somebody else
+    Carpool,
+    /// Riding in a ride-hailing vehicle that nobody in the simulation owns
+    RideHail,
+    /// Driving a truck to make deliveries at several buildings
+    Delivery,
+    /// Walking to a bike share station, biking to another station, then walking to the
+    /// destination
+    BikeShare,
+    /// Driving to a parking lot, then riding transit and walking to the destination. Coming back,
+    /// the same in reverse.
+    ParkAndRide,
 }
 
 impl TripMode {
@@ -1432,6 +2679,11 @@ impl TripMode {
             TripMode::Bike,
             TripMode::Transit,
             TripMode::Drive,
+            TripMode::Carpool,
+            TripMode::RideHail,
+            TripMode::Delivery,
+            TripMode::BikeShare,
+            TripMode::ParkAndRide,
         ]
     }
 
@@ -1441,6 +2693,11 @@ impl TripMode {
             TripMode::Bike => "bike",
             TripMode::Transit => "use transit",
             TripMode::Drive => "drive",
+            TripMode::Carpool => "carpool",
+            TripMode::RideHail => "take a ride-hail",
+            TripMode::Delivery => "deliver",
+            TripMode::BikeShare => "use bike share",
+            TripMode::ParkAndRide => "park and ride",
         }
     }
 
@@ -1451,6 +2708,11 @@ impl TripMode {
             TripMode::Bike => "biking",
             TripMode::Transit => "using transit",
             TripMode::Drive => "driving",
+            TripMode::Carpool => "carpooling",
+            TripMode::RideHail => "ride-hailing",
+            TripMode::Delivery => "delivering",
+            TripMode::BikeShare => "using bike share",
+            TripMode::ParkAndRide => "parking and riding",
         }
     }
 
@@ -1460,16 +2722,25 @@ impl TripMode {
             TripMode::Bike => "Bike",
             TripMode::Transit => "Bus",
             TripMode::Drive => "Car",
+            TripMode::Carpool => "Carpool",
+            TripMode::RideHail => "Ride-hail",
+            TripMode::Delivery => "Delivery truck",
+            TripMode::BikeShare => "Bike share",
+            TripMode::ParkAndRide => "Park and ride",
         }
     }
 
     pub fn to_constraints(self) -> PathConstraints {
         match self {
             TripMode::Walk => PathConstraints::Pedestrian,
-            TripMode::Bike => PathConstraints::Bike,
+            TripMode::Bike | TripMode::BikeShare => PathConstraints::Bike,
             // TODO WRONG
             TripMode::Transit => PathConstraints::Bus,
-            TripMode::Drive => PathConstraints::Car,
+            TripMode::Drive
+            | TripMode::Carpool
+            | TripMode::RideHail
+            | TripMode::Delivery
+            | TripMode::ParkAndRide => PathConstraints::Car,
         }
     }
 
@@ -1504,8 +2775,15 @@ impl TripEndpoint {
             start: pos(from, mode, true, map)?,
             end: pos(to, mode, false, map)?,
             constraints: match mode {
-                TripMode::Walk | TripMode::Transit => PathConstraints::Pedestrian,
-                TripMode::Drive => PathConstraints::Car,
+                // Bike share trips start and end walking
+                TripMode::Walk | TripMode::Transit | TripMode::BikeShare => {
+                    PathConstraints::Pedestrian
+                }
+                TripMode::Drive
+                | TripMode::Carpool
+                | TripMode::RideHail
+                | TripMode::Delivery
+                | TripMode::ParkAndRide => PathConstraints::Car,
                 TripMode::Bike => PathConstraints::Bike,
             },
         })
@@ -1515,22 +2793,33 @@ impl TripEndpoint {
 fn pos(endpt: TripEndpoint, mode: TripMode, from: bool, map: &Map) -> Option<Position> {
     match endpt {
         TripEndpoint::Bldg(b) => match mode {
-            TripMode::Walk | TripMode::Transit => Some(map.get_b(b).sidewalk_pos),
+            TripMode::Walk | TripMode::Transit | TripMode::BikeShare => {
+                Some(map.get_b(b).sidewalk_pos)
+            }
             TripMode::Bike => Some(DrivingGoal::ParkNear(b).goal_pos(PathConstraints::Bike, map)?),
-            TripMode::Drive => Some(
+            TripMode::Drive
+            | TripMode::Carpool
+            | TripMode::RideHail
+            | TripMode::Delivery
+            | TripMode::ParkAndRide => Some(
                 DrivingGoal::ParkNear(b)
                     .goal_pos(PathConstraints::Car, map)
                     .unwrap(),
             ),
         },
         TripEndpoint::Border(i) => match mode {
-            TripMode::Walk | TripMode::Transit => if from {
+            TripMode::Walk | TripMode::Transit | TripMode::BikeShare => if from {
                 SidewalkSpot::start_at_border(i, map)
             } else {
                 SidewalkSpot::end_at_border(i, map)
             }
             .map(|spot| spot.sidewalk_pos),
-            TripMode::Bike | TripMode::Drive => (if from {
+            TripMode::Bike
+            | TripMode::Drive
+            | TripMode::Carpool
+            | TripMode::RideHail
+            | TripMode::Delivery
+            | TripMode::ParkAndRide => (if from {
                 map.get_i(i).some_outgoing_road(map)
             } else {
                 map.get_i(i).some_incoming_road(map)
@@ -1596,6 +2885,10 @@ pub struct Person {
 
     delayed_trips: Vec<(TripID, TripSpec)>,
     on_bus: Option<CarID>,
+    /// A carpool or ride-hailing vehicle
+    in_car: Option<CarID>,
+    /// A bike from a bike share station
+    on_bike_share: Option<CarID>,
 }
 
 impl Person {
@@ -1604,6 +2897,17 @@ impl Person {
     }
 }
 
+/// Bike share bikes aren't owned by anybody, but whoever is riding one counts as the owner.
+fn bike_share_bike(id: CarID, person: PersonID) -> Vehicle {
+    VehicleSpec {
+        vehicle_type: VehicleType::Bike,
+        length: BIKE_LENGTH,
+        // Shared bikes are heavier than most people's own
+        max_speed: Some(Speed::miles_per_hour(8.0)),
+    }
+    .make(id, Some(person))
+}
+
 #[derive(Serialize, Deserialize, PartialEq, Debug, Clone)]
 pub enum PersonState {
     Trip(TripID),
@@ -1611,6 +2915,35 @@ pub enum PersonState {
     OffMap,
 }
 
+/// A driver picking up and dropping off other people along the way.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct Carpool {
+    car: CarID,
+    /// In the order the driver visits them
+    stops: VecDeque<CarpoolStop>,
+    /// Passengers who've started their trip and are waiting inside, since some time
+    waiting: BTreeMap<TripID, Time>,
+    riding: BTreeSet<TripID>,
+    /// When the driver pulled up to the current stop
+    arrived_at_stop: Option<Time>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct CarpoolStop {
+    bldg: BuildingID,
+    pick_up: Vec<TripID>,
+    drop_off: Vec<TripID>,
+}
+
+/// A delivery truck's stops before it heads to its destination.
+#[derive(Serialize, Deserialize, Debug, Clone)]
+struct DeliveryTour {
+    /// Each building and how long to spend loading or unloading there
+    stops: Vec<(BuildingID, Duration)>,
+    /// The stop the truck is heading to or loading at
+    next: usize,
+}
+
 /// The number of active vehicles and commuters, broken into different categories.
 pub struct CommutersVehiclesCounts {
     pub walking_commuters: usize,
@@ -1620,10 +2953,26 @@ pub struct CommutersVehiclesCounts {
 
     pub cyclists: usize,
 
+    /// This counts all drivers, even those with carpool passengers and delivery trucks
     pub sov_drivers: usize,
+    pub car_passengers: usize,
 
     pub buses: usize,
     pub trains: usize,
     pub bus_riders: usize,
     pub train_riders: usize,
 }
+
+/// Cars use observed travel times to pick their route, if they're available. Either way, lanes and
+/// roads closed at this time of day are avoided.
+fn pathfind(
+    travel_times: &Option<Arc<TravelTimes>>,
+    req: PathRequest,
+    now: Time,
+    map: &Map,
+) -> Option<Path> {
+    match travel_times {
+        Some(ref times) if req.constraints == PathConstraints::Car => times.pathfind(req, now, map),
+        _ => map.pathfind_at(req, now),
+    }
+}
@@ -1,5 +1,6 @@
 //! Integration tests
 
+use std::collections::{BTreeMap, BTreeSet};
 use std::fs::File;
 use std::io::Write;
 
@@ -7,13 +8,21 @@ use rand::seq::SliceRandom;
 
 use abstutil::{MapName, Timer};
 use geom::{Duration, Time};
-use map_model::{IntersectionID, Map};
+use map_model::{
+    BuildingID, BusStopID, IntersectionCluster, IntersectionID, Map, PathConstraints, RoadID,
+};
 use sim::{IndividTrip, PersonSpec, Scenario, TripEndpoint, TripMode, TripPurpose};
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     test_lane_changing(&import_map(abstutil::path(
         "../tests/input/lane_selection.osm",
     )))?;
+    let small_grid = import_map(abstutil::path("../tests/input/small_grid.osm"));
+    test_ride_hailing(&small_grid)?;
+    test_delivery_trucks(&small_grid)?;
+    test_bike_share(&small_grid)?;
+    test_park_and_ride(&small_grid)?;
+    test_transit_crowding(&small_grid)?;
     test_map_importer()?;
     check_proposals()?;
     smoke_test()?;
@@ -35,11 +44,26 @@ fn test_map_importer() -> Result<(), std::io::Error> {
         println!("Producing goldenfiles for {}", map.get_name().describe());
         dump_turn_goldenfile(&map)?;
     }
+    for name in &[
+        "turn_restriction_except",
+        "turn_restriction_conditional",
+        "turn_restriction_via_ways",
+        "turn_restriction_only_via_way",
+    ] {
+        let map = import_map(abstutil::path(format!("../tests/input/{}.osm", name)));
+        println!("Producing goldenfiles for {}", map.get_name().describe());
+        dump_turn_restrictions_goldenfile(&map)?;
+    }
     Ok(())
 }
 
 /// Run the contents of a .osm through the full map importer with default options.
 fn import_map(path: String) -> Map {
+    import_map_with(path, true)
+}
+
+/// Like `import_map`, but without contraction hierarchies, pathfinding uses Dijkstra's algorithm.
+fn import_map_with(path: String, build_ch: bool) -> Map {
     let mut timer = abstutil::Timer::new("convert synthetic map");
     let raw = convert_osm::convert(
         convert_osm::Options {
@@ -56,10 +80,11 @@ fn import_map(path: String) -> Map {
             private_offstreet_parking: convert_osm::PrivateOffstreetParking::FixedPerBldg(0),
             elevation: None,
             include_railroads: true,
+            bike_share_gbfs: None,
         },
         &mut timer,
     );
-    let map = Map::create_from_raw(raw, true, true, &mut timer);
+    let map = Map::create_from_raw(raw, build_ch, true, &mut timer);
     map
 }
 
@@ -73,6 +98,110 @@ fn dump_turn_goldenfile(map: &Map) -> Result<(), std::io::Error> {
     Ok(())
 }
 
+/// Verify turn restrictions are imported and respected by writing each restriction, then the
+/// movements between roads and uber-turns each type of vehicle can make. Intersections in a
+/// cluster only have their uber-turns written. Restrictions that only apply at some times are
+/// checked at 8am.
+fn dump_turn_restrictions_goldenfile(map: &Map) -> Result<(), std::io::Error> {
+    let path = abstutil::path(format!("../tests/goldenfiles/{}.txt", map.get_name().map));
+    let mut f = File::create(path)?;
+    let name = |r: RoadID| map.get_r(r).get_name(None);
+    let time = Time::START_OF_DAY + Duration::hours(8);
+    let vehicles = [
+        PathConstraints::Car,
+        PathConstraints::Bike,
+        PathConstraints::Bus,
+    ];
+
+    for r in map.all_roads() {
+        for (rt, to, scope) in &r.turn_restrictions {
+            writeln!(f, "{:?} from {} to {}{}", rt, name(r.id), name(*to), scope)?;
+        }
+        for (rt, via, to, scope) in &r.complicated_turn_restrictions {
+            writeln!(
+                f,
+                "{:?} from {} via {} to {}{}",
+                rt,
+                name(r.id),
+                via.iter().map(|r| name(*r)).collect::<Vec<_>>().join(", "),
+                name(*to),
+                scope
+            )?;
+        }
+    }
+
+    let clusters = IntersectionCluster::find_all(map);
+    for i in map.all_intersections() {
+        if i.roads.len() < 3 || clusters.iter().any(|ic| ic.members.contains(&i.id)) {
+            continue;
+        }
+        writeln!(f, "{}:", i.id)?;
+        for constraints in &vehicles {
+            let mut movements = BTreeSet::new();
+            for l in &i.incoming_lanes {
+                if !constraints.can_use(map.get_l(*l), map) {
+                    continue;
+                }
+                for (t, dst) in map.get_next_turns_and_lanes(*l, i.id) {
+                    if !constraints.can_use(dst, map) {
+                        continue;
+                    }
+                    movements.insert(format!(
+                        "{} -> {}{}",
+                        name(map.get_l(*l).parent),
+                        name(dst.parent),
+                        if map.is_turn_restricted(t, *constraints, None) {
+                            " (banned)"
+                        } else if map.is_turn_restricted(t, *constraints, Some(time)) {
+                            " (banned at 08:00)"
+                        } else {
+                            ""
+                        }
+                    ));
+                }
+            }
+            for m in movements {
+                writeln!(f, "  {:?}: {}", constraints, m)?;
+            }
+        }
+    }
+
+    for ic in clusters {
+        writeln!(f, "Uber-turns:")?;
+        for constraints in &vehicles {
+            let mut movements = BTreeSet::new();
+            for ut in &ic.uber_turns {
+                if !ut
+                    .path
+                    .iter()
+                    .all(|t| constraints.can_use(map.get_l(t.dst), map))
+                {
+                    continue;
+                }
+                movements.insert(format!(
+                    "{}{}",
+                    ut.roads(map)
+                        .into_iter()
+                        .map(name)
+                        .collect::<Vec<_>>()
+                        .join(" -> "),
+                    if ut.is_restricted(*constraints, None, map) {
+                        " (banned)"
+                    } else if ut.is_restricted(*constraints, Some(time), map) {
+                        " (banned at 08:00)"
+                    } else {
+                        ""
+                    }
+                ));
+            }
+            for m in movements {
+                writeln!(f, "  {:?}: {}", constraints, m)?;
+            }
+        }
+    }
+    Ok(())
+}
+
 /// Simulate an hour on every map.
 fn smoke_test() -> Result<(), std::io::Error> {
     let mut timer = Timer::new("run a smoke-test for all maps");
@@ -227,3 +356,446 @@ fn test_lane_changing(map: &Map) -> Result<(), String> {
 
     Ok(())
 }
+
+/// Verify ride-hailing vehicles are dispatched to everybody, reposition when there's nobody left
+/// to pick up, and that people give up when no vehicle comes for too long.
+fn test_ride_hailing(map: &Map) -> Result<(), String> {
+    // Everybody rides to the building on the opposite side of the map
+    let bldgs: Vec<BuildingID> = map.all_buildings().iter().map(|b| b.id).collect();
+    let make_scenario = |spacing: Duration| {
+        let mut scenario = Scenario::empty(map, "ride_hailing");
+        for (idx, b) in bldgs.iter().enumerate() {
+            scenario.people.push(PersonSpec {
+                orig_id: None,
+                origin: TripEndpoint::Bldg(*b),
+                trips: vec![IndividTrip::new(
+                    Time::START_OF_DAY + spacing * (idx as f64),
+                    TripPurpose::Shopping,
+                    TripEndpoint::Bldg(bldgs[bldgs.len() - 1 - idx]),
+                    TripMode::RideHail,
+                )],
+            });
+        }
+        scenario
+    };
+    let run = |scenario: Scenario, fleet: usize| {
+        let mut opts = sim::SimOptions::new("test_ride_hailing");
+        opts.alerts = sim::AlertHandler::Silence;
+        opts.ride_hail_fleet = fleet;
+        let mut sim = sim::Sim::new(&map, opts, &mut Timer::throwaway());
+        let mut rng = sim::SimFlags::for_test("test_ride_hailing").make_rng();
+        scenario.instantiate(&mut sim, &map, &mut rng, &mut Timer::throwaway());
+        while !sim.is_done() {
+            sim.tiny_step(&map, &mut None);
+        }
+        // Give the fleet time to reposition after the last dropoff
+        sim.timed_step(
+            &map,
+            Duration::minutes(10),
+            &mut None,
+            &mut Timer::throwaway(),
+        );
+        sim
+    };
+
+    // With requests spread out, two vehicles can serve everybody
+    let sim = run(make_scenario(Duration::minutes(5)), 2);
+    let analytics = sim.get_analytics();
+    if analytics.ride_hail_waits.len() != bldgs.len() {
+        return Err(format!(
+            "{} people requested a ride, but only {} were picked up",
+            bldgs.len(),
+            analytics.ride_hail_waits.len()
+        ));
+    }
+    for (_, info) in sim.all_trip_info() {
+        if let Some(reason) = info.cancellation_reason {
+            return Err(format!("A ride-hailing trip was cancelled: {}", reason));
+        }
+    }
+    // After the last dropoff, each vehicle drives empty back to a staging spot
+    let mut last_leg = BTreeMap::new();
+    for (_, car, _, occupied) in &analytics.ride_hail_distance {
+        last_leg.insert(*car, *occupied);
+    }
+    if last_leg.is_empty() || last_leg.values().any(|occupied| *occupied) {
+        return Err(format!(
+            "Ride-hailing vehicles didn't reposition after their last dropoff: {:?}",
+            last_leg
+        ));
+    }
+
+    // With one vehicle and everybody asking at once, the last people in line give up
+    let sim = run(make_scenario(Duration::ZERO), 1);
+    let mut gave_up = 0;
+    for (_, info) in sim.all_trip_info() {
+        if let Some(reason) = info.cancellation_reason {
+            if !reason.starts_with("no ride-hailing vehicle came") {
+                return Err(format!("A ride-hailing trip was cancelled: {}", reason));
+            }
+            gave_up += 1;
+        }
+    }
+    if gave_up == 0 || gave_up == bldgs.len() {
+        return Err(format!(
+            "{} of {} people gave up waiting for one ride-hailing vehicle",
+            gave_up,
+            bldgs.len()
+        ));
+    }
+
+    Ok(())
+}
+
+/// Verify delivery trucks load at the curb when there's room, and otherwise double-park in the
+/// driving lane until they're done.
+fn test_delivery_trucks(map: &Map) -> Result<(), String> {
+    let bldgs: Vec<BuildingID> = map.all_buildings().iter().map(|b| b.id).collect();
+    let home = bldgs[0];
+    let stop = bldgs[bldgs.len() / 2];
+    let dwell = Duration::minutes(5);
+    let run = |parked_near_stop: usize| {
+        let mut scenario = Scenario::empty(map, "delivery_trucks");
+        let mut trip = IndividTrip::new(
+            Time::START_OF_DAY,
+            TripPurpose::Work,
+            TripEndpoint::Bldg(bldgs[bldgs.len() - 1]),
+            TripMode::Delivery,
+        );
+        trip.delivery_stops = vec![(stop, dwell)];
+        scenario.people.push(PersonSpec {
+            orig_id: None,
+            origin: TripEndpoint::Bldg(home),
+            trips: vec![trip],
+        });
+        // Cars belonging to people at the stop fill up the curb there. They don't leave until long
+        // after the truck is gone.
+        for _ in 0..parked_near_stop {
+            scenario.people.push(PersonSpec {
+                orig_id: None,
+                origin: TripEndpoint::Bldg(stop),
+                trips: vec![IndividTrip::new(
+                    Time::START_OF_DAY + Duration::hours(1),
+                    TripPurpose::Work,
+                    TripEndpoint::Bldg(home),
+                    TripMode::Drive,
+                )],
+            });
+        }
+
+        let mut opts = sim::SimOptions::new("test_delivery_trucks");
+        opts.alerts = sim::AlertHandler::Silence;
+        let mut sim = sim::Sim::new(&map, opts, &mut Timer::throwaway());
+        let mut rng = sim::SimFlags::for_test("test_delivery_trucks").make_rng();
+        scenario.instantiate(&mut sim, &map, &mut rng, &mut Timer::throwaway());
+        while !sim.is_done() {
+            sim.tiny_step(&map, &mut None);
+        }
+        sim
+    };
+
+    for (parked_near_stop, should_double_park) in vec![(0, false), (60, true)] {
+        let sim = run(parked_near_stop);
+        let analytics = sim.get_analytics();
+        let stops: Vec<_> = analytics
+            .curbside_loading
+            .iter()
+            .filter(|(_, _, b, _, _, _)| *b == stop)
+            .collect();
+        if stops.len() != 1 {
+            return Err(format!(
+                "The truck should stop once at {}, but stopped {} times",
+                stop,
+                stops.len()
+            ));
+        }
+        let (_, _, _, lane, double_parked, stayed) = stops[0];
+        if *double_parked != should_double_park || *stayed != dwell {
+            return Err(format!(
+                "With {} cars parked nearby, the truck should double-park: {}, but it \
+                 double-parked: {}, staying {}",
+                parked_near_stop, should_double_park, double_parked, stayed
+            ));
+        }
+
+        let blocked = analytics.double_parking_per_lane();
+        if should_double_park {
+            if !map.get_l(*lane).is_driving() || blocked.get(lane) != Some(&dwell) {
+                return Err(format!(
+                    "The truck double-parked on {}, but the lane was blocked for {:?}",
+                    lane,
+                    blocked.get(lane)
+                ));
+            }
+        } else if !blocked.is_empty() {
+            return Err(format!(
+                "The truck loaded at the curb, but lanes were blocked: {:?}",
+                blocked
+            ));
+        }
+    }
+
+    Ok(())
+}
+
+/// Verify people take bike share bikes between the stations closest to their start and end, and
+/// go to the next closest station when one is empty or full.
+fn test_bike_share(map: &Map) -> Result<(), String> {
+    let station = |name: &str| {
+        map.all_bike_share_stations()
+            .iter()
+            .find(|s| s.name == name)
+            .unwrap()
+    };
+    let closest_bldg = |name: &str| {
+        let pt = station(name).point;
+        map.all_buildings()
+            .iter()
+            .min_by_key(|b| b.polygon.center().dist_to(pt))
+            .unwrap()
+            .id
+    };
+    let (start, end) = (station("Southwest"), station("Northeast"));
+    if start.initial_bikes != 1 || end.initial_bikes != 1 {
+        return Err(format!(
+            "Stations with 2 docks should start half full, not with {} and {} bikes",
+            start.initial_bikes, end.initial_bikes
+        ));
+    }
+
+    // Two people ride between the same stations. The first takes the only bike at the start and
+    // fills the last dock at the end, so the second has to go elsewhere both times.
+    let mut scenario = Scenario::empty(map, "bike_share");
+    for idx in 0..2 {
+        scenario.people.push(PersonSpec {
+            orig_id: None,
+            origin: TripEndpoint::Bldg(closest_bldg("Southwest")),
+            trips: vec![IndividTrip::new(
+                Time::START_OF_DAY + Duration::minutes(idx),
+                TripPurpose::Shopping,
+                TripEndpoint::Bldg(closest_bldg("Northeast")),
+                TripMode::BikeShare,
+            )],
+        });
+    }
+    let mut opts = sim::SimOptions::new("test_bike_share");
+    opts.alerts = sim::AlertHandler::Silence;
+    let mut sim = sim::Sim::new(&map, opts, &mut Timer::throwaway());
+    let mut rng = sim::SimFlags::for_test("test_bike_share").make_rng();
+    scenario.instantiate(&mut sim, &map, &mut rng, &mut Timer::throwaway());
+    while !sim.is_done() {
+        sim.tiny_step(&map, &mut None);
+    }
+    for (_, info) in sim.all_trip_info() {
+        if let Some(reason) = info.cancellation_reason {
+            return Err(format!("A bike share trip was cancelled: {}", reason));
+        }
+    }
+
+    let stats = sim
+        .get_analytics()
+        .bike_share_station_stats(sim.time(), &map);
+    let pickups: usize = stats.values().map(|s| s.pickups).sum();
+    let dropoffs: usize = stats.values().map(|s| s.dropoffs).sum();
+    if pickups != 2 || dropoffs != 2 {
+        return Err(format!(
+            "Both people should ride, but there were {} pickups and {} dropoffs",
+            pickups, dropoffs
+        ));
+    }
+    let at_start = &stats[&start.id];
+    if at_start.pickups != 1 || at_start.no_bikes != 1 {
+        return Err(format!(
+            "At {}, one person should take the only bike, and the other should find none, but \
+             there were {} pickups and {} turned away",
+            start.name, at_start.pickups, at_start.no_bikes
+        ));
+    }
+    let at_end = &stats[&end.id];
+    if at_end.dropoffs != 1 || at_end.no_docks != 1 || at_end.max_bikes != 2 {
+        return Err(format!(
+            "At {}, one person should fill the last dock, and the other should find it full, but \
+             there were {} dropoffs and {} turned away",
+            end.name, at_end.dropoffs, at_end.no_docks
+        ));
+    }
+
+    Ok(())
+}
+
+/// Verify people drive to the lot next to a bus stop and ride from there, and drive the whole way
+/// once the lot is full.
+fn test_park_and_ride(map: &Map) -> Result<(), String> {
+    let closest_bldg = |name: &str| {
+        let pt = map
+            .all_bike_share_stations()
+            .iter()
+            .find(|s| s.name == name)
+            .unwrap()
+            .point;
+        map.all_buildings()
+            .iter()
+            .min_by_key(|b| b.polygon.center().dist_to(pt))
+            .unwrap()
+            .id
+    };
+    let (home, work) = (closest_bldg("Southwest"), closest_bldg("Northeast"));
+    let lot = &map.all_parking_lots()[0];
+    let route = &map.all_bus_routes()[0];
+
+    // Both pathfinders should suggest the same plan
+    let dijkstra_map = import_map_with(abstutil::path("../tests/input/small_grid.osm"), false);
+    for m in vec![map, &dijkstra_map] {
+        let start = m.get_b(home).driving_connection(m).unwrap().0;
+        let plan = m.should_use_park_and_ride(start, m.get_b(work).sidewalk_pos);
+        if plan != Some((lot.id, route.stops[0], Some(route.stops[1]), route.id)) {
+            return Err(format!(
+                "Should park at {} and ride {} the whole way, but the plan is {:?}",
+                lot.id, route.full_name, plan
+            ));
+        }
+    }
+
+    // One more person than fits in the lot
+    let mut scenario = Scenario::empty(map, "park_and_ride");
+    scenario.only_seed_buses = None;
+    for idx in 0..lot.capacity() + 1 {
+        scenario.people.push(PersonSpec {
+            orig_id: None,
+            origin: TripEndpoint::Bldg(home),
+            trips: vec![IndividTrip::new(
+                Time::START_OF_DAY + Duration::minutes(30 + idx),
+                TripPurpose::Work,
+                TripEndpoint::Bldg(work),
+                TripMode::ParkAndRide,
+            )],
+        });
+    }
+    let mut opts = sim::SimOptions::new("test_park_and_ride");
+    opts.alerts = sim::AlertHandler::Silence;
+    let mut sim = sim::Sim::new(&map, opts, &mut Timer::throwaway());
+    let mut rng = sim::SimFlags::for_test("test_park_and_ride").make_rng();
+    scenario.instantiate(&mut sim, &map, &mut rng, &mut Timer::throwaway());
+    while !sim.is_done() {
+        sim.tiny_step(&map, &mut None);
+    }
+
+    let mut rode_bus = 0;
+    for (trip, info) in sim.all_trip_info() {
+        if let Some(reason) = info.cancellation_reason {
+            return Err(format!("A park-and-ride trip was cancelled: {}", reason));
+        }
+        if sim
+            .get_analytics()
+            .get_trip_phases(trip, &map)
+            .iter()
+            .any(|p| matches!(p.phase_type, sim::TripPhaseType::RidingBus(_, _, _)))
+        {
+            rode_bus += 1;
+        }
+    }
+    if rode_bus != lot.capacity() {
+        return Err(format!(
+            "{} people should fit in {} and ride the bus, but {} did",
+            lot.capacity(),
+            lot.id,
+            rode_bus
+        ));
+    }
+
+    Ok(())
+}
+
+/// Verify people who don't fit on a full bus wait for the next one, and that the load profile
+/// counts them.
+fn test_transit_crowding(map: &Map) -> Result<(), String> {
+    let route = &map.all_bus_routes()[0];
+    let closest_bldg = |stop: BusStopID| {
+        let pt = map.get_bs(stop).sidewalk_pos.pt(map);
+        map.all_buildings()
+            .iter()
+            .min_by_key(|b| b.polygon.center().dist_to(pt))
+            .unwrap()
+            .id
+    };
+    let (home, work) = (closest_bldg(route.stops[0]), closest_bldg(route.stops[1]));
+    let num_people = 5;
+    let run = |capacity: Option<usize>| {
+        // Everybody is waiting at the stop before the first bus comes
+        let mut scenario = Scenario::empty(map, "transit_crowding");
+        scenario.only_seed_buses = None;
+        for idx in 0..num_people {
+            scenario.people.push(PersonSpec {
+                orig_id: None,
+                origin: TripEndpoint::Bldg(home),
+                trips: vec![IndividTrip::new(
+                    Time::START_OF_DAY + Duration::minutes(30 + idx),
+                    TripPurpose::Work,
+                    TripEndpoint::Bldg(work),
+                    TripMode::Transit,
+                )],
+            });
+        }
+        let mut opts = sim::SimOptions::new("test_transit_crowding");
+        opts.alerts = sim::AlertHandler::Silence;
+        opts.transit_crowding = capacity.map(|n| {
+            let mut crowding = sim::TransitCrowding::defaults();
+            crowding.route_capacity.insert(route.id, n);
+            crowding
+        });
+        let mut sim = sim::Sim::new(&map, opts, &mut Timer::throwaway());
+        let mut rng = sim::SimFlags::for_test("test_transit_crowding").make_rng();
+        scenario.instantiate(&mut sim, &map, &mut rng, &mut Timer::throwaway());
+        while !sim.is_done() {
+            sim.tiny_step(&map, &mut None);
+        }
+        sim
+    };
+
+    for capacity in vec![None, Some(2)] {
+        let sim = run(capacity);
+        for (_, info) in sim.all_trip_info() {
+            if let Some(reason) = info.cancellation_reason {
+                return Err(format!("A transit trip was cancelled: {}", reason));
+            }
+        }
+        let analytics = sim.get_analytics();
+        let profile = analytics.transit_load_profile(route.id, &map);
+        let (first, last) = (&profile[0], &profile[1]);
+        if first.boardings != num_people || last.alightings != num_people {
+            return Err(format!(
+                "Everybody should ride from {} to {}, but {} boarded and {} got off",
+                first.stop, last.stop, first.boardings, last.alightings
+            ));
+        }
+
+        let max_load = capacity.unwrap_or(num_people);
+        if first.max_load != max_load {
+            return Err(format!(
+                "With capacity {:?}, the fullest bus should leave {} with {} people, not {}",
+                capacity, first.stop, max_load, first.max_load
+            ));
+        }
+        let over_capacity = analytics
+            .transit_vehicle_loads(route.id)
+            .values()
+            .flatten()
+            .any(|(_, _, load)| *load > max_load);
+        if over_capacity {
+            return Err(format!(
+                "Some bus carried more than {} people with capacity {:?}",
+                max_load, capacity
+            ));
+        }
+        // Each full bus turns away everybody left waiting
+        let denied = if capacity.is_some() { 3 + 1 } else { 0 };
+        if first.denied != denied {
+            return Err(format!(
+                "With capacity {:?}, {} boardings should be denied at {}, not {}",
+                capacity, denied, first.stop, first.denied
+            ));
+        }
+    }
+
+    Ok(())
+}